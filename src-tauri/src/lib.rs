@@ -1,383 +1,550 @@
-use reqwest::Client;
-use serde::Serialize;
+//! The desktop app of yad. It is a thin layer over the engine in `yad_core`: the commands called
+//! by the frontend are passed on to the engine, and the events of the engine are emitted to the
+//! windows and shown as notifications.
+
 use std::{
-    collections::HashMap,
-    fs::{self, File},
-    io::{Seek, SeekFrom, Write},
-    path::Path,
-    process::Command,
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Mutex, OnceLock,
-    },
+    fs,
+    path::{Path, PathBuf},
     time::{SystemTime, UNIX_EPOCH},
 };
 
 use tauri::{self, Emitter};
 use tauri_plugin_notification::NotificationExt;
-use tokio::sync::Semaphore;
-
-pub mod config;
-pub mod files;
-pub mod storage;
-
-const CHUNK_SIZE: u64 = 1024 * 1024;
-const MAX_CONCURRENT_CHUNKS: usize = 4;
-const BROWSER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use yad_core::{
+    bundle, chunks, config, crash, criteria, decisions, engine, eta, file_manager, ftp,
+    integrity, jobfile, latency, metalink, metrics, onboarding, organize, progress, proxy,
+    record_query, report, retry, s3, scheduler, settings, sftp, storage, templates, upgrade,
+};
 
-fn active_downloads() -> &'static Mutex<HashMap<i64, Arc<AtomicBool>>> {
-    static MAP: OnceLock<Mutex<HashMap<i64, Arc<AtomicBool>>>> = OnceLock::new();
-    MAP.get_or_init(|| Mutex::new(HashMap::new()))
+/// This function emits the events of the engine to the windows for as long as the application
+/// is open.
+async fn forward_events(app: tauri::AppHandle, mut events: Receiver<engine::Event>) {
+    loop {
+        let event = match events.recv().await {
+            Ok(event) => event,
+            // the progress that was dropped is carried by the next progress event
+            Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        };
+        match event {
+            engine::Event::Started(e) => {
+                let _ = app.emit("download-started", e);
+            }
+            engine::Event::Progress(e) => {
+                let _ = app.emit("download-progress", e);
+            }
+            engine::Event::Message(e) => {
+                let _ = app.emit("download-message", e);
+            }
+            engine::Event::Bulk(e) => {
+                let _ = app.emit("bulk-action", e);
+            }
+            engine::Event::Notification { title, body } => {
+                let _ = app.notification().builder().title(title).body(body).show();
+            }
+            engine::Event::DecisionRequired(e) => {
+                let _ = app.emit("decision-required", e);
+            }
+            engine::Event::Verifying(e) => {
+                let _ = app.emit("download-verifying", e);
+            }
+        }
+    }
 }
 
+/// This command answers a decision a download waits for, see `decisions`.
 #[tauri::command]
-fn fetch_records() -> Vec<storage::DownloadRecord> {
-    let cfg = config::Config::default();
-    storage::read_download_records(&cfg).unwrap_or_default()
-}
-
-#[derive(Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct DownloadStarted<'a> {
-    download_id: i64,
-    file_url: &'a str,
-    file_name: &'a str,
-    file_type: &'a str,
-    download_status: &'a str,
+fn resolve_decision(token: u64, choice: String, input: Option<String>) -> Result<(), String> {
+    decisions::resolve(token, &choice, input)
 }
 
-#[derive(Clone, Serialize, Debug)]
-#[serde(rename_all = "camelCase")]
-struct DownloadProgress {
-    download_id: i64,
-    total_size: u64,
-    downloaded: u64,
-    timestamp: u64,
+/// This command returns the decisions waiting for an answer, for a window opened after they were
+/// emitted.
+#[tauri::command]
+fn get_pending_decisions() -> Vec<decisions::Decision> {
+    decisions::waiting()
 }
 
-#[derive(Clone, Serialize)]
-#[serde(rename_all = "camelCase")]
-struct DownloadMessage<'a> {
-    download_id: i64,
-    message: &'a str,
-    status: &'a str,
+/// This command returns the download records. Without a query every field of every record is
+/// returned, see `record_query::RecordQuery` to ask for fewer fields or for the chunk map and
+/// speed.
+#[tauri::command]
+fn fetch_records(
+    query: Option<record_query::RecordQuery>,
+) -> Result<Vec<record_query::Row>, String> {
+    let cfg = config::Config::default();
+    record_query::fetch(&query.unwrap_or_default(), &cfg)
 }
 
 #[tauri::command]
 async fn download(
-    window: tauri::Window,
     url: String,
     file_name: Option<String>,
     destination_dir: Option<String>,
+    referer: Option<String>,
+    criteria: Option<criteria::SuccessCriteria>,
+    template_id: Option<i64>,
+    byte_range: Option<chunks::ByteRange>,
+    bind_to: Option<String>,
+    mirrors: Option<Vec<String>>,
+    open_when_done: Option<file_manager::OpenWhenDone>,
+    retry_overrides: Option<retry::RetryOverrides>,
+    video_height: Option<u32>,
 ) -> Result<(), String> {
-    if !url.starts_with("http://") && !url.starts_with("https://") && !url.starts_with("ftp://") {
-        let _ = window.emit(
-            "download-message",
-            DownloadMessage {
-                download_id: 0,
-                message: "Invalid URL. Must start with http://, https://, or ftp://",
-                status: "error",
-            },
-        );
-        return Err("Invalid URL".into());
-    }
+    engine::add(engine::DownloadRequest {
+        url,
+        file_name,
+        destination_dir,
+        referer,
+        criteria,
+        template_id,
+        parent_id: None,
+        byte_range,
+        bind_to,
+        mirrors: mirrors.unwrap_or_default(),
+        replace_finished: false,
+        open_when_done,
+        retry_overrides: retry_overrides.filter(|r| !r.is_empty()),
+        video_height,
+        record_id: None,
+    })
+    .await
+}
 
+/// This command sets what is opened when a download finishes, its file or its folder. `None`
+/// opens nothing.
+#[tauri::command]
+fn set_open_when_done(id: i64, action: Option<file_manager::OpenWhenDone>) -> Result<(), String> {
     let cfg = config::Config::default();
-    let client = Client::new();
-
-    let total_size = client
-        .head(&url)
-        .send()
-        .await
-        .map_err(|e| format!("HEAD request failed: {e}"))?
-        .headers()
-        .get(reqwest::header::CONTENT_LENGTH)
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.parse::<u64>().ok())
-        .ok_or_else(|| {
-            let _ = window.emit(
-                "download-message",
-                DownloadMessage {
-                    download_id: 0,
-                    message: "Server did not provide Content-Length header",
-                    status: "error",
-                },
-            );
-            "Missing Content-Length header".to_string()
-        })?;
-
-    if total_size == 0 {
-        return Err("File has zero size".into());
-    }
+    storage::update_open_when_done(id, action, &cfg)
+        .map_err(|e| format!("Failed to save the choice: {e}"))
+}
 
-    let mut file = files::File::new(&url, &cfg);
-
-    if let Some(custom_name) = &file_name {
-        let trimmed = custom_name.trim();
-        if !trimmed.is_empty() {
-            let ext = Path::new(&file.file_name)
-                .extension()
-                .and_then(|e| e.to_str())
-                .unwrap_or("");
-            let final_name = if !trimmed.contains('.') && !ext.is_empty() {
-                format!("{}.{}", trimmed, ext)
-            } else {
-                trimmed.to_string()
-            };
-            file.file_name = final_name;
-            file.destination_path = format!("{}/{}", file.destination_dir, file.file_name);
-        }
-    }
+/// This command adds mirrors, other urls serving the same file, to a download. They are used
+/// the next time it starts, see `mirrors`.
+#[tauri::command]
+fn add_mirrors(id: i64, urls: Vec<String>) -> Result<(), String> {
+    engine::add_mirrors(id, &urls)
+}
 
-    if let Some(custom_dir) = &destination_dir {
-        let trimmed = custom_dir.trim();
-        if !trimmed.is_empty() {
-            let dir_path = Path::new(trimmed).join(&file.file_name);
-            if let Some(dir) = dir_path.parent() {
-                file.destination_dir = dir.to_str().unwrap_or(&file.destination_dir).to_string();
-            }
-            file.destination_path = dir_path.to_str().unwrap_or(&file.destination_path).to_string();
-        }
-    }
+#[tauri::command]
+fn cancel_download(download_id: i64) -> Result<(), String> {
+    engine::pause(download_id)
+}
 
-    let mut record = storage::search_by_url(&url, &cfg).unwrap_or_default();
-
-    fs::create_dir_all(&file.destination_dir)
-        .map_err(|e| format!("Failed to create directory: {e}"))?;
-
-    if record.id == 0 {
-        let dr = storage::DownloadRecord::from(file.clone());
-        record.id = storage::insert_record(&dr, total_size, &cfg)
-            .map_err(|e| format!("Failed to save download record: {e}"))?;
-    } else if record.download_status == "Finished" {
-        let _ = window.emit(
-            "download-message",
-            DownloadMessage {
-                download_id: record.id,
-                message: "File already downloaded",
-                status: "success",
-            },
-        );
-        return Ok(());
-    }
+/// This command limits the speed of a running download in bytes per second, 0 removes the limit.
+/// The global and template limits still apply when they are lower. The limit is kept until the
+/// download stops.
+#[tauri::command]
+fn set_speed_limit(id: i64, bytes_per_sec: u64) -> Result<(), String> {
+    engine::set_speed_limit(id, bytes_per_sec)
+}
+
+/// This command pauses several running downloads. The records are updated in one transaction.
+#[tauri::command]
+fn pause_downloads(ids: Vec<i64>) -> Result<engine::BulkSummary, String> {
+    engine::pause_many(&ids)
+}
+
+/// This command pauses every running and queued download in one call.
+#[tauri::command]
+fn pause_all() -> Result<engine::BulkSummary, String> {
+    engine::pause_all()
+}
+
+/// This command downloads the failed and pending chunks of a download again, writing them into
+/// the existing file. Finished chunks are kept.
+#[tauri::command]
+async fn retry_download(id: i64) -> Result<(), String> {
+    engine::retry(id).await
+}
+
+/// This command checks whether the file of a finished download changed on the server, and marks
+/// the download as outdated if so.
+#[tauri::command]
+async fn check_for_update(id: i64) -> Result<bool, String> {
+    engine::check_for_update(id).await
+}
+
+/// This command downloads the file of a finished download again, replacing the record.
+#[tauri::command]
+async fn redownload(id: i64) -> Result<(), String> {
+    engine::redownload(id).await
+}
+
+/// This command moves the files of an existing downloads folder into the YAD folders and adds
+/// them to the history, see `organize`.
+#[tauri::command]
+fn organize_existing(path: String) -> Result<organize::Summary, String> {
+    organize::organize_existing(Path::new(&path), &config::Config::default())
+}
+
+/// This command resumes several paused downloads.
+#[tauri::command]
+async fn resume_downloads(ids: Vec<i64>) -> Result<engine::BulkSummary, String> {
+    engine::resume_many(&ids)
+}
+
+/// This command resumes every paused or interrupted download in one call.
+#[tauri::command]
+async fn resume_all() -> Result<engine::BulkSummary, String> {
+    engine::resume_all()
+}
+
+/// This command retries several failed, paused or pending downloads.
+#[tauri::command]
+async fn retry_downloads(ids: Vec<i64>) -> Result<engine::BulkSummary, String> {
+    engine::retry_many(&ids)
+}
+
+/// This command deletes several records in one transaction. They can be restored with
+/// `undo_delete` until the undo window has passed.
+#[tauri::command]
+async fn delete_records(ids: Vec<i64>) -> Result<engine::BulkSummary, String> {
+    engine::delete_many(&ids)
+}
+
+/// This command returns the records moved out of the history into the archive, oldest first.
+#[tauri::command]
+fn fetch_archived_records() -> Result<Vec<storage::DownloadRecord>, String> {
+    engine::archived_records()
+}
+
+/// This command moves archived records back into the history.
+#[tauri::command]
+fn restore_archived_records(ids: Vec<i64>) -> Result<engine::BulkSummary, String> {
+    engine::restore_archived(&ids)
+}
+
+/// This command deletes a record. The record can be restored with `undo_delete` until the undo
+/// window has passed, after which it is purged.
+#[tauri::command]
+async fn delete_record(id: i64) -> Result<(), String> {
+    engine::delete(id)
+}
+
+/// This command restores a record deleted with `delete_record` or `delete_records`.
+#[tauri::command]
+fn undo_delete(download_id: i64) -> Result<(), String> {
+    engine::undelete(download_id)
+}
+
+/// This command permanently deletes the deleted records without waiting for their undo window.
+#[tauri::command]
+fn purge_deleted() -> Result<usize, String> {
+    engine::purge_deleted()
+}
+
+/// This command returns the live progress of the running downloads so that a reloaded window can
+/// restore its progress bars straight away.
+#[tauri::command]
+fn get_active_downloads() -> Vec<progress::ActiveDownload> {
+    progress::active_downloads()
+}
+
+/// This command changes the priority of a download, higher priorities leave the queue first.
+#[tauri::command]
+fn set_priority(id: i64, priority: i64) -> Result<(), String> {
+    engine::set_priority(id, priority)
+}
+
+/// This command changes the category of a download, optionally moving its file into the folder
+/// of the category. Later downloads with the same extension get the same category.
+#[tauri::command]
+fn set_category(download_id: i64, category: String, move_file: bool) -> Result<(), String> {
+    engine::set_category(download_id, &category, move_file)
+}
+
+/// This command moves a queued download to `position` in the queue, 0 being next.
+#[tauri::command]
+fn move_in_queue(id: i64, position: usize) -> Result<(), String> {
+    engine::move_in_queue(id, position)
+}
 
-    let _ = window.emit(
-        "download-started",
-        DownloadStarted {
-            download_id: record.id,
-            file_url: &file.file_url,
-            file_name: &file.file_name,
-            file_type: &file.file_type.to_string(),
-            download_status: "InProgress",
-        },
+/// This command estimates when every running and queued download will have finished, e.g. to
+/// tell whether everything is done by morning.
+#[tauri::command]
+fn get_queue_eta() -> Result<eta::QueueEta, String> {
+    engine::queue_eta()
+}
+
+/// This command returns the time to first byte and chunk durations of a running download, to
+/// tell a slow server from a slow connection.
+#[tauri::command]
+fn get_download_latency(id: i64) -> Option<latency::LatencyStats> {
+    latency::for_download(id)
+}
+
+/// This command returns the chunks of a download with how often each was attempted, to show which
+/// byte ranges keep failing.
+#[tauri::command]
+fn fetch_chunks(id: i64) -> Result<Vec<storage::Chunk>, String> {
+    let cfg = config::Config::default();
+    storage::get_chunks_by_record(id, &cfg).map_err(|e| format!("Failed to read chunks: {e}"))
+}
+
+/// This command returns the time to first byte and chunk durations of every host downloaded from
+/// since the application started, slowest first.
+#[tauri::command]
+fn get_host_latency() -> Vec<latency::HostLatency> {
+    latency::hosts()
+}
+
+#[tauri::command]
+fn get_settings() -> settings::Settings {
+    settings::current()
+}
+
+/// This command returns the proxy configured in the operating system, which downloads use unless a
+/// proxy is set in the settings.
+#[tauri::command]
+fn get_system_proxy() -> Option<proxy::SystemProxy> {
+    let cfg = config::Config::default();
+    proxy::detect(&cfg.os)
+}
+
+#[tauri::command]
+fn update_settings(new_settings: settings::Settings) -> Result<(), String> {
+    let cfg = config::Config::default();
+    settings::update(new_settings, &cfg).map_err(|e| format!("Failed to update settings: {e}"))
+}
+
+/// This command saves the settings and templates as a bundle that can be imported on another
+/// machine. Template headers and proxy credentials are only included when `include_secrets` is
+/// set, and a passphrase signs the bundle.
+#[tauri::command]
+fn export_settings_bundle(
+    path: String,
+    include_secrets: bool,
+    passphrase: Option<String>,
+) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let templates =
+        storage::read_templates(&cfg).map_err(|e| format!("Failed to read templates: {e}"))?;
+    let bundle = bundle::Bundle::new(
+        &settings::current(),
+        &templates,
+        include_secrets,
+        passphrase.as_deref(),
     );
+    fs::write(&path, bundle.to_json()).map_err(|e| format!("Failed to save settings bundle: {e}"))
+}
 
-    let d_file = File::create(&file.destination_path)
-        .map_err(|e| format!("Failed to create file: {e}"))?;
-    d_file
-        .set_len(total_size)
-        .map_err(|e| format!("Failed to allocate file: {e}"))?;
-    let d_file = Arc::new(Mutex::new(d_file));
-
-    let cancelled = Arc::new(AtomicBool::new(false));
-    active_downloads()
-        .lock()
-        .unwrap()
-        .insert(record.id, Arc::clone(&cancelled));
-
-    let existing_chunks = storage::get_chunks_by_record(record.id, &cfg).unwrap_or_default();
-    let finished: HashMap<(u64, u64), bool> = existing_chunks
-        .iter()
-        .filter(|c| c.status == "Finished")
-        .map(|c| ((c.start, c.end), true))
-        .collect();
-
-    let mut ranges: Vec<(u64, u64)> = Vec::new();
-    for start in (0..total_size).step_by(CHUNK_SIZE as usize) {
-        let end = (start + CHUNK_SIZE - 1).min(total_size - 1);
-        if finished.contains_key(&(start, end)) {
-            continue;
-        }
-        let chunk = storage::Chunk::new(record.id, start, end);
-        let _ = storage::save_chunk(&chunk, &cfg);
-        ranges.push((start, end));
-    }
+/// This command imports a settings bundle after checking its checksum or signature.
+#[tauri::command]
+fn import_settings_bundle(
+    path: String,
+    mode: bundle::ImportMode,
+    passphrase: Option<String>,
+) -> Result<bundle::ImportSummary, String> {
+    let content =
+        fs::read_to_string(&path).map_err(|e| format!("Failed to read settings bundle: {e}"))?;
+    let bundle = bundle::Bundle::parse(&content, passphrase.as_deref())?;
+    bundle::import(&bundle, mode, &config::Config::default())
+}
 
-    let progress = Arc::new(Mutex::new(0u64));
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<DownloadProgress>(64);
-    let pw = window.clone();
-    let progress_task = tokio::spawn(async move {
-        while let Some(p) = rx.recv().await {
-            let _ = pw.emit("download-progress", p);
+/// This command returns what the setup wizard shows on the first run. The speed is estimated from
+/// earlier downloads, or measured by downloading from `speed_test_url` when it is given.
+#[tauri::command]
+async fn get_first_run_info(
+    speed_test_url: Option<String>,
+) -> Result<onboarding::FirstRunInfo, String> {
+    let cfg = config::Config::default();
+    let current = settings::current();
+    let first_run = !storage::settings_saved(&cfg).map_err(|e| e.to_string())?;
+    let speed_estimate = match speed_test_url {
+        Some(url) => {
+            let client = settings::shared_client(&current)?;
+            Some(onboarding::speed_test(&client, &url).await?)
         }
-    });
-
-    let sem = Arc::new(Semaphore::new(MAX_CONCURRENT_CHUNKS));
-    let mut handles = Vec::with_capacity(ranges.len());
-
-    for (start, end) in ranges {
-        let s = Arc::clone(&sem);
-        let client = client.clone();
-        let d_file = Arc::clone(&d_file);
-        let tx = tx.clone();
-        let url = url.clone();
-        let p = Arc::clone(&progress);
-        let cancelled = Arc::clone(&cancelled);
-        let c = config::Config::default();
-        let rid = record.id;
-
-        handles.push(tokio::spawn(async move {
-            let _permit = s.acquire().await;
-
-            if cancelled.load(Ordering::Relaxed) {
-                let _ = storage::update_chunk(rid, start, "Cancelled", &c);
-                return;
-            }
+        None => {
+            let records = storage::read_download_records(&cfg).map_err(|e| e.to_string())?;
+            onboarding::speed_from_history(&records)
+        }
+    };
+    Ok(onboarding::first_run_info(
+        &current,
+        first_run,
+        speed_estimate,
+        &cfg,
+    ))
+}
 
-            let result = client
-                .get(&url)
-                .header("Range", format!("bytes={start}-{end}"))
-                .header("User-Agent", BROWSER_AGENT)
-                .send()
-                .await;
-
-            match result {
-                Ok(resp) => match resp.bytes().await {
-                    Ok(bytes) => {
-                        if let Ok(mut f) = d_file.lock() {
-                            let _ = f.seek(SeekFrom::Start(start));
-                            let _ = f.write_all(&bytes);
-                        }
-
-                        let mut prog = p.lock().unwrap();
-                        *prog += bytes.len() as u64;
-                        let current = *prog;
-                        drop(prog);
-
-                        let now = SystemTime::now()
-                            .duration_since(UNIX_EPOCH)
-                            .unwrap_or_default()
-                            .as_millis() as u64;
-                        let _ = tx.try_send(DownloadProgress {
-                            download_id: rid,
-                            downloaded: current,
-                            total_size,
-                            timestamp: now,
-                        });
-
-                        let _ = storage::update_chunk(rid, start, "Finished", &c);
-                    }
-                    Err(e) => {
-                        eprintln!("Chunk {start}-{end} body failed: {e}");
-                        let _ = storage::update_chunk(rid, start, "Failed", &c);
-                    }
-                },
-                Err(e) => {
-                    eprintln!("Chunk {start}-{end} request failed: {e}");
-                    let _ = storage::update_chunk(rid, start, "Failed", &c);
-                }
-            }
-        }));
+/// This command saves the settings chosen in the setup wizard, after which
+/// `get_first_run_info` no longer reports a first run.
+#[tauri::command]
+fn complete_onboarding(new_settings: settings::Settings) -> Result<(), String> {
+    let cfg = config::Config::default();
+    fs::create_dir_all(&cfg.download_dir)
+        .map_err(|e| format!("Failed to create the downloads folder: {e}"))?;
+    settings::update(new_settings, &cfg).map_err(|e| format!("Failed to update settings: {e}"))
+}
+
+
+/// This command opens a file with its default application, or a folder with the file manager.
+#[tauri::command]
+async fn open_file(path: String) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let os: &str = &cfg.os;
+    let path = PathBuf::from(path);
+    if path.is_dir() {
+        let configured = settings::current().file_manager;
+        return file_manager::folder_opener(os, configured.as_deref()).open(&path);
     }
+    file_manager::file_opener(os).open(&path)
+}
+
+/// This command shows a downloaded file in the file manager.
+#[tauri::command]
+async fn reveal_file(path: String) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let configured = settings::current().file_manager;
+    file_manager::reveal(Path::new(&path), &cfg.os, configured.as_deref())
+}
+
 
-    for h in handles {
-        let _ = h.await;
+#[tauri::command]
+fn schedule_download(job: scheduler::ScheduledJob) -> Result<i64, String> {
+    if !job.file_url.starts_with("http://")
+        && !job.file_url.starts_with("https://")
+        && !ftp::is_ftp(&job.file_url)
+        && !sftp::is_sftp(&job.file_url)
+        && !s3::is_s3(&job.file_url)
+    {
+        return Err("Invalid URL".into());
     }
+    let cfg = config::Config::default();
+    storage::insert_scheduled_job(&job, &cfg)
+        .map_err(|e| format!("Failed to schedule download: {e}"))
+}
 
-    drop(tx);
-    let _ = progress_task.await;
-
-    active_downloads().lock().unwrap().remove(&record.id);
-
-    let (pending, _finished, failed) =
-        storage::count_chunks(record.id, &cfg).unwrap_or_default();
-
-    if failed > 0 || pending > 0 {
-        let _ = window.emit(
-            "download-message",
-            DownloadMessage {
-                download_id: record.id,
-                message: if failed > 0 {
-                    "Download completed with errors — some chunks failed"
-                } else {
-                    "Download incomplete — some chunks are pending"
-                },
-                status: "error",
-            },
-        );
-        let _ = window
-            .notification()
-            .builder()
-            .title("YAD — Download incomplete")
-            .body(&format!("{} — {} chunks failed", file.file_name, failed))
-            .show();
-    } else {
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap_or_default()
-            .as_secs();
-        let _ =
-            storage::update_download_record(record.id, "Finished", Some(now), total_size, &cfg);
-
-        let _ = window.emit(
-            "download-message",
-            DownloadMessage {
-                download_id: record.id,
-                message: "Download completed successfully",
-                status: "success",
-            },
-        );
-        let _ = window
-            .notification()
-            .builder()
-            .title("YAD — Download complete")
-            .body(&format!("{} downloaded successfully", file.file_name))
-            .show();
+#[tauri::command]
+fn fetch_scheduled_jobs() -> Vec<scheduler::ScheduledJob> {
+    let cfg = config::Config::default();
+    storage::read_scheduled_jobs(&cfg).unwrap_or_default()
+}
+
+#[tauri::command]
+fn delete_scheduled_job(id: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    storage::delete_scheduled_job(id, &cfg)
+        .map_err(|e| format!("Failed to delete scheduled download: {e}"))
+}
+
+/// This command saves a download as a `.yad` job file. The checksum of a finished download is
+/// included so that whoever opens the job file gets exactly the same file.
+#[tauri::command]
+async fn export_job_file(id: i64, path: String) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let record = storage::read_records_by_ids(&[id], &cfg)
+        .map_err(|e| format!("Failed to read record: {e}"))?
+        .pop()
+        .ok_or("No download record found with this id")?;
+    let mut job = jobfile::JobFile::new(&record.file_url);
+    job.file_name = Some(record.file_name.clone());
+    if record.download_status == "Finished" {
+        let destination = std::path::PathBuf::from(&record.destination_path);
+        let sha256 = tokio::task::spawn_blocking(move || integrity::sha256_file(&destination))
+            .await
+            .map_err(|e| e.to_string())?;
+        job.sha256 = Some(sha256.map_err(|e| format!("Failed to compute the checksum: {e}"))?);
     }
+    fs::write(&path, job.to_json()).map_err(|e| format!("Failed to save job file: {e}"))
+}
 
-    Ok(())
+
+/// This command adds the download described by a `.yad` job file.
+#[tauri::command]
+async fn import_job_file(path: String) -> Result<(), String> {
+    let job = jobfile::read(Path::new(&path))?;
+    engine::start_job(job).await
 }
 
+/// This command adds the downloads of the files a `.meta4` Metalink file lists.
 #[tauri::command]
-fn cancel_download(download_id: i64) -> Result<(), String> {
-    let map = active_downloads().lock().unwrap();
-    if let Some(cancelled) = map.get(&download_id) {
-        cancelled.store(true, Ordering::Relaxed);
-        let cfg = config::Config::default();
-        let _ = storage::update_download_record(
-            download_id,
-            "Cancelled",
-            None,
-            0,
-            &cfg,
-        );
-        Ok(())
+async fn import_metalink(path: String) -> Result<(), String> {
+    let files = metalink::read(Path::new(&path))?;
+    engine::start_metalink(files, None).await
+}
+
+#[tauri::command]
+fn fetch_templates() -> Vec<templates::Template> {
+    let cfg = config::Config::default();
+    storage::read_templates(&cfg).unwrap_or_default()
+}
+
+/// This command saves a download template, creating it if its id is 0.
+///
+/// # Returns
+/// - `Ok(i64)`: The id of the template.
+/// - `Err(String)`: If the template is invalid or could not be saved.
+#[tauri::command]
+fn save_template(template: templates::Template) -> Result<i64, String> {
+    template.validate()?;
+    let cfg = config::Config::default();
+    if template.id == 0 {
+        storage::insert_template(&template, &cfg)
     } else {
-        Err("No active download found with this id".into())
+        storage::update_template(&template, &cfg).map(|()| template.id)
     }
+    .map_err(|e| format!("Failed to save template: {e}"))
 }
 
 #[tauri::command]
-fn delete_record(id: i64) -> Result<(), String> {
+fn delete_template(id: i64) -> Result<(), String> {
     let cfg = config::Config::default();
-    storage::delete_record(id, &cfg).map_err(|e| format!("Failed to delete record: {e}"))
+    storage::delete_template(id, &cfg).map_err(|e| format!("Failed to delete template: {e}"))
 }
 
+/// This command returns the scheduled downloads as an iCalendar document which the frontend can
+/// save as a `.ics` file.
 #[tauri::command]
-async fn open_file(path: String) -> Result<(), String> {
+fn export_schedule_ics() -> Result<String, String> {
     let cfg = config::Config::default();
-    let os: &str = &cfg.os;
-    let command = match os {
-        "Windows" => "explorer",
-        "Darwin" => "open",
-        _ => "xdg-open",
-    };
+    let jobs = storage::read_scheduled_jobs(&cfg)
+        .map_err(|e| format!("Failed to read scheduled downloads: {e}"))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    Ok(scheduler::to_ics(&jobs, now))
+}
 
-    Command::new(command)
-        .arg(&path)
-        .output()
-        .map_err(|e| format!("Failed to open file: {e}"))?;
-    Ok(())
+/// This command saves a report of the downloads in `ids`, or of every download when not given.
+/// The checksums of the finished files are computed for it, which takes a while for big files.
+#[tauri::command]
+async fn export_report(
+    format: report::ReportFormat,
+    ids: Option<Vec<i64>>,
+    path: String,
+) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let records = match ids {
+        Some(ids) => storage::read_records_by_ids(&ids, &cfg),
+        None => storage::read_download_records(&cfg),
+    }
+    .map_err(|e| format!("Failed to read records: {e}"))?;
+    let entries = tokio::task::spawn_blocking(move || {
+        records
+            .iter()
+            .map(|record| {
+                let sha256 = (record.download_status == "Finished")
+                    .then(|| integrity::sha256_file(Path::new(&record.destination_path)).ok())
+                    .flatten();
+                report::Entry::new(record, sha256)
+            })
+            .collect::<Vec<_>>()
+    })
+    .await
+    .map_err(|e| e.to_string())?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let report = report::render(&entries, format, now, settings::units());
+    fs::write(&path, report)
+        .map_err(|e| format!("Failed to save report: {e}"))
 }
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
@@ -391,17 +558,95 @@ pub fn run() {
             panic!("Failed to create tables because {e}");
         }
     };
+    engine::purge_deleted_records(&cfg);
+    engine::archive_history(&cfg);
+    crash::interrupt_stale_downloads(&cfg);
+    crash::repair_records_without_chunks(&cfg);
+    upgrade::after_update(env!("CARGO_PKG_VERSION"), &cfg);
+    crash::install(cfg.clone(), engine::active_download_ids);
+    if let Some(port) = settings::current().metrics_port {
+        match metrics::serve(port) {
+            Ok(()) => println!("serving metrics at http://127.0.0.1:{port}/metrics"),
+            Err(e) => eprintln!("failed to serve metrics on port {port} because {e}"),
+        }
+    }
 
     tauri::Builder::default()
         .plugin(tauri_plugin_opener::init())
         .plugin(tauri_plugin_notification::init())
         .plugin(tauri_plugin_dialog::init())
+        .setup(|app| {
+            // subscribe before anything can start a download so that no event is missed
+            let events = engine::subscribe();
+            tauri::async_runtime::spawn(forward_events(app.handle().clone(), events));
+            tauri::async_runtime::spawn(engine::resume_interrupted());
+            // job and Metalink files double-clicked in the file manager are passed as arguments
+            for arg in std::env::args().skip(1) {
+                if jobfile::is_job_file(Path::new(&arg)) {
+                    tauri::async_runtime::spawn(import_job_file(arg));
+                } else if metalink::is_metalink(Path::new(&arg)) {
+                    tauri::async_runtime::spawn(import_metalink(arg));
+                }
+            }
+            tauri::async_runtime::spawn(engine::watch_folders_loop());
+            tauri::async_runtime::spawn(engine::scheduler_loop());
+            tauri::async_runtime::spawn(engine::bandwidth_loop());
+            tauri::async_runtime::spawn(engine::watchdog_loop());
+            tauri::async_runtime::spawn(engine::reconnect_loop());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             fetch_records,
             download,
             cancel_download,
+            add_mirrors,
+            set_open_when_done,
+            set_speed_limit,
+            retry_download,
+            check_for_update,
+            redownload,
+            organize_existing,
             delete_record,
-            open_file
+            undo_delete,
+            purge_deleted,
+            fetch_archived_records,
+            restore_archived_records,
+            pause_downloads,
+            pause_all,
+            resume_downloads,
+            resume_all,
+            retry_downloads,
+            delete_records,
+            open_file,
+            reveal_file,
+            get_active_downloads,
+            resolve_decision,
+            get_pending_decisions,
+            get_queue_eta,
+            set_priority,
+            set_category,
+            move_in_queue,
+            get_download_latency,
+            fetch_chunks,
+            get_host_latency,
+            get_settings,
+            get_system_proxy,
+            update_settings,
+            get_first_run_info,
+            complete_onboarding,
+            schedule_download,
+            fetch_scheduled_jobs,
+            delete_scheduled_job,
+            export_schedule_ics,
+            export_job_file,
+            export_report,
+            import_job_file,
+            import_metalink,
+            export_settings_bundle,
+            import_settings_bundle,
+            fetch_templates,
+            save_template,
+            delete_template
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
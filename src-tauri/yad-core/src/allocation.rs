@@ -0,0 +1,133 @@
+//! This module reserves the space of a file before its chunks are written. Sparse files cost
+//! nothing up front but may fragment, and on copy-on-write file systems like btrfs or APFS
+//! reserving blocks gains nothing. `fallocate` reserves the blocks without writing them on Linux,
+//! so a full disk is noticed before the download starts. Writing zeros works everywhere but
+//! writes the whole file twice, which wears small SSDs.
+
+use std::{
+    fs::File,
+    io::{self, Seek, SeekFrom, Write},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// The size of the buffer zeros are written from.
+const ZERO_BUFFER: usize = 1024 * 1024;
+
+/// This enum represents how the space of a file is reserved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Allocation {
+    /// The file is only given its size, blocks are allocated as chunks are written.
+    #[default]
+    Sparse,
+    /// The blocks are reserved without being written. Sparse on other systems than Linux.
+    Fallocate,
+    /// The file is filled with zeros.
+    ZeroFill,
+}
+
+/// This function reserves the space of `file` up to `size` bytes. Bytes already in the file are
+/// kept, so that the finished chunks of a resumed download stay.
+///
+/// # Returns
+/// Whether every block of the file is on the disk, see `is_reserved`. Only then can the file be
+/// mapped into memory without a full disk crashing YAD, see `file_writer::FileWriter::open_mapped`.
+pub fn allocate(file: &mut File, size: u64, allocation: Allocation) -> io::Result<bool> {
+    let len = file.metadata()?.len();
+    match allocation {
+        Allocation::Sparse => return file.set_len(size).map(|()| false),
+        Allocation::Fallocate => fallocate(file, size)?,
+        Allocation::ZeroFill if len < size => {
+            file.seek(SeekFrom::Start(len))?;
+            let zeros = vec![0; ZERO_BUFFER];
+            let mut left = size - len;
+            while left > 0 {
+                let n = left.min(ZERO_BUFFER as u64) as usize;
+                file.write_all(&zeros[..n])?;
+                left -= n as u64;
+            }
+            file.flush()?;
+        }
+        Allocation::ZeroFill => file.set_len(size)?,
+    }
+    is_reserved(file, size)
+}
+
+/// This function returns whether the blocks of the first `size` bytes of `file` are on the disk.
+/// A file with holes, e.g. one that fell back to sparse or was started sparse, is not.
+#[cfg(unix)]
+fn is_reserved(file: &File, size: u64) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    // the size of a block in `blocks` is always 512 bytes
+    Ok(file.metadata()?.blocks().saturating_mul(512) >= size)
+}
+
+/// Files on Windows are only sparse when asked to be, which YAD never does.
+#[cfg(not(unix))]
+fn is_reserved(_file: &File, _size: u64) -> io::Result<bool> {
+    Ok(true)
+}
+
+#[cfg(target_os = "linux")]
+fn fallocate(file: &File, size: u64) -> io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    file.set_len(size)?;
+    if size == 0 {
+        return Ok(());
+    }
+    // SAFETY: the descriptor belongs to `file`, which is open for the whole call
+    let result = unsafe { libc::fallocate(file.as_raw_fd(), 0, 0, size as libc::off_t) };
+    if result == 0 {
+        return Ok(());
+    }
+    let e = io::Error::last_os_error();
+    // file systems that cannot reserve blocks, e.g. some network shares, stay sparse
+    if e.raw_os_error() == Some(libc::EOPNOTSUPP) {
+        return Ok(());
+    }
+    Err(e)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn fallocate(file: &File, size: u64) -> io::Result<()> {
+    file.set_len(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocation_keeps_what_was_written() {
+        for allocation in [
+            Allocation::Sparse,
+            Allocation::Fallocate,
+            Allocation::ZeroFill,
+        ] {
+            let path = std::env::temp_dir().join(format!("yad_allocation_{allocation:?}.bin"));
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&path)
+                .unwrap();
+            file.write_all(b"resumed").unwrap();
+            let reserved = allocate(&mut file, 3 * 1024 * 1024, allocation).unwrap();
+            if allocation == Allocation::Sparse {
+                assert!(!reserved);
+            }
+            if cfg!(unix) && allocation == Allocation::ZeroFill {
+                assert!(reserved);
+            }
+            let content = std::fs::read(&path).unwrap();
+            assert_eq!(content.len(), 3 * 1024 * 1024, "{allocation:?}");
+            assert_eq!(&content[..7], b"resumed", "{allocation:?}");
+            assert!(content[7..].iter().all(|b| *b == 0), "{allocation:?}");
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+}
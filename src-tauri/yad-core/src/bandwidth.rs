@@ -0,0 +1,129 @@
+//! This module changes the total speed limit by the time of day, e.g. 500 KB/s from 09:00 to 18:00
+//! while others need the connection and unlimited otherwise. Rules are kept in the settings and
+//! use the local time. Outside of every rule `Settings::max_total_speed` applies. While the user is
+//! away the limit may be raised, see `idle`.
+
+use chrono::Timelike;
+use serde::{Deserialize, Serialize};
+
+use crate::{idle, settings::Settings};
+
+/// How often the rules are checked against the clock.
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// This struct represents a total speed limit applied between two times of the day.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct BandwidthRule {
+    /// When the rule starts, e.g. `09:00`.
+    pub from: String,
+    /// When the rule ends, e.g. `18:00`. A rule ending before it starts goes past midnight.
+    pub to: String,
+    /// The speed of all downloads together in bytes per second. 0 means unlimited.
+    pub max_speed: u64,
+}
+
+/// This function reads a time of the day as `HH:MM`.
+///
+/// # Returns
+/// - `Ok(u32)`: The minutes since midnight.
+/// - `Err(String)`: If it is not a time.
+pub fn parse_time(time: &str) -> Result<u32, String> {
+    let invalid = || format!("{time} is not a time of the day like 09:00");
+    let (hours, minutes) = time.trim().split_once(':').ok_or_else(invalid)?;
+    let hours: u32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: u32 = minutes.parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+impl BandwidthRule {
+    /// This function checks that both times can be read and differ.
+    pub fn validate(&self) -> Result<(), String> {
+        if parse_time(&self.from)? == parse_time(&self.to)? {
+            return Err(format!(
+                "The bandwidth rule from {} to {} is empty",
+                self.from, self.to
+            ));
+        }
+        Ok(())
+    }
+
+    /// This function checks whether the rule applies at `minute` minutes after midnight. The end
+    /// is not included.
+    pub fn applies_at(&self, minute: u32) -> bool {
+        let (Ok(from), Ok(to)) = (parse_time(&self.from), parse_time(&self.to)) else {
+            return false;
+        };
+        if from <= to {
+            from <= minute && minute < to
+        } else {
+            minute >= from || minute < to
+        }
+    }
+}
+
+/// This function returns the total speed limit at `minute` minutes after midnight: the limit of
+/// the first rule that applies, or `Settings::max_total_speed` if none does.
+pub fn total_limit(settings: &Settings, minute: u32) -> u64 {
+    settings
+        .bandwidth_rules
+        .iter()
+        .find(|r| r.applies_at(minute))
+        .map_or(settings.max_total_speed, |r| r.max_speed)
+}
+
+/// This function returns the minutes since midnight of the local time.
+pub fn local_minute() -> u32 {
+    let now = chrono::Local::now();
+    now.hour() * 60 + now.minute()
+}
+
+/// This function returns the total speed limit right now.
+pub fn current_limit(settings: &Settings) -> u64 {
+    let limit = total_limit(settings, local_minute());
+    idle::total_speed(settings, limit, idle::is_idle())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: &str, max_speed: u64) -> BandwidthRule {
+        BandwidthRule {
+            from: from.into(),
+            to: to.into(),
+            max_speed,
+        }
+    }
+
+    #[test]
+    fn test_parse_time() {
+        assert_eq!(parse_time("09:00"), Ok(540));
+        assert_eq!(parse_time("23:59"), Ok(1439));
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("9").is_err());
+        assert!(parse_time("nine:00").is_err());
+        assert!(rule("09:00", "09:00", 0).validate().is_err());
+        assert!(rule("22:00", "06:00", 0).validate().is_ok());
+    }
+
+    #[test]
+    fn test_total_limit() {
+        let settings = Settings {
+            max_total_speed: 0,
+            bandwidth_rules: vec![
+                rule("09:00", "18:00", 500 * 1024),
+                rule("22:00", "06:00", 1),
+            ],
+            ..Settings::default()
+        };
+        assert_eq!(total_limit(&settings, 8 * 60 + 59), 0);
+        assert_eq!(total_limit(&settings, 9 * 60), 500 * 1024);
+        assert_eq!(total_limit(&settings, 18 * 60), 0);
+        // past midnight
+        assert_eq!(total_limit(&settings, 23 * 60), 1);
+        assert_eq!(total_limit(&settings, 60), 1);
+    }
+}
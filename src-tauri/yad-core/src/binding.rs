@@ -0,0 +1,105 @@
+//! This module binds downloads to a network interface or a local address, e.g. to force them over
+//! Ethernet instead of Wi-Fi or through the `tun0` interface of a VPN. Without a binding the
+//! operating system picks the route. Interfaces can be bound by name on Linux and macOS only,
+//! Windows needs the address of the interface instead.
+
+use std::net::IpAddr;
+
+use reqwest::ClientBuilder;
+
+/// This enum represents what the connections of a download are bound to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Binding {
+    /// The local address connections are made from, e.g. `192.168.1.20`.
+    Address(IpAddr),
+    /// The name of a network interface, e.g. `eth0`.
+    Interface(String),
+}
+
+/// This function reads a binding from the settings. An ip address is bound as the local address,
+/// anything else is taken as the name of an interface.
+///
+/// # Returns
+/// - `Ok(Binding)`: The binding.
+/// - `Err(String)`: If the value is empty or cannot be the name of an interface.
+pub fn parse(value: &str) -> Result<Binding, String> {
+    let value = value.trim();
+    if let Ok(addr) = value.parse::<IpAddr>() {
+        return Ok(Binding::Address(addr));
+    }
+    if value.is_empty()
+        || value.len() > 15
+        || value.contains(|c: char| c.is_whitespace() || c == '/')
+    {
+        return Err(format!(
+            "{value:?} is neither an ip address nor a network interface"
+        ));
+    }
+    Ok(Binding::Interface(value.to_string()))
+}
+
+/// This function binds the connections of a client.
+///
+/// # Arguments
+/// - `builder`: The client being built.
+/// - `value`: The interface or address, see `parse`. Nothing is bound when not set.
+pub fn apply(builder: ClientBuilder, value: Option<&str>) -> Result<ClientBuilder, String> {
+    let Some(value) = value.filter(|v| !v.trim().is_empty()) else {
+        return Ok(builder);
+    };
+    match parse(value)? {
+        Binding::Address(addr) => Ok(builder.local_address(addr)),
+        Binding::Interface(name) => bind_interface(builder, &name),
+    }
+}
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+))]
+fn bind_interface(builder: ClientBuilder, name: &str) -> Result<ClientBuilder, String> {
+    Ok(builder.interface(name))
+}
+
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios"
+)))]
+fn bind_interface(_builder: ClientBuilder, name: &str) -> Result<ClientBuilder, String> {
+    Err(format!(
+        "Binding to interface {name} is not supported on this system, use its ip address instead"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("192.168.1.20"),
+            Ok(Binding::Address("192.168.1.20".parse().unwrap()))
+        );
+        assert_eq!(
+            parse(" fe80::1 "),
+            Ok(Binding::Address("fe80::1".parse().unwrap()))
+        );
+        assert_eq!(parse("tun0"), Ok(Binding::Interface("tun0".into())));
+        assert!(parse("").is_err());
+        assert!(parse("my interface").is_err());
+        assert!(parse("a-name-that-is-far-too-long").is_err());
+    }
+
+    #[test]
+    fn test_apply_builds_a_client() {
+        let builder = apply(reqwest::Client::builder(), Some("127.0.0.1")).unwrap();
+        assert!(builder.build().is_ok());
+        assert!(apply(reqwest::Client::builder(), Some("not an interface")).is_err());
+        assert!(apply(reqwest::Client::builder(), Some("  ")).is_ok());
+    }
+}
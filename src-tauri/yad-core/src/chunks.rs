@@ -0,0 +1,508 @@
+//! This module plans the byte ranges of the chunks of a file and checks that the finished chunks
+//! cover the whole file before the download is marked as finished. Ranges are inclusive on both
+//! ends, the same as the `Range: bytes=start-end` header.
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use serde::{Deserialize, Serialize};
+
+/// The smallest chunk size. Downloads saved before the chunk size was chosen per file used it for
+/// every file.
+pub const MIN_CHUNK_SIZE: u64 = 1024 * 1024;
+
+/// The biggest chunk size.
+pub const MAX_CHUNK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Files are split into between half this many and this many chunks, unless the chunk size
+/// limits do not allow it.
+pub const TARGET_CHUNKS: u64 = 128;
+
+/// This function chooses the chunk size of a file of `total_size` bytes. Big files get big chunks
+/// so that they do not need tens of thousands of requests and chunk rows. The size is a power of
+/// two between `MIN_CHUNK_SIZE` and `MAX_CHUNK_SIZE`.
+///
+/// # Example
+/// ```ignore
+/// assert_eq!(chunks::chunk_size(1024 * 1024 * 1024), 8 * 1024 * 1024);
+/// ```
+pub fn chunk_size(total_size: u64) -> u64 {
+    total_size
+        .div_ceil(TARGET_CHUNKS)
+        .next_power_of_two()
+        .clamp(MIN_CHUNK_SIZE, MAX_CHUNK_SIZE)
+}
+
+/// This function splits a file of `total_size` bytes into ranges of at most `chunk_size` bytes.
+/// The ranges cover every byte of the file exactly once, the last one ending at
+/// `total_size - 1`. A file of 0 bytes has no ranges.
+///
+/// # Example
+/// ```ignore
+/// assert_eq!(chunks::plan_chunks(10, 4), vec![(0, 3), (4, 7), (8, 9)]);
+/// ```
+pub fn plan_chunks(total_size: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    plan_range(0, total_size, chunk_size)
+}
+
+/// This function returns the number of bytes of the range `[start, end]`, which is inclusive on
+/// both ends, so `end - start` is one byte short.
+pub fn range_len(start: u64, end: u64) -> u64 {
+    (end + 1).saturating_sub(start)
+}
+
+/// This function splits the bytes from `start` up to, but not including, `stop` into ranges of at
+/// most `chunk_size` bytes.
+pub fn plan_range(start: u64, stop: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    let chunk_size = chunk_size.max(1);
+    let mut ranges = Vec::new();
+    let mut s = start;
+    while s < stop {
+        let e = s.saturating_add(chunk_size - 1).min(stop - 1);
+        ranges.push((s, e));
+        s = e + 1;
+    }
+    ranges
+}
+
+/// This struct describes how a set of ranges covers a file.
+#[derive(Debug, Default, PartialEq)]
+pub struct Coverage {
+    /// Byte ranges not covered by any range.
+    pub gaps: Vec<(u64, u64)>,
+    /// Byte ranges covered by more than one range.
+    pub overlaps: Vec<(u64, u64)>,
+}
+
+impl Coverage {
+    /// This function checks whether every byte is covered exactly once.
+    pub fn is_exact(&self) -> bool {
+        self.gaps.is_empty() && self.overlaps.is_empty()
+    }
+}
+
+/// This function checks how `ranges` cover the bytes `[0, total_size)`. Bytes outside the file
+/// are ignored.
+pub fn check(ranges: &[(u64, u64)], total_size: u64) -> Coverage {
+    let mut sorted: Vec<(u64, u64)> = ranges
+        .iter()
+        .filter(|(s, e)| s <= e && *s < total_size)
+        .map(|(s, e)| (*s, (*e).min(total_size.saturating_sub(1))))
+        .collect();
+    sorted.sort();
+
+    let mut coverage = Coverage::default();
+    // the first byte not yet covered
+    let mut cursor = 0u64;
+    for (s, e) in sorted {
+        if s > cursor {
+            coverage.gaps.push((cursor, s - 1));
+        } else if s < cursor {
+            coverage.overlaps.push((s, e.min(cursor - 1)));
+        }
+        cursor = cursor.max(e + 1);
+    }
+    if cursor < total_size {
+        coverage.gaps.push((cursor, total_size - 1));
+    }
+    coverage
+}
+
+/// This function checks whether a server said it cannot send parts of a file, going by its
+/// `Accept-Ranges` header. A missing header is not taken as a no, as many servers that support
+/// ranges do not send it.
+pub fn ranges_refused(accept_ranges: Option<&str>) -> bool {
+    accept_ranges.is_some_and(|v| v.trim().eq_ignore_ascii_case("none"))
+}
+
+/// This function checks whether a server sent the whole file in answer to a request for the
+/// chunk `[start, end]` of a file of `total_size` bytes. The body then starts at the first byte of
+/// the file rather than the chunk and cannot be written at the chunk's offset.
+pub fn range_ignored(status: u16, start: u64, end: u64, total_size: u64) -> bool {
+    status == 200 && (start > 0 || end + 1 < total_size)
+}
+
+/// Files up to this size are downloaded over a single connection when nothing is configured, as
+/// the extra requests of chunks cost more than they gain.
+pub const DEFAULT_SINGLE_STREAM_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Files that one connection to their host downloads within this many seconds are downloaded over
+/// a single connection when nothing is configured.
+pub const DEFAULT_SINGLE_STREAM_SECS: u64 = 2;
+
+/// This function decides whether a file is downloaded over a single connection instead of in
+/// chunks. Splitting only pays off for files that take a while over one connection.
+///
+/// # Arguments
+/// - `total_size`: The size of the file.
+/// - `host_speed`: The bytes per second of one connection to the host, measured from the chunks
+///   downloaded from it before. `None` if nothing was downloaded from it yet.
+/// - `max_size`: Files up to this size are not split. 0 turns this off.
+/// - `max_secs`: Files the host sends within this many seconds are not split. 0 turns this off.
+pub fn prefers_single_stream(
+    total_size: u64,
+    host_speed: Option<u64>,
+    max_size: u64,
+    max_secs: u64,
+) -> bool {
+    if max_size > 0 && total_size <= max_size {
+        return true;
+    }
+    match host_speed {
+        Some(speed) if max_secs > 0 => total_size <= speed.saturating_mul(max_secs),
+        _ => false,
+    }
+}
+
+/// A chunk is only split when both halves of its unread bytes are at least this big.
+pub const MIN_SPLIT_SIZE: u64 = 512 * 1024;
+
+/// This function returns where to split the chunk `[start, end]` of which `received` bytes have
+/// been read, i.e. the first byte of the new chunk. The unread bytes are shared equally.
+///
+/// # Example
+/// ```ignore
+/// let mb = 1024 * 1024;
+/// assert_eq!(chunks::split_point(0, 4 * mb - 1, 2 * mb), Some(3 * mb));
+/// ```
+pub fn split_point(start: u64, end: u64, received: u64) -> Option<u64> {
+    let next = start.checked_add(received)?;
+    if next > end {
+        return None;
+    }
+    let unread = end - next + 1;
+    if unread < 2 * MIN_SPLIT_SIZE {
+        return None;
+    }
+    Some(next + unread / 2)
+}
+
+/// This struct represents the part of a file to download instead of the whole file, e.g. to look
+/// at the header of an archive or the start of a video.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ByteRange {
+    pub start: u64,
+    /// The last byte, inclusive. The range goes to the end of the file when not set.
+    pub end: Option<u64>,
+}
+
+impl ByteRange {
+    /// This function returns the range of the first `bytes` bytes of a file.
+    pub fn first(bytes: u64) -> Self {
+        ByteRange {
+            start: 0,
+            end: Some(bytes.saturating_sub(1)),
+        }
+    }
+
+    /// This function returns the first and last byte of the range in a file of `size` bytes. A
+    /// range going past the end of the file is cut at the end.
+    ///
+    /// # Example
+    /// ```ignore
+    /// assert_eq!(chunks::ByteRange::first(100).resolve(Some(50)), Ok((0, 49)));
+    /// ```
+    pub fn resolve(&self, size: Option<u64>) -> Result<(u64, u64), String> {
+        let end = match (self.end, size) {
+            (Some(end), Some(size)) => end.min(size.saturating_sub(1)),
+            (Some(end), None) => end,
+            (None, Some(size)) => size.saturating_sub(1),
+            (None, None) => return Err("The range needs an end as the size is unknown".into()),
+        };
+        if self.end.is_some_and(|e| e < self.start) {
+            return Err("The range ends before it starts".into());
+        }
+        if size.is_some_and(|s| self.start >= s) {
+            return Err("The range starts after the end of the file".into());
+        }
+        Ok((self.start, end))
+    }
+}
+
+/// This function checks that all the bytes of the chunk `[start, end]` were written. A server
+/// that closes the connection early sends a body that is cut short without an error.
+///
+/// # Returns
+/// - `Ok(u64)`: The number of bytes written.
+/// - `Err(String)`: If fewer bytes than the chunk has were written.
+pub fn check_length(start: u64, end: u64, written: u64) -> Result<u64, String> {
+    let expected = range_len(start, end);
+    if written < expected {
+        return Err(format!("was cut short after {written} of {expected} bytes"));
+    }
+    Ok(written)
+}
+
+/// This struct tracks a chunk while it is downloaded so that an idle worker can take over the
+/// second half of its unread bytes. The worker stops reading once it reaches `end`, which may
+/// move while it runs.
+#[derive(Debug)]
+pub struct InFlight {
+    start: u64,
+    /// The bytes received and the last byte of the chunk.
+    state: Mutex<(u64, u64)>,
+}
+
+impl InFlight {
+    pub fn new(start: u64, end: u64) -> Self {
+        InFlight {
+            start,
+            state: Mutex::new((0, end)),
+        }
+    }
+
+    /// This function returns the last byte of the chunk.
+    pub fn end(&self) -> u64 {
+        self.lock().1
+    }
+
+    /// This function returns how many bytes are still to be read.
+    pub fn unread(&self) -> u64 {
+        let (received, end) = *self.lock();
+        (end + 1).saturating_sub(self.start + received)
+    }
+
+    /// This function starts counting from the beginning of the chunk again, e.g. for a retry.
+    pub fn restart(&self) {
+        self.lock().0 = 0;
+    }
+
+    /// This function counts `bytes` more bytes as received and returns how many of them are part
+    /// of the chunk. Bytes after `end` belong to a chunk split off from this one.
+    pub fn receive(&self, bytes: u64) -> u64 {
+        let mut state = self.lock();
+        let wanted = (state.1 + 1).saturating_sub(self.start + state.0).min(bytes);
+        state.0 += wanted;
+        wanted
+    }
+
+    /// This function ends the chunk before its split point and returns the range split off.
+    /// `save` is called with the split point while the worker is held up, the chunk is only
+    /// split if it returns `true`.
+    pub fn split(&self, save: impl FnOnce(u64) -> bool) -> Option<(u64, u64)> {
+        let mut state = self.lock();
+        let (received, end) = *state;
+        let at = split_point(self.start, end, received)?;
+        if !save(at) {
+            return None;
+        }
+        state.1 = at - 1;
+        Some((at, end))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, (u64, u64)> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_chunks() {
+        assert_eq!(plan_chunks(10, 4), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(plan_chunks(8, 4), vec![(0, 3), (4, 7)]);
+        assert_eq!(plan_chunks(1, 4), vec![(0, 0)]);
+        assert!(plan_chunks(0, 4).is_empty());
+        // a chunk as big as the file is the whole file, e.g. over a single connection
+        assert_eq!(plan_chunks(10, 10), vec![(0, 9)]);
+        assert_eq!(plan_chunks(10, u64::MAX), vec![(0, 9)]);
+    }
+
+    #[test]
+    fn test_range_len() {
+        assert_eq!(range_len(0, 0), 1);
+        assert_eq!(range_len(4, 7), 4);
+        assert_eq!(range_len(5, 4), 0);
+    }
+
+    #[test]
+    fn test_ranges_refused() {
+        assert!(ranges_refused(Some("none")));
+        assert!(ranges_refused(Some(" None ")));
+        assert!(!ranges_refused(Some("bytes")));
+        assert!(!ranges_refused(None));
+    }
+
+    #[test]
+    fn test_prefers_single_stream() {
+        let mb = 1024 * 1024;
+        assert!(prefers_single_stream(mb, None, 4 * mb, 2));
+        assert!(!prefers_single_stream(100 * mb, None, 4 * mb, 2));
+        // a host sending 60 MB/s gets a 100 MB file to us within 2 seconds
+        assert!(prefers_single_stream(100 * mb, Some(60 * mb), 4 * mb, 2));
+        assert!(!prefers_single_stream(100 * mb, Some(10 * mb), 4 * mb, 2));
+        // both thresholds turned off
+        assert!(!prefers_single_stream(mb, Some(60 * mb), 0, 0));
+    }
+
+    #[test]
+    fn test_byte_range_resolve() {
+        assert_eq!(ByteRange::first(100).resolve(Some(1000)), Ok((0, 99)));
+        assert_eq!(ByteRange::first(100).resolve(Some(50)), Ok((0, 49)));
+        assert_eq!(ByteRange::first(100).resolve(None), Ok((0, 99)));
+        let tail = ByteRange {
+            start: 900,
+            end: None,
+        };
+        assert_eq!(tail.resolve(Some(1000)), Ok((900, 999)));
+        assert!(tail.resolve(None).is_err());
+        assert!(tail.resolve(Some(900)).is_err());
+        let backwards = ByteRange {
+            start: 10,
+            end: Some(5),
+        };
+        assert!(backwards.resolve(Some(1000)).is_err());
+    }
+
+    #[test]
+    fn test_check_length() {
+        assert_eq!(check_length(100, 199, 100), Ok(100));
+        assert!(check_length(100, 199, 99).is_err());
+        assert!(check_length(0, 0, 0).is_err());
+    }
+
+    #[test]
+    fn test_range_ignored() {
+        assert!(range_ignored(200, 100, 199, 1000));
+        assert!(range_ignored(200, 0, 99, 1000));
+        assert!(!range_ignored(200, 0, 999, 1000), "the whole file was asked for");
+        assert!(!range_ignored(206, 100, 199, 1000));
+    }
+
+    #[test]
+    fn test_split_point() {
+        const MB: u64 = 1024 * 1024;
+        assert_eq!(split_point(0, 4 * MB - 1, 0), Some(2 * MB));
+        assert_eq!(split_point(0, 4 * MB - 1, 2 * MB), Some(3 * MB));
+        assert_eq!(split_point(8 * MB, 12 * MB - 1, MB), Some(10 * MB + MB / 2));
+        assert_eq!(split_point(0, 4 * MB - 1, 3 * MB + 1), None, "too little left");
+        assert_eq!(split_point(0, 4 * MB - 1, 4 * MB), None, "finished");
+    }
+
+    #[test]
+    fn test_in_flight_split() {
+        const MB: u64 = 1024 * 1024;
+        let chunk = InFlight::new(4 * MB, 8 * MB - 1);
+        assert_eq!(chunk.receive(MB), MB);
+        assert_eq!(chunk.unread(), 3 * MB);
+        assert_eq!(chunk.split(|_| false), None, "not saved");
+        assert_eq!(chunk.end(), 8 * MB - 1);
+        assert_eq!(chunk.split(|_| true), Some((6 * MB + MB / 2, 8 * MB - 1)));
+        assert_eq!(chunk.end(), 6 * MB + MB / 2 - 1);
+        // a read crossing the split point is cut at the new end
+        assert_eq!(chunk.receive(2 * MB), MB + MB / 2);
+        assert_eq!(chunk.receive(10), 0);
+        assert_eq!(chunk.unread(), 0);
+        assert_eq!(chunk.split(|_| true), None);
+
+        chunk.restart();
+        assert_eq!(chunk.unread(), 2 * MB + MB / 2);
+    }
+
+    #[test]
+    fn test_chunk_size() {
+        const MB: u64 = 1024 * 1024;
+        assert_eq!(chunk_size(10 * MB), MIN_CHUNK_SIZE);
+        assert_eq!(chunk_size(1024 * MB), 8 * MB);
+        assert_eq!(chunk_size(1000 * MB), 8 * MB);
+        assert_eq!(chunk_size(1024 * 1024 * MB), MAX_CHUNK_SIZE);
+        for total in [200 * MB, 777 * MB, 3000 * MB, 5000 * MB] {
+            let chunks = plan_chunks(total, chunk_size(total)).len() as u64;
+            assert!(
+                (TARGET_CHUNKS / 2..=TARGET_CHUNKS).contains(&chunks),
+                "{total} bytes in {chunks} chunks"
+            );
+        }
+    }
+
+    /// Every planned set of ranges covers `[0, total_size)` exactly, without gaps or overlaps,
+    /// and no range is larger than the chunk size.
+    #[test]
+    fn test_plan_covers_file_exactly() {
+        let mut sizes: Vec<u64> = (1..=300).collect();
+        sizes.extend([1024 * 1024 - 1, 1024 * 1024, 1024 * 1024 + 1, 5 * 1024 * 1024 + 17]);
+        for total in sizes {
+            for chunk in [1, 2, 3, 7, 16, 1000, 1024 * 1024] {
+                assert_plan_is_exact(total, chunk);
+            }
+        }
+    }
+
+    fn assert_plan_is_exact(total: u64, chunk: u64) {
+        let ranges = plan_chunks(total, chunk);
+        let coverage = check(&ranges, total);
+        assert!(coverage.is_exact(), "{total}/{chunk}: {coverage:?}");
+        assert_eq!(ranges.first().unwrap().0, 0);
+        assert_eq!(ranges.last().unwrap().1, total - 1);
+        assert!(ranges.iter().all(|(s, e)| range_len(*s, *e) <= chunk));
+        // only the last chunk may be smaller
+        let (last, full) = ranges.split_last().unwrap();
+        assert!(full.iter().all(|(s, e)| range_len(*s, *e) == chunk));
+        assert!(range_len(last.0, last.1) >= 1);
+        let sum: u64 = ranges.iter().map(|(s, e)| range_len(*s, *e)).sum();
+        assert_eq!(sum, total, "{total}/{chunk}");
+    }
+
+    /// The same as `test_plan_covers_file_exactly` for random sizes up to many GB, with the chunk
+    /// sizes chosen by `chunk_size`, and for the chunks split off while they run.
+    #[test]
+    fn test_plan_covers_random_files_exactly() {
+        // xorshift, so that the sizes are the same on every run
+        let mut x: u64 = 0x2545_F491_4F6C_DD1D;
+        let mut next = || {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            x
+        };
+        for _ in 0..500 {
+            let total = next() % (8 * 1024 * 1024 * 1024) + 1;
+            assert_plan_is_exact(total, chunk_size(total));
+            let chunk = next() % total + 1;
+            let mut ranges = plan_chunks(total, chunk);
+            assert!(check(&ranges, total).is_exact());
+
+            // splitting any chunk keeps the coverage exact
+            let i = (next() % ranges.len() as u64) as usize;
+            let (start, end) = ranges[i];
+            let received = next() % range_len(start, end);
+            if let Some(at) = split_point(start, end, received) {
+                ranges[i] = (start, at - 1);
+                ranges.push((at, end));
+                assert!(check(&ranges, total).is_exact(), "{total}/{chunk} at {at}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_plan_range() {
+        assert_eq!(plan_range(5, 12, 4), vec![(5, 8), (9, 11)]);
+        assert!(plan_range(5, 5, 4).is_empty());
+        // a chunk size of 0 must not loop forever
+        assert_eq!(plan_range(0, 2, 0), vec![(0, 0), (1, 1)]);
+    }
+
+    #[test]
+    fn test_check_finds_gaps() {
+        let coverage = check(&[(0, 3), (8, 9)], 12);
+        assert_eq!(coverage.gaps, vec![(4, 7), (10, 11)]);
+        assert!(coverage.overlaps.is_empty());
+
+        let coverage = check(&[], 5);
+        assert_eq!(coverage.gaps, vec![(0, 4)]);
+    }
+
+    #[test]
+    fn test_check_finds_overlaps() {
+        let coverage = check(&[(0, 5), (4, 9), (4, 9)], 10);
+        assert!(coverage.gaps.is_empty());
+        assert_eq!(coverage.overlaps, vec![(4, 5), (4, 9)]);
+    }
+
+    #[test]
+    fn test_check_ignores_bytes_past_the_end() {
+        let coverage = check(&[(0, 3), (4, 20), (30, 40)], 8);
+        assert!(coverage.is_exact(), "{coverage:?}");
+    }
+}
@@ -0,0 +1,252 @@
+//! This module is used to handle application configurations.
+//! In future, the user might be able to change some of these configs.
+
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use sys_info;
+
+pub const APP_NAME: &str = "Yad";
+
+/// The number of idle connections kept open to each host.
+pub const DEFAULT_POOL_MAX_IDLE_PER_HOST: usize = 16;
+/// How long an idle connection is kept open.
+pub const DEFAULT_POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+/// How often TCP keep-alive probes are sent.
+pub const DEFAULT_TCP_KEEPALIVE: Duration = Duration::from_secs(60);
+/// How long connecting to a server may take.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a response may send nothing before the request fails.
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+
+/// This is the main configs struct with basic configs for the application.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// The operating system. Windows, Linux or Darwin (MacOS)
+    pub os: String,
+    /// The logged in username.
+    pub user: String,
+    /// The downloads directory usually ~/Downloads
+    pub download_dir: String,
+    /// The directory where the operating system will store its data.
+    pub config_dir: String,
+    /// The tmp directory where file chunks are downloaded
+    pub tmp_dir: String,
+    /// THe name of the sqlite3 database.
+    pub db_name: String,
+    /// The number of idle connections kept open to each host, reused by later chunks and
+    /// downloads from the same host instead of connecting again.
+    pub pool_max_idle_per_host: usize,
+    /// How long an idle connection is kept open.
+    pub pool_idle_timeout: Duration,
+    /// How often TCP keep-alive probes are sent, so that open connections are not dropped.
+    pub tcp_keepalive: Duration,
+    /// Whether small writes are sent right away instead of being batched.
+    pub tcp_nodelay: bool,
+    /// How long connecting to a server may take.
+    pub connect_timeout: Duration,
+    /// How long a response may send nothing before the request fails, so that a stalled chunk is
+    /// retried.
+    pub read_timeout: Duration,
+}
+
+/// This function finds the home directory of the user: `HOME` or `USERPROFILE`, then the one the
+/// operating system knows of, then the temp directory.
+fn home_dir() -> PathBuf {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .or_else(dirs::home_dir)
+        .unwrap_or_else(|| {
+            let tmp = env::temp_dir();
+            eprintln!("Warning: the home directory is unknown, using {}", tmp.display());
+            tmp
+        })
+}
+
+/// This function reads the Downloads directory from the `user-dirs.dirs` of `xdg-user-dirs`,
+/// e.g. `XDG_DOWNLOAD_DIR="$HOME/Téléchargements"`.
+///
+/// # Arguments
+/// - `user_dirs`: The content of `user-dirs.dirs`.
+/// - `home`: The home directory `$HOME` stands for.
+pub fn xdg_download_dir(user_dirs: &str, home: &Path) -> Option<PathBuf> {
+    let value = user_dirs
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("XDG_DOWNLOAD_DIR="))?;
+    let value = value.trim().trim_matches('"');
+    let dir = match value.strip_prefix("$HOME") {
+        Some(rest) => home.join(rest.trim_start_matches('/')),
+        None if value.starts_with('/') => PathBuf::from(value),
+        None => return None,
+    };
+    // a Downloads directory set to the home directory means it is turned off
+    (dir != home).then_some(dir)
+}
+
+/// This function finds the Downloads directory of the user with the platform APIs, which know
+/// where it is when it has a localized name like `Téléchargements` or was moved: the known folder
+/// on Windows, `xdg-user-dirs` on Linux and the home directory on macOS, whose Downloads folder
+/// is only shown localized. Inside a snap, `HOME` is a directory of the snap, so the real home of
+/// the user is looked in instead.
+fn downloads_dir(os: &str, home: &Path) -> PathBuf {
+    if os == "Linux" {
+        if let Some(real_home) = env::var_os("SNAP_REAL_HOME").map(PathBuf::from) {
+            return fs::read_to_string(real_home.join(".config").join("user-dirs.dirs"))
+                .ok()
+                .and_then(|user_dirs| xdg_download_dir(&user_dirs, &real_home))
+                .unwrap_or_else(|| real_home.join("Downloads"));
+        }
+    }
+    dirs::download_dir().unwrap_or_else(|| home.join("Downloads"))
+}
+
+impl Default for Config {
+    /// The default constructor of configs. This method creates a config instance with default
+    /// settings.
+    ///
+    /// It fills in all the fields in the `Config` struct from default values from the operating
+    /// system.
+    ///
+    /// # Example
+    /// ```ignore
+    /// let cfg = config::Config::default();
+    /// ```
+    fn default() -> Self {
+        let user = match env::var("USER").or_else(|_| env::var("USERNAME")) {
+            Ok(user) => user,
+            Err(e) => {
+                println!("failed to get user because {}", e);
+                "".to_string()
+            }
+        };
+
+        let os = match sys_info::os_type() {
+            Ok(os) => os,
+            Err(e) => {
+                println!("failed to get operating system because {}", e);
+                "".to_string()
+            }
+        };
+
+        let home_dir = home_dir();
+        let _os: &str = &os;
+
+        let _home_dir = home_dir.as_path();
+        let config_dir = match _os {
+            "Windows" => _home_dir
+                .join("AppData")
+                .join("Local")
+                .join(APP_NAME)
+                .to_str()
+                .unwrap_or("_")
+                .to_string(),
+            "Darwin" => _home_dir
+                .join("Library")
+                .join("Application Support")
+                .join(APP_NAME)
+                .to_str()
+                .unwrap_or("_")
+                .to_string(),
+            "Linux" => _home_dir
+                .join(".config")
+                .join(APP_NAME)
+                .to_str()
+                .unwrap_or("_")
+                .to_string(),
+            _ => String::from("~/"),
+        };
+
+        let tmp_dir = match _os {
+            "Windows" => _home_dir
+                .join("AppData")
+                .join("Local")
+                .join("Temp")
+                .join(APP_NAME)
+                .to_str()
+                .unwrap_or("_")
+                .to_string(),
+            "Darwin" | "Linux" => format!("/tmp/{APP_NAME}"),
+            _ => format!("/tmp/{APP_NAME}").to_string(),
+        };
+
+        let download_dir = downloads_dir(_os, _home_dir)
+            .join(APP_NAME)
+            .to_str()
+            .unwrap_or("_")
+            .to_string();
+        let db_name = format!("{}.db", APP_NAME);
+
+        Config {
+            user: user.to_string(),
+            os: os.to_string(),
+            download_dir,
+            config_dir,
+            tmp_dir,
+            db_name,
+            pool_max_idle_per_host: DEFAULT_POOL_MAX_IDLE_PER_HOST,
+            pool_idle_timeout: DEFAULT_POOL_IDLE_TIMEOUT,
+            tcp_keepalive: DEFAULT_TCP_KEEPALIVE,
+            tcp_nodelay: true,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_config_has_non_empty_fields() {
+        let cfg = Config::default();
+        assert!(!cfg.os.is_empty(), "OS should not be empty");
+        assert!(!cfg.download_dir.is_empty(), "download_dir should not be empty");
+        assert!(!cfg.config_dir.is_empty(), "config_dir should not be empty");
+        assert!(!cfg.tmp_dir.is_empty(), "tmp_dir should not be empty");
+        assert_eq!(cfg.db_name, "Yad.db");
+    }
+
+    #[test]
+    fn test_download_dir_ends_with_yad() {
+        let cfg = Config::default();
+        assert!(
+            cfg.download_dir.ends_with("Yad") || cfg.download_dir.ends_with("yad"),
+            "download_dir should end with Yad: {}",
+            cfg.download_dir
+        );
+    }
+
+    #[test]
+    fn test_xdg_download_dir() {
+        let home = Path::new("/home/marie");
+        let user_dirs = "# written by xdg-user-dirs-update\n\
+                         XDG_DESKTOP_DIR=\"$HOME/Bureau\"\n\
+                         XDG_DOWNLOAD_DIR=\"$HOME/Téléchargements\"\n";
+        assert_eq!(
+            xdg_download_dir(user_dirs, home),
+            Some(PathBuf::from("/home/marie/Téléchargements"))
+        );
+        let moved = "XDG_DOWNLOAD_DIR=\"/mnt/data/downloads\"";
+        assert_eq!(
+            xdg_download_dir(moved, home),
+            Some(PathBuf::from("/mnt/data/downloads"))
+        );
+        assert_eq!(xdg_download_dir("XDG_DOWNLOAD_DIR=\"$HOME/\"", home), None);
+        assert_eq!(xdg_download_dir("XDG_MUSIC_DIR=\"$HOME/Musique\"", home), None);
+    }
+
+    #[test]
+    fn test_tmp_dir_is_absolute() {
+        let cfg = Config::default();
+        let p = Path::new(&cfg.tmp_dir);
+        assert!(p.is_absolute(), "tmp_dir should be absolute: {}", cfg.tmp_dir);
+    }
+}
+
+
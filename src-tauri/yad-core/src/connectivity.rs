@@ -0,0 +1,76 @@
+//! This module keeps downloads from failing when the network drops for a while, e.g. when the
+//! Wi-Fi reconnects. A download whose chunks fail because the connection was lost is set to
+//! `Waiting` instead of `Failed`, its chunks are kept `Pending`, and `engine::reconnect_loop`
+//! resumes it once its host answers again. The host is asked less and less often while it cannot
+//! be reached, so a long outage costs next to nothing, and a download whose host stays away, or
+//! keeps dropping it, fails after `MAX_ATTEMPTS`.
+
+use std::time::Duration;
+
+use reqwest::Client;
+
+/// The status of a download waiting for the network.
+pub const WAITING: &str = "Waiting";
+
+/// How long after the connection was lost the host is asked first.
+pub const MIN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The longest time between two checks of the host.
+pub const MAX_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many times a waiting download is checked on, or resumed, before it is failed. With checks
+/// at most `MAX_INTERVAL` apart, a download gives up after about two hours without its host.
+pub const MAX_ATTEMPTS: u32 = 120;
+
+/// How long the host is given to answer a check.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// This function tells whether a request failed because the network is down rather than because
+/// of the server or the file. Only errors of connecting count, a connection dropped in the middle
+/// of a response is retried like any other failure.
+///
+/// # Arguments
+/// - `error`: The error of sending a request, before the server answered.
+pub fn is_network_error(error: &reqwest::Error) -> bool {
+    error.is_connect() || error.is_timeout()
+}
+
+/// This function returns how long to wait before the next check of a host that could not be
+/// reached, twice as long as the last time.
+pub fn next_interval(interval: Duration) -> Duration {
+    (interval * 2).clamp(MIN_INTERVAL, MAX_INTERVAL)
+}
+
+/// This function checks whether the host of `url` can be reached. Any answer counts, even an
+/// error, since only the network is checked.
+pub async fn is_reachable(client: &Client, url: &str) -> bool {
+    client.head(url).timeout(PROBE_TIMEOUT).send().await.is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_network_error() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let client = Client::new();
+        // nothing listens on port 1
+        let refused = rt
+            .block_on(client.get("http://127.0.0.1:1/a.iso").send())
+            .unwrap_err();
+        assert!(is_network_error(&refused));
+        let invalid = rt.block_on(client.get("not a url").send()).unwrap_err();
+        assert!(!is_network_error(&invalid));
+    }
+
+    #[test]
+    fn test_next_interval() {
+        assert_eq!(next_interval(MIN_INTERVAL), Duration::from_secs(4));
+        assert_eq!(next_interval(Duration::ZERO), MIN_INTERVAL);
+        assert_eq!(next_interval(Duration::from_secs(50)), MAX_INTERVAL);
+    }
+}
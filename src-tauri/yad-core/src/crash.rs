@@ -0,0 +1,214 @@
+//! This module records panics. A panic anywhere in the application writes a crash report to the
+//! `crashes` folder in the config directory. A panic in the task of a download marks that download
+//! as `Interrupted`, so that it can be resumed cleanly instead of staying `InProgress` forever; the
+//! other downloads keep running.
+
+use std::{
+    any::Any,
+    backtrace::Backtrace,
+    cell::Cell,
+    fs,
+    future::Future,
+    panic::{self, PanicHookInfo},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{config::Config, storage};
+
+/// The status of a download that stopped because the application crashed.
+pub const INTERRUPTED: &str = "Interrupted";
+
+tokio::task_local! {
+    /// The download the running task belongs to, 0 until its record is known, see `watch`.
+    static DOWNLOAD: Cell<i64>;
+}
+
+/// This function runs the task of a download, so that a panic in it marks the download as
+/// interrupted once the task named it with `set_download`.
+pub async fn watch<F: Future>(task: F) -> F::Output {
+    DOWNLOAD.scope(Cell::new(0), task).await
+}
+
+/// This function names the download the running task belongs to, see `watch`.
+pub fn set_download(id: i64) {
+    let _ = DOWNLOAD.try_with(|download| download.set(id));
+}
+
+/// This function returns the download whose task is panicking, if the panic is in one.
+fn panicking_download() -> Option<i64> {
+    DOWNLOAD.try_with(Cell::get).ok().filter(|id| *id != 0)
+}
+
+/// This function returns the message a panic was raised with.
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// This function returns the folder crash reports are written to.
+pub fn crash_dir(cfg: &Config) -> PathBuf {
+    Path::new(&cfg.config_dir).join("crashes")
+}
+
+/// This function formats a crash report.
+///
+/// # Arguments
+/// - `message`: The panic message.
+/// - `location`: Where the panic happened, e.g. `src/lib.rs:42:9`.
+/// - `thread`: The name of the thread that panicked.
+/// - `download_ids`: The ids of the downloads running at the time.
+/// - `backtrace`: The backtrace of the panic.
+pub fn report(
+    message: &str,
+    location: &str,
+    thread: &str,
+    download_ids: &[i64],
+    backtrace: &str,
+) -> String {
+    let ids: Vec<String> = download_ids.iter().map(i64::to_string).collect();
+    format!(
+        "YAD {} crashed\n\
+         message: {message}\n\
+         location: {location}\n\
+         thread: {thread}\n\
+         os: {}\n\
+         active downloads: [{}]\n\
+         \n\
+         {backtrace}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        ids.join(", "),
+    )
+}
+
+fn write_report(
+    info: &PanicHookInfo,
+    download_ids: &[i64],
+    cfg: &Config,
+) -> std::io::Result<PathBuf> {
+    let location = info
+        .location()
+        .map(|l| l.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let thread = std::thread::current();
+    let content = report(
+        panic_message(info.payload()),
+        &location,
+        thread.name().unwrap_or("unnamed"),
+        download_ids,
+        &Backtrace::force_capture().to_string(),
+    );
+    let dir = crash_dir(cfg);
+    fs::create_dir_all(&dir)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let path = dir.join(format!(
+        "crash-{}-{}.txt",
+        now.as_secs(),
+        now.subsec_millis()
+    ));
+    fs::write(&path, content)?;
+    Ok(path)
+}
+
+/// This function installs the panic hook. The default hook still runs afterwards so that the
+/// panic is printed as before.
+///
+/// # Arguments
+/// - `cfg`: An instance of `Config`.
+/// - `active_downloads`: Returns the ids of the running downloads, listed in the report. It is
+///   called while panicking, so it must not block or panic.
+///
+/// # Example
+/// ```ignore
+/// crash::install(config::Config::default(), active_download_ids);
+/// ```
+pub fn install(cfg: Config, active_downloads: fn() -> Vec<i64>) {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        let ids = active_downloads();
+        match write_report(info, &ids, &cfg) {
+            Ok(path) => eprintln!("crash report written to {}", path.display()),
+            Err(e) => eprintln!("failed to write crash report because {e}"),
+        }
+        if let Some(id) = panicking_download() {
+            if let Err(e) = storage::update_records_status(&[id], INTERRUPTED, &cfg) {
+                eprintln!("failed to mark download {id} as interrupted because {e}");
+            }
+        }
+        default_hook(info);
+    }));
+}
+
+/// This function marks the downloads left `InProgress` by a previous run as `Interrupted`. It is
+/// called on start, before any download runs, and covers crashes the panic hook did not see, e.g.
+/// the process being killed.
+pub fn interrupt_stale_downloads(cfg: &Config) {
+    match storage::replace_status("InProgress", INTERRUPTED, cfg) {
+        Ok(0) => {}
+        Ok(n) => println!("marked {n} downloads of the previous run as interrupted"),
+        Err(e) => eprintln!("failed to mark interrupted downloads because {e}"),
+    }
+}
+
+/// This function marks the downloads saved without chunks by older versions as interrupted, so
+/// that they are planned again when they resume instead of showing as finished. It must be called
+/// before any download starts.
+pub fn repair_records_without_chunks(cfg: &Config) {
+    match storage::interrupt_records_without_chunks(INTERRUPTED, cfg) {
+        Ok(0) => {}
+        Ok(n) => println!("marked {n} downloads without chunks as interrupted"),
+        Err(e) => eprintln!("failed to repair downloads without chunks because {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watch() {
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        runtime.block_on(watch(async {
+            assert_eq!(panicking_download(), None, "the record is not known yet");
+            set_download(7);
+            assert_eq!(panicking_download(), Some(7));
+        }));
+        set_download(8);
+        assert_eq!(panicking_download(), None, "outside of a download");
+    }
+
+    #[test]
+    fn test_panic_message() {
+        let payload: Box<dyn Any + Send> = Box::new("static message");
+        assert_eq!(panic_message(payload.as_ref()), "static message");
+        let payload: Box<dyn Any + Send> = Box::new(format!("chunk {} failed", 3));
+        assert_eq!(panic_message(payload.as_ref()), "chunk 3 failed");
+        let payload: Box<dyn Any + Send> = Box::new(42);
+        assert_eq!(panic_message(payload.as_ref()), "Box<dyn Any>");
+    }
+
+    #[test]
+    fn test_report() {
+        let r = report(
+            "index out of bounds",
+            "src/lib.rs:42:9",
+            "tokio-runtime-worker",
+            &[3, 7],
+            "0: yad::download",
+        );
+        assert!(r.starts_with(&format!("YAD {} crashed\n", env!("CARGO_PKG_VERSION"))));
+        assert!(r.contains("message: index out of bounds\n"));
+        assert!(r.contains("location: src/lib.rs:42:9\n"));
+        assert!(r.contains("thread: tokio-runtime-worker\n"));
+        assert!(r.contains("active downloads: [3, 7]\n"));
+        assert!(r.ends_with("\n0: yad::download\n"));
+    }
+}
@@ -0,0 +1,233 @@
+//! This module handles the conditions a download must meet to be successful. Servers sometimes
+//! answer with an error or login page instead of the file, which would otherwise be saved as if it
+//! was the file. The user can require a minimum size and a content type for a download, and a
+//! checksum which is verified once the file has been downloaded, see `hashing`.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hashing::{self, Checksum, HashAlgorithm};
+
+/// This struct represents the conditions a download must meet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct SuccessCriteria {
+    /// The minimum size of the file in bytes.
+    pub min_size: Option<u64>,
+    /// The maximum size of the file in bytes, the same as `min_size` for an exact size.
+    pub max_size: Option<u64>,
+    /// The accepted content types separated by commas, e.g. `application/zip, video/*`.
+    pub content_type: Option<String>,
+    /// The expected SHA-256 checksum of the file as hex, optionally prefixed with `sha256:`.
+    pub sha256: Option<String>,
+    /// The expected BLAKE3 checksum of the file as hex, optionally prefixed with `blake3:`. Checked
+    /// instead of `sha256` when both are given, since it is faster to compute.
+    pub blake3: Option<String>,
+}
+
+impl SuccessCriteria {
+    /// This function checks the size and content type announced by the server.
+    ///
+    /// # Arguments
+    /// - `size`: The size of the file in bytes, `None` if the server did not announce it. The size
+    ///   of such a file is checked with `check_size` once it has been downloaded.
+    /// - `content_type`: The `Content-Type` header, if the server sent one.
+    ///
+    /// # Returns
+    /// - `Ok(())`: If the download meets the criteria.
+    /// - `Err(String)`: A message describing the criteria that was not met.
+    pub fn check(&self, size: Option<u64>, content_type: Option<&str>) -> Result<(), String> {
+        if let Some(size) = size {
+            self.check_size(size)?;
+        }
+        if let Some(accepted) = &self.content_type {
+            let actual = content_type.map(essence).unwrap_or_default();
+            let matched = accepted
+                .split(',')
+                .map(essence)
+                .filter(|a| !a.is_empty())
+                .any(|a| content_type_matches(&a, &actual));
+            if !matched {
+                let actual = if actual.is_empty() { "none" } else { &actual };
+                return Err(format!(
+                    "Expected content type {accepted} but the server sent {actual}"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// This function checks the downloaded file against the expected checksum.
+    ///
+    /// # Arguments
+    /// - `path`: The downloaded file.
+    /// - `algorithm`: The algorithm the file is hashed with when no checksum is expected, `None`
+    ///   to only hash files with an expected checksum.
+    /// - `on_progress`: Called with the bytes hashed so far and the size of the file.
+    ///
+    /// # Returns
+    /// - `Ok(Some(Checksum))`: The checksum of the file, which matches the expected one if any.
+    /// - `Ok(None)`: If no checksum is expected and `algorithm` is `None`.
+    /// - `Err(String)`: If the checksum differs or the file could not be read.
+    pub fn check_file(
+        &self,
+        path: &Path,
+        algorithm: Option<HashAlgorithm>,
+        on_progress: impl FnMut(u64, u64),
+    ) -> Result<Option<Checksum>, String> {
+        let expected = self.expected_checksum()?;
+        let Some(algorithm) = expected.as_ref().map(|c| c.algorithm).or(algorithm) else {
+            return Ok(None);
+        };
+        let actual = hashing::hash_file(path, algorithm, on_progress)
+            .map_err(|e| format!("Failed to compute the checksum: {e}"))?;
+        match expected {
+            Some(expected) if expected != actual => Err(format!(
+                "Checksum mismatch: expected {expected} but the file has {}",
+                actual.digest
+            )),
+            _ => Ok(Some(actual)),
+        }
+    }
+
+    /// This function returns the expected checksum, `None` if none is expected.
+    pub fn expected_checksum(&self) -> Result<Option<Checksum>, String> {
+        let expected = [
+            (HashAlgorithm::Blake3, &self.blake3),
+            (HashAlgorithm::Sha256, &self.sha256),
+        ]
+        .into_iter()
+        .find_map(|(algorithm, digest)| {
+            let digest = digest.as_deref()?.trim();
+            (!digest.is_empty()).then_some((algorithm, digest))
+        });
+        let Some((algorithm, digest)) = expected else {
+            return Ok(None);
+        };
+        let prefixed = if digest.contains(':') {
+            digest.to_string()
+        } else {
+            format!("{}:{digest}", algorithm.name())
+        };
+        match Checksum::parse(&prefixed) {
+            Some(checksum) if checksum.algorithm == algorithm => Ok(Some(checksum)),
+            _ => Err(format!("Invalid {} checksum: {digest}", algorithm.name())),
+        }
+    }
+
+    /// This function checks the size of the file.
+    pub fn check_size(&self, size: u64) -> Result<(), String> {
+        match (self.min_size, self.max_size) {
+            (Some(min), _) if size < min => Err(format!(
+                "The file is {size} bytes, smaller than the expected minimum of {min} bytes"
+            )),
+            (_, Some(max)) if size > max => Err(format!(
+                "The file is {size} bytes, larger than the expected maximum of {max} bytes"
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// This function returns the media type without parameters, e.g. `text/html; charset=utf-8`
+/// becomes `text/html`.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_lowercase()
+}
+
+/// This function matches a content type against an accepted one which may end with `/*`.
+fn content_type_matches(accepted: &str, actual: &str) -> bool {
+    if accepted == "*/*" {
+        return true;
+    }
+    match accepted.strip_suffix("/*") {
+        Some(kind) => actual.split('/').next() == Some(kind),
+        None => accepted == actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_criteria_always_passes() {
+        assert!(SuccessCriteria::default().check(Some(0), None).is_ok());
+    }
+
+    #[test]
+    fn test_min_size() {
+        let c = SuccessCriteria {
+            min_size: Some(1000),
+            ..SuccessCriteria::default()
+        };
+        assert!(c.check(Some(1000), None).is_ok());
+        assert!(c.check(Some(999), None).is_err());
+        assert!(c.check(None, None).is_ok());
+
+        let exact = SuccessCriteria {
+            max_size: Some(1000),
+            ..c
+        };
+        assert!(exact.check_size(1000).is_ok());
+        assert!(exact.check_size(1001).is_err());
+    }
+
+    #[test]
+    fn test_content_type() {
+        let c = SuccessCriteria {
+            content_type: Some("application/zip, video/*".into()),
+            ..SuccessCriteria::default()
+        };
+        assert!(c.check(Some(1), Some("application/zip")).is_ok());
+        assert!(c.check(Some(1), Some("Video/MP4")).is_ok());
+        let err = c.check(Some(1), Some("text/html; charset=utf-8")).unwrap_err();
+        assert!(err.contains("text/html"), "{err}");
+        assert!(c.check(Some(1), None).is_err());
+    }
+
+    #[test]
+    fn test_check_file() {
+        let dir = std::env::temp_dir().join("yad_test").join("criteria");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hello.txt");
+        std::fs::write(&path, b"hello").unwrap();
+
+        let none = |_, _| {};
+        assert_eq!(
+            SuccessCriteria::default().check_file(&path, None, none),
+            Ok(None)
+        );
+        let hashed = SuccessCriteria::default()
+            .check_file(&path, Some(HashAlgorithm::Blake3), none)
+            .unwrap()
+            .unwrap();
+        assert_eq!(hashed.algorithm, HashAlgorithm::Blake3);
+        let c = SuccessCriteria {
+            sha256: Some(
+                "sha256:2CF24DBA5FB0A30E26E83B2AC5B9E29E1B161E5C1FA7425E73043362938B9824".into(),
+            ),
+            ..SuccessCriteria::default()
+        };
+        // the expected checksum decides the algorithm
+        let checksum = c.check_file(&path, Some(HashAlgorithm::Blake3), none);
+        assert_eq!(checksum.unwrap().unwrap().algorithm, HashAlgorithm::Sha256);
+        let c = SuccessCriteria {
+            sha256: Some("00".repeat(32)),
+            ..SuccessCriteria::default()
+        };
+        let err = c.check_file(&path, None, none).unwrap_err();
+        assert!(err.contains("mismatch"), "{err}");
+        let c = SuccessCriteria {
+            blake3: Some("not hex".into()),
+            ..SuccessCriteria::default()
+        };
+        assert!(c.check_file(&path, None, none).is_err());
+    }
+}
@@ -0,0 +1,629 @@
+//! This module reads MPEG-DASH manifests (`.mpd`), which split a video into short segments
+//! served in several qualities, with the audio apart from the video. A download picks the video
+//! of the quality asked for and the best audio, downloads the segments of both side by side and
+//! muxes them into a single MP4 file, see `mux`.
+//!
+//! The tracks are kept while they download, each with a progress file listing the size of the
+//! track after every segment written, so that a resumed download carries on after the last one.
+//!
+//! Only static manifests of MP4 segments are supported: live streams never end, and streams
+//! protected with DRM cannot be played once downloaded anyway.
+
+use std::{
+    cmp::Reverse,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use reqwest::Url;
+use roxmltree::{Document, Node};
+
+/// The namespace of the elements of a manifest.
+const NAMESPACE: &str = "urn:mpeg:dash:schema:mpd:2011";
+
+/// The most segments a representation may have, to refuse manifests that would never end.
+const MAX_SEGMENTS: u64 = 500_000;
+
+/// This enum represents what a representation holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Video,
+    Audio,
+}
+
+/// This struct represents a part of a representation. The first segment of a representation is
+/// its initialization segment when it has one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub url: String,
+    /// The first and last byte of the segment, when it is a part of the file at `url`.
+    pub range: Option<(u64, u64)>,
+}
+
+/// This struct represents a version of the video or the audio, in one quality.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Representation {
+    pub id: String,
+    pub kind: Kind,
+    /// The bits per second of the representation.
+    pub bandwidth: u64,
+    /// The height of the video in pixels.
+    pub height: Option<u32>,
+    pub segments: Vec<Segment>,
+}
+
+/// This struct represents the MP4 representations of a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    /// The length of the stream in seconds, if the manifest tells.
+    pub duration: Option<f64>,
+    pub representations: Vec<Representation>,
+}
+
+impl Manifest {
+    /// This function picks the representations downloaded: the video of the highest quality not
+    /// above `height` and with the most bandwidth, or the lowest quality when all are above it,
+    /// and the audio with the most bandwidth. Without a `height` the best video is picked.
+    pub fn pick(&self, height: Option<u32>) -> (Option<&Representation>, Option<&Representation>) {
+        let of_kind = |kind| self.representations.iter().filter(move |r| r.kind == kind);
+        let quality = |r: &&Representation| (r.height.unwrap_or(0), r.bandwidth);
+        let fitting = of_kind(Kind::Video)
+            .filter(|r| height.is_none_or(|h| r.height.unwrap_or(0) <= h))
+            .max_by_key(quality);
+        let video = fitting.or_else(|| {
+            of_kind(Kind::Video).min_by_key(|r| (r.height.unwrap_or(0), Reverse(r.bandwidth)))
+        });
+        let audio = of_kind(Kind::Audio).max_by_key(|r| r.bandwidth);
+        (video, audio)
+    }
+
+    /// This function returns the estimated size of the representations, from their bandwidth and
+    /// the length of the stream.
+    pub fn estimated_size(&self, picked: &[&Representation]) -> Option<u64> {
+        let duration = self.duration?;
+        let bits: u64 = picked.iter().map(|r| r.bandwidth).sum();
+        Some((bits as f64 / 8.0 * duration) as u64)
+    }
+}
+
+/// This function checks whether `url` points to a DASH manifest, going by its extension.
+pub fn is_manifest_url(url: &str) -> bool {
+    Url::parse(url).is_ok_and(|url| {
+        matches!(url.scheme(), "http" | "https")
+            && url.path().to_ascii_lowercase().ends_with(".mpd")
+    })
+}
+
+/// This function returns the name of the file a manifest is downloaded to, e.g. `talk.mp4` for
+/// `https://example.com/talk.mpd`.
+pub fn file_name(url: &str) -> String {
+    let stem = Url::parse(url)
+        .ok()
+        .and_then(|url| {
+            let last = url.path_segments()?.next_back()?.to_string();
+            let stem = last
+                .rsplit_once('.')
+                .map_or(last.as_str(), |(stem, _)| stem);
+            (!stem.is_empty()).then(|| crate::files::percent_decode(stem))
+        })
+        .unwrap_or_else(|| "video".to_string());
+    format!("{stem}.mp4")
+}
+
+/// This function returns the file the progress of a track downloaded into `path` is kept in.
+pub fn progress_path(path: &Path) -> PathBuf {
+    let mut progress = path.as_os_str().to_owned();
+    progress.push(".segments");
+    PathBuf::from(progress)
+}
+
+/// This function returns the first line of the progress file of a track, which tells it apart
+/// from the other representations, e.g. when the manifest changed since the earlier attempt.
+fn progress_header(track: &Representation) -> String {
+    format!("{} {} {}", track.id, track.bandwidth, track.segments.len())
+}
+
+/// This function reads how far an earlier attempt downloaded a track into `path`.
+///
+/// # Returns
+/// The size of the track after each of the segments written whole, empty when the track starts
+/// over.
+pub fn read_progress(path: &Path, track: &Representation) -> Vec<u64> {
+    let Ok(content) = fs::read_to_string(progress_path(path)) else {
+        return Vec::new();
+    };
+    // a line is only whole once its line break was written
+    let whole = &content[..content.rfind('\n').map_or(0, |i| i + 1)];
+    let mut lines = whole.lines();
+    if lines.next() != Some(progress_header(track).as_str()) {
+        return Vec::new();
+    }
+    let len = fs::metadata(path).map_or(0, |m| m.len());
+    let mut ends = Vec::new();
+    for line in lines.take(track.segments.len()) {
+        match line.parse::<u64>() {
+            Ok(end) if end <= len && end >= ends.last().copied().unwrap_or(0) => ends.push(end),
+            _ => break,
+        }
+    }
+    ends
+}
+
+/// This function starts the progress file of a track over with the segments in `ends`, see
+/// `read_progress`.
+pub fn write_progress(path: &Path, track: &Representation, ends: &[u64]) -> io::Result<()> {
+    let mut content = progress_header(track);
+    for end in ends {
+        content.push_str(&format!("\n{end}"));
+    }
+    content.push('\n');
+    fs::write(progress_path(path), content)
+}
+
+/// This function records that the next segment of a track was written, after which the track is
+/// `end` bytes long.
+pub fn record_segment(path: &Path, end: u64) -> io::Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .open(progress_path(path))?;
+    writeln!(file, "{end}")
+}
+
+/// This function returns the child elements of the DASH namespace named `name`.
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name((NAMESPACE, name)))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &'static str) -> Option<Node<'a, 'input>> {
+    children(node, name).next()
+}
+
+/// This function resolves the `BaseURL` of an element against the url of its parent.
+fn base_url(node: Node, parent: &Url) -> Result<Url, String> {
+    match child(node, "BaseURL").and_then(|n| n.text()) {
+        Some(base) => parent
+            .join(base.trim())
+            .map_err(|e| format!("Invalid BaseURL {base} in the manifest: {e}")),
+        None => Ok(parent.clone()),
+    }
+}
+
+/// This function reads a duration like `PT1H2M3.5S` in seconds.
+fn parse_duration(value: &str) -> Option<f64> {
+    let rest = value.trim().strip_prefix('P')?;
+    let (days, time) = rest.split_once('T').unwrap_or((rest, ""));
+    let mut seconds = match days.strip_suffix('D') {
+        Some(days) => days.parse::<f64>().ok()? * 86_400.0,
+        None if days.is_empty() => 0.0,
+        None => return None,
+    };
+    let mut number = String::new();
+    for c in time.chars() {
+        let unit = match c {
+            'H' => 3_600.0,
+            'M' => 60.0,
+            'S' => 1.0,
+            _ => {
+                number.push(c);
+                continue;
+            }
+        };
+        seconds += number.parse::<f64>().ok()? * unit;
+        number.clear();
+    }
+    number.is_empty().then_some(seconds)
+}
+
+/// This function reads a byte range like `0-861`.
+fn parse_range(value: &str) -> Result<(u64, u64), String> {
+    value
+        .split_once('-')
+        .and_then(|(start, end)| Some((start.trim().parse().ok()?, end.trim().parse().ok()?)))
+        .filter(|(start, end)| start <= end)
+        .ok_or_else(|| format!("Invalid byte range {value} in the manifest"))
+}
+
+/// This function fills in the identifiers of a `SegmentTemplate`, e.g. `$Number%05d$`.
+fn fill_template(template: &str, id: &str, bandwidth: u64, number: u64, time: u64) -> String {
+    let mut filled = String::new();
+    let mut parts = template.split('$');
+    filled.push_str(parts.next().unwrap_or_default());
+    // identifiers are at the odd places between the `$`
+    while let Some(identifier) = parts.next() {
+        let (name, format) = identifier.split_once('%').unwrap_or((identifier, ""));
+        let value = match name {
+            "" => Some("$".to_string()),
+            "RepresentationID" => Some(id.to_string()),
+            "Bandwidth" => Some(bandwidth.to_string()),
+            "Number" => Some(number.to_string()),
+            "Time" => Some(time.to_string()),
+            _ => None,
+        };
+        match value {
+            Some(value) => {
+                let width = format
+                    .strip_suffix('d')
+                    .and_then(|w| w.trim_start_matches('0').parse().ok())
+                    .unwrap_or(0);
+                filled.push_str(&format!("{value:0>width$}"));
+            }
+            None => {
+                filled.push('$');
+                filled.push_str(identifier);
+                filled.push('$');
+            }
+        }
+        filled.push_str(parts.next().unwrap_or_default());
+    }
+    filled
+}
+
+/// This function returns an attribute of the `SegmentTemplate` of a representation, or of its
+/// adaptation set, which the one of the representation inherits from.
+fn template_attribute<'a>(templates: &[Node<'a, '_>], name: &str) -> Option<&'a str> {
+    templates.iter().find_map(|t| t.attribute(name))
+}
+
+/// This function lists the segments of a representation described by `SegmentTemplate`s, the
+/// one of the representation first.
+fn template_segments(
+    templates: &[Node],
+    base: &Url,
+    id: &str,
+    bandwidth: u64,
+    duration: Option<f64>,
+) -> Result<Vec<Segment>, String> {
+    let number = |name, default| {
+        template_attribute(templates, name).map_or(Ok(default), |value: &str| {
+            value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("Invalid {name} {value} in the manifest"))
+        })
+    };
+    let timescale = number("timescale", 1)?.max(1);
+    let start_number = number("startNumber", 1)?;
+    let resolve = |template: &str, number, time| {
+        let url = fill_template(template, id, bandwidth, number, time);
+        base.join(&url)
+            .map(|url| Segment {
+                url: url.to_string(),
+                range: None,
+            })
+            .map_err(|e| format!("Invalid segment url {url} in the manifest: {e}"))
+    };
+
+    let mut segments = Vec::new();
+    if let Some(initialization) = template_attribute(templates, "initialization") {
+        segments.push(resolve(initialization, 0, 0)?);
+    }
+    let media = template_attribute(templates, "media")
+        .ok_or("A SegmentTemplate of the manifest has no media")?;
+    let timeline = templates.iter().find_map(|t| child(*t, "SegmentTimeline"));
+    if let Some(timeline) = timeline {
+        let mut time = 0;
+        let mut number = start_number;
+        for s in children(timeline, "S") {
+            let attribute = |name| s.attribute(name).and_then(|v| v.trim().parse::<i64>().ok());
+            time = attribute("t").map_or(time, |t| t.max(0) as u64);
+            let d = attribute("d")
+                .filter(|d| *d > 0)
+                .ok_or("A segment has no duration")? as u64;
+            // a negative repeat lasts until the end of the period
+            let repeat = match attribute("r") {
+                Some(r) if r < 0 => {
+                    let end = duration.ok_or("The manifest does not tell how long it is")?;
+                    let end = (end * timescale as f64) as u64;
+                    end.saturating_sub(time).div_ceil(d).saturating_sub(1)
+                }
+                r => r.unwrap_or(0) as u64,
+            };
+            if segments.len() as u64 + repeat > MAX_SEGMENTS {
+                return Err("The manifest has too many segments".into());
+            }
+            for _ in 0..=repeat {
+                segments.push(resolve(media, number, time)?);
+                time += d;
+                number += 1;
+            }
+        }
+    } else {
+        let d = number("duration", 0)?;
+        let length = duration.ok_or("The manifest does not tell how long it is")?;
+        if d == 0 {
+            return Err("A SegmentTemplate of the manifest has no duration".into());
+        }
+        let count = (length * timescale as f64 / d as f64).ceil() as u64;
+        if count > MAX_SEGMENTS {
+            return Err("The manifest has too many segments".into());
+        }
+        for i in 0..count {
+            segments.push(resolve(media, start_number + i, i * d)?);
+        }
+    }
+    Ok(segments)
+}
+
+/// This function lists the segments of a representation described by a `SegmentList`.
+fn list_segments(list: Node, base: &Url) -> Result<Vec<Segment>, String> {
+    let segment = |url: Option<&str>, range: Option<&str>| -> Result<Segment, String> {
+        let url = match url {
+            Some(url) => base
+                .join(url.trim())
+                .map_err(|e| format!("Invalid segment url {url} in the manifest: {e}"))?,
+            None => base.clone(),
+        };
+        Ok(Segment {
+            url: url.to_string(),
+            range: range.map(parse_range).transpose()?,
+        })
+    };
+    let mut segments = Vec::new();
+    if let Some(init) = child(list, "Initialization") {
+        segments.push(segment(
+            init.attribute("sourceURL"),
+            init.attribute("range"),
+        )?);
+    }
+    for url in children(list, "SegmentURL") {
+        segments.push(segment(
+            url.attribute("media"),
+            url.attribute("mediaRange"),
+        )?);
+    }
+    Ok(segments)
+}
+
+/// This function reads a representation, `None` if it is neither MP4 video nor MP4 audio.
+fn read_representation(
+    representation: Node,
+    set: Node,
+    base: &Url,
+    duration: Option<f64>,
+) -> Result<Option<Representation>, String> {
+    let attribute = |name| representation.attribute(name).or(set.attribute(name));
+    let mime = attribute("mimeType").unwrap_or_default();
+    let kind = match (mime, attribute("contentType")) {
+        ("video/mp4", _) | ("", Some("video")) => Kind::Video,
+        ("audio/mp4", _) | ("", Some("audio")) => Kind::Audio,
+        _ => return Ok(None),
+    };
+    let id = representation.attribute("id").unwrap_or_default();
+    let bandwidth = representation
+        .attribute("bandwidth")
+        .and_then(|b| b.trim().parse().ok())
+        .unwrap_or(0);
+    let base = base_url(representation, base)?;
+    let templates: Vec<Node> = [representation, set]
+        .iter()
+        .filter_map(|n| child(*n, "SegmentTemplate"))
+        .collect();
+    let segments = if !templates.is_empty() {
+        template_segments(&templates, &base, id, bandwidth, duration)?
+    } else if let Some(list) = child(representation, "SegmentList").or(child(set, "SegmentList")) {
+        list_segments(list, &base)?
+    } else {
+        // the whole representation is a single file
+        vec![Segment {
+            url: base.to_string(),
+            range: None,
+        }]
+    };
+    if segments.is_empty() {
+        return Err(format!(
+            "The representation {id} of the manifest has no segments"
+        ));
+    }
+    Ok(Some(Representation {
+        id: id.to_string(),
+        kind,
+        bandwidth,
+        height: representation
+            .attribute("height")
+            .and_then(|h| h.trim().parse().ok()),
+        segments,
+    }))
+}
+
+/// This function parses a manifest.
+///
+/// # Arguments
+/// - `content`: The manifest.
+/// - `url`: The url the manifest was read from, which relative urls are resolved against.
+///
+/// # Returns
+/// - `Ok(Manifest)`: The manifest, with at least one representation.
+/// - `Err(String)`: If the manifest is invalid or cannot be downloaded.
+pub fn parse(content: &str, url: &str) -> Result<Manifest, String> {
+    let url = Url::parse(url).map_err(|e| format!("Invalid manifest url: {e}"))?;
+    let document = Document::parse(content).map_err(|e| format!("Invalid DASH manifest: {e}"))?;
+    let mpd = document.root_element();
+    if !mpd.has_tag_name((NAMESPACE, "MPD")) {
+        return Err("Invalid DASH manifest: it has no MPD element".into());
+    }
+    if mpd.attribute("type") == Some("dynamic") {
+        return Err("Live streams cannot be downloaded".into());
+    }
+    if mpd
+        .descendants()
+        .any(|n| n.has_tag_name((NAMESPACE, "ContentProtection")))
+    {
+        return Err("The stream is protected with DRM".into());
+    }
+    let mut periods = children(mpd, "Period");
+    let period = periods.next().ok_or("The manifest has no Period")?;
+    if periods.next().is_some() {
+        return Err("Manifests with several periods are not supported".into());
+    }
+    let duration = period
+        .attribute("duration")
+        .or(mpd.attribute("mediaPresentationDuration"))
+        .and_then(parse_duration);
+
+    let base = base_url(period, &base_url(mpd, &url)?)?;
+    let mut representations = Vec::new();
+    for set in children(period, "AdaptationSet") {
+        let set_base = base_url(set, &base)?;
+        for representation in children(set, "Representation") {
+            if let Some(r) = read_representation(representation, set, &set_base, duration)? {
+                representations.push(r);
+            }
+        }
+    }
+    if representations.is_empty() {
+        return Err("The manifest has no MP4 video or audio".into());
+    }
+    Ok(Manifest {
+        duration,
+        representations,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"<?xml version="1.0"?>
+        <MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="static"
+             mediaPresentationDuration="PT0H0M9.5S">
+          <BaseURL>media/</BaseURL>
+          <Period>
+            <AdaptationSet mimeType="video/mp4">
+              <SegmentTemplate timescale="1000" duration="4000" startNumber="1"
+                  initialization="$RepresentationID$/init.mp4"
+                  media="$RepresentationID$/seg-$Number%03d$.m4s"/>
+              <Representation id="v360" bandwidth="800000" height="360"/>
+              <Representation id="v720" bandwidth="2500000" height="720"/>
+              <Representation id="v1080" bandwidth="5000000" height="1080"/>
+            </AdaptationSet>
+            <AdaptationSet contentType="audio" lang="en">
+              <Representation id="a128" bandwidth="128000" mimeType="audio/mp4">
+                <SegmentTemplate timescale="48000" initialization="a/init.mp4" media="a/$Time$.m4s">
+                  <SegmentTimeline>
+                    <S t="0" d="192000" r="1"/>
+                    <S d="72000"/>
+                  </SegmentTimeline>
+                </SegmentTemplate>
+              </Representation>
+              <Representation id="a64" bandwidth="64000" mimeType="audio/mp4">
+                <BaseURL>https://cdn.example.com/a64.mp4</BaseURL>
+                <SegmentList>
+                  <Initialization range="0-861"/>
+                  <SegmentURL mediaRange="862-50000"/>
+                </SegmentList>
+              </Representation>
+            </AdaptationSet>
+            <AdaptationSet mimeType="video/webm">
+              <Representation id="webm" bandwidth="9000000" height="2160"/>
+            </AdaptationSet>
+          </Period>
+        </MPD>"#;
+
+    #[test]
+    fn test_parse() {
+        let manifest = parse(MANIFEST, "https://example.com/talks/talk.mpd").unwrap();
+        assert_eq!(manifest.duration, Some(9.5));
+        assert_eq!(manifest.representations.len(), 5);
+
+        let (video, audio) = manifest.pick(Some(1000));
+        let video = video.unwrap();
+        assert_eq!(video.id, "v720");
+        let urls: Vec<&str> = video.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/talks/media/v720/init.mp4",
+                "https://example.com/talks/media/v720/seg-001.m4s",
+                "https://example.com/talks/media/v720/seg-002.m4s",
+                "https://example.com/talks/media/v720/seg-003.m4s",
+            ]
+        );
+        let audio = audio.unwrap();
+        assert_eq!(audio.id, "a128");
+        let urls: Vec<&str> = audio.segments.iter().map(|s| s.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/talks/media/a/init.mp4",
+                "https://example.com/talks/media/a/0.m4s",
+                "https://example.com/talks/media/a/192000.m4s",
+                "https://example.com/talks/media/a/384000.m4s",
+            ]
+        );
+        let listed = &manifest.representations[4];
+        assert_eq!(
+            listed.segments[1],
+            Segment {
+                url: "https://cdn.example.com/a64.mp4".into(),
+                range: Some((862, 50000)),
+            }
+        );
+
+        assert_eq!(manifest.pick(None).0.unwrap().id, "v1080");
+        assert_eq!(manifest.pick(Some(240)).0.unwrap().id, "v360");
+        assert_eq!(
+            manifest.estimated_size(&[video, audio]),
+            Some((2_628_000.0 / 8.0 * 9.5) as u64)
+        );
+    }
+
+    #[test]
+    fn test_invalid_manifests() {
+        let url = "https://example.com/a.mpd";
+        assert!(parse("<MPD/>", url).is_err());
+        let live = r#"<MPD xmlns="urn:mpeg:dash:schema:mpd:2011" type="dynamic"/>"#;
+        assert!(parse(live, url).unwrap_err().contains("Live"));
+        let drm = MANIFEST.replace(
+            r#"<AdaptationSet mimeType="video/mp4">"#,
+            r#"<AdaptationSet mimeType="video/mp4"><ContentProtection schemeIdUri="x"/>"#,
+        );
+        assert!(parse(&drm, url).unwrap_err().contains("DRM"));
+        assert_eq!(parse_duration("PT1H2M3.5S"), Some(3723.5));
+        assert_eq!(parse_duration("P1DT1S"), Some(86401.0));
+        assert_eq!(parse_duration("1H"), None);
+        assert_eq!(
+            fill_template("$Number%05d$-$$.m4s", "v", 1, 42, 0),
+            "00042-$.m4s"
+        );
+        assert!(is_manifest_url("https://example.com/stream.MPD?token=1"));
+        assert_eq!(
+            file_name("https://example.com/my%20talk.mpd"),
+            "my talk.mp4"
+        );
+    }
+
+    #[test]
+    fn test_progress() {
+        let manifest = parse(MANIFEST, "https://example.com/talks/talk.mpd").unwrap();
+        let track = &manifest.representations[0];
+        let path = std::env::temp_dir().join("yad_dash_progress_test.video");
+        fs::write(&path, [0; 300]).unwrap();
+        let _ = fs::remove_file(progress_path(&path));
+        assert!(read_progress(&path, track).is_empty());
+
+        write_progress(&path, track, &[100]).unwrap();
+        record_segment(&path, 250).unwrap();
+        assert_eq!(read_progress(&path, track), [100, 250]);
+        // a segment recorded past the end of the track was not written
+        record_segment(&path, 400).unwrap();
+        assert_eq!(read_progress(&path, track), [100, 250]);
+        // nor is a line cut short
+        write_progress(&path, track, &[100]).unwrap();
+        fs::OpenOptions::new()
+            .append(true)
+            .open(progress_path(&path))
+            .and_then(|mut file| file.write_all(b"2"))
+            .unwrap();
+        assert_eq!(read_progress(&path, track), [100]);
+        // nor are the segments of another representation
+        assert!(read_progress(&path, &manifest.representations[1]).is_empty());
+
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(progress_path(&path));
+    }
+}
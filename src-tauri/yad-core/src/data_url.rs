@@ -0,0 +1,176 @@
+//! This module reads `data:` urls (RFC 2397), which hold their content instead of pointing to
+//! it, e.g. `data:image/png;base64,iVBORw0...` copied from a web page. Their content is saved
+//! to a file named after its media type, so it lands in the folder of its file type. The
+//! download is recorded with a short url naming the media type and the SHA-256 of the content,
+//! see `stored_url`, as the content may be megabytes long.
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::{files, hashing::hex};
+
+/// The media type of a `data:` url that does not name one.
+const DEFAULT_MEDIA_TYPE: &str = "text/plain";
+
+/// This struct represents the content of a `data:` url.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataUrl {
+    /// The media type without its parameters, in lowercase, e.g. `image/png`.
+    pub media_type: String,
+    pub content: Vec<u8>,
+}
+
+/// The parameter of the url a `data:` url is recorded with, followed by the SHA-256 of its
+/// content.
+const STORED_PARAM: &str = ";sha256=";
+
+/// This function checks whether `url` is a `data:` url, or the one a download of a `data:` url is
+/// recorded with, see `stored_url`.
+pub fn is_data_url(url: &str) -> bool {
+    url.get(..5)
+        .is_some_and(|scheme| scheme.eq_ignore_ascii_case("data:"))
+        && (url.contains(',') || url.contains(STORED_PARAM))
+}
+
+/// This function returns the url the download of a `data:` url is recorded with, e.g.
+/// `data:image/png;sha256=4c4b6a3b...`.
+pub fn stored_url(data: &DataUrl) -> String {
+    let hash = Sha256::digest(&data.content);
+    format!("data:{}{STORED_PARAM}{}", data.media_type, hex(&hash))
+}
+
+/// This function returns the media type and whether the content is base64 from the header of a
+/// `data:` url, the part before the comma.
+fn read_header(url: &str) -> Option<(String, bool, &str)> {
+    let rest = url.get(5..).filter(|_| is_data_url(url))?;
+    // the url a download is recorded with has no content
+    let (header, data) = rest.split_once(',').unwrap_or((rest, ""));
+    let mut params = header.split(';').map(str::trim);
+    let media_type = params
+        .next()
+        .filter(|t| t.contains('/'))
+        .map_or(DEFAULT_MEDIA_TYPE.to_string(), str::to_ascii_lowercase);
+    let base64 = params.any(|p| p.eq_ignore_ascii_case("base64"));
+    // the fragment is not part of the content
+    let data = data.split('#').next().unwrap_or_default();
+    Some((media_type, base64, data))
+}
+
+/// This function decodes a `data:` url.
+///
+/// # Returns
+/// - `Ok(DataUrl)`: The media type and the content of the url.
+/// - `Err(String)`: If the url is not a `data:` url, only the one a download was recorded with,
+///   or its base64 is invalid.
+pub fn parse(url: &str) -> Result<DataUrl, String> {
+    let (media_type, base64, data) = read_header(url).ok_or("Invalid data: url")?;
+    if !url.contains(',') {
+        return Err("The content of this data: url was not kept, add the data: url again".into());
+    }
+    let decoded = files::percent_decode_bytes(data);
+    let content = if base64 {
+        // padding and line breaks are optional, and some encoders use the url safe alphabet
+        let text: Vec<u8> = decoded
+            .into_iter()
+            .filter(|b| !b.is_ascii_whitespace() && *b != b'=')
+            .map(|b| match b {
+                b'-' => b'+',
+                b'_' => b'/',
+                b => b,
+            })
+            .collect();
+        STANDARD_NO_PAD
+            .decode(text)
+            .map_err(|e| format!("Invalid base64 in data: url: {e}"))?
+    } else {
+        decoded
+    };
+    Ok(DataUrl {
+        media_type,
+        content,
+    })
+}
+
+/// This function returns the extension of files of a media type, e.g. `png` for `image/png`.
+fn extension(media_type: &str) -> &str {
+    match media_type {
+        "image/jpeg" | "image/jpg" => "jpg",
+        "image/svg+xml" => "svg",
+        "image/x-icon" | "image/vnd.microsoft.icon" => "ico",
+        "text/plain" => "txt",
+        "text/javascript" | "application/javascript" => "js",
+        "text/markdown" => "md",
+        "audio/mpeg" => "mp3",
+        "audio/mp4" => "m4a",
+        "audio/x-wav" => "wav",
+        "video/quicktime" => "mov",
+        "application/gzip" | "application/x-gzip" => "gz",
+        "application/x-7z-compressed" => "7z",
+        "application/x-tar" => "tar",
+        "application/msword" => "doc",
+        "application/epub+zip" => "epub",
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document" => "docx",
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet" => "xlsx",
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation" => "pptx",
+        // e.g. image/png, application/pdf, video/mp4
+        other => other
+            .split_once('/')
+            .map(|(_, subtype)| subtype)
+            .filter(|s| (1..=5).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+            .unwrap_or("bin"),
+    }
+}
+
+/// This function returns the name of the file the content of a `data:` url is saved as, e.g.
+/// `download.png` for an url of `image/png`.
+pub fn file_name(url: &str) -> String {
+    let media_type = read_header(url).map_or(DEFAULT_MEDIA_TYPE.to_string(), |(t, _, _)| t);
+    format!("download.{}", extension(&media_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let png = parse("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!(png.media_type, "image/png");
+        assert_eq!(png.content, b"\x89PNG\r\n\x1a\n");
+        let text = parse("data:,Hello%2C%20World%21").unwrap();
+        assert_eq!(text.media_type, "text/plain");
+        assert_eq!(text.content, b"Hello, World!");
+        assert_eq!(parse("data:,a#b").unwrap().content, b"a");
+        let unpadded = parse("DATA:text/html;charset=utf-8;base64,PGI+aGk8L2I+\n").unwrap();
+        assert_eq!(unpadded.content, b"<b>hi</b>");
+        assert!(parse("data:image/png;base64,!!!").is_err());
+        assert!(parse("https://example.com/a.png").is_err());
+    }
+
+    #[test]
+    fn test_stored_url() {
+        let png = parse("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        let stored = stored_url(&png);
+        assert_eq!(
+            stored,
+            "data:image/png;sha256=\
+             4c4b6a3be1314ab86138bef4314dde022e600960d8689a2c8f8631802d20dab6"
+        );
+        assert!(is_data_url(&stored));
+        assert_eq!(file_name(&stored), "download.png");
+        assert!(parse(&stored).is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("data:image/png;base64,AAAA"), "download.png");
+        assert_eq!(file_name("data:image/svg+xml,<svg/>"), "download.svg");
+        assert_eq!(file_name("data:,hello"), "download.txt");
+        assert_eq!(
+            file_name("data:application/x-unknown-format,x"),
+            "download.bin"
+        );
+        assert!(is_data_url("data:,x"));
+        assert!(!is_data_url("database.example.com"));
+    }
+}
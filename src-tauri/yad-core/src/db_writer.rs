@@ -0,0 +1,137 @@
+//! This module writes chunk status updates to the database from a dedicated thread. Chunk workers
+//! queue their updates instead of opening a connection each, the writer applies whatever has
+//! queued up in one transaction and only the latest status of each chunk is written. The queue is
+//! bounded so that workers wait when the database falls behind.
+
+use std::{collections::HashMap, sync::OnceLock, thread, time::Instant};
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{config::Config, metrics, retry, storage};
+
+/// The number of updates that can be queued before workers wait for the writer.
+const QUEUE_SIZE: usize = 1024;
+
+enum Message {
+    ChunkStatus {
+        record_id: i64,
+        start: u64,
+        status: &'static str,
+    },
+    /// Answered once every update queued before it has been written.
+    Flush(oneshot::Sender<()>),
+}
+
+fn sender() -> &'static mpsc::Sender<Message> {
+    static SENDER: OnceLock<mpsc::Sender<Message>> = OnceLock::new();
+    SENDER.get_or_init(|| {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        thread::Builder::new()
+            .name("yad-db-writer".into())
+            .spawn(move || run(rx, Config::default()))
+            .expect("failed to start the database writer thread");
+        tx
+    })
+}
+
+/// This function keeps only the latest status of each chunk, in the order the chunks were first
+/// updated, together with the number of attempts the dropped updates ended.
+fn coalesce(updates: Vec<(i64, u64, &'static str)>) -> Vec<(i64, u64, String, u32)> {
+    let mut index: HashMap<(i64, u64), usize> = HashMap::new();
+    let mut latest: Vec<(i64, u64, String, u32)> = Vec::new();
+    for (record_id, start, status) in updates {
+        let attempts = u32::from(retry::counts_as_attempt(status));
+        match index.get(&(record_id, start)) {
+            Some(i) => {
+                latest[*i].2 = status.to_string();
+                latest[*i].3 += attempts;
+            }
+            None => {
+                index.insert((record_id, start), latest.len());
+                latest.push((record_id, start, status.to_string(), attempts));
+            }
+        }
+    }
+    latest
+}
+
+fn write(updates: Vec<(i64, u64, &'static str)>, cfg: &Config) {
+    if updates.is_empty() {
+        return;
+    }
+    let started = Instant::now();
+    if let Err(e) = storage::update_chunks(&coalesce(updates), cfg) {
+        eprintln!("failed to write chunk updates because {e}");
+    }
+    metrics::db_write(started.elapsed());
+}
+
+fn run(mut rx: mpsc::Receiver<Message>, cfg: Config) {
+    while let Some(first) = rx.blocking_recv() {
+        let mut updates = Vec::new();
+        let mut next = Some(first);
+        while let Some(message) = next {
+            match message {
+                Message::ChunkStatus {
+                    record_id,
+                    start,
+                    status,
+                } => updates.push((record_id, start, status)),
+                Message::Flush(done) => {
+                    // everything queued before the flush goes into this batch
+                    write(std::mem::take(&mut updates), &cfg);
+                    let _ = done.send(());
+                }
+            }
+            next = rx.try_recv().ok();
+        }
+        write(updates, &cfg);
+    }
+}
+
+/// This function queues a chunk status update, waiting if the queue is full.
+pub async fn update_chunk(record_id: i64, start: u64, status: &'static str) {
+    let message = Message::ChunkStatus {
+        record_id,
+        start,
+        status,
+    };
+    if sender().send(message).await.is_err() {
+        eprintln!("the database writer has stopped, chunk {start} of {record_id} was not saved");
+    }
+}
+
+/// This function waits until every update queued so far has been written. Call it before reading
+/// chunk statuses back from the database.
+pub async fn flush() {
+    let (done, wait) = oneshot::channel();
+    if sender().send(Message::Flush(done)).await.is_ok() {
+        let _ = wait.await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coalesce_keeps_the_latest_status() {
+        let updates = vec![
+            (1, 0, "Pending"),
+            (1, 1024, "Finished"),
+            (1, 0, "Failed"),
+            (2, 0, "Finished"),
+            (1, 0, "Finished"),
+            (2, 1024, "Cancelled"),
+        ];
+        assert_eq!(
+            coalesce(updates),
+            vec![
+                (1, 0, "Finished".to_string(), 3),
+                (1, 1024, "Finished".to_string(), 1),
+                (2, 0, "Finished".to_string(), 1),
+                (2, 1024, "Cancelled".to_string(), 0),
+            ]
+        );
+    }
+}
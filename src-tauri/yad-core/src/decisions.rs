@@ -0,0 +1,191 @@
+//! This module lets the engine ask the user instead of deciding for them, e.g. whether a file that
+//! is already there is overwritten. The engine registers a decision, emits it as an event with a
+//! token and waits; the frontend answers with the token and one of the choices offered, see
+//! `resolve`. Until then only the download that asked waits, the others carry on.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::Serialize;
+use tokio::sync::oneshot;
+
+/// This enum represents what a decision is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecisionKind {
+    /// A file or another download already has the destination of the download.
+    FileExists,
+    /// The url has already been downloaded.
+    Duplicate,
+    /// The file is bigger than `Settings::confirm_size_above`.
+    Oversized,
+    /// The server asks for a user name and password.
+    Credentials,
+    /// The host key of an SSH server is not known yet.
+    HostKey,
+}
+
+/// This struct represents a question to the user, emitted as `Event::DecisionRequired`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Decision {
+    pub token: u64,
+    /// The download waiting for the answer, 0 if it has no record yet.
+    pub download_id: i64,
+    pub kind: DecisionKind,
+    /// The question, e.g. `report.pdf already exists`.
+    pub message: String,
+    pub choices: Vec<&'static str>,
+    /// The choice taken when nobody can answer.
+    pub default: &'static str,
+}
+
+/// This struct represents the answer to a decision.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Answer {
+    pub choice: String,
+    /// What the user typed in, e.g. `user:password` for `DecisionKind::Credentials`.
+    pub input: Option<String>,
+}
+
+impl Answer {
+    pub fn new(choice: &str) -> Self {
+        Answer {
+            choice: choice.to_string(),
+            input: None,
+        }
+    }
+}
+
+struct Pending {
+    decision: Decision,
+    answer: oneshot::Sender<Answer>,
+}
+
+fn pending() -> &'static Mutex<HashMap<u64, Pending>> {
+    static PENDING: OnceLock<Mutex<HashMap<u64, Pending>>> = OnceLock::new();
+    PENDING.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn next_token() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// This function registers a decision waiting for an answer.
+///
+/// # Arguments
+/// - `download_id`: The download waiting for the answer, 0 if it has no record yet.
+/// - `kind`: What the decision is about.
+/// - `message`: The question.
+/// - `choices`: The choices offered, the first is the default.
+///
+/// # Returns
+/// The decision to show and where its answer arrives.
+pub fn register(
+    download_id: i64,
+    kind: DecisionKind,
+    message: String,
+    choices: Vec<&'static str>,
+) -> (Decision, oneshot::Receiver<Answer>) {
+    let decision = Decision {
+        token: next_token(),
+        download_id,
+        kind,
+        message,
+        default: choices.first().copied().unwrap_or_default(),
+        choices,
+    };
+    let (answer, wait) = oneshot::channel();
+    let entry = Pending {
+        decision: decision.clone(),
+        answer,
+    };
+    pending().lock().unwrap().insert(decision.token, entry);
+    (decision, wait)
+}
+
+/// This function answers a decision, which lets the download that asked carry on.
+///
+/// # Arguments
+/// - `token`: The token of the decision.
+/// - `choice`: One of the choices offered.
+/// - `input`: What the user typed in, if the choice needs it.
+pub fn resolve(token: u64, choice: &str, input: Option<String>) -> Result<(), String> {
+    let mut map = pending().lock().unwrap();
+    let entry = map
+        .get(&token)
+        .ok_or_else(|| "This decision has already been made".to_string())?;
+    if !entry.decision.choices.contains(&choice) {
+        return Err(format!("{choice} is not one of the choices"));
+    }
+    let entry = map.remove(&token).expect("the decision is pending");
+    let answer = Answer {
+        choice: choice.to_string(),
+        input,
+    };
+    // the download may have been stopped while it waited
+    let _ = entry.answer.send(answer);
+    Ok(())
+}
+
+/// This function forgets a decision nobody can answer.
+pub fn withdraw(token: u64) {
+    pending().lock().unwrap().remove(&token);
+}
+
+/// This function returns the decisions waiting for an answer, oldest first, e.g. for a window
+/// opened after they were emitted.
+pub fn waiting() -> Vec<Decision> {
+    let mut decisions: Vec<Decision> = pending()
+        .lock()
+        .unwrap()
+        .values()
+        .map(|p| p.decision.clone())
+        .collect();
+    decisions.sort_by_key(|d| d.token);
+    decisions
+}
+
+/// This function returns the `Authorization` header of a user name and password typed in as
+/// `user:password`.
+pub fn basic_authorization(credentials: &str) -> String {
+    format!("Basic {}", STANDARD.encode(credentials))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decisions_are_answered_once() {
+        let (decision, mut answer) = register(
+            7,
+            DecisionKind::FileExists,
+            "report.pdf already exists".into(),
+            vec!["rename", "overwrite", "skip"],
+        );
+        assert_eq!(decision.default, "rename");
+        assert!(waiting().iter().any(|d| d.token == decision.token));
+
+        assert!(resolve(decision.token, "delete", None).is_err());
+        resolve(decision.token, "overwrite", None).unwrap();
+        assert_eq!(answer.try_recv().unwrap(), Answer::new("overwrite"));
+        assert!(resolve(decision.token, "skip", None).is_err());
+        assert!(!waiting().iter().any(|d| d.token == decision.token));
+    }
+
+    #[test]
+    fn test_basic_authorization() {
+        assert_eq!(
+            basic_authorization("Aladdin:open sesame"),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+}
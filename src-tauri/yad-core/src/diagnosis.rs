@@ -0,0 +1,180 @@
+//! This module suggests how to fix a failed download. The engine reports failures as text, so
+//! the error is classified by what it says, e.g. the status code of an HTTP error, and combined
+//! with what is known about the server, e.g. whether it can resume downloads. The suggestion is
+//! saved with the record and shown next to it.
+
+/// This struct represents a failed download.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Failure<'a> {
+    /// The error the download failed with.
+    pub error: &'a str,
+    /// The url the file was requested from.
+    pub url: &'a str,
+    /// Whether the server sends parts of the file, i.e. whether a retry carries on where the
+    /// download stopped.
+    pub resumable: bool,
+}
+
+/// Query parameters of urls which are signed and stop working after a while.
+const SIGNED_URL_PARAMS: [&str; 9] = [
+    "expires",
+    "x-amz-expires",
+    "x-amz-signature",
+    "x-goog-expires",
+    "x-goog-signature",
+    "signature",
+    "token",
+    "sig",
+    "se",
+];
+
+/// This function returns the status code of an HTTP error, e.g. 403 from
+/// `HTTP status client error (403 Forbidden) for url (...)`.
+pub fn http_status(error: &str) -> Option<u16> {
+    let (_, rest) = error.split_once("HTTP status ")?;
+    let (_, code) = rest.split_once('(')?;
+    code.get(..3)?.parse().ok()
+}
+
+/// This function tells whether a url is signed, which means it usually expires.
+pub fn is_signed_url(url: &str) -> bool {
+    let Ok(url) = reqwest::Url::parse(url) else {
+        return false;
+    };
+    url.query_pairs()
+        .any(|(name, _)| SIGNED_URL_PARAMS.contains(&name.to_ascii_lowercase().as_str()))
+}
+
+/// This function suggests how to fix a failed download.
+///
+/// # Returns
+/// `None` if the error is not one a suggestion is known for.
+pub fn suggest(failure: &Failure) -> Option<String> {
+    let error = failure.error.to_ascii_lowercase();
+    let restart = if failure.resumable {
+        "retry carries on where it stopped"
+    } else {
+        "retry will restart from zero"
+    };
+    let suggestion = match http_status(failure.error) {
+        Some(401 | 403) if is_signed_url(failure.url) => {
+            "Link appears expired — refresh it from the source page".to_string()
+        }
+        Some(401 | 403) => {
+            "The server refused access — the link may need a login, cookies or a referer, add a \
+             host preset or refresh it from the source page"
+                .to_string()
+        }
+        Some(404) => "The file is no longer at this address — check the source page".to_string(),
+        Some(410) => "Link appears expired — refresh it from the source page".to_string(),
+        Some(416) => {
+            "The server rejected the part of the file asked for — retry will restart from zero"
+                .to_string()
+        }
+        Some(429 | 503) => format!(
+            "The server is limiting requests — wait a few minutes and lower the chunks per \
+             download, {restart}"
+        ),
+        Some(500..=599) => format!("The server had an error — wait a while, {restart}"),
+        _ if error.contains("certificate") && error.contains("pin") => {
+            "The certificate of the server changed — update the pin only if you trust the new \
+             certificate"
+                .to_string()
+        }
+        _ if error.contains("certificate verify failed") => {
+            "The certificate of the server is not trusted — add the certificate authority of the \
+             server to the trusted certificates if it belongs to your company"
+                .to_string()
+        }
+        _ if error.contains("checksum mismatch") => {
+            "The file does not match its checksum — check the checksum on the source page, or \
+             delete the download and start it again"
+                .to_string()
+        }
+        _ if error.contains("no space left") || error.contains("disk full") => {
+            format!("The disk is full — free some space, {restart}")
+        }
+        _ if error.contains("permission denied") || error.contains("access is denied") => {
+            "The file cannot be written — choose another folder or check its permissions"
+                .to_string()
+        }
+        _ if error.contains("dns error") || error.contains("failed to lookup address") => {
+            "The host could not be found — check the link and your internet connection".to_string()
+        }
+        _ if error.contains("timed out")
+            || error.contains("connection refused")
+            || error.contains("connection reset")
+            || error.contains("error sending request")
+            || error.contains("ftp connection failed")
+            || error.contains("ssh connection failed")
+            || error.contains("body failed") =>
+        {
+            format!("The connection was lost — check your internet connection, {restart}")
+        }
+        _ if error.contains("changed on the server") => {
+            "The file changed on the server — retry to download the new version from the start"
+                .to_string()
+        }
+        _ if error.contains("chunks failed") => {
+            format!("Some parts of the file could not be downloaded — {restart}")
+        }
+        _ if !failure.resumable => {
+            "The server doesn't allow resuming — retry will restart from zero".to_string()
+        }
+        _ => return None,
+    };
+    Some(suggestion)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failure<'a>(error: &'a str, url: &'a str, resumable: bool) -> Failure<'a> {
+        Failure {
+            error,
+            url,
+            resumable,
+        }
+    }
+
+    #[test]
+    fn test_http_status() {
+        let e = "request failed: HTTP status client error (403 Forbidden) for url (https://a.b/c)";
+        assert_eq!(http_status(e), Some(403));
+        assert_eq!(http_status("write failed: disk full"), None);
+    }
+
+    #[test]
+    fn test_expired_links() {
+        let e = "request failed: HTTP status client error (403 Forbidden) for url (x)";
+        let signed = "https://bucket.s3.amazonaws.com/f.zip?X-Amz-Expires=60&X-Amz-Signature=ab";
+        let suggestion = suggest(&failure(e, signed, true)).unwrap();
+        assert!(suggestion.starts_with("Link appears expired"));
+        let plain = suggest(&failure(e, "https://example.com/f.zip", true)).unwrap();
+        assert!(plain.starts_with("The server refused access"));
+    }
+
+    #[test]
+    fn test_resuming() {
+        let e = "request failed: error sending request for url (https://example.com/f.zip)";
+        let suggestion = suggest(&failure(e, "https://example.com/f.zip", false)).unwrap();
+        assert!(suggestion.ends_with("retry will restart from zero"));
+        let suggestion = suggest(&failure(e, "https://example.com/f.zip", true)).unwrap();
+        assert!(suggestion.ends_with("retry carries on where it stopped"));
+
+        let unknown = failure("something else", "https://example.com/f.zip", false);
+        assert_eq!(
+            suggest(&unknown).unwrap(),
+            "The server doesn't allow resuming — retry will restart from zero"
+        );
+        let unknown = failure("something else", "https://example.com/f.zip", true);
+        assert_eq!(suggest(&unknown), None);
+        let changed = failure(
+            "the file changed on the server since the download started",
+            "https://example.com/f.zip",
+            true,
+        );
+        assert!(suggest(&changed).unwrap().ends_with("the new version from the start"));
+    }
+}
@@ -0,0 +1,4099 @@
+//! This module is the download engine. It runs downloads, pauses and resumes them, and reports
+//! what happens as `Event`s instead of talking to a user interface, so that the desktop app, a
+//! command line tool or a server can all drive the same engine. Every frontend subscribes to the
+//! events with `subscribe` and shows them however it likes. The engine starts tasks of its own,
+//! so it has to be used from within a tokio runtime.
+
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    future::Future,
+    path::{Path, PathBuf},
+    pin::Pin,
+    process::Command,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard, OnceLock, PoisonError,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::{broadcast, Semaphore},
+    task::AbortHandle,
+};
+
+use crate::{
+    allocation, bandwidth, chunks, config, connectivity, crash, criteria, dash, data_url,
+    db_writer, decisions, diagnosis, eta, file_manager, file_writer, files, ftp,
+    hashing, health, history, idle, integrity, jobfile, latency, metalink, metrics, mirrors,
+    music, mux, pins, post_processing, power, presets, privacy, progress, push, queue, redirects,
+    retry, s3, scheduler, settings, sftp, simulation, ssh, storage, subtitles, templates, throttle,
+    upgrade, validators, watch_folders, watchdog,
+};
+
+const BROWSER_AGENT: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/131.0.0.0 Safari/537.36";
+
+/// This struct represents a running download. Cancelling it aborts the chunk tasks that are
+/// in flight instead of waiting for their requests to finish.
+#[derive(Default)]
+struct RunningDownload {
+    cancelled: AtomicBool,
+    chunks: Mutex<Vec<AbortHandle>>,
+    /// Limits the speed of the chunk reads of this download.
+    limiter: throttle::RateLimiter,
+    /// The limit from the settings and the template.
+    base_limit: AtomicU64,
+    /// The limit set with `set_speed_limit`, 0 if none.
+    own_limit: AtomicU64,
+    /// When a chunk last started an attempt or wrote, in seconds since the epoch, see `watchdog`.
+    last_activity: AtomicU64,
+}
+
+impl RunningDownload {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        for chunk in self.chunks.lock().unwrap().drain(..) {
+            chunk.abort();
+        }
+    }
+
+    /// This function records that the download is still making progress.
+    fn touch(&self) {
+        self.last_activity.store(unix_now(), Ordering::Relaxed);
+    }
+
+    /// This function changes the limits and applies the lowest of them.
+    fn set_limits(&self, id: i64, base_limit: Option<u64>, own_limit: Option<u64>) {
+        if let Some(limit) = base_limit {
+            self.base_limit.store(limit, Ordering::Relaxed);
+        }
+        if let Some(limit) = own_limit {
+            self.own_limit.store(limit, Ordering::Relaxed);
+        }
+        let rate = throttle::lowest_limit(&[
+            self.base_limit.load(Ordering::Relaxed),
+            self.own_limit.load(Ordering::Relaxed),
+        ]);
+        if rate != self.limiter.rate() {
+            self.limiter.set_rate(rate);
+            health::update(id, |t| t.set_speed_limit(rate));
+        }
+    }
+
+    /// This function keeps the handle of a chunk task so that it can be aborted.
+    fn track(&self, chunk: AbortHandle) {
+        if self.is_cancelled() {
+            chunk.abort();
+            return;
+        }
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks.retain(|c| !c.is_finished());
+        chunks.push(chunk);
+    }
+}
+
+fn active_downloads() -> &'static Mutex<HashMap<i64, Arc<RunningDownload>>> {
+    static MAP: OnceLock<Mutex<HashMap<i64, Arc<RunningDownload>>>> = OnceLock::new();
+    MAP.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// This function returns the ids of the running downloads for a crash report. It is called from
+/// the panic hook, so it gives up instead of waiting when the map is locked.
+pub fn active_download_ids() -> Vec<i64> {
+    match active_downloads().try_lock() {
+        Ok(map) => map.keys().copied().collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadStarted {
+    pub download_id: i64,
+    pub file_url: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub download_status: String,
+}
+
+#[derive(Clone, Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadProgress {
+    pub download_id: i64,
+    pub total_size: u64,
+    pub downloaded: u64,
+    pub timestamp: u64,
+    /// Whole percentage downloaded.
+    pub percent: u8,
+    /// Set to 25, 50, 75 or 100 on the event that reaches that percentage.
+    pub milestone: Option<u8>,
+    /// Bytes per second, smoothed over the last few seconds, see `progress`.
+    pub speed: u64,
+    /// Seconds until the download finishes at the current speed, `None` while the speed or the
+    /// size of the file is unknown.
+    pub eta: Option<u64>,
+    /// A sentence describing the progress for screen readers.
+    pub summary: String,
+}
+
+/// This struct represents how far the checksum of a downloaded file has been computed.
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct VerifyProgress {
+    pub download_id: i64,
+    pub hashed: u64,
+    pub total_size: u64,
+    /// Whole percentage hashed.
+    pub percent: u8,
+}
+
+#[derive(Clone, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct DownloadMessage {
+    pub download_id: i64,
+    pub message: String,
+    pub status: &'static str,
+}
+
+/// This struct summarizes a bulk action. It is returned by the bulk actions and emitted once as
+/// an `Event::Bulk` instead of one event per record.
+#[derive(Clone, Serialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkSummary {
+    pub action: &'static str,
+    pub succeeded: Vec<i64>,
+    pub skipped: Vec<i64>,
+}
+
+impl BulkSummary {
+    /// This function builds the summary of `action` on `ids`, the ids not in `succeeded` are
+    /// reported as skipped.
+    fn new(action: &'static str, ids: &[i64], succeeded: Vec<i64>) -> Self {
+        let skipped = ids
+            .iter()
+            .filter(|id| !succeeded.contains(id))
+            .copied()
+            .collect();
+        BulkSummary {
+            action,
+            succeeded,
+            skipped,
+        }
+    }
+
+    fn emit(self) -> BulkSummary {
+        emit(Event::Bulk(self.clone()));
+        self
+    }
+}
+
+/// This enum represents something the engine reports to its frontends.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Started(DownloadStarted),
+    Progress(DownloadProgress),
+    Message(DownloadMessage),
+    Bulk(BulkSummary),
+    /// Something worth a desktop notification, e.g. a finished download.
+    Notification { title: String, body: String },
+    /// A download waits for the user to decide, see `decisions`.
+    DecisionRequired(decisions::Decision),
+    /// The file of a finished download is being hashed, see `verify_file`.
+    Verifying(VerifyProgress),
+}
+
+/// The number of events kept for a subscriber that falls behind. Older events are dropped, which
+/// only loses progress that a later event carries again.
+const EVENT_BUFFER: usize = 256;
+
+fn events() -> &'static broadcast::Sender<Event> {
+    static EVENTS: OnceLock<broadcast::Sender<Event>> = OnceLock::new();
+    EVENTS.get_or_init(|| broadcast::channel(EVENT_BUFFER).0)
+}
+
+/// This function returns a receiver of every event emitted from now on.
+pub fn subscribe() -> broadcast::Receiver<Event> {
+    events().subscribe()
+}
+
+fn emit(event: Event) {
+    // sending only fails when nobody is subscribed
+    let _ = events().send(event);
+}
+
+/// This function asks the user to decide, see `decisions`, and waits for the answer. The first
+/// choice is taken when no frontend listens.
+async fn decide(
+    download_id: i64,
+    kind: decisions::DecisionKind,
+    question: String,
+    choices: Vec<&'static str>,
+) -> decisions::Answer {
+    let (decision, answer) = decisions::register(download_id, kind, question, choices);
+    let default = decisions::Answer::new(decision.default);
+    if events().receiver_count() == 0 {
+        decisions::withdraw(decision.token);
+        return default;
+    }
+    emit(Event::DecisionRequired(decision));
+    answer.await.unwrap_or(default)
+}
+
+fn message(download_id: i64, message: &str, status: &'static str) {
+    emit(Event::Message(DownloadMessage {
+        download_id,
+        message: message.to_string(),
+        status,
+    }));
+}
+
+/// This function saves how to fix a failed download with its record, see `diagnosis`.
+fn note_failure(record_id: i64, failure: &diagnosis::Failure, cfg: &config::Config) {
+    metrics::download_failed();
+    let note = diagnosis::suggest(failure);
+    if let Err(e) = storage::update_failure_note(record_id, note.as_deref(), cfg) {
+        eprintln!("failed to save the failure note of download {record_id} because {e}");
+    }
+}
+
+fn notify(title: &str, body: String) {
+    emit(Event::Notification {
+        title: title.to_string(),
+        body,
+    });
+}
+
+/// This function opens the file of a download that has just finished, or its folder, if the user
+/// asked for it, see `DownloadRecord::open_when_done`. The choice is read again from the database
+/// since it can be changed while the download runs.
+fn open_finished(record_id: i64, cfg: &config::Config) {
+    let Some(record) = storage::read_records_by_ids(&[record_id], cfg)
+        .ok()
+        .and_then(|mut r| r.pop())
+    else {
+        return;
+    };
+    let Some(action) = record.open_when_done else {
+        return;
+    };
+    let configured = settings::current().file_manager;
+    let path = Path::new(&record.destination_path);
+    if let Err(e) = action.open(path, &cfg.os, configured.as_deref()) {
+        eprintln!("failed to open download {record_id} when done because {e}");
+    }
+}
+
+/// This function sends a notification about a download to the push services of the settings that
+/// want it, see `push`, without waiting for them to answer.
+fn push(event: push::PushEvent, file_name: &str, size: u64, reason: Option<&str>) {
+    let current_settings = settings::current();
+    if !current_settings.push_targets.iter().any(|t| t.wants(event)) {
+        return;
+    }
+    let Ok(client) = settings::shared_client(&current_settings) else {
+        return;
+    };
+    let notification = push::Push {
+        event,
+        file_name: file_name.to_string(),
+        size,
+        reason: reason.map(str::to_string),
+    };
+    let units = settings::units();
+    tokio::spawn(async move {
+        push::send(&client, &current_settings.push_targets, &notification, units).await;
+    });
+}
+
+/// This function locks a mutex shared by the chunk workers of a download, even if a worker
+/// panicked while holding it. The workers only keep clients, pins and counters behind locks,
+/// which stay usable, and the chunk of the worker is downloaded again.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// How often a chunk being downloaded reports its progress.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The number of reads a chunk worker may have waiting for the disk while it downloads on, so that
+/// a slow disk and a slow network do not hold each other up.
+const PREFETCH_DEPTH: usize = 4;
+
+/// This function reads the body of a chunk and queues each read to be written at its offset as it
+/// arrives, so that a chunk is never held in memory. After each read it waits as long as `limiter`
+/// or the global limiter asks so that a limit changed while the chunk is downloading takes effect
+/// right away. Reading stops at the end of the chunk, which moves when the chunk is split.
+///
+/// # Arguments
+/// - `start`: The first byte of the chunk.
+/// - `pending`: The writes not written yet. Once there are more than `PREFETCH_DEPTH` the
+///   oldest is waited for; the rest are left for the caller to wait for.
+/// - `on_write`: Called with the number of bytes after each write is queued.
+///
+/// # Returns
+/// The number of bytes read.
+async fn stream_chunk(
+    mut resp: reqwest::Response,
+    limiter: &throttle::RateLimiter,
+    flight: &chunks::InFlight,
+    writer: &file_writer::FileWriter,
+    start: u64,
+    pending: &mut VecDeque<file_writer::PendingWrite>,
+    mut on_write: impl FnMut(u64),
+) -> Result<u64, String> {
+    let mut written = 0;
+    while let Some(bytes) = resp.chunk().await.map_err(|e| format!("body failed: {e}"))? {
+        let read = flight.receive(bytes.len() as u64);
+        if read > 0 {
+            let bytes = bytes[..read as usize].to_vec();
+            pending.push_back(writer.queue(start + written, bytes).await?);
+            while pending.len() > PREFETCH_DEPTH {
+                if let Some(write) = pending.pop_front() {
+                    write.wait().await?;
+                }
+            }
+            written += read;
+            on_write(read);
+        }
+        if flight.unread() == 0 {
+            break;
+        }
+        let wait = limiter.delay_for(read).max(throttle::global().delay_for(read));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+    Ok(written)
+}
+
+/// This function records the progress of a download and queues it to be emitted. The progress is
+/// dropped if the queue is full, the next one carries the same information.
+fn report_progress(
+    tx: &tokio::sync::mpsc::Sender<DownloadProgress>,
+    download_id: i64,
+    downloaded: u64,
+    total_size: u64,
+) {
+    progress::update(download_id, downloaded);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let _ = tx.try_send(DownloadProgress {
+        download_id,
+        downloaded,
+        total_size,
+        timestamp: now,
+        ..DownloadProgress::default()
+    });
+}
+
+/// How many bytes are read from an FTP data connection at a time.
+const FTP_READ_SIZE: usize = 64 * 1024;
+
+/// How often a download checks whether a worker is idle and a chunk can be split.
+const SPLIT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often a chunk waiting out a break asked for by the server checks whether it was cancelled.
+const COOLDOWN_TICK: Duration = Duration::from_secs(1);
+
+/// This function splits the chunk with the most bytes left so that an idle worker can download the
+/// second half. The split is saved so that a resumed download keeps it.
+///
+/// # Returns
+/// - `Some((u64, u64))`: The range split off, to be downloaded by the idle worker.
+/// - `None`: If no chunk has enough bytes left to be worth splitting.
+fn split_slowest_chunk(
+    record_id: i64,
+    in_flight: &Mutex<HashMap<u64, Arc<chunks::InFlight>>>,
+    cfg: &config::Config,
+) -> Option<(u64, u64)> {
+    let (start, slowest) = lock(in_flight)
+        .iter()
+        .max_by_key(|(_, f)| f.unread())
+        .map(|(s, f)| (*s, Arc::clone(f)))?;
+    slowest.split(|at| match storage::split_chunk(record_id, start, at, cfg) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Failed to split chunk {start} at {at} because {e}");
+            false
+        }
+    })
+}
+
+/// This function checks the file of a download against its criteria and hashes it with
+/// `Settings::checksum_algorithm`, emitting `Event::Verifying` at each percent. It blocks for as
+/// long as the file takes to read.
+fn verify_file(
+    record_id: i64,
+    path: &Path,
+    criteria: &criteria::SuccessCriteria,
+) -> Result<Option<hashing::Checksum>, String> {
+    let algorithm = settings::current().checksum_algorithm;
+    let mut last_percent = None;
+    criteria.check_file(path, algorithm, |hashed, total_size| {
+        let percent = (hashed * 100 / total_size.max(1)) as u8;
+        if last_percent != Some(percent) {
+            last_percent = Some(percent);
+            emit(Event::Verifying(VerifyProgress {
+                download_id: record_id,
+                hashed,
+                total_size,
+                percent,
+            }));
+        }
+    })
+}
+
+/// This function saves the checksum computed by `verify_file`.
+fn save_checksum(record_id: i64, checksum: Option<hashing::Checksum>, cfg: &config::Config) {
+    if let Some(checksum) = checksum {
+        let _ = storage::update_record_checksum(record_id, Some(&checksum.to_string()), cfg);
+    }
+}
+
+/// This function removes the query strings and credentials from the urls of a finished download
+/// when the settings ask for it.
+fn strip_finished_urls(record_id: i64, cfg: &config::Config) {
+    if !settings::current().strip_finished_urls {
+        return;
+    }
+    let Some(record) = storage::read_records_by_ids(&[record_id], cfg)
+        .ok()
+        .and_then(|mut r| r.pop())
+    else {
+        return;
+    };
+    let file_url = privacy::strip_url(&record.file_url);
+    let final_url = record.final_url.as_deref().map(privacy::strip_url);
+    let redirect_chain = privacy::strip_redirect_chain(&record.redirect_chain);
+    if let Err(e) = storage::update_record_urls(
+        record_id,
+        &file_url,
+        final_url.as_deref(),
+        &redirect_chain,
+        cfg,
+    ) {
+        eprintln!("failed to strip the urls of download {record_id} because {e}");
+    }
+}
+
+/// This function starts the post processing steps configured for the file type of a finished
+/// download, see `post_processing`. They run in the background.
+fn post_process(record_id: i64, file: &files::File) {
+    let steps = post_processing::for_file_type(
+        &settings::current().post_processing,
+        &file.file_type.to_string(),
+    );
+    if steps.fetch_subtitles && matches!(file.file_type, files::FileType::Videos) {
+        tokio::spawn(fetch_subtitles(record_id, file.clone(), steps.subtitle_languages));
+    }
+    if steps.import_music && matches!(file.file_type, files::FileType::Audio) {
+        tokio::spawn(import_music(record_id, file.destination_path.clone(), steps.keep_original));
+    }
+}
+
+/// This function files a finished audio download into the music library of the settings, see
+/// `music::import`, and saves where it is now.
+async fn import_music(record_id: i64, path: String, keep_original: bool) {
+    let Some(library) = settings::current().music_library else {
+        return;
+    };
+    let imported = tokio::task::spawn_blocking(move || {
+        music::import(Path::new(&path), Path::new(&library), keep_original)
+    })
+    .await
+    .unwrap_or_else(|e| Err(format!("Failed to import music: {e}")));
+    let target = match imported {
+        Ok(target) => target,
+        Err(e) => {
+            message(record_id, &format!("Not added to the music library: {e}"), "error");
+            return;
+        }
+    };
+    let dir = target.parent().and_then(|d| d.to_str()).unwrap_or_default();
+    let path = target.to_str().unwrap_or_default();
+    let cfg = config::Config::default();
+    if let Err(e) = storage::update_record_path(record_id, dir, path, &cfg) {
+        eprintln!("failed to save the library path of download {record_id} because {e}");
+    }
+    message(record_id, &format!("Added to the music library: {path}"), "success");
+}
+
+/// This function looks for subtitles of a finished video with the providers of the settings and
+/// downloads the ones found next to it, as downloads belonging to the video. The providers are
+/// asked in order until one has a subtitle.
+async fn fetch_subtitles(record_id: i64, video: files::File, languages: Vec<String>) {
+    let current_settings = settings::current();
+    if current_settings.subtitle_providers.is_empty() {
+        return;
+    }
+    let path = video.destination_path.clone();
+    let hash = tokio::task::spawn_blocking(move || subtitles::movie_hash(Path::new(&path)))
+        .await
+        .unwrap_or_else(|e| Err(format!("Failed to hash video: {e}")));
+    let hash = match hash {
+        Ok(hash) => hash,
+        Err(e) => {
+            eprintln!("no subtitles for download {record_id} because {e}");
+            return;
+        }
+    };
+    let Ok(client) = settings::shared_client(&current_settings) else {
+        return;
+    };
+    let query = subtitles::Video {
+        hash,
+        size: fs::metadata(&video.destination_path).map(|m| m.len()).unwrap_or(0),
+        name: video.file_name.clone(),
+    };
+    for provider in &current_settings.subtitle_providers {
+        let matches = match subtitles::search(&client, provider, &query).await {
+            Ok(matches) => matches,
+            Err(e) => {
+                eprintln!("no subtitles for download {record_id} because {e}");
+                continue;
+            }
+        };
+        let picked = subtitles::pick(&matches, &languages);
+        if picked.is_empty() {
+            continue;
+        }
+        for subtitle in picked {
+            let request = DownloadRequest {
+                file_name: Some(subtitles::file_name(
+                    &video.file_name,
+                    subtitle.language.as_deref(),
+                )),
+                destination_dir: Some(video.destination_dir.clone()),
+                parent_id: Some(record_id),
+                ..DownloadRequest::new(&subtitle.url)
+            };
+            if let Err(e) = add(request).await {
+                eprintln!("failed to download subtitle {} because {e}", subtitle.url);
+            }
+        }
+        return;
+    }
+}
+
+/// This struct holds what is needed to download a file whose size is unknown, or a part of a file,
+/// over a single connection.
+struct UnknownSize<'a> {
+    client: reqwest::Client,
+    url: &'a str,
+    request_headers: &'a [(String, String)],
+    max_speed: u64,
+    /// The first and last byte when only a part of the file is downloaded.
+    range: Option<(u64, u64)>,
+    /// The command of the template run once the file is in place.
+    post_command: Option<&'a str>,
+}
+
+/// This function reads the whole body of a file whose size is unknown, or of the part of the file
+/// asked for, and writes it front to back.
+///
+/// # Returns
+/// The size of the file.
+async fn stream_unknown_size(
+    stream: &UnknownSize<'_>,
+    running: &RunningDownload,
+    writer: &file_writer::FileWriter,
+    record_id: i64,
+    file_name: &str,
+) -> Result<u64, String> {
+    let mut request = stream
+        .client
+        .get(stream.url)
+        .header("User-Agent", BROWSER_AGENT);
+    for (name, value) in stream.request_headers {
+        request = request.header(name.as_str(), value);
+    }
+    if let Some((start, end)) = stream.range {
+        request = request.header("Range", format!("bytes={start}-{end}"));
+    }
+    let mut resp = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("request failed: {e}"))?;
+    // the probe may have been answered over another connection
+    pins::check(&settings::current().cert_pins, &resp)?;
+    if stream.range.is_some() && resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err("The server sent the whole file instead of the part asked for".into());
+    }
+    let wanted = stream.range.map(|(start, end)| chunks::range_len(start, end));
+    let mut downloaded = 0;
+    let mut last_report = Instant::now();
+    while let Some(bytes) = resp.chunk().await.map_err(|e| format!("body failed: {e}"))? {
+        if running.is_cancelled() {
+            return Err("Download cancelled".into());
+        }
+        let read = match wanted {
+            Some(wanted) => (bytes.len() as u64).min(wanted - downloaded),
+            None => bytes.len() as u64,
+        };
+        writer.write(downloaded, bytes[..read as usize].to_vec()).await?;
+        running.touch();
+        downloaded += read;
+        progress::update(record_id, downloaded);
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            emit(Event::Progress(DownloadProgress {
+                download_id: record_id,
+                downloaded,
+                timestamp: unix_now() * 1000,
+                summary: progress::summary(file_name, downloaded, 0, 0, None, settings::units()),
+                ..DownloadProgress::default()
+            }));
+        }
+        if wanted == Some(downloaded) {
+            break;
+        }
+        let wait = running
+            .limiter
+            .delay_for(read)
+            .max(throttle::global().delay_for(read));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+    writer.sync().await?;
+    if let Some((start, end)) = stream.range {
+        chunks::check_length(start, end, downloaded)?;
+    }
+    Ok(downloaded)
+}
+
+/// This function downloads a file whose size the server did not announce, e.g. one sent with
+/// chunked transfer encoding. The file is not allocated up front and cannot be split into chunks,
+/// so it is downloaded over a single connection, progress reports the bytes downloaded so far and
+/// the size is saved once the body has ended. Until then a placeholder chunk keeps the record
+/// pending. A resumed download starts over since there is nothing to resume from. A part of a file
+/// is downloaded the same way.
+async fn download_unknown_size(
+    stream: UnknownSize<'_>,
+    record_id: i64,
+    file: &files::File,
+    criteria: &criteria::SuccessCriteria,
+) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let working = files::working_path(record_id, &file.destination_path);
+    fs::create_dir_all(&file.destination_dir)
+        .map_err(|e| format!("Failed to create directory: {e}"))?;
+    let d_file =
+        fs::File::create(&working).map_err(|e| format!("Failed to create file: {e}"))?;
+    let writer = file_writer::FileWriter::open(d_file)?;
+
+    let running = Arc::new(RunningDownload::default());
+    running.touch();
+    active_downloads()
+        .lock()
+        .unwrap()
+        .insert(record_id, Arc::clone(&running));
+    running.set_limits(record_id, Some(stream.max_speed), None);
+
+    // chunk updates of an earlier attempt may still be queued
+    db_writer::flush().await;
+    let _ = storage::replace_chunks(record_id, &[(0, 0)], &cfg);
+    progress::start(record_id, &file.file_name, 0, 0);
+
+    let result =
+        stream_unknown_size(&stream, &running, &writer, record_id, &file.file_name).await;
+
+    active_downloads().lock().unwrap().remove(&record_id);
+    progress::finish(record_id);
+    if running.is_cancelled() {
+        return Ok(());
+    }
+
+    drop(writer);
+    // the file is created again when the download starts
+    let completion = Completion {
+        url: stream.url,
+        resumable: false,
+        post_command: stream.post_command,
+    };
+    complete_stream(record_id, file, criteria, result, completion).await;
+    Ok(())
+}
+
+/// This struct describes where a download that is not read over HTTP chunks came from and what
+/// follows it, see `complete_stream`.
+struct Completion<'a> {
+    url: &'a str,
+    /// Whether the server can carry on from where the download stopped.
+    resumable: bool,
+    /// The command of the template run once the file is in place.
+    post_command: Option<&'a str>,
+}
+
+/// This function runs the command of the template of a finished download with the path of its
+/// file.
+fn run_post_command(command: Option<&str>, path: &str) {
+    if let Some(command) = command {
+        if let Err(e) = Command::new(command).arg(path).spawn() {
+            eprintln!("failed to run post download command {command} because {e}");
+        }
+    }
+}
+
+/// This function ends a download read over a single connection: once its file was checked
+/// against `criteria` and moved into place, the download is finished, otherwise it failed.
+///
+/// # Arguments
+/// - `result`: The size of the file, or why reading it failed.
+/// - `url`: The url the file was read from.
+/// - `resumable`: Whether the download can carry on from the bytes it has.
+async fn complete_stream(
+    record_id: i64,
+    file: &files::File,
+    criteria: &criteria::SuccessCriteria,
+    result: Result<u64, String>,
+    completion: Completion<'_>,
+) {
+    let cfg = config::Config::default();
+    let working = files::working_path(record_id, &file.destination_path);
+    let destination = PathBuf::from(&file.destination_path);
+    let criteria = criteria.clone();
+    let verified = match result {
+        Ok(0) => Err("File has zero size".to_string()),
+        Ok(size) => tokio::task::spawn_blocking(move || {
+            criteria.check_size(size)?;
+            let checksum = verify_file(record_id, &working, &criteria)?;
+            files::move_into_place(&working, &destination).map(|()| (size, checksum))
+        })
+        .await
+        .unwrap_or_else(|e| Err(format!("Failed to verify the file: {e}"))),
+        Err(e) => Err(e),
+    };
+
+    match verified {
+        Ok((size, checksum)) => {
+            save_checksum(record_id, checksum, &cfg);
+            let _ = storage::replace_chunks(record_id, &[(0, size - 1)], &cfg);
+            let _ = storage::update_chunk(record_id, 0, "Finished", &cfg);
+            let _ = storage::update_download_record(
+                record_id,
+                "Finished",
+                Some(unix_now()),
+                size,
+                &cfg,
+            );
+            strip_finished_urls(record_id, &cfg);
+            metrics::download_finished();
+            archive_history(&cfg);
+            post_process(record_id, file);
+            open_finished(record_id, &cfg);
+            message(record_id, "Download completed successfully", "success");
+            notify(
+                "YAD — Download complete",
+                format!("{} downloaded successfully", file.file_name),
+            );
+            push(push::PushEvent::Finished, &file.file_name, size, None);
+            run_post_command(completion.post_command, &file.destination_path);
+        }
+        Err(e) => {
+            eprintln!("Download {record_id} {e}");
+            let _ = storage::update_chunk(record_id, 0, "Failed", &cfg);
+            let _ = storage::update_download_record(record_id, "Failed", None, 0, &cfg);
+            let failure = diagnosis::Failure {
+                error: &e,
+                url: completion.url,
+                resumable: completion.resumable,
+            };
+            note_failure(record_id, &failure, &cfg);
+            message(record_id, &e, "error");
+            notify("YAD — Download failed", format!("{} — {e}", file.file_name));
+            push(push::PushEvent::Failed, &file.file_name, 0, Some(&e));
+        }
+    }
+}
+
+/// This function reads a file from an FTP server from byte `offset` on, or from the start when
+/// the server cannot resume, and writes it front to back, see `ftp`.
+///
+/// # Returns
+/// The size of the file.
+async fn stream_ftp(
+    location: &ftp::Location,
+    offset: u64,
+    total_size: u64,
+    running: &RunningDownload,
+    writer: &file_writer::FileWriter,
+    record_id: i64,
+) -> Result<u64, String> {
+    let mut session = ftp::Session::connect(location, &settings::current()).await?;
+    let (mut data, start) = session.retrieve(&location.path, offset).await?;
+    let mut buffer = vec![0; FTP_READ_SIZE];
+    let mut downloaded = start;
+    let mut announced = start;
+    let mut last_report = Instant::now();
+    loop {
+        let read = data
+            .read(&mut buffer)
+            .await
+            .map_err(|e| format!("body failed: {e}"))?;
+        if read == 0 {
+            break;
+        }
+        if running.is_cancelled() {
+            return Err("Download cancelled".into());
+        }
+        writer.write(downloaded, buffer[..read].to_vec()).await?;
+        running.touch();
+        downloaded += read as u64;
+        progress::update(record_id, downloaded);
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            let event = progress::event(record_id, downloaded, total_size, announced);
+            emit(Event::Progress(event));
+            announced = downloaded;
+        }
+        let read = read as u64;
+        let wait = running
+            .limiter
+            .delay_for(read)
+            .max(throttle::global().delay_for(read));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+    // a TLS data connection is closed cleanly, some servers count the transfer as failed otherwise
+    let _ = data.shutdown().await;
+    drop(data);
+    session.finish().await?;
+    session.quit().await;
+    writer.sync().await?;
+    Ok(downloaded)
+}
+
+/// This function downloads a file from an FTP server over a single connection. A resumed download
+/// carries on from the end of the file of the earlier attempt, which the server is asked to skip
+/// with `REST`; when the size of the file is unknown it starts over.
+async fn download_ftp(
+    url: &str,
+    location: &ftp::Location,
+    total_size: Option<u64>,
+    max_speed: u64,
+    single: &SingleDownload,
+) -> Result<(), String> {
+    let SingleDownload {
+        record_id,
+        file,
+        criteria,
+        ..
+    } = single;
+    let record_id = *record_id;
+    let working = files::working_path(record_id, &file.destination_path);
+    fs::create_dir_all(&file.destination_dir)
+        .map_err(|e| format!("Failed to create directory: {e}"))?;
+    let d_file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&working)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let have = d_file.metadata().map(|m| m.len()).unwrap_or(0);
+    let offset = match total_size {
+        Some(size) if have < size => have,
+        _ => 0,
+    };
+    d_file
+        .set_len(offset)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let writer = file_writer::FileWriter::open(d_file)?;
+
+    let running = Arc::new(RunningDownload::default());
+    running.touch();
+    active_downloads()
+        .lock()
+        .unwrap()
+        .insert(record_id, Arc::clone(&running));
+    running.set_limits(record_id, Some(max_speed), None);
+
+    // chunk updates of an earlier attempt may still be queued
+    db_writer::flush().await;
+    let total = total_size.unwrap_or(0);
+    progress::start(record_id, &file.file_name, total, offset);
+
+    let result = stream_ftp(location, offset, total, &running, &writer, record_id).await;
+
+    active_downloads().lock().unwrap().remove(&record_id);
+    progress::finish(record_id);
+    if running.is_cancelled() {
+        return Ok(());
+    }
+
+    drop(writer);
+    let result = result.and_then(|size| match total_size {
+        Some(total) if size != total => Err(format!("The FTP server sent {size} of {total} bytes")),
+        _ => Ok(size),
+    });
+    complete_stream(record_id, file, criteria, result, single.completion(url, true)).await;
+    Ok(())
+}
+
+/// This struct represents the bytes of a file a download of SFTP reads.
+#[derive(Clone, Copy)]
+struct Part {
+    /// The first byte.
+    start: u64,
+    /// The number of bytes, `None` if the server cannot tell.
+    size: Option<u64>,
+}
+
+/// This function reads a file from an SSH server and writes it in order, see `stream_ftp`.
+async fn stream_sftp(
+    location: &sftp::Location,
+    part: Part,
+    offset: u64,
+    running: &RunningDownload,
+    writer: &file_writer::FileWriter,
+    record_id: i64,
+) -> Result<u64, String> {
+    let cfg = config::Config::default();
+    let mut session = sftp::Session::connect(location, &settings::current(), &cfg).await?;
+    let end = part.size.map_or(u64::MAX, |size| part.start + size);
+    let mut transfer = session
+        .read(&location.path, part.start + offset, end)
+        .await?;
+    let total_size = part.size.unwrap_or(0);
+    let mut downloaded = offset;
+    let mut announced = offset;
+    let mut last_report = Instant::now();
+    while let Some((position, data)) = session.next(&mut transfer).await? {
+        if running.is_cancelled() {
+            return Err("Download cancelled".into());
+        }
+        let read = data.len() as u64;
+        writer.write(position - part.start, data).await?;
+        downloaded += read;
+        running.touch();
+        progress::update(record_id, downloaded);
+        if last_report.elapsed() >= PROGRESS_INTERVAL {
+            last_report = Instant::now();
+            let event = progress::event(record_id, downloaded, total_size, announced);
+            emit(Event::Progress(event));
+            announced = downloaded;
+        }
+        let wait = running
+            .limiter
+            .delay_for(read)
+            .max(throttle::global().delay_for(read));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+    session.close(transfer).await?;
+    session.quit().await;
+    writer.sync().await?;
+    Ok(downloaded)
+}
+
+/// This function downloads a file, or a part of it, from an SSH server over a single connection.
+/// A resumed download carries on from the end of the file of the earlier attempt; when the size
+/// of the file is unknown it starts over.
+async fn download_sftp(
+    url: &str,
+    location: &sftp::Location,
+    part: Part,
+    max_speed: u64,
+    single: &SingleDownload,
+) -> Result<(), String> {
+    let SingleDownload {
+        record_id,
+        file,
+        criteria,
+        ..
+    } = single;
+    let record_id = *record_id;
+    let working = files::working_path(record_id, &file.destination_path);
+    fs::create_dir_all(&file.destination_dir)
+        .map_err(|e| format!("Failed to create directory: {e}"))?;
+    let d_file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&working)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let have = d_file.metadata().map(|m| m.len()).unwrap_or(0);
+    let offset = match part.size {
+        Some(size) if have < size => have,
+        _ => 0,
+    };
+    d_file
+        .set_len(offset)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let writer = file_writer::FileWriter::open(d_file)?;
+
+    let running = Arc::new(RunningDownload::default());
+    running.touch();
+    active_downloads()
+        .lock()
+        .unwrap()
+        .insert(record_id, Arc::clone(&running));
+    running.set_limits(record_id, Some(max_speed), None);
+
+    // chunk updates of an earlier attempt may still be queued
+    db_writer::flush().await;
+    progress::start(record_id, &file.file_name, part.size.unwrap_or(0), offset);
+
+    let result = stream_sftp(location, part, offset, &running, &writer, record_id).await;
+
+    active_downloads().lock().unwrap().remove(&record_id);
+    progress::finish(record_id);
+    if running.is_cancelled() {
+        return Ok(());
+    }
+
+    drop(writer);
+    let result = result.and_then(|size| match part.size {
+        Some(total) if size != total => {
+            Err(format!("The SFTP server sent {size} of {total} bytes"))
+        }
+        _ => Ok(size),
+    });
+    complete_stream(record_id, file, criteria, result, single.completion(url, true)).await;
+    Ok(())
+}
+
+/// This struct represents the tracks of a DASH stream to download, see `add_dash`.
+struct DashStream {
+    client: reqwest::Client,
+    request_headers: Vec<(String, String)>,
+    cert_pins: Vec<pins::CertPin>,
+    /// The video first, then the audio; a stream may lack either.
+    tracks: Vec<dash::Representation>,
+}
+
+/// This struct represents what the tracks of a DASH stream being downloaded share.
+struct DashProgress {
+    record_id: i64,
+    running: Arc<RunningDownload>,
+    downloaded: AtomicU64,
+    /// The size of the stream estimated from the bandwidth of its tracks, 0 if unknown.
+    total_size: u64,
+    /// When progress was last reported and the bytes downloaded then.
+    last_report: Mutex<(Instant, u64)>,
+    /// Whether a track failed, which stops the other one.
+    failed: AtomicBool,
+}
+
+impl DashProgress {
+    /// This function counts `read` more bytes and reports the progress of the stream.
+    fn add(&self, read: u64) {
+        self.running.touch();
+        let downloaded = self.downloaded.fetch_add(read, Ordering::Relaxed) + read;
+        progress::update(self.record_id, downloaded);
+        let mut last_report = lock(&self.last_report);
+        let (reported, announced) = *last_report;
+        if reported.elapsed() < PROGRESS_INTERVAL {
+            return;
+        }
+        *last_report = (Instant::now(), downloaded);
+        let event = progress::event(self.record_id, downloaded, self.total_size, announced);
+        emit(Event::Progress(event));
+    }
+}
+
+/// This function reads a segment of a DASH stream and writes it at `offset` of the file of its
+/// track.
+///
+/// # Arguments
+/// - `received`: The bytes of the segment read, counted also when reading fails.
+async fn stream_segment(
+    stream: &DashStream,
+    segment: &dash::Segment,
+    offset: u64,
+    writer: &file_writer::FileWriter,
+    shared: &DashProgress,
+    received: &mut u64,
+) -> Result<(), String> {
+    let mut request = stream
+        .client
+        .get(&segment.url)
+        .header("User-Agent", BROWSER_AGENT);
+    for (name, value) in &stream.request_headers {
+        request = request.header(name.as_str(), value);
+    }
+    if let Some((start, end)) = segment.range {
+        request = request.header("Range", format!("bytes={start}-{end}"));
+    }
+    let mut resp = request
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("request failed: {e}"))?;
+    pins::check(&stream.cert_pins, &resp)?;
+    if segment.range.is_some() && resp.status() != reqwest::StatusCode::PARTIAL_CONTENT {
+        return Err("The server sent the whole file instead of the part asked for".into());
+    }
+    while let Some(bytes) = resp.chunk().await.map_err(|e| format!("body failed: {e}"))? {
+        if shared.running.is_cancelled() || shared.failed.load(Ordering::Relaxed) {
+            return Err("Download cancelled".into());
+        }
+        let read = bytes.len() as u64;
+        writer.write(offset + *received, bytes.to_vec()).await?;
+        *received += read;
+        shared.add(read);
+        let wait = shared
+            .running
+            .limiter
+            .delay_for(read)
+            .max(throttle::global().delay_for(read));
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+    match segment.range {
+        Some((start, end)) => chunks::check_length(start, end, *received).map(|_| ()),
+        None => Ok(()),
+    }
+}
+
+/// This function downloads the segments of a track of a DASH stream one after the other into
+/// `path`, after the ones an earlier attempt wrote, see `dash::read_progress`. A segment that fails
+/// is downloaded again, as often as a chunk would be.
+///
+/// # Arguments
+/// - `ends`: The size of the track after each segment the earlier attempt wrote.
+async fn stream_track(
+    stream: &DashStream,
+    track: &dash::Representation,
+    path: &Path,
+    ends: &[u64],
+    shared: &DashProgress,
+) -> Result<(), String> {
+    let d_file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let mut written = ends.last().copied().unwrap_or(0);
+    // the bytes of a segment that was not recorded are downloaded again
+    d_file
+        .set_len(written)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    dash::write_progress(path, track, ends).map_err(|e| format!("Failed to write file: {e}"))?;
+    let handle = d_file
+        .try_clone()
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let writer = file_writer::FileWriter::open(d_file)?;
+    let current_settings = settings::current();
+    for segment in &track.segments[ends.len()..] {
+        let mut attempt = 0;
+        loop {
+            let mut received = 0;
+            match stream_segment(stream, segment, written, &writer, shared, &mut received).await {
+                Ok(()) => {
+                    written += received;
+                    dash::record_segment(path, written)
+                        .map_err(|e| format!("Failed to write file: {e}"))?;
+                    break;
+                }
+                Err(e) => {
+                    shared.downloaded.fetch_sub(received, Ordering::Relaxed);
+                    let stopped =
+                        shared.running.is_cancelled() || shared.failed.load(Ordering::Relaxed);
+                    if stopped || attempt >= current_settings.chunk_retries {
+                        return Err(e);
+                    }
+                    attempt += 1;
+                    tokio::time::sleep(retry::backoff(
+                        attempt,
+                        current_settings.retry_backoff_ms,
+                        current_settings.retry_max_delay_ms,
+                    ))
+                    .await;
+                }
+            }
+        }
+    }
+    writer.sync().await?;
+    drop(writer);
+    // a segment downloaded again may have been shorter than the attempt before
+    handle
+        .set_len(written)
+        .map_err(|e| format!("Failed to write file: {e}"))
+}
+
+/// This function downloads the tracks of a DASH stream side by side, each into a file of its own
+/// next to the working file, and muxes them into the working file once both are complete, see
+/// `mux`. The tracks are kept until then, so a resumed download carries on after the last segment
+/// of each.
+async fn download_dash(
+    stream: DashStream,
+    url: &str,
+    estimated_size: Option<u64>,
+    max_speed: u64,
+    single: &SingleDownload,
+) -> Result<(), String> {
+    let SingleDownload {
+        record_id,
+        file,
+        criteria,
+        ..
+    } = single;
+    let record_id = *record_id;
+    let cfg = config::Config::default();
+    let working = files::working_path(record_id, &file.destination_path);
+    fs::create_dir_all(&file.destination_dir)
+        .map_err(|e| format!("Failed to create directory: {e}"))?;
+
+    let running = Arc::new(RunningDownload::default());
+    running.touch();
+    active_downloads()
+        .lock()
+        .unwrap()
+        .insert(record_id, Arc::clone(&running));
+    running.set_limits(record_id, Some(max_speed), None);
+
+    // chunk updates of an earlier attempt may still be queued
+    db_writer::flush().await;
+    let _ = storage::replace_chunks(record_id, &[(0, 0)], &cfg);
+    let paths: Vec<PathBuf> = stream
+        .tracks
+        .iter()
+        .map(|track| {
+            let kind = match track.kind {
+                dash::Kind::Video => "video",
+                dash::Kind::Audio => "audio",
+            };
+            let mut path = working.clone().into_os_string();
+            path.push(format!(".{kind}"));
+            PathBuf::from(path)
+        })
+        .collect();
+    let resumed: Vec<Vec<u64>> = paths
+        .iter()
+        .zip(&stream.tracks)
+        .map(|(path, track)| dash::read_progress(path, track))
+        .collect();
+    let downloaded: u64 = resumed.iter().filter_map(|ends| ends.last()).sum();
+    let total_size = estimated_size.unwrap_or(0);
+    progress::start(record_id, &file.file_name, total_size, downloaded);
+
+    let shared = Arc::new(DashProgress {
+        record_id,
+        running: Arc::clone(&running),
+        downloaded: AtomicU64::new(downloaded),
+        total_size,
+        last_report: Mutex::new((Instant::now(), downloaded)),
+        failed: AtomicBool::new(false),
+    });
+    let stream = Arc::new(stream);
+    let mut downloads = Vec::new();
+    for (index, ends) in resumed.into_iter().enumerate() {
+        let path = paths[index].clone();
+        let (stream, shared) = (Arc::clone(&stream), Arc::clone(&shared));
+        downloads.push(tokio::spawn(async move {
+            let track = &stream.tracks[index];
+            let result = stream_track(&stream, track, &path, &ends, &shared).await;
+            if result.is_err() {
+                shared.failed.store(true, Ordering::Relaxed);
+            }
+            result
+        }));
+    }
+    let mut result = Ok(());
+    for download in downloads {
+        let outcome = download.await.unwrap_or_else(|e| Err(e.to_string()));
+        if result.is_ok() {
+            result = outcome;
+        }
+    }
+
+    active_downloads().lock().unwrap().remove(&record_id);
+    progress::finish(record_id);
+    if running.is_cancelled() {
+        return Ok(());
+    }
+
+    let result = match result {
+        Ok(()) => {
+            let tracks = paths.clone();
+            tokio::task::spawn_blocking(move || {
+                match tracks.as_slice() {
+                    [video, audio] => mux::mux(video, audio, &working)?,
+                    [track] => fs::rename(track, &working)
+                        .map_err(|e| format!("Failed to write file: {e}"))?,
+                    _ => return Err("The stream has no tracks".to_string()),
+                }
+                fs::metadata(&working)
+                    .map(|m| m.len())
+                    .map_err(|e| format!("Failed to read file: {e}"))
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Failed to mux the stream: {e}")))
+        }
+        Err(e) => Err(e),
+    };
+    if result.is_ok() {
+        for path in &paths {
+            let _ = fs::remove_file(path);
+            let _ = fs::remove_file(dash::progress_path(path));
+        }
+    }
+    complete_stream(record_id, file, criteria, result, single.completion(url, true)).await;
+    Ok(())
+}
+
+/// This struct represents a download of FTP, SFTP, DASH or a `data:` url, ready to start.
+struct SingleDownload {
+    record_id: i64,
+    file: files::File,
+    criteria: criteria::SuccessCriteria,
+    /// The command of the template run once the file is in place.
+    post_command: Option<String>,
+    /// The slot is held until the download ends, which starts the next queued download.
+    _slot: queue::Slot,
+}
+
+impl SingleDownload {
+    fn completion<'a>(&'a self, url: &'a str, resumable: bool) -> Completion<'a> {
+        Completion {
+            url,
+            resumable,
+            post_command: self.post_command.as_deref(),
+        }
+    }
+}
+
+/// This function checks a download that is not read over HTTP chunks, e.g. of FTP, against what
+/// was asked for, adds its record or finds the one of an earlier attempt, and waits for its turn
+/// in the queue.
+///
+/// # Arguments
+/// - `request`: The download.
+/// - `size`: The size of the file, or of its part.
+/// - `partial`: The first and last byte, if only a part of the file is downloaded.
+/// - `template`: The template of the download.
+///
+/// # Returns
+/// `None` if the download was skipped or stopped while it waited.
+async fn prepare_single(
+    request: DownloadRequest,
+    size: Option<u64>,
+    partial: Option<(u64, u64)>,
+    template: Option<&templates::Template>,
+) -> Result<Option<SingleDownload>, String> {
+    let DownloadRequest {
+        url,
+        file_name,
+        destination_dir,
+        criteria,
+        template_id,
+        parent_id,
+        replace_finished,
+        open_when_done,
+        video_height,
+        record_id,
+        ..
+    } = request;
+    let cfg = config::Config::default();
+    let current_settings = settings::current();
+    if size == Some(0) {
+        return Err("File has zero size".into());
+    }
+    let criteria = criteria.unwrap_or_default();
+    if let Err(e) = criteria.check(size, None) {
+        message(0, &e, "error");
+        return Err(e);
+    }
+
+    // a DASH stream is saved as the MP4 file it is muxed into, a data: url after its media type
+    let mut file = if dash::is_manifest_url(&url) {
+        files::File::named(&url, &dash::file_name(&url), &cfg)
+    } else if data_url::is_data_url(&url) {
+        files::File::named(&url, &data_url::file_name(&url), &cfg)
+    } else {
+        files::File::new(&url, None, &cfg)
+    };
+    file.apply_category_rules(&current_settings.category_rules, &cfg);
+    let original_file_name = name_file(
+        &mut file,
+        file_name.as_deref(),
+        destination_dir,
+        partial,
+        template,
+        current_settings.transliterate_file_names,
+    );
+
+    let byte_range = partial.map(|(start, end)| chunks::ByteRange {
+        start,
+        end: Some(end),
+    });
+    let Some(mut record) = find_record(&url, record_id, byte_range, replace_finished, &cfg).await? else {
+        return Ok(None);
+    };
+    if record.id == 0 {
+        if !free_destination(&mut file, &cfg).await {
+            return Ok(None);
+        }
+        let mut dr = storage::DownloadRecord::from(file.clone());
+        dr.parent_id = parent_id;
+        dr.byte_range = byte_range;
+        dr.original_file_name = original_file_name;
+        dr.open_when_done = open_when_done;
+        dr.video_height = video_height;
+        dr.template_id = template_id;
+        dr.criteria = Some(criteria.clone()).filter(|c| *c != criteria::SuccessCriteria::default());
+        let whole_file = (0, size.map_or(0, |s| s - 1));
+        record.id =
+            storage::insert_record_with_chunks(&dr, size.unwrap_or(0), &[whole_file], &cfg)
+                .map_err(|e| format!("Failed to save download record: {e}"))?;
+    } else {
+        if open_when_done.is_some() {
+            let _ = storage::update_open_when_done(record.id, open_when_done, &cfg);
+        }
+        // carry on writing into the file of the earlier attempt
+        file.file_name = record.file_name.clone();
+        file.destination_dir = record.destination_dir.clone();
+        file.destination_path = record.destination_path.clone();
+    }
+
+    fs::create_dir_all(&file.destination_dir)
+        .map_err(|e| format!("Failed to create directory: {e}"))?;
+    crash::set_download(record.id);
+    let Some(slot) = enter_queue(&record, &file, &cfg).await else {
+        return Ok(None);
+    };
+    Ok(Some(SingleDownload {
+        record_id: record.id,
+        file,
+        criteria,
+        post_command: templates::post_command(template).map(str::to_string),
+        _slot: slot,
+    }))
+}
+
+/// This function reads the template of a download.
+fn read_template(template_id: Option<i64>) -> Result<Option<templates::Template>, String> {
+    let cfg = config::Config::default();
+    template_id
+        .map(|id| storage::read_template(id, &cfg))
+        .transpose()
+        .map_err(|e| format!("Failed to read template: {e}"))
+}
+
+/// This function asks the user for the user name and password a server asks for.
+///
+/// # Returns
+/// What the user typed in as `user:password`, `None` if they cancelled.
+async fn ask_credentials(host: &str) -> Option<String> {
+    let question = format!("{host} asks for a user name and password");
+    let kind = decisions::DecisionKind::Credentials;
+    let answer = decide(0, kind, question, vec!["cancel", "sign_in"]).await;
+    answer.input.filter(|_| answer.choice == "sign_in")
+}
+
+/// This function adds a download from an FTP server, see `ftp`. The server is asked for a user
+/// name and password when it refuses the ones of the url. Parts of files and mirrors are only
+/// downloaded over HTTP.
+async fn add_ftp(request: DownloadRequest) -> Result<(), String> {
+    if request.byte_range.is_some() || !request.mirrors.is_empty() {
+        let e = "Parts of files and mirrors cannot be downloaded over FTP".to_string();
+        message(0, &e, "error");
+        return Err(e);
+    }
+    let url = request.url.clone();
+    let mut location = ftp::Location::parse(&url).inspect_err(|e| message(0, e, "error"))?;
+    let current_settings = settings::current();
+    let template = read_template(request.template_id)?;
+
+    let announced_size = loop {
+        let probed = match ftp::Session::connect(&location, &current_settings).await {
+            Ok(mut session) => {
+                let size = session.size(&location.path).await;
+                session.quit().await;
+                size
+            }
+            Err(e) => Err(e),
+        };
+        match probed {
+            Ok(size) => break size,
+            Err(e) if ftp::is_login_refused(&e) => {
+                let typed = ask_credentials(&location.host)
+                    .await
+                    .ok_or(e)
+                    .and_then(|c| location.clone().with_credentials(&c));
+                match typed {
+                    Ok(typed) => location = typed,
+                    Err(e) => {
+                        message(0, &e, "error");
+                        return Err(e);
+                    }
+                }
+            }
+            Err(e) => {
+                message(0, &e, "error");
+                return Err(e);
+            }
+        }
+    };
+
+    let Some(single) = prepare_single(request, announced_size, None, template.as_ref()).await?
+    else {
+        return Ok(());
+    };
+    let max_speed = templates::max_speed(template.as_ref(), &current_settings);
+    download_ftp(&url, &location, announced_size, max_speed, &single).await
+}
+
+/// This function adds a download from an SSH server, see `sftp`. The user is asked whether a
+/// server that is not known yet is trusted, and for a user name and password when the server
+/// refuses the ones of the url and the private key. Mirrors are only downloaded over HTTP.
+async fn add_sftp(request: DownloadRequest) -> Result<(), String> {
+    if !request.mirrors.is_empty() {
+        let e = "Mirrors cannot be downloaded over SFTP".to_string();
+        message(0, &e, "error");
+        return Err(e);
+    }
+    let url = request.url.clone();
+    let mut location = sftp::Location::parse(&url).inspect_err(|e| message(0, e, "error"))?;
+    let cfg = config::Config::default();
+    let current_settings = settings::current();
+    let template = read_template(request.template_id)?;
+
+    let size = loop {
+        let probed = match sftp::Session::connect(&location, &current_settings, &cfg).await {
+            Ok(mut session) => {
+                let size = session.size(&location.path).await;
+                session.quit().await;
+                size
+            }
+            Err(e) => Err(e),
+        };
+        let e = match probed {
+            Ok(size) => break size,
+            Err(e) => e,
+        };
+        if let Some(fingerprint) = ssh::unknown_host_key(&e) {
+            let question = format!(
+                "{} is not known yet, its host key has the fingerprint {fingerprint}. Only trust \
+                 it if the owner of the server confirms the fingerprint",
+                location.host
+            );
+            let kind = decisions::DecisionKind::HostKey;
+            if decide(0, kind, question, vec!["cancel", "trust"]).await.choice == "trust" {
+                location.accept_host_key = Some(fingerprint.to_string());
+                continue;
+            }
+        } else if ssh::is_login_refused(&e) {
+            if let Some(credentials) = ask_credentials(&location.host).await {
+                location = location.with_credentials(&credentials);
+                continue;
+            }
+        }
+        message(0, &e, "error");
+        return Err(e);
+    };
+
+    let partial = match &request.byte_range {
+        Some(range) => match range.resolve(size) {
+            Ok(partial) => Some(partial),
+            Err(e) => {
+                message(0, &e, "error");
+                return Err(e);
+            }
+        },
+        None => None,
+    };
+    let part_size = match partial {
+        Some((start, end)) => Some(chunks::range_len(start, end)),
+        None => size,
+    };
+    let Some(single) = prepare_single(request, part_size, partial, template.as_ref()).await?
+    else {
+        return Ok(());
+    };
+    let part = Part {
+        start: partial.map_or(0, |(start, _)| start),
+        size: part_size,
+    };
+    let max_speed = templates::max_speed(template.as_ref(), &current_settings);
+    download_sftp(&url, &location, part, max_speed, &single).await
+}
+
+/// This function adds the download of a DASH stream, see `dash`. The video of the quality asked
+/// for and the best audio are downloaded and muxed into a single MP4 file. Parts of streams and
+/// mirrors are not supported.
+async fn add_dash(request: DownloadRequest) -> Result<(), String> {
+    if request.byte_range.is_some() || !request.mirrors.is_empty() {
+        let e = "Parts of streams and mirrors cannot be downloaded from DASH manifests".to_string();
+        message(0, &e, "error");
+        return Err(e);
+    }
+    let url = request.url.clone();
+    let mut current_settings = settings::current();
+    if let Some(bind_to) = request.bind_to.clone().filter(|b| !b.trim().is_empty()) {
+        current_settings.bind_to = Some(bind_to);
+    }
+    let client = settings::shared_client(&current_settings)?;
+    let template = read_template(request.template_id)?;
+    let (request_headers, _) = download_headers(
+        &url,
+        request.referer.as_deref(),
+        template.as_ref(),
+        &current_settings,
+    );
+
+    let fetched = async {
+        let mut get = client.get(&url).header("User-Agent", BROWSER_AGENT);
+        for (name, value) in &request_headers {
+            get = get.header(name.as_str(), value);
+        }
+        let resp = get
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        pins::check(&current_settings.cert_pins, &resp)?;
+        // relative urls of the manifest are resolved against where it was redirected to
+        let final_url = resp.url().to_string();
+        let content = resp.text().await.map_err(|e| e.to_string())?;
+        Ok::<_, String>((content, final_url))
+    }
+    .await
+    .map_err(|e| format!("Failed to download the DASH manifest: {e}"));
+    let manifest = fetched
+        .and_then(|(content, final_url)| dash::parse(&content, &final_url))
+        .inspect_err(|e| message(0, e, "error"))?;
+    let (video, audio) = manifest.pick(request.video_height);
+    let picked: Vec<&dash::Representation> = video.into_iter().chain(audio).collect();
+    let estimated_size = manifest.estimated_size(&picked);
+    let stream = DashStream {
+        client,
+        request_headers,
+        cert_pins: current_settings.cert_pins.clone(),
+        tracks: picked.into_iter().cloned().collect(),
+    };
+
+    let Some(single) = prepare_single(request, None, None, template.as_ref()).await? else {
+        return Ok(());
+    };
+    let max_speed = templates::max_speed(template.as_ref(), &current_settings);
+    download_dash(stream, &url, estimated_size, max_speed, &single).await
+}
+
+/// This function saves the content of a `data:` url to a file, see `data_url`. It is recorded
+/// like any other download, which finishes as soon as it starts, under the short url of
+/// `data_url::stored_url`.
+async fn add_data_url(request: DownloadRequest) -> Result<(), String> {
+    if request.byte_range.is_some() || !request.mirrors.is_empty() {
+        let e = "Parts of files and mirrors cannot be saved from data: urls".to_string();
+        message(0, &e, "error");
+        return Err(e);
+    }
+    let data = data_url::parse(&request.url).inspect_err(|e| message(0, e, "error"))?;
+    let url = data_url::stored_url(&data);
+    let request = DownloadRequest {
+        url: url.clone(),
+        ..request
+    };
+    let template = read_template(request.template_id)?;
+    let size = data.content.len() as u64;
+    let Some(single) = prepare_single(request, Some(size), None, template.as_ref()).await? else {
+        return Ok(());
+    };
+    let working = files::working_path(single.record_id, &single.file.destination_path);
+    let written = fs::create_dir_all(&single.file.destination_dir)
+        .and_then(|()| fs::write(&working, &data.content))
+        .map(|()| size)
+        .map_err(|e| format!("Failed to write file: {e}"));
+    complete_stream(
+        single.record_id,
+        &single.file,
+        &single.criteria,
+        written,
+        single.completion(&url, false),
+    )
+    .await;
+    Ok(())
+}
+
+/// This function picks the headers sent for a download: the referer given for it, or else the
+/// ones of the host preset of its url, and the ones of its template.
+///
+/// # Returns
+/// The headers, and the name of the preset applied.
+fn download_headers(
+    url: &str,
+    referer: Option<&str>,
+    template: Option<&templates::Template>,
+    current_settings: &settings::Settings,
+) -> (Vec<(String, String)>, Option<String>) {
+    // a referer given for this download wins over the presets
+    let (mut request_headers, applied_preset) = match referer.map(str::trim) {
+        Some(r) if !r.is_empty() => (
+            vec![("Referer".to_string(), r.to_string())],
+            Some("Custom".to_string()),
+        ),
+        _ => match presets::find(&current_settings.host_presets, url) {
+            Some(p) => (p.headers(), Some(p.name.clone())),
+            None => (Vec::new(), None),
+        },
+    };
+    if let Some(t) = template {
+        t.apply_headers(&mut request_headers);
+    }
+    (request_headers, applied_preset)
+}
+
+/// This function names the file of a download and picks its folder, from what was asked for the
+/// download, its template and the settings.
+///
+/// # Returns
+/// The file name before it was sanitized, if sanitizing changed it.
+fn name_file(
+    file: &mut files::File,
+    file_name: Option<&str>,
+    destination_dir: Option<String>,
+    partial: Option<(u64, u64)>,
+    template: Option<&templates::Template>,
+    transliterate: bool,
+) -> Option<String> {
+    if let Some(custom_name) = file_name {
+        let trimmed = custom_name.trim();
+        if !trimmed.is_empty() {
+            let ext = Path::new(&file.file_name)
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or("");
+            let final_name = if !trimmed.contains('.') && !ext.is_empty() {
+                format!("{}.{}", trimmed, ext)
+            } else {
+                trimmed.to_string()
+            };
+            file.file_name = final_name;
+            file.destination_path = format!("{}/{}", file.destination_dir, file.file_name);
+        }
+    }
+
+    if let Some((start, end)) = partial {
+        file.file_name = files::partial_file_name(&file.file_name, start, end);
+        file.destination_path = format!("{}/{}", file.destination_dir, file.file_name);
+    }
+
+    let sanitized = files::sanitize_file_name(&file.file_name, transliterate);
+    let original_file_name = if sanitized != file.file_name {
+        let original = std::mem::replace(&mut file.file_name, sanitized);
+        file.destination_path = format!("{}/{}", file.destination_dir, file.file_name);
+        Some(original)
+    } else {
+        None
+    };
+
+    let destination_dir = destination_dir
+        .filter(|d| !d.trim().is_empty())
+        .or_else(|| template.and_then(|t| t.destination_dir.clone()));
+    if let Some(custom_dir) = &destination_dir {
+        let trimmed = custom_dir.trim();
+        if !trimmed.is_empty() {
+            let dir_path = Path::new(trimmed).join(&file.file_name);
+            if let Some(dir) = dir_path.parent() {
+                file.destination_dir = dir.to_str().unwrap_or(&file.destination_dir).to_string();
+            }
+            file.destination_path = dir_path.to_str().unwrap_or(&file.destination_path).to_string();
+        }
+    }
+    original_file_name
+}
+
+/// This function returns the record of an earlier download of `url` to resume, or an empty one
+/// for a new download. A finished download is only downloaded again if the user says so.
+///
+/// # Returns
+/// - `Ok(Some(DownloadRecord))`: The record, with an id of 0 for a new download.
+/// - `Ok(None)`: If the file was already downloaded and is skipped.
+/// - `Err(String)`: If the download is running or queued already, or cannot be resumed.
+async fn find_record(
+    url: &str,
+    record_id: Option<i64>,
+    byte_range: Option<chunks::ByteRange>,
+    replace_finished: bool,
+    cfg: &config::Config,
+) -> Result<Option<storage::DownloadRecord>, String> {
+    let mut record = match record_id {
+        Some(id) => storage::read_records_by_ids(&[id], cfg)
+            .map_err(|e| format!("Failed to read record: {e}"))?
+            .pop()
+            .ok_or("No download record found with this id")?,
+        None => storage::search_by_url(url, cfg).unwrap_or_default(),
+    };
+    if record.deleted_at.is_some() {
+        // downloading a deleted record again starts over instead of restoring it
+        storage::delete_record(record.id, cfg)
+            .map_err(|e| format!("Failed to delete record: {e}"))?;
+        record = storage::DownloadRecord::default();
+    }
+
+    if active_downloads().lock().unwrap().contains_key(&record.id) {
+        return Err("This download is already running".into());
+    }
+    if queue::global().is_queued(record.id) {
+        return Err("This download is already queued".into());
+    }
+
+    if record.id != 0 && record.byte_range != byte_range {
+        // the file of a part cannot be resumed as the whole file or another part
+        let e = "This url is already in the list with another range, delete it first".to_string();
+        message(record.id, &e, "error");
+        return Err(e);
+    }
+
+    if record.id != 0 && record.download_status == "Finished" {
+        if !replace_finished {
+            let question = format!("{} has already been downloaded", record.file_name);
+            let kind = decisions::DecisionKind::Duplicate;
+            let answer = decide(record.id, kind, question, vec!["skip", "download_again"]).await;
+            if answer.choice != "download_again" {
+                message(record.id, "File already downloaded", "success");
+                return Ok(None);
+            }
+        }
+        storage::delete_record(record.id, cfg)
+            .map_err(|e| format!("Failed to delete record: {e}"))?;
+        record = storage::DownloadRecord::default();
+    }
+    Ok(Some(record))
+}
+
+/// This function makes sure a new download does not write over the file of another download, or
+/// one saved some other way, unless the user says so.
+///
+/// # Returns
+/// Whether the download goes ahead.
+async fn free_destination(file: &mut files::File, cfg: &config::Config) -> bool {
+    let taken =
+        |path: &str| Path::new(path).exists() || storage::path_in_use(path, cfg).unwrap_or(false);
+    if !taken(&file.destination_path) {
+        return true;
+    }
+    let owned = storage::path_in_use(&file.destination_path, cfg).unwrap_or(false);
+    let choices = if owned {
+        vec!["rename", "skip"]
+    } else {
+        vec!["rename", "overwrite", "skip"]
+    };
+    let question = format!("{} already exists in {}", file.file_name, file.destination_dir);
+    let kind = decisions::DecisionKind::FileExists;
+    match decide(0, kind, question, choices).await.choice.as_str() {
+        "overwrite" => {}
+        "skip" => {
+            message(0, "The download was skipped, its file already exists", "success");
+            return false;
+        }
+        _ => {
+            let dir = file.destination_dir.clone();
+            file.file_name = files::free_file_name(&file.file_name, |name| {
+                taken(&format!("{dir}/{name}"))
+            });
+            file.destination_path = format!("{dir}/{}", file.file_name);
+        }
+    }
+    true
+}
+
+/// This function waits for a free slot in the queue, see `queue`, and marks the download as
+/// running once it has one.
+///
+/// # Returns
+/// The slot, held until the download ends, or `None` if the download was paused while queued.
+async fn enter_queue(
+    record: &storage::DownloadRecord,
+    file: &files::File,
+    cfg: &config::Config,
+) -> Option<queue::Slot> {
+    let started = |download_status: &str| {
+        emit(Event::Started(DownloadStarted {
+            download_id: record.id,
+            file_url: file.file_url.clone(),
+            file_name: file.file_name.clone(),
+            file_type: file.file_type.to_string(),
+            download_status: download_status.to_string(),
+        }))
+    };
+    let slot = match queue::global().enter(record.id, record.priority) {
+        Ok(slot) => slot,
+        Err(ticket) => {
+            started("Queued");
+            let _ = storage::update_records_status(&[record.id], "Queued", cfg);
+            // `None` when paused while it was queued, see `pause`
+            ticket.wait().await?
+        }
+    };
+    started("InProgress");
+    // left as it is if the application is closed while downloading, see `resume_interrupted`
+    let _ = storage::update_records_status(&[record.id], "InProgress", cfg);
+    let _ = storage::update_failure_note(record.id, None, cfg);
+    Some(slot)
+}
+
+/// This struct describes a download to add.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadRequest {
+    pub url: String,
+    /// The name the file is saved as instead of the name in the url.
+    pub file_name: Option<String>,
+    /// The folder the file is saved in instead of the folder for its file type.
+    pub destination_dir: Option<String>,
+    /// A referer sent instead of the headers of the host presets.
+    pub referer: Option<String>,
+    pub criteria: Option<criteria::SuccessCriteria>,
+    pub template_id: Option<i64>,
+    /// The download this one belongs to, e.g. the video of a subtitle.
+    pub parent_id: Option<i64>,
+    /// The part of the file to download instead of the whole file.
+    pub byte_range: Option<chunks::ByteRange>,
+    /// The network interface or local address this download is sent from instead of the one of
+    /// the settings, see `binding`.
+    pub bind_to: Option<String>,
+    /// Other urls serving the same file, which chunks are downloaded from in parallel, see
+    /// `mirrors`.
+    pub mirrors: Vec<String>,
+    /// Whether a finished download of the same url is downloaded again without asking, e.g. when
+    /// its file changed on the server, see `check_for_update`.
+    pub replace_finished: bool,
+    /// What is opened once the download has finished, see `DownloadRecord::open_when_done`.
+    pub open_when_done: Option<file_manager::OpenWhenDone>,
+    /// The retry settings used instead of the ones of the settings. They are saved on the record
+    /// and kept when the download resumes.
+    pub retry_overrides: Option<retry::RetryOverrides>,
+    /// The highest video quality, in pixels of height, of a DASH stream, see `add_dash`. The
+    /// best quality when `None`.
+    pub video_height: Option<u32>,
+    /// The record of an earlier attempt this download carries on, see `retry`. The record of the
+    /// same url when `None`.
+    pub record_id: Option<i64>,
+}
+
+impl DownloadRequest {
+    pub fn new(url: &str) -> Self {
+        DownloadRequest {
+            url: url.to_string(),
+            ..DownloadRequest::default()
+        }
+    }
+}
+
+/// This function checks that a mirror serves the file of a download, following its redirects.
+///
+/// # Returns
+/// The url the mirror redirects to, or why it cannot be used.
+async fn probe_mirror(
+    client: &reqwest::Client,
+    mirror: &str,
+    total_size: u64,
+    request_headers: &[(String, String)],
+    max_redirects: usize,
+    cert_pins: &[pins::CertPin],
+) -> Result<String, String> {
+    let (head, url, _) =
+        redirects::probe(client, mirror, request_headers, max_redirects, cert_pins).await?;
+    let headers = head.headers();
+    let size = headers
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    let accept_ranges = headers
+        .get(reqwest::header::ACCEPT_RANGES)
+        .and_then(|v| v.to_str().ok());
+    mirrors::serves_same_file(total_size, size, accept_ranges)?;
+    Ok(url)
+}
+
+/// This function splits a new download into chunks. Small files, and files the host sends quickly
+/// anyway, gain nothing from being split.
+///
+/// # Arguments
+/// - `single_stream`: Whether the server cannot send parts of the file.
+///
+/// # Returns
+/// The first and last byte of each chunk, and whether the file is downloaded over a single
+/// connection.
+fn plan_download(
+    total_size: u64,
+    chunk_size: u64,
+    single_stream: bool,
+    url: &str,
+    current_settings: &settings::Settings,
+) -> (Vec<(u64, u64)>, bool) {
+    let host_speed = latency::host_of(url)
+        .and_then(|h| latency::for_host(&h))
+        .map(|s| s.speed);
+    let single_stream = single_stream
+        || chunks::prefers_single_stream(
+            total_size,
+            host_speed,
+            current_settings.single_stream_max_size,
+            current_settings.single_stream_max_secs,
+        );
+    if single_stream {
+        (chunks::plan_chunks(total_size, total_size), true)
+    } else {
+        (chunks::plan_chunks(total_size, chunk_size), false)
+    }
+}
+
+/// This function downloads a file, carrying on where an earlier attempt stopped. It returns once
+/// the download has finished, failed or been paused, and reports what happens as events. The url
+/// of a Metalink file adds the downloads of the files it lists, see `start_metalink`.
+///
+/// # Example
+/// ```ignore
+/// let mut events = engine::subscribe();
+/// engine::add(engine::DownloadRequest::new("https://example.com/file.zip")).await?;
+/// ```
+pub async fn add(request: DownloadRequest) -> Result<(), String> {
+    if metalink::is_metalink_url(&request.url) {
+        return add_metalink(&request.url, request.destination_dir).await;
+    }
+    crash::watch(add_file(request)).await
+}
+
+/// This function returns the chunk size of an S3 object uploaded in parts from the headers of
+/// its first part, see `s3::chunk_size`.
+///
+/// # Returns
+/// `None` if the object was uploaded in one piece or its first part cannot be requested.
+async fn s3_part_chunk_size(
+    client: &reqwest::Client,
+    part_url: Option<String>,
+    headers: &[(String, String)],
+) -> Option<u64> {
+    let mut request = client.head(part_url?);
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value);
+    }
+    let response = request.send().await.ok()?;
+    s3::chunk_size(response.headers())
+}
+
+/// This function tells whether `url` is one `add` can download: an HTTP, FTP, SFTP or S3 url,
+/// or a data url.
+pub fn is_supported_url(url: &str) -> bool {
+    let http = ["http://", "https://"].iter().any(|s| url.starts_with(s));
+    http || ftp::is_ftp(url) || sftp::is_sftp(url) || s3::is_s3(url) || data_url::is_data_url(url)
+}
+
+/// This function downloads a single file, see `add`, which also takes the urls of Metalink files.
+async fn add_file(request: DownloadRequest) -> Result<(), String> {
+    if ftp::is_ftp(&request.url) {
+        return add_ftp(request).await;
+    }
+    if sftp::is_sftp(&request.url) {
+        return add_sftp(request).await;
+    }
+    if dash::is_manifest_url(&request.url) {
+        return add_dash(request).await;
+    }
+    if data_url::is_data_url(&request.url) {
+        return add_data_url(request).await;
+    }
+    let DownloadRequest {
+        url,
+        file_name,
+        destination_dir,
+        referer,
+        criteria,
+        template_id,
+        parent_id,
+        byte_range,
+        bind_to,
+        mirrors,
+        replace_finished,
+        open_when_done,
+        retry_overrides,
+        video_height: _,
+        record_id,
+    } = request;
+    if !is_supported_url(&url) {
+        let text = "Invalid URL. Must start with http://, https://, ftp://, ftps://, ftpes://, \
+                    sftp://, s3:// or data:";
+        message(0, text, "error");
+        return Err("Invalid URL".into());
+    }
+    if let Err(e) = mirrors.iter().try_for_each(|m| mirrors::validate(m)) {
+        message(0, &e, "error");
+        return Err(e);
+    }
+
+    let cfg = config::Config::default();
+    let mut current_settings = settings::current();
+    if let Some(Err(e)) = retry_overrides.map(|r| r.validate(&current_settings)) {
+        message(0, &e, "error");
+        return Err(e);
+    }
+    let bind_to = bind_to.filter(|b| !b.trim().is_empty());
+    if bind_to.is_some() {
+        current_settings.bind_to = bind_to.clone();
+    }
+    let client = settings::shared_client(&current_settings)?;
+    let template = match template_id {
+        Some(id) => Some(
+            storage::read_template(id, &cfg).map_err(|e| format!("Failed to read template: {e}"))?,
+        ),
+        None => None,
+    };
+
+    let (mut request_headers, applied_preset) = download_headers(
+        &url,
+        referer.as_deref(),
+        template.as_ref(),
+        &current_settings,
+    );
+
+    // a signature covers the method of the request, so S3 objects are probed with a url of their
+    // own, see `s3::head_url`
+    let s3_head_url = match s3::head_url(&url, &current_settings.s3, unix_now()) {
+        Ok(head_url) => head_url,
+        Err(e) => {
+            message(0, &e, "error");
+            return Err(e);
+        }
+    };
+    let probe_client = settings::shared_probe_client(&current_settings)?;
+    let (head, final_url, redirect_chain) = loop {
+        let probed = match redirects::probe(
+            &probe_client,
+            s3_head_url.as_deref().unwrap_or(&url),
+            &request_headers,
+            current_settings.max_redirects,
+            &current_settings.cert_pins,
+        )
+        .await
+        {
+            Ok(probed) => probed,
+            Err(e) => {
+                message(0, &e, "error");
+                return Err(e);
+            }
+        };
+        if probed.0.status() != reqwest::StatusCode::UNAUTHORIZED {
+            break probed;
+        }
+        // wrong credentials are asked for again
+        let question = format!("{} asks for a user name and password", probed.1);
+        let kind = decisions::DecisionKind::Credentials;
+        let answer = decide(0, kind, question, vec!["cancel", "sign_in"]).await;
+        match answer.input.filter(|_| answer.choice == "sign_in") {
+            Some(credentials) => {
+                request_headers.retain(|(name, _)| !name.eq_ignore_ascii_case("Authorization"));
+                let value = decisions::basic_authorization(&credentials);
+                request_headers.push(("Authorization".to_string(), value));
+            }
+            None => {
+                let e = "The server asks for a user name and password".to_string();
+                message(0, &e, "error");
+                return Err(e);
+            }
+        }
+    };
+    let (final_url, s3_chunk_size) = match &s3_head_url {
+        Some(_) => {
+            let s3_settings = &current_settings.s3;
+            let signed = s3::signed_url(&url, s3_settings, unix_now())?.into_owned();
+            let part_url = s3::first_part_url(&url, s3_settings, unix_now());
+            let chunk_size = s3_part_chunk_size(&probe_client, part_url, &request_headers).await;
+            (signed, chunk_size)
+        }
+        None => (final_url, None),
+    };
+    // a signed url grants access to the object, so it is not saved with the download
+    let saved_url = s3::unsigned_url(&final_url);
+    let redirect_chain: Vec<redirects::RedirectHop> = redirect_chain
+        .into_iter()
+        .map(|hop| redirects::RedirectHop {
+            url: s3::unsigned_url(&hop.url),
+            location: s3::unsigned_url(&hop.location),
+            ..hop
+        })
+        .collect();
+    let request_headers = Arc::new(request_headers);
+
+    // servers using chunked transfer encoding do not announce the size of the file
+    let announced_size = head
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    // the chunks of an S3 object uploaded in parts do not span two parts
+    let planned_chunk_size = s3_chunk_size.or(announced_size.map(chunks::chunk_size));
+
+    if announced_size == Some(0) {
+        return Err("File has zero size".into());
+    }
+    // identifies the version of the file, see `validators`
+    let validator = validators::validator(head.headers());
+    let http2 = head.version() == reqwest::Version::HTTP_2;
+
+    // a server that cannot send parts of the file is downloaded over a single connection
+    let mut single_stream = chunks::ranges_refused(
+        head.headers()
+            .get(reqwest::header::ACCEPT_RANGES)
+            .and_then(|v| v.to_str().ok()),
+    );
+    let resumable = !single_stream;
+
+    let content_type = head
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok());
+    let criteria = criteria.unwrap_or_default();
+    if let Err(e) = criteria.check(announced_size, content_type) {
+        message(0, &e, "error");
+        return Err(e);
+    }
+    let confirm_above = current_settings.confirm_size_above;
+    if let Some(size) = announced_size.filter(|s| confirm_above > 0 && *s > confirm_above) {
+        let question = format!(
+            "{final_url} is {}, download it anyway?",
+            settings::units().size(size)
+        );
+        let kind = decisions::DecisionKind::Oversized;
+        if decide(0, kind, question, vec!["cancel", "download"]).await.choice != "download" {
+            message(0, "The download was cancelled because of its size", "success");
+            return Ok(());
+        }
+    }
+
+    let partial = match byte_range {
+        Some(_) if single_stream => Err("The server cannot send a part of the file".to_string()),
+        Some(range) => range.resolve(announced_size).map(Some),
+        None => Ok(None),
+    };
+    let partial = match partial {
+        Ok(partial) => partial,
+        Err(e) => {
+            message(0, &e, "error");
+            return Err(e);
+        }
+    };
+    let byte_range = partial.map(|(start, end)| chunks::ByteRange {
+        start,
+        end: Some(end),
+    });
+
+    let content_disposition = head
+        .headers()
+        .get(reqwest::header::CONTENT_DISPOSITION)
+        .and_then(|v| v.to_str().ok());
+    let mut file = files::File::new(&url, content_disposition, &cfg);
+    file.apply_category_rules(&current_settings.category_rules, &cfg);
+
+    let original_file_name = name_file(
+        &mut file,
+        file_name.as_deref(),
+        destination_dir,
+        partial,
+        template.as_ref(),
+        current_settings.transliterate_file_names,
+    );
+
+    let Some(mut record) = find_record(&url, record_id, byte_range, replace_finished, &cfg).await? else {
+        return Ok(());
+    };
+    if record.id == 0 && !free_destination(&mut file, &cfg).await {
+        return Ok(());
+    }
+
+    let resuming = record.id != 0;
+    if record.id == 0 {
+        let mut dr = storage::DownloadRecord::from(file.clone());
+        dr.applied_preset = applied_preset;
+        dr.redirect_chain = redirect_chain;
+        dr.final_url = Some(saved_url);
+        dr.parent_id = parent_id;
+        dr.byte_range = byte_range;
+        dr.original_file_name = original_file_name;
+        dr.chunk_size = planned_chunk_size;
+        dr.validator = validator.clone();
+        dr.open_when_done = open_when_done;
+        dr.retry_overrides = retry_overrides;
+        dr.template_id = template_id;
+        dr.criteria = Some(criteria.clone()).filter(|c| *c != criteria::SuccessCriteria::default());
+        dr.referer = referer.clone();
+        record.chunk_size = dr.chunk_size;
+        record.validator = validator;
+        let ranges = match (partial, announced_size, dr.chunk_size) {
+            (None, Some(total_size), Some(chunk_size)) => {
+                let (planned, single) = plan_download(
+                    total_size,
+                    chunk_size,
+                    single_stream,
+                    &final_url,
+                    &current_settings,
+                );
+                single_stream = single;
+                planned
+            }
+            // parts of a file and files of unknown size are streamed, see `download_unknown_size`
+            _ => vec![(0, 0)],
+        };
+        record.id =
+            storage::insert_record_with_chunks(&dr, announced_size.unwrap_or(0), &ranges, &cfg)
+                .map_err(|e| format!("Failed to save download record: {e}"))?;
+    } else {
+        // mirrors may send each attempt to another host, so the parts left are requested from
+        // wherever this one was sent
+        let _ = storage::update_redirect_chain(record.id, &saved_url, &redirect_chain, &cfg);
+        if open_when_done.is_some() {
+            let _ = storage::update_open_when_done(record.id, open_when_done, &cfg);
+        }
+        if retry_overrides.is_some() {
+            let _ = storage::update_retry_overrides(record.id, retry_overrides, &cfg);
+            record.retry_overrides = retry_overrides;
+        }
+        // carry on writing into the file of the earlier attempt
+        file.file_name = record.file_name.clone();
+        file.destination_dir = record.destination_dir.clone();
+        file.destination_path = record.destination_path.clone();
+        // the bytes of the new file cannot be written next to the ones of the old one
+        let resized = record.chunk_size.is_some() && announced_size != Some(record.file_size);
+        if record.byte_range.is_none()
+            && (resized || validators::changed(record.validator.as_deref(), head.headers()))
+        {
+            let text = "The file changed on the server, downloading it again from the start";
+            message(record.id, text, "info");
+            let _ = fs::remove_file(files::working_path(record.id, &file.destination_path));
+            record.chunk_size = planned_chunk_size;
+            record.validator = validator;
+            storage::restart_record(
+                record.id,
+                announced_size.unwrap_or(0),
+                record.chunk_size,
+                record.validator.as_deref(),
+                &cfg,
+            )
+            .map_err(|e| format!("Failed to restart the download: {e}"))?;
+        }
+    }
+
+    crash::set_download(record.id);
+    if !mirrors.is_empty() {
+        storage::add_mirrors(record.id, &mirrors, &cfg)
+            .map_err(|e| format!("Failed to save mirrors: {e}"))?;
+    }
+
+    // a resumed download keeps the chunk size it was started with so that its chunks still match
+    let chunk_size = record.chunk_size.unwrap_or(chunks::MIN_CHUNK_SIZE);
+
+    fs::create_dir_all(&file.destination_dir)
+        .map_err(|e| format!("Failed to create directory: {e}"))?;
+
+    // the slot is held until this function returns, which starts the next queued download
+    let Some(_slot) = enter_queue(&record, &file, &cfg).await else {
+        return Ok(());
+    };
+
+    if let Some(range) = partial {
+        // the checksum of the whole file cannot match a part of it
+        let criteria = criteria::SuccessCriteria::default();
+        let stream = UnknownSize {
+            client,
+            url: &final_url,
+            request_headers: &request_headers,
+            max_speed: templates::max_speed(template.as_ref(), &current_settings),
+            range: Some(range),
+            post_command: templates::post_command(template.as_ref()),
+        };
+        return download_unknown_size(stream, record.id, &file, &criteria).await;
+    }
+
+    let Some(total_size) = announced_size else {
+        let max_speed = templates::max_speed(template.as_ref(), &current_settings);
+        let stream = UnknownSize {
+            client,
+            url: &final_url,
+            request_headers: &request_headers,
+            max_speed,
+            range: None,
+            post_command: templates::post_command(template.as_ref()),
+        };
+        return download_unknown_size(stream, record.id, &file, &criteria).await;
+    };
+
+    let working = files::working_path(record.id, &file.destination_path);
+    // downloads started before they were written to a working file carry on in their file
+    if resuming && !working.exists() && Path::new(&file.destination_path).exists() {
+        files::move_into_place(Path::new(&file.destination_path), &working)?;
+    }
+    if resuming {
+        // finished chunks are only kept while the file still holds their bytes
+        db_writer::flush().await;
+        let chunks = storage::get_chunks_by_record(record.id, &cfg).unwrap_or_default();
+        let file_len = fs::metadata(&working).ok().map(|m| m.len());
+        if let upgrade::Finding::Truncated { from } = upgrade::check(&record, &chunks, file_len) {
+            let text =
+                format!("The partial file lost the bytes from {from} on, downloading them again");
+            message(record.id, &text, "error");
+            storage::reset_chunks_from(record.id, from, &cfg)
+                .map_err(|e| format!("Failed to reset chunks: {e}"))?;
+        }
+    }
+    // the file is not truncated so that the finished chunks of a resumed download are kept
+    let mut d_file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&working)
+        .map_err(|e| format!("Failed to create file: {e}"))?;
+    let reserved = allocation::allocate(&mut d_file, total_size, current_settings.allocation)
+        .map_err(|e| format!("Failed to allocate file: {e}"))?;
+    let mmap_min_size = current_settings.mmap_min_size;
+    // a mapping of a file with holes crashes YAD when the disk fills up
+    let mapped = if reserved && mmap_min_size > 0 && total_size >= mmap_min_size {
+        file_writer::FileWriter::open_mapped(&d_file)
+            .inspect_err(|e| eprintln!("Download {} {e}, writing it instead", record.id))
+            .ok()
+    } else {
+        None
+    };
+    let writer = match mapped {
+        Some(writer) => writer,
+        None => {
+            // every worker that can run at the same time writes through a handle of its own
+            let workers = if single_stream {
+                1
+            } else {
+                templates::max_concurrent_chunks(template.as_ref(), &current_settings)
+            };
+            let mut handles = vec![d_file];
+            for _ in 1..workers.clamp(1, file_writer::MAX_HANDLES) {
+                let handle = fs::OpenOptions::new()
+                    .write(true)
+                    .open(&working)
+                    .map_err(|e| format!("Failed to open file: {e}"))?;
+                handles.push(handle);
+            }
+            file_writer::FileWriter::open_handles(handles)?
+        }
+    };
+
+    let running = Arc::new(RunningDownload::default());
+    running.touch();
+    active_downloads()
+        .lock()
+        .unwrap()
+        .insert(record.id, Arc::clone(&running));
+
+    health::register(
+        record.id,
+        templates::max_speed(template.as_ref(), &current_settings),
+    );
+
+    // chunk updates of an earlier attempt may still be queued
+    db_writer::flush().await;
+    let _ = storage::delete_duplicate_chunks(record.id, &cfg);
+    let mut existing_chunks = storage::get_chunks_by_record(record.id, &cfg).unwrap_or_default();
+    let whole_file = (0, total_size - 1);
+    let is_whole = |c: &[storage::Chunk]| c.len() == 1 && (c[0].start, c[0].end) == whole_file;
+    if single_stream && !existing_chunks.is_empty() && !is_whole(&existing_chunks) {
+        // chunks saved before the server stopped supporting ranges cannot be finished
+        let _ = storage::replace_chunks(record.id, &[whole_file], &cfg);
+        existing_chunks = storage::get_chunks_by_record(record.id, &cfg).unwrap_or_default();
+    }
+    let existing: HashMap<(u64, u64), String> = existing_chunks
+        .into_iter()
+        .map(|c| ((c.start, c.end), c.status))
+        .collect();
+
+    // a resumed download carries on with its saved chunks, which may have been split, and the
+    // coverage check below downloads any bytes they miss
+    let mut ranges: Vec<(u64, u64)> = Vec::new();
+    if existing.is_empty() {
+        // saved by an older version without its chunks, see `crash::repair_records_without_chunks`
+        let (planned, single) = plan_download(
+            total_size,
+            chunk_size,
+            single_stream,
+            &final_url,
+            &current_settings,
+        );
+        single_stream = single;
+        let _ = storage::replace_chunks(record.id, &planned, &cfg);
+        ranges = planned;
+    } else {
+        let max_attempts = current_settings.max_chunk_attempts;
+        let _ = storage::reset_unfinished_chunks(record.id, max_attempts, &cfg);
+        let mut given_up = 0;
+        for c in storage::get_chunks_by_record(record.id, &cfg).unwrap_or_default() {
+            match c.status.as_str() {
+                "Finished" => {}
+                "Pending" => ranges.push((c.start, c.end)),
+                _ => given_up += 1,
+            }
+        }
+        if given_up > 0 {
+            let text = format!("{given_up} chunks failed {max_attempts} times and were given up");
+            message(record.id, &text, "error");
+        }
+        ranges.sort();
+    }
+
+    // resumed downloads start from the bytes of the chunks finished before
+    let already_downloaded: u64 = existing
+        .iter()
+        .filter(|(_, status)| status.as_str() == "Finished")
+        .map(|((start, end), _)| chunks::range_len(*start, *end))
+        .sum();
+    progress::start(record.id, &file.file_name, total_size, already_downloaded);
+
+    let progress = Arc::new(Mutex::new(already_downloaded));
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<DownloadProgress>(64);
+    let progress_task = tokio::spawn(async move {
+        let mut announced = already_downloaded;
+        while let Some(p) = rx.recv().await {
+            let event = progress::event(p.download_id, p.downloaded, p.total_size, announced);
+            announced = announced.max(p.downloaded);
+            emit(Event::Progress(event));
+        }
+    });
+
+    let max_chunks = templates::max_concurrent_chunks(template.as_ref(), &current_settings);
+    let sem = Arc::new(Semaphore::new(max_chunks));
+    let max_speed = throttle::lowest_limit(&[
+        templates::max_speed(template.as_ref(), &current_settings),
+        simulation::bandwidth(&current_settings),
+    ]);
+    running.set_limits(record.id, Some(max_speed), None);
+    let client = Arc::new(Mutex::new(client));
+    let cert_pins = Arc::new(Mutex::new(current_settings.cert_pins.clone()));
+    let simulation = Arc::new(Mutex::new(current_settings.simulation.clone()));
+    let spot_check_min_size = current_settings.spot_check_min_size;
+    let s3_settings = Arc::new(current_settings.s3.clone());
+    if let Some(overrides) = retry_overrides.or(record.retry_overrides) {
+        overrides.apply(&mut current_settings);
+    }
+    let chunk_retries = current_settings.chunk_retries;
+    let retry_backoff_ms = current_settings.retry_backoff_ms;
+    let retry_max_delay_ms = current_settings.retry_max_delay_ms;
+
+    // chunks take turns between the url and the mirrors serving the same file, and a download
+    // over a single connection can still fail over to them as long as the server resumes
+    let sources = if !resumable {
+        mirrors::Sources::new(&final_url, Vec::new())
+    } else {
+        let mirrors = storage::read_mirrors(record.id, &cfg).unwrap_or_default();
+        let mut usable = Vec::new();
+        for mirror in mirrors {
+            let probed = probe_mirror(
+                &probe_client,
+                &mirror,
+                total_size,
+                &request_headers,
+                current_settings.max_redirects,
+                &current_settings.cert_pins,
+            )
+            .await;
+            match probed {
+                Ok(url) => usable.push(url),
+                Err(e) => eprintln!("skipping mirror {mirror} because it {e}"),
+            }
+        }
+        mirrors::Sources::new(&final_url, usable)
+    };
+    let sources = Arc::new(sources);
+
+    // mirrors may not speak HTTP/2, so only the chunks of a single source share a connection
+    let multiplexed = http2 && current_settings.http2_multiplexing && sources.is_single();
+    let client_for: fn(&settings::Settings) -> Result<reqwest::Client, String> = if multiplexed {
+        settings::shared_http2_client
+    } else {
+        settings::shared_client
+    };
+    if multiplexed {
+        *lock(&client) = client_for(&current_settings)?;
+    }
+
+    // apply settings changes to this download while it is running
+    let mut settings_rx = settings::subscribe();
+    let settings_task = {
+        let sem = Arc::clone(&sem);
+        let running = Arc::clone(&running);
+        let client = Arc::clone(&client);
+        let cert_pins = Arc::clone(&cert_pins);
+        let simulation = Arc::clone(&simulation);
+        let record_id = record.id;
+        let template = template.clone();
+        let bind_to = bind_to.clone();
+        let mut applied = current_settings;
+        tokio::spawn(async move {
+            while settings_rx.changed().await.is_ok() {
+                let mut new = settings_rx.borrow_and_update().clone();
+                // the interface chosen for this download wins over the settings
+                if bind_to.is_some() {
+                    new.bind_to = bind_to.clone();
+                }
+                let old_chunks = templates::max_concurrent_chunks(template.as_ref(), &applied);
+                let new_chunks = templates::max_concurrent_chunks(template.as_ref(), &new);
+                if new_chunks > old_chunks {
+                    sem.add_permits(new_chunks - old_chunks);
+                } else if new_chunks < old_chunks {
+                    // permits held by running chunks are taken away as soon as they finish
+                    let excess = (old_chunks - new_chunks) as u32;
+                    let sem = Arc::clone(&sem);
+                    tokio::spawn(async move {
+                        if let Ok(permits) = sem.acquire_many_owned(excess).await {
+                            permits.forget();
+                        }
+                    });
+                }
+                let new_speed = throttle::lowest_limit(&[
+                    templates::max_speed(template.as_ref(), &new),
+                    simulation::bandwidth(&new),
+                ]);
+                running.set_limits(record_id, Some(new_speed), None);
+                *lock(&simulation) = new.simulation.clone();
+                if new.proxy != applied.proxy
+                    || new.use_system_proxy != applied.use_system_proxy
+                    || new.bind_to != applied.bind_to
+                    || new.cert_pins != applied.cert_pins
+                {
+                    match client_for(&new) {
+                        Ok(c) => *lock(&client) = c,
+                        Err(e) => eprintln!("{e}"),
+                    }
+                    *lock(&cert_pins) = new.cert_pins.clone();
+                }
+                applied = new;
+            }
+        })
+    };
+
+    // the chunks being downloaded by their start, see `split_slowest_chunk`
+    let in_flight: Arc<Mutex<HashMap<u64, Arc<chunks::InFlight>>>> = Arc::default();
+    // the chunks waiting for a worker
+    let queued = Arc::new(AtomicUsize::new(0));
+    // set when the server sends the whole file for a chunk
+    let ranges_ignored = Arc::new(AtomicBool::new(false));
+    // set when `If-Range` shows that the file changed on the server
+    let file_changed = Arc::new(AtomicBool::new(false));
+    // set when a chunk is cut off by a lost connection, see `connectivity`
+    let network_lost = Arc::new(AtomicBool::new(false));
+    // the error of the last chunk that failed, see `diagnosis`
+    let last_error: Arc<Mutex<Option<String>>> = Arc::default();
+    // a break from requests the server asked for, see `retry::Cooldown`
+    let cooldown = Arc::new(retry::Cooldown::default());
+
+    let spawn_chunk = |start: u64, end: u64| {
+        let s = Arc::clone(&sem);
+        let in_flight = Arc::clone(&in_flight);
+        let queued = Arc::clone(&queued);
+        let ranges_ignored = Arc::clone(&ranges_ignored);
+        let file_changed = Arc::clone(&file_changed);
+        let network_lost = Arc::clone(&network_lost);
+        let last_error = Arc::clone(&last_error);
+        let cooldown = Arc::clone(&cooldown);
+        let client = Arc::clone(&client);
+        let cert_pins = Arc::clone(&cert_pins);
+        let simulation = Arc::clone(&simulation);
+        let request_headers = Arc::clone(&request_headers);
+        let if_range = record.validator.clone();
+        let writer = writer.for_worker();
+        let tx = tx.clone();
+        let sources = Arc::clone(&sources);
+        let s3_settings = Arc::clone(&s3_settings);
+        let source = sources.next_index();
+        let path = working.clone();
+        let p = Arc::clone(&progress);
+        let running = Arc::clone(&running);
+        let tracker = Arc::clone(&running);
+        let rid = record.id;
+
+        queued.fetch_add(1, Ordering::Relaxed);
+        let handle = tokio::spawn(async move {
+            let permit = s.acquire().await;
+            queued.fetch_sub(1, Ordering::Relaxed);
+
+            if running.is_cancelled() {
+                db_writer::update_chunk(rid, start, "Cancelled").await;
+                return;
+            }
+
+            let flight = Arc::new(chunks::InFlight::new(start, end));
+            lock(&in_flight).insert(start, Arc::clone(&flight));
+
+            let mut attempt = 0;
+            // the reads of this chunk waiting for the disk, see `PREFETCH_DEPTH`
+            let mut pending = VecDeque::new();
+            // set when the last attempt ended before the end of the chunk
+            let mut short;
+            // set when the last attempt could not connect, see `connectivity`
+            let mut lost;
+            let written = loop {
+                // the server asked for a break, see `retry::Cooldown`
+                while !running.is_cancelled() {
+                    let left = cooldown.remaining();
+                    if left.is_zero() {
+                        break;
+                    }
+                    // a break is not a stuck download, see `watchdog`
+                    running.touch();
+                    tokio::time::sleep(left.min(COOLDOWN_TICK)).await;
+                }
+                flight.restart();
+                running.touch();
+                short = false;
+                lost = false;
+                let end = flight.end();
+                let client = lock(&client).clone();
+                let cert_pins = lock(&cert_pins).clone();
+                let url = sources.url(source, attempt);
+                // presigned urls are signed again before they expire
+                let signed = s3::signed_url(url, &s3_settings, unix_now());
+                let mut request = client
+                    .get(signed.as_deref().unwrap_or(url))
+                    .header("Range", format!("bytes={start}-{end}"))
+                    .header("User-Agent", BROWSER_AGENT);
+                // the server sends the whole file instead if it changed since the download
+                // started, mirrors are only checked by their size when they are probed
+                let validator = if_range.as_deref().filter(|_| sources.is_primary(url));
+                if let Some(validator) = validator {
+                    request = request.header("If-Range", validator);
+                }
+                for (name, value) in request_headers.iter() {
+                    request = request.header(name.as_str(), value);
+                }
+                let simulated = lock(&simulation).clone();
+                if let Some(sim) = &simulated {
+                    tokio::time::sleep(sim.latency()).await;
+                }
+                // bytes are counted as they are written and taken back if the attempt fails, as
+                // a retry downloads the chunk again from its start
+                let mut counted = 0;
+                let mut last_report = Instant::now();
+                let on_write = |bytes: u64| {
+                    running.touch();
+                    counted += bytes;
+                    let current = {
+                        let mut prog = lock(&p);
+                        *prog += bytes;
+                        *prog
+                    };
+                    if last_report.elapsed() >= PROGRESS_INTERVAL {
+                        last_report = Instant::now();
+                        progress::set_write_queue(rid, writer.queue_depth());
+                        report_progress(&tx, rid, current, total_size);
+                    }
+                };
+                // a certificate that does not match its pin is not retried
+                let sent_at = Instant::now();
+                let sent = if simulated
+                    .as_ref()
+                    .is_some_and(|sim| sim.fails(rid, start, attempt))
+                {
+                    Err("failed in the network simulation".to_string())
+                } else {
+                    request.send().await.map_err(|e| {
+                        lost = connectivity::is_network_error(&e);
+                        format!("request failed: {e}")
+                    })
+                };
+                let (result, retryable) = match sent {
+                    Ok(resp) => match pins::check(&cert_pins, &resp) {
+                        Ok(()) if resp.status().as_u16() >= 400 => {
+                            let status = resp.status().as_u16();
+                            let server_wait = resp
+                                .headers()
+                                .get(reqwest::header::RETRY_AFTER)
+                                .and_then(|v| v.to_str().ok())
+                                .and_then(|v| retry::retry_after(v, unix_now()))
+                                .filter(|_| retry::is_rate_limited(status));
+                            if let Some(wait) = server_wait {
+                                if cooldown.extend(wait) {
+                                    eprintln!("{url} asked to be left alone for {wait:?}");
+                                }
+                            }
+                            let e = match resp.error_for_status_ref() {
+                                Err(e) => format!("request failed: {e}"),
+                                Ok(_) => format!("request failed: HTTP status {status}"),
+                            };
+                            let retryable = retry::is_transient_status(status)
+                                || retry::is_rate_limited(status);
+                            (Err(e), retryable)
+                        }
+                        Ok(()) if validators::changed(validator, resp.headers()) => {
+                            // the other chunks are stopped and the download starts over
+                            file_changed.store(true, Ordering::Relaxed);
+                            running.cancel();
+                            let e = "the file changed on the server since the download started";
+                            (Err(e.to_string()), false)
+                        }
+                        Ok(())
+                            if chunks::range_ignored(
+                                resp.status().as_u16(),
+                                start,
+                                end,
+                                total_size,
+                            ) =>
+                        {
+                            ranges_ignored.store(true, Ordering::Relaxed);
+                            (Err("was sent as the whole file".to_string()), false)
+                        }
+                        Ok(()) => {
+                            let ttfb = sent_at.elapsed();
+                            let limiter = &running.limiter;
+                            let read = stream_chunk(
+                                resp,
+                                limiter,
+                                &flight,
+                                &writer,
+                                start,
+                                &mut pending,
+                                on_write,
+                            );
+                            let result = read.await.and_then(|bytes| {
+                                let checked = chunks::check_length(start, flight.end(), bytes);
+                                short = checked.is_err();
+                                checked
+                            });
+                            if let Ok(bytes) = result {
+                                sources.succeeded(url);
+                                let duration = sent_at.elapsed();
+                                let sample = latency::Sample {
+                                    ttfb,
+                                    duration,
+                                    bytes,
+                                };
+                                let host = latency::host_of(url).unwrap_or_default();
+                                latency::record(rid, &host, sample);
+                            }
+                            (result, true)
+                        }
+                        Err(e) => (Err(e), false),
+                    },
+                    Err(e) => (Err(e), true),
+                };
+                if result.is_err() && counted > 0 {
+                    let mut prog = lock(&p);
+                    *prog = prog.saturating_sub(counted);
+                }
+                match result {
+                    Ok(len) => break Some(len),
+                    Err(e)
+                        if retryable
+                            && attempt < sources.retries(chunk_retries)
+                            && !running.is_cancelled() =>
+                    {
+                        if !short && sources.failed(url) {
+                            eprintln!("{url} keeps failing, moving its chunks to the mirrors");
+                        }
+                        let wait = retry::backoff(attempt, retry_backoff_ms, retry_max_delay_ms);
+                        eprintln!("Chunk {start}-{end} {e}, retrying in {wait:?}");
+                        health::update(rid, |t| t.record_failure());
+                        metrics::chunk_error();
+                        tokio::time::sleep(wait).await;
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        eprintln!("Chunk {start}-{end} {e}");
+                        // a chunk that was cut short, or cut off by a lost connection, is fetched
+                        // again when the download resumes
+                        if lost {
+                            network_lost.store(true, Ordering::Relaxed);
+                        }
+                        let status = if short || lost { "Pending" } else { "Failed" };
+                        db_writer::update_chunk(rid, start, status).await;
+                        *lock(&last_error) = Some(e);
+                        health::update(rid, |t| t.record_failure());
+                        metrics::chunk_error();
+                        break None;
+                    }
+                }
+            };
+            lock(&in_flight).remove(&start);
+            let end = flight.end();
+            let Some(written) = written else {
+                return;
+            };
+            // the next chunk can be requested while the last reads of this one reach the disk
+            drop(permit);
+            for write in pending {
+                if let Err(e) = write.wait().await {
+                    eprintln!("Chunk {start}-{end} {e}");
+                    db_writer::update_chunk(rid, start, "Failed").await;
+                    *lock(&last_error) = Some(e);
+                    health::update(rid, |t| t.record_failure());
+                    metrics::chunk_error();
+                    return;
+                }
+            }
+
+            let current = *lock(&p);
+            report_progress(&tx, rid, current, total_size);
+
+            db_writer::update_chunk(rid, start, "Finished").await;
+            health::update(rid, |t| t.record_chunk(written));
+            if let Some(stats) = latency::for_download(rid) {
+                let slow_ttfb = Duration::from_millis(stats.p90_ttfb_ms);
+                health::update(rid, |t| t.set_slow_ttfb(slow_ttfb));
+            }
+
+            let check = integrity::should_check(
+                start,
+                chunk_size,
+                total_size,
+                spot_check_min_size,
+            );
+            if check {
+                let client = lock(&client).clone();
+                let seed = integrity::seed(start);
+                let sample = integrity::sample_range(start, end, seed);
+                let url = sources.url(source, attempt);
+                let signed = s3::signed_url(url, &s3_settings, unix_now());
+                match integrity::verify_sample(
+                    &client,
+                    signed.as_deref().unwrap_or(url),
+                    &request_headers,
+                    Path::new(&path),
+                    sample,
+                )
+                .await
+                {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        eprintln!("Chunk {start}-{end} failed its spot check");
+                        let cfg = config::Config::default();
+                        let _ = storage::add_spot_check_failure(rid, &cfg);
+                        db_writer::update_chunk(rid, start, "Failed").await;
+                        health::update(rid, |t| t.record_failure());
+                        metrics::chunk_error();
+                    }
+                    Err(e) => {
+                        eprintln!("Chunk {start}-{end} was not spot checked: {e}")
+                    }
+                }
+            }
+        });
+        tracker.track(handle.abort_handle());
+        handle
+    };
+
+    let mut repaired = false;
+    loop {
+        let mut handles: VecDeque<_> = ranges
+            .iter()
+            .map(|(s, e)| (*s, *e, 0, spawn_chunk(*s, *e)))
+            .collect();
+        while let Some((start, end, panics, mut h)) = handles.pop_front() {
+            let joined = loop {
+                // a worker with nothing left to do takes over half of the slowest chunk
+                let idle = queued.load(Ordering::Relaxed) == 0 && sem.available_permits() > 0;
+                let splittable = !single_stream && !ranges_ignored.load(Ordering::Relaxed);
+                if idle && splittable && !running.is_cancelled() {
+                    if let Some((s, e)) = split_slowest_chunk(record.id, &in_flight, &cfg) {
+                        handles.push_back((s, e, 0, spawn_chunk(s, e)));
+                        continue;
+                    }
+                }
+                if let Ok(joined) = tokio::time::timeout(SPLIT_INTERVAL, &mut h).await {
+                    break joined;
+                }
+            };
+            // the panic itself is recorded by the panic hook, the chunk is retried like any other
+            // failed chunk
+            let Err(e) = joined else {
+                continue;
+            };
+            if !e.is_panic() {
+                continue;
+            }
+            let end = lock(&in_flight).remove(&start).map_or(end, |f| f.end());
+            health::update(record.id, |t| t.record_failure());
+            if panics < chunk_retries && !running.is_cancelled() {
+                eprintln!("Chunk {start}-{end} panicked, retrying");
+                handles.push_back((start, end, panics + 1, spawn_chunk(start, end)));
+            } else {
+                eprintln!("Chunk {start}-{end} panicked");
+                db_writer::update_chunk(record.id, start, "Failed").await;
+            }
+        }
+
+        // make sure the finished chunks cover every byte before the file is marked as finished
+        db_writer::flush().await;
+        if ranges_ignored.load(Ordering::Relaxed) && !single_stream && !running.is_cancelled() {
+            eprintln!(
+                "Download {} ignores ranges, downloading it over a single connection",
+                record.id
+            );
+            single_stream = true;
+            ranges = vec![whole_file];
+            let _ = storage::replace_chunks(record.id, &ranges, &cfg);
+            *lock(&progress) = 0;
+            progress::update(record.id, 0);
+            continue;
+        }
+        let (pending, _finished, failed) =
+            storage::count_chunks(record.id, &cfg).unwrap_or_default();
+        if repaired || pending > 0 || failed > 0 || running.is_cancelled() {
+            break;
+        }
+        let finished_ranges: Vec<(u64, u64)> = storage::get_chunks_by_record(record.id, &cfg)
+            .unwrap_or_default()
+            .iter()
+            .filter(|c| c.status == "Finished")
+            .map(|c| (c.start, c.end))
+            .collect();
+        let coverage = chunks::check(&finished_ranges, total_size);
+        if !coverage.overlaps.is_empty() {
+            let _ = storage::delete_duplicate_chunks(record.id, &cfg);
+        }
+        if coverage.gaps.is_empty() {
+            break;
+        }
+
+        eprintln!(
+            "Download {} is missing {:?}, downloading them again",
+            record.id, coverage.gaps
+        );
+        ranges = coverage
+            .gaps
+            .iter()
+            .flat_map(|(s, e)| chunks::plan_range(*s, e + 1, chunk_size))
+            .collect();
+        for (start, end) in &ranges {
+            let _ = storage::save_chunk(&storage::Chunk::new(record.id, *start, *end), &cfg);
+        }
+        repaired = true;
+    }
+    settings_task.abort();
+    // ends the tasks still waiting to take away permits after the concurrency was lowered
+    sem.close();
+
+    drop(tx);
+    let _ = progress_task.await;
+
+    active_downloads().lock().unwrap().remove(&record.id);
+    health::remove(record.id);
+    latency::remove(record.id);
+    progress::finish(record.id);
+
+    if file_changed.load(Ordering::Relaxed) {
+        // the bytes of the new file cannot be written next to the ones of the old one, so the
+        // download is started again, which finds the new file like a resumed download does
+        drop(writer);
+        spawn_retry(record.id);
+        return Ok(());
+    }
+
+    let (pending, _finished, failed) =
+        storage::count_chunks(record.id, &cfg).unwrap_or_default();
+
+    let verified = if failed == 0 && pending == 0 {
+        let path = working.clone();
+        let destination = PathBuf::from(&file.destination_path);
+        let criteria = criteria.clone();
+        let record_id = record.id;
+        let synced = writer.sync().await;
+        // the writer threads let go of the file once the last handle is dropped
+        drop(writer);
+        match synced {
+            Ok(()) => tokio::task::spawn_blocking(move || {
+                let checksum = verify_file(record_id, &path, &criteria)?;
+                files::move_into_place(&path, &destination).map(|()| checksum)
+            })
+            .await
+            .unwrap_or_else(|e| Err(format!("Failed to verify the file: {e}"))),
+            Err(e) => Err(e),
+        }
+    } else {
+        Ok(None)
+    };
+
+    let waiting = failed == 0
+        && pending > 0
+        && network_lost.load(Ordering::Relaxed)
+        && !running.is_cancelled();
+    if waiting {
+        // resumed by `reconnect_loop` once the host can be reached again
+        let _ = storage::update_records_status(&[record.id], connectivity::WAITING, &cfg);
+        emit(Event::Started(DownloadStarted {
+            download_id: record.id,
+            file_url: file.file_url.clone(),
+            file_name: file.file_name.clone(),
+            file_type: file.file_type.to_string(),
+            download_status: connectivity::WAITING.to_string(),
+        }));
+        let text = "The connection was lost, the download carries on once the network is back";
+        message(record.id, text, "error");
+    } else if failed > 0 || pending > 0 {
+        let text = if failed > 0 {
+            "Download completed with errors — some chunks failed"
+        } else {
+            "Download incomplete — some chunks are pending"
+        };
+        if failed > 0 {
+            let _ = storage::update_download_record(record.id, "Failed", None, total_size, &cfg);
+            let error = lock(&last_error).take().unwrap_or_else(|| text.to_string());
+            let failure = diagnosis::Failure {
+                error: &error,
+                url: &final_url,
+                resumable: resumable && !ranges_ignored.load(Ordering::Relaxed),
+            };
+            note_failure(record.id, &failure, &cfg);
+            push(push::PushEvent::Failed, &file.file_name, total_size, Some(&error));
+        }
+        message(record.id, text, "error");
+        notify(
+            "YAD — Download incomplete",
+            format!("{} — {} chunks failed", file.file_name, failed),
+        );
+    } else if let Err(e) = &verified {
+        let _ = storage::update_download_record(record.id, "Failed", None, total_size, &cfg);
+        let failure = diagnosis::Failure {
+            error: e,
+            url: &final_url,
+            resumable: false,
+        };
+        note_failure(record.id, &failure, &cfg);
+        message(record.id, e, "error");
+        notify(
+            "YAD — Download failed verification",
+            format!("{} — {e}", file.file_name),
+        );
+        push(push::PushEvent::Failed, &file.file_name, total_size, Some(e));
+    } else {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        save_checksum(record.id, verified.ok().flatten(), &cfg);
+        let _ =
+            storage::update_download_record(record.id, "Finished", Some(now), total_size, &cfg);
+        strip_finished_urls(record.id, &cfg);
+        metrics::download_finished();
+        archive_history(&cfg);
+        post_process(record.id, &file);
+        open_finished(record.id, &cfg);
+
+        message(record.id, "Download completed successfully", "success");
+        notify(
+            "YAD — Download complete",
+            format!("{} downloaded successfully", file.file_name),
+        );
+        push(push::PushEvent::Finished, &file.file_name, total_size, None);
+
+        run_post_command(templates::post_command(template.as_ref()), &file.destination_path);
+    }
+
+    Ok(())
+}
+
+/// This function pauses a running download. Its finished chunks are kept, so `resume` carries on
+/// where it stopped.
+pub fn pause(download_id: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    if queue::global().remove(download_id) {
+        // a queued download has not started yet, it only leaves the queue
+        let _ = storage::update_records_status(&[download_id], "Cancelled", &cfg);
+        return Ok(());
+    }
+    if storage::record_ids_with_status(connectivity::WAITING, &cfg)
+        .unwrap_or_default()
+        .contains(&download_id)
+    {
+        // stops waiting for the network
+        let _ = storage::update_records_status(&[download_id], "Cancelled", &cfg);
+        return Ok(());
+    }
+    let map = active_downloads().lock().unwrap();
+    if let Some(running) = map.get(&download_id) {
+        running.cancel();
+        let _ = storage::update_download_record(
+            download_id,
+            "Cancelled",
+            None,
+            0,
+            &cfg,
+        );
+        Ok(())
+    } else {
+        Err("No active download found with this id".into())
+    }
+}
+
+/// This function limits the speed of a running download in bytes per second, 0 removes the limit.
+/// The global and template limits still apply when they are lower. The limit is kept until the
+/// download stops.
+pub fn set_speed_limit(id: i64, bytes_per_sec: u64) -> Result<(), String> {
+    let map = active_downloads().lock().unwrap();
+    let running = map
+        .get(&id)
+        .ok_or_else(|| "No active download found with this id".to_string())?;
+    running.set_limits(id, None, Some(bytes_per_sec));
+    Ok(())
+}
+
+/// This function changes the priority of a download. Downloads with a higher priority leave the
+/// queue first. A queued download is moved right away, otherwise the priority applies the next
+/// time the download is queued.
+pub fn set_priority(id: i64, priority: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    storage::update_record_priority(id, priority, &cfg)
+        .map_err(|e| format!("Failed to save priority: {e}"))?;
+    queue::global().set_priority(id, priority);
+    Ok(())
+}
+
+/// This function adds mirrors to a download. They are used the next time it starts, see
+/// `mirrors`.
+pub fn add_mirrors(id: i64, urls: &[String]) -> Result<(), String> {
+    urls.iter().try_for_each(|url| mirrors::validate(url))?;
+    let cfg = config::Config::default();
+    storage::add_mirrors(id, urls, &cfg).map_err(|e| format!("Failed to save mirrors: {e}"))
+}
+
+/// This function changes the category, i.e. the file type, of a download and remembers it for
+/// later downloads with the same extension, see `files::learn_category`.
+///
+/// # Arguments
+/// - `id`: The id of the download.
+/// - `category`: The file type, e.g. `Others`.
+/// - `move_file`: Whether the file is moved into the folder of the category. A running download
+///   cannot be moved.
+pub fn set_category(id: i64, category: &str, move_file: bool) -> Result<(), String> {
+    let file_type =
+        files::FileType::parse(category).ok_or_else(|| format!("Unknown category {category}"))?;
+    let cfg = config::Config::default();
+    let record = storage::read_records_by_ids(&[id], &cfg)
+        .map_err(|e| format!("Failed to read record: {e}"))?
+        .pop()
+        .ok_or("No download record found with this id")?;
+    if move_file && active_download_ids().contains(&id) {
+        return Err("Pause the download before moving its file".into());
+    }
+
+    let extension = record.extension.clone();
+    let mut file = files::File::from(record);
+    let old_path = PathBuf::from(&file.destination_path);
+    file.set_file_type(file_type.clone(), &cfg);
+    if move_file && old_path != Path::new(&file.destination_path) {
+        let target = Path::new(&file.destination_path);
+        if target.exists() {
+            return Err(format!("{} already exists", target.display()));
+        }
+        if old_path.exists() {
+            fs::create_dir_all(&file.destination_dir)
+                .map_err(|e| format!("Failed to create folder: {e}"))?;
+            // renaming fails when the folder is on another drive, so the file is copied instead
+            if fs::rename(&old_path, target).is_err() {
+                fs::copy(&old_path, target).map_err(|e| format!("Failed to copy file: {e}"))?;
+                let _ = fs::remove_file(&old_path);
+            }
+        }
+        storage::update_record_path(id, &file.destination_dir, &file.destination_path, &cfg)
+            .map_err(|e| format!("Failed to save path: {e}"))?;
+    }
+    storage::update_record_file_type(id, category, &cfg)
+        .map_err(|e| format!("Failed to save category: {e}"))?;
+
+    let mut new_settings = settings::current();
+    if files::learn_category(&mut new_settings.category_rules, &extension, &file_type) {
+        settings::update(new_settings, &cfg)
+            .map_err(|e| format!("Failed to update settings: {e}"))?;
+    }
+    Ok(())
+}
+
+/// This function moves a queued download to `position` in the queue, 0 being next. The download
+/// takes the priority of its new neighbours, see `queue::Queue::move_to`.
+pub fn move_in_queue(id: i64, position: usize) -> Result<(), String> {
+    let priority = queue::global()
+        .move_to(id, position)
+        .ok_or("This download is not queued")?;
+    let cfg = config::Config::default();
+    storage::update_record_priority(id, priority, &cfg)
+        .map_err(|e| format!("Failed to save priority: {e}"))
+}
+
+/// This function stops the running and queued downloads in `ids` and marks them paused, in one
+/// transaction.
+///
+/// # Returns
+/// The ids of the downloads paused.
+fn stop(ids: &[i64]) -> Result<Vec<i64>, String> {
+    let cfg = config::Config::default();
+    let waiting = storage::record_ids_with_status(connectivity::WAITING, &cfg).unwrap_or_default();
+    let running: Vec<i64> = {
+        let map = active_downloads().lock().unwrap();
+        ids.iter()
+            .filter_map(|id| {
+                if queue::global().remove(*id) || waiting.contains(id) {
+                    return Some(*id);
+                }
+                map.get(id)?.cancel();
+                Some(*id)
+            })
+            .collect()
+    };
+    storage::update_records_status(&running, "Cancelled", &cfg)
+        .map_err(|e| format!("Failed to pause downloads: {e}"))
+}
+
+/// This function pauses several running downloads. The records are updated in one transaction.
+pub fn pause_many(ids: &[i64]) -> Result<BulkSummary, String> {
+    let paused = stop(ids)?;
+    Ok(BulkSummary::new("pause", ids, paused).emit())
+}
+
+/// This function pauses every running and queued download. A single `Event::Bulk` is emitted
+/// for all of them.
+pub fn pause_all() -> Result<BulkSummary, String> {
+    let mut ids: Vec<i64> = active_downloads().lock().unwrap().keys().copied().collect();
+    ids.extend(queue::global().queued());
+    ids.sort_unstable();
+    ids.dedup();
+    let paused = stop(&ids)?;
+    Ok(BulkSummary::new("pause_all", &ids, paused).emit())
+}
+
+/// This function downloads the failed and pending chunks of a download again, writing them into
+/// the existing file. Finished chunks are kept.
+pub async fn retry(id: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let record = storage::read_records_by_ids(&[id], &cfg)
+        .map_err(|e| format!("Failed to read record: {e}"))?
+        .pop()
+        .ok_or("No download record found with this id")?;
+    if record.download_status == "Finished" {
+        return Err("This download has already finished".into());
+    }
+    // the mirrors are read from the record when the download starts
+    add(DownloadRequest {
+        referer: record.referer,
+        criteria: record.criteria,
+        template_id: record.template_id,
+        parent_id: record.parent_id,
+        byte_range: record.byte_range,
+        open_when_done: record.open_when_done,
+        retry_overrides: record.retry_overrides,
+        video_height: record.video_height,
+        record_id: Some(record.id),
+        ..DownloadRequest::new(&record.file_url)
+    })
+    .await
+}
+
+/// This function starts a download again in the background, see `retry`. The future is boxed
+/// because `retry` ends up in `add_file`, which calls this function.
+fn spawn_retry(id: i64) {
+    let retry: Pin<Box<dyn Future<Output = Result<(), String>> + Send>> = Box::pin(retry(id));
+    tokio::spawn(retry);
+}
+
+/// This function checks whether the file of a finished download changed on the server since it
+/// was downloaded, comparing its ETag or `Last-Modified` date and its size with the ones the
+/// server sends now, and marks the download as outdated if so, see `redownload`.
+///
+/// # Returns
+/// - `Ok(bool)`: Whether the file changed.
+/// - `Err(String)`: If the download has not finished or the server cannot be reached.
+pub async fn check_for_update(id: i64) -> Result<bool, String> {
+    let cfg = config::Config::default();
+    let record = storage::read_records_by_ids(&[id], &cfg)
+        .map_err(|e| format!("Failed to read record: {e}"))?
+        .pop()
+        .ok_or("No download record found with this id")?;
+    if record.download_status != "Finished" {
+        return Err("Only finished downloads can be checked for updates".into());
+    }
+    let current_settings = settings::current();
+    // the server is asked the way the file was downloaded, a template deleted since is left out
+    let template = read_template(record.template_id).ok().flatten();
+    let (request_headers, _) = download_headers(
+        &record.file_url,
+        record.referer.as_deref(),
+        template.as_ref(),
+        &current_settings,
+    );
+    let head_url = s3::head_url(&record.file_url, &current_settings.s3, unix_now())?;
+    let probe_client = settings::shared_probe_client(&current_settings)?;
+    let (head, _, _) = redirects::probe(
+        &probe_client,
+        head_url.as_deref().unwrap_or(&record.file_url),
+        &request_headers,
+        current_settings.max_redirects,
+        &current_settings.cert_pins,
+    )
+    .await?;
+    if !head.status().is_success() {
+        return Err(format!("The server answered {}", head.status()));
+    }
+    // the size of a part is not the size of the file
+    let size = if record.byte_range.is_some() {
+        0
+    } else {
+        record.file_size
+    };
+    let outdated = validators::outdated(record.validator.as_deref(), size, head.headers());
+    storage::update_record_outdated(id, outdated, &cfg)
+        .map_err(|e| format!("Failed to save the check: {e}"))?;
+    Ok(outdated)
+}
+
+/// This function downloads the file of a finished download again, into the same folder and under
+/// the same name, e.g. after `check_for_update` found that it changed on the server.
+pub async fn redownload(id: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let record = storage::read_records_by_ids(&[id], &cfg)
+        .map_err(|e| format!("Failed to read record: {e}"))?
+        .pop()
+        .ok_or("No download record found with this id")?;
+    if record.download_status != "Finished" {
+        return Err("Only finished downloads can be downloaded again".into());
+    }
+    add(DownloadRequest {
+        file_name: Some(record.file_name),
+        destination_dir: Some(record.destination_dir),
+        referer: record.referer,
+        criteria: record.criteria,
+        template_id: record.template_id,
+        parent_id: record.parent_id,
+        byte_range: record.byte_range,
+        replace_finished: true,
+        open_when_done: record.open_when_done,
+        retry_overrides: record.retry_overrides,
+        video_height: record.video_height,
+        ..DownloadRequest::new(&record.file_url)
+    })
+    .await
+}
+
+/// This function starts the downloads of the records in `ids` whose status is one of `statuses`
+/// again. Finished chunks are kept, so the downloads carry on where they stopped.
+fn restart(ids: &[i64], statuses: &[&str]) -> Result<Vec<i64>, String> {
+    let cfg = config::Config::default();
+    let records = storage::read_records_by_ids(ids, &cfg)
+        .map_err(|e| format!("Failed to read records: {e}"))?;
+    let running: Vec<i64> = active_downloads().lock().unwrap().keys().copied().collect();
+    let mut started = Vec::new();
+    for r in records {
+        if running.contains(&r.id)
+            || queue::global().is_queued(r.id)
+            || !statuses.contains(&r.download_status.as_str())
+        {
+            continue;
+        }
+        tokio::spawn(retry(r.id));
+        started.push(r.id);
+    }
+    Ok(started)
+}
+
+/// The statuses of the downloads that can be resumed.
+const RESUMABLE: [&str; 5] = [
+    "Cancelled",
+    "Interrupted",
+    "Pending",
+    "Queued",
+    connectivity::WAITING,
+];
+
+/// This function resumes several paused downloads.
+pub fn resume_many(ids: &[i64]) -> Result<BulkSummary, String> {
+    let resumed = restart(ids, &RESUMABLE)?;
+    Ok(BulkSummary::new("resume", ids, resumed).emit())
+}
+
+/// This function resumes every paused or interrupted download, in the order they were added.
+/// Downloads which are running or already waiting in the queue are left alone. A single
+/// `Event::Bulk` is emitted for all of them.
+pub fn resume_all() -> Result<BulkSummary, String> {
+    let cfg = config::Config::default();
+    let mut ids = RESUMABLE
+        .iter()
+        .map(|status| storage::record_ids_with_status(status, &cfg))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Failed to read paused downloads: {e}"))?
+        .concat();
+    ids.sort_unstable();
+    let queued = queue::global().queued();
+    ids.retain(|id| !queued.contains(id));
+    let resumed = restart(&ids, &RESUMABLE)?;
+    Ok(BulkSummary::new("resume_all", &ids, resumed).emit())
+}
+
+/// This function resumes the downloads that were running or queued when the application last
+/// closed, see `crash::interrupt_stale_downloads`, unless the settings turn it off. It must be
+/// called after `interrupt_stale_downloads` and before any download starts.
+pub async fn resume_interrupted() {
+    if !settings::current().resume_on_start {
+        return;
+    }
+    let cfg = config::Config::default();
+    let ids = [crash::INTERRUPTED, "Queued"]
+        .iter()
+        .map(|status| storage::record_ids_with_status(status, &cfg))
+        .collect::<Result<Vec<_>, _>>();
+    let mut ids: Vec<i64> = match ids {
+        Ok(ids) => ids.concat(),
+        Err(e) => {
+            eprintln!("failed to read interrupted downloads because {e}");
+            return;
+        }
+    };
+    // the queued downloads are started in the order they were added
+    ids.sort_unstable();
+    if ids.is_empty() {
+        return;
+    }
+    if let Err(e) = resume_many(&ids) {
+        eprintln!("failed to resume interrupted downloads because {e}");
+    }
+}
+
+/// This function retries several failed, paused or pending downloads.
+pub fn retry_many(ids: &[i64]) -> Result<BulkSummary, String> {
+    let retried = restart(ids, &["Failed", "Cancelled", "Interrupted", "Pending", "Queued"])?;
+    Ok(BulkSummary::new("retry", ids, retried).emit())
+}
+
+/// This function returns the bytes of a queued download left to download, `None` if its size is
+/// unknown. Records paused before they were queued do not keep their size, so it is taken from
+/// the chunks.
+fn queued_remaining(record: &storage::DownloadRecord, cfg: &config::Config) -> Option<u64> {
+    // downloads of unknown size have no chunk size
+    record.chunk_size?;
+    let chunks = storage::get_chunks_by_record(record.id, cfg).unwrap_or_default();
+    let size = match record.file_size {
+        0 => chunks.iter().map(|c| c.end + 1).max()?,
+        size => size,
+    };
+    let finished: u64 = chunks
+        .iter()
+        .filter(|c| c.status == "Finished")
+        .map(|c| chunks::range_len(c.start, c.end))
+        .sum();
+    Some(size.saturating_sub(finished))
+}
+
+/// This function estimates when every running and queued download will have finished, see
+/// `eta`. Scheduled downloads are counted but not covered.
+pub fn queue_eta() -> Result<eta::QueueEta, String> {
+    let cfg = config::Config::default();
+    let current = settings::current();
+    let mut estimate = eta::QueueEta::default();
+
+    let mut running = Vec::new();
+    for a in progress::active_downloads() {
+        if a.total_size == 0 {
+            estimate.unknown_size += 1;
+            continue;
+        }
+        running.push(eta::Transfer {
+            remaining: a.total_size.saturating_sub(a.downloaded),
+            speed: a.speed,
+        });
+    }
+
+    let ids = queue::global().queued();
+    let records = storage::read_records_by_ids(&ids, &cfg)
+        .map_err(|e| format!("Failed to read records: {e}"))?;
+    let mut queued = Vec::new();
+    // the records are read in any order, the estimate needs the order of the queue
+    for id in ids {
+        match records
+            .iter()
+            .find(|r| r.id == id)
+            .and_then(|r| queued_remaining(r, &cfg))
+        {
+            Some(remaining) => queued.push(remaining),
+            None => estimate.unknown_size += 1,
+        }
+    }
+
+    let jobs = storage::read_scheduled_jobs(&cfg)
+        .map_err(|e| format!("Failed to read scheduled jobs: {e}"))?;
+    estimate.scheduled = jobs.len();
+    estimate.last_scheduled_at = jobs.iter().map(|j| j.start_at).max();
+
+    let limits = eta::Limits {
+        max_concurrent_downloads: idle::concurrent_downloads(&current, idle::is_idle()),
+        max_speed: current.max_speed,
+        max_total_speed: bandwidth::current_limit(&current),
+    };
+    estimate.downloads = running.len() + queued.len();
+    estimate.remaining_bytes =
+        running.iter().map(|t| t.remaining).sum::<u64>() + queued.iter().sum::<u64>();
+    estimate.seconds = eta::estimate(&running, &queued, &limits);
+    estimate.finish_at = estimate.seconds.map(|s| unix_now() + s);
+    Ok(estimate)
+}
+
+/// This function deletes several records in one transaction. They can be restored with `undelete`
+/// until the undo window has passed.
+pub fn delete_many(ids: &[i64]) -> Result<BulkSummary, String> {
+    let cfg = config::Config::default();
+    let deleted = storage::soft_delete_records(ids, unix_now(), &cfg)
+        .map_err(|e| format!("Failed to delete records: {e}"))?;
+    schedule_purge();
+    Ok(BulkSummary::new("delete", ids, deleted).emit())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// This function permanently deletes the records whose undo window has passed, see
+/// `Settings::undo_window_secs`.
+pub fn purge_deleted_records(cfg: &config::Config) {
+    let before = unix_now().saturating_sub(settings::current().undo_window_secs);
+    if let Err(e) = storage::purge_deleted_records(before, cfg) {
+        eprintln!("failed to purge deleted records because {e}");
+    }
+}
+
+/// This function permanently deletes all deleted records without waiting for their undo window
+/// to pass.
+///
+/// # Returns
+/// The number of records purged.
+pub fn purge_deleted() -> Result<usize, String> {
+    let cfg = config::Config::default();
+    storage::purge_deleted_records(u64::MAX, &cfg)
+        .map_err(|e| format!("Failed to purge deleted records: {e}"))
+}
+
+/// This function archives the oldest finished records once the history is longer than
+/// `Settings::max_history_records`, see `history`.
+pub fn archive_history(cfg: &config::Config) {
+    let max_records = settings::current().max_history_records;
+    if let Err(e) = history::archive_overflow(max_records, cfg) {
+        eprintln!("failed to archive the history because {e}");
+    }
+}
+
+/// This function returns the records moved to the archive, oldest first.
+pub fn archived_records() -> Result<Vec<storage::DownloadRecord>, String> {
+    let cfg = config::Config::default();
+    history::read_archive(&history::archive_path(&cfg))
+}
+
+/// This function moves archived records back into the history.
+///
+/// # Arguments
+/// - `ids`: The ids the records had when they were archived.
+pub fn restore_archived(ids: &[i64]) -> Result<BulkSummary, String> {
+    let cfg = config::Config::default();
+    let restored = history::restore(ids, &cfg)?;
+    Ok(BulkSummary::new("restore_archived", ids, restored).emit())
+}
+
+/// This function deletes a record. The record can be restored with `undelete` until the undo
+/// window has passed, after which it is purged.
+pub fn delete(id: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    storage::soft_delete_record(id, unix_now(), &cfg)
+        .map_err(|e| format!("Failed to delete record: {e}"))?;
+    schedule_purge();
+    Ok(())
+}
+
+/// This function purges the records deleted now once their undo window has passed.
+fn schedule_purge() {
+    tokio::spawn(async {
+        let window = Duration::from_secs(settings::current().undo_window_secs);
+        tokio::time::sleep(window + Duration::from_secs(1)).await;
+        purge_deleted_records(&config::Config::default());
+    });
+}
+
+/// This function restores a record deleted with `delete` or `delete_many`.
+pub fn undelete(id: i64) -> Result<(), String> {
+    let cfg = config::Config::default();
+    match storage::restore_record(id, &cfg) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err("The record can no longer be restored".to_string()),
+        Err(e) => Err(format!("Failed to restore record: {e}")),
+    }
+}
+
+/// This function scans the watch folders from the settings and starts downloading the urls found
+/// in dropped files. It runs forever and picks up settings changes on the next scan.
+pub async fn watch_folders_loop() {
+    loop {
+        tokio::time::sleep(watch_folders::SCAN_INTERVAL).await;
+
+        let current_settings = settings::current();
+        for folder in &current_settings.watch_folders {
+            for path in watch_folders::scan(Path::new(folder)) {
+                let bytes = match fs::read(&path) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        eprintln!("failed to read {} because {e}", path.display());
+                        continue;
+                    }
+                };
+                // binary files such as torrents are rejected instead of being read on every scan
+                let contents =
+                    String::from_utf8(bytes).map_err(|_| "it is not a text file".to_string());
+                // a Metalink 4 file lists files, each downloaded once from all its mirrors
+                let ingested = contents.and_then(|contents| {
+                    if metalink::is_metalink(&path) {
+                        metalink::parse(&contents).map(|files| {
+                            tokio::spawn(start_metalink(files, None));
+                        })
+                    } else {
+                        watch_folders::extract_urls(&path, &contents).map(|urls| {
+                            for url in urls {
+                                tokio::spawn(add(DownloadRequest::new(&url)));
+                            }
+                        })
+                    }
+                });
+                let accepted = match ingested {
+                    Ok(()) => true,
+                    Err(e) => {
+                        eprintln!("skipping {} because {e}", path.display());
+                        false
+                    }
+                };
+                if let Err(e) =
+                    watch_folders::finish(&path, accepted, current_settings.archive_watched_files)
+                {
+                    eprintln!(
+                        "failed to move {} out of the watch folder because {e}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// This function adds the download described by a job file. When the url cannot be reached the
+/// mirrors are tried, the hosts that answered fastest so far first.
+pub async fn start_job(job: jobfile::JobFile) -> Result<(), String> {
+    let cfg = config::Config::default();
+    let template_id = match &job.template {
+        Some(name) => Some(
+            storage::read_templates(&cfg)
+                .unwrap_or_default()
+                .into_iter()
+                .find(|t| t.name.eq_ignore_ascii_case(name.trim()))
+                .map(|t| t.id)
+                .ok_or_else(|| format!("The job file needs the template {name}"))?,
+        ),
+        None => None,
+    };
+    let criteria = criteria::SuccessCriteria {
+        sha256: job.sha256.clone(),
+        ..criteria::SuccessCriteria::default()
+    };
+    let mut result = Err("The job file has no url".to_string());
+    for url in latency::rank(job.urls()) {
+        result = add(DownloadRequest {
+            file_name: job.file_name.clone(),
+            criteria: Some(criteria.clone()),
+            template_id,
+            ..DownloadRequest::new(url)
+        })
+        .await;
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+/// This function adds the downloads of the files a Metalink file lists, see `metalink`. Each file
+/// is downloaded from all of its HTTP urls at once and checked against its checksum. The
+/// downloads run side by side, as far as the queue lets them.
+///
+/// # Returns
+/// The error of the first download that failed, if any.
+pub async fn start_metalink(
+    files: Vec<metalink::MetalinkFile>,
+    destination_dir: Option<String>,
+) -> Result<(), String> {
+    let downloads: Vec<_> = files
+        .iter()
+        .filter_map(|file| {
+            let (url, mirrors) = file.sources()?;
+            Some(tokio::spawn(crash::watch(add_file(DownloadRequest {
+                file_name: Some(file.name.clone()),
+                destination_dir: destination_dir.clone(),
+                criteria: Some(file.criteria()),
+                mirrors,
+                ..DownloadRequest::new(url)
+            }))))
+        })
+        .collect();
+    let mut result = Ok(());
+    for download in downloads {
+        let outcome = download.await.unwrap_or_else(|e| Err(e.to_string()));
+        if result.is_ok() {
+            result = outcome;
+        }
+    }
+    result
+}
+
+/// This function downloads the Metalink file at `url` and adds the downloads of the files it
+/// lists, see `start_metalink`.
+async fn add_metalink(url: &str, destination_dir: Option<String>) -> Result<(), String> {
+    let client = settings::shared_client(&settings::current())?;
+    let content = async {
+        client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await
+    }
+    .await
+    .map_err(|e| format!("Failed to download the Metalink file: {e}"));
+    let files = content
+        .and_then(|content| metalink::parse(&content))
+        .inspect_err(|e| message(0, e, "error"))?;
+    start_metalink(files, destination_dir).await
+}
+
+/// This function restarts the running downloads that are stuck, see `watchdog`. It runs forever.
+pub async fn watchdog_loop() {
+    loop {
+        tokio::time::sleep(watchdog::CHECK_INTERVAL).await;
+        let stuck_after = settings::current().stuck_after_secs;
+        let now = unix_now();
+        let stuck: Vec<(i64, Arc<RunningDownload>)> = active_downloads()
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, running)| {
+                let last_activity = running.last_activity.load(Ordering::Relaxed);
+                !running.is_cancelled() && watchdog::is_stuck(last_activity, now, stuck_after)
+            })
+            .map(|(id, running)| (*id, Arc::clone(running)))
+            .collect();
+        for (id, running) in stuck {
+            tokio::spawn(recover(id, running));
+        }
+    }
+}
+
+/// This function resumes the downloads waiting for the network, see `connectivity`, once their
+/// host can be reached again, and fails the ones that have waited `connectivity::MAX_ATTEMPTS`
+/// times. It runs forever.
+pub async fn reconnect_loop() {
+    let cfg = config::Config::default();
+    let mut interval = connectivity::MIN_INTERVAL;
+    // the checks of each download, kept while it waits or runs after being resumed
+    let mut attempts: HashMap<i64, u32> = HashMap::new();
+    loop {
+        tokio::time::sleep(interval).await;
+        let ids = storage::record_ids_with_status(connectivity::WAITING, &cfg).unwrap_or_default();
+        {
+            let running = active_downloads().lock().unwrap();
+            attempts.retain(|id, _| ids.contains(id) || running.contains_key(id));
+        }
+        if ids.is_empty() {
+            interval = connectivity::MIN_INTERVAL;
+            continue;
+        }
+        // a host that answers but keeps dropping the connection is not retried in a tight loop
+        interval = connectivity::next_interval(interval);
+        let Ok(client) = settings::shared_probe_client(&settings::current()) else {
+            continue;
+        };
+        for record in storage::read_records_by_ids(&ids, &cfg).unwrap_or_default() {
+            let attempt = attempts.entry(record.id).or_default();
+            *attempt += 1;
+            if *attempt > connectivity::MAX_ATTEMPTS {
+                attempts.remove(&record.id);
+                let note = "The host could not be reached for too long. Retry the download once \
+                            the network is back.";
+                let _ = storage::update_records_status(&[record.id], "Failed", &cfg);
+                let _ = storage::update_failure_note(record.id, Some(note), &cfg);
+                emit(Event::Started(DownloadStarted {
+                    download_id: record.id,
+                    file_url: record.file_url.clone(),
+                    file_name: record.file_name.clone(),
+                    file_type: record.file_type.clone(),
+                    download_status: "Failed".to_string(),
+                }));
+                message(record.id, note, "error");
+                continue;
+            }
+            let url = record.final_url.as_deref().unwrap_or(&record.file_url);
+            if connectivity::is_reachable(&client, url).await {
+                println!("Download {} can reach its host again, resuming it", record.id);
+                tokio::spawn(retry(record.id));
+            }
+        }
+    }
+}
+
+/// This function stops a stuck download and starts it again. Its chunks that have not finished
+/// are set back to `Pending` without counting an attempt, the finished ones are kept.
+async fn recover(id: i64, running: Arc<RunningDownload>) {
+    eprintln!("Download {id} has made no progress for a while, restarting it");
+    running.cancel();
+    let stopped = tokio::time::timeout(watchdog::STOP_TIMEOUT, async {
+        while active_downloads().lock().unwrap().contains_key(&id) {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await;
+    if stopped.is_err() {
+        // the task running the download is gone, so nothing else will let go of it
+        eprintln!("Download {id} did not stop, starting it again anyway");
+        let mut map = active_downloads().lock().unwrap();
+        if map.get(&id).is_some_and(|r| Arc::ptr_eq(r, &running)) {
+            map.remove(&id);
+        }
+        drop(map);
+        health::remove(id);
+        latency::remove(id);
+        progress::finish(id);
+    }
+
+    let cfg = config::Config::default();
+    db_writer::flush().await;
+    if let Err(e) = storage::reset_unfinished_chunks(id, 0, &cfg) {
+        eprintln!("failed to reset the chunks of download {id} because {e}");
+    }
+    message(id, "The download was stuck and has been restarted", "error");
+    if let Err(e) = retry(id).await {
+        eprintln!("failed to restart download {id} because {e}");
+    }
+}
+
+/// This function applies the bandwidth rule of the time of day to the total speed limit whenever
+/// another rule starts or ends, see `bandwidth`, and raises the limits while the user is away,
+/// see `idle`. It runs forever.
+pub async fn bandwidth_loop() {
+    let os = config::Config::default().os;
+    loop {
+        tokio::time::sleep(bandwidth::CHECK_INTERVAL).await;
+        let current = settings::current();
+        let checked = {
+            let (os, current) = (os.clone(), current.clone());
+            tokio::task::spawn_blocking(move || idle::check(&os, &current)).await
+        };
+        if checked.unwrap_or(false) {
+            let state = if idle::is_idle() { "away" } else { "back" };
+            eprintln!("the user is {state}, applying the limits for it");
+        }
+        let limit = bandwidth::current_limit(&current);
+        if limit != throttle::global().rate() {
+            throttle::global().set_rate(limit);
+        }
+        let max_downloads = idle::concurrent_downloads(&current, idle::is_idle());
+        if max_downloads != queue::global().limit() {
+            queue::global().set_limit(max_downloads);
+        }
+    }
+}
+
+/// This function starts scheduled downloads once they are due. Recurring jobs are moved to their
+/// next run and one-off jobs are removed. The wake timer is kept set for the next job while
+/// `Settings::wake_for_scheduled` is on, see `power`. It runs forever.
+pub async fn scheduler_loop() {
+    let mut last = unix_now();
+    loop {
+        tokio::time::sleep(scheduler::CHECK_INTERVAL).await;
+
+        let cfg = config::Config::default();
+        let now = unix_now();
+        let jobs = storage::read_scheduled_jobs(&cfg).unwrap_or_default();
+        let slept = power::slept(last, now, scheduler::CHECK_INTERVAL.as_secs());
+        let woken = power::woken_by_timer(slept, now);
+        last = now;
+
+        let mut started = Vec::new();
+        let mut upcoming = Vec::new();
+        for job in jobs {
+            if !job.is_due(now) {
+                upcoming.push(job.start_at);
+                continue;
+            }
+            let next = job.next_run(now);
+            let result = match next {
+                Some(next) => storage::reschedule_job(job.id, next, &cfg),
+                None => storage::delete_scheduled_job(job.id, &cfg),
+            };
+            if let Err(e) = result {
+                eprintln!("failed to update scheduled job {} because {e}", job.id);
+                continue;
+            }
+            upcoming.extend(next);
+            started.push(tokio::spawn(add(DownloadRequest {
+                file_name: job.file_name,
+                destination_dir: job.destination_dir,
+                ..DownloadRequest::new(&job.file_url)
+            })));
+        }
+
+        let current = settings::current();
+        let wake_at = power::next_wake(upcoming, now).filter(|_| current.wake_for_scheduled);
+        if let Err(e) = power::arm(&cfg.os, wake_at) {
+            eprintln!("failed to set the wake timer because {e}");
+        }
+        if woken && current.sleep_after_scheduled && !started.is_empty() {
+            tokio::spawn(sleep_when_done(started, cfg.os));
+        }
+    }
+}
+
+/// This function puts the computer back to sleep once the scheduled downloads it was woken for
+/// are done, unless other downloads are running or queued by then.
+async fn sleep_when_done(started: Vec<tokio::task::JoinHandle<Result<(), String>>>, os: String) {
+    for download in started {
+        let _ = download.await;
+    }
+    let idle = active_downloads().lock().unwrap().is_empty() && queue::global().queued().is_empty();
+    if !idle || !settings::current().sleep_after_scheduled {
+        return;
+    }
+    if let Err(e) = power::sleep(&os) {
+        eprintln!("failed to put the computer to sleep because {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_supported_url() {
+        for url in [
+            "https://example.com/a.zip",
+            "ftpes://ftp.example.com/a.zip",
+            "sftp://me@example.com/a.zip",
+            "s3://bucket/a.zip",
+            "data:text/plain,hello",
+        ] {
+            assert!(is_supported_url(url), "{url}");
+        }
+        assert!(!is_supported_url("file:///etc/passwd"));
+        assert!(!is_supported_url("example.com/a.zip"));
+    }
+
+    #[test]
+    fn test_bulk_summary_reports_skipped_ids() {
+        let mut events = subscribe();
+        let summary = BulkSummary::new("pause", &[1, 2, 3], vec![2]).emit();
+        assert_eq!(summary.succeeded, [2]);
+        assert_eq!(summary.skipped, [1, 3]);
+        match events.try_recv().unwrap() {
+            Event::Bulk(emitted) => assert_eq!(emitted.action, "pause"),
+            e => panic!("unexpected event {e:?}"),
+        }
+    }
+}
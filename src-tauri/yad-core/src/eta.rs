@@ -0,0 +1,168 @@
+//! This module estimates when every running and queued download will have finished. The running
+//! downloads carry on at their current speed, queued downloads take the slots that free up in
+//! order and are expected to reach the average speed of the running ones, and the speed limits
+//! of the settings cap both. Downloads whose size is unknown are left out.
+
+use std::collections::VecDeque;
+
+use serde::Serialize;
+
+/// This struct represents a running download.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transfer {
+    /// The bytes left to download.
+    pub remaining: u64,
+    /// Bytes per second over the last few seconds.
+    pub speed: u64,
+}
+
+/// This struct holds the settings the estimate depends on. 0 means unlimited for all of them.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Limits {
+    pub max_concurrent_downloads: usize,
+    pub max_speed: u64,
+    pub max_total_speed: u64,
+}
+
+/// This struct represents the estimate as sent to the frontend.
+#[derive(Debug, Clone, PartialEq, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct QueueEta {
+    /// The running and queued downloads the estimate covers.
+    pub downloads: usize,
+    /// The downloads left out because their size is unknown.
+    pub unknown_size: usize,
+    pub remaining_bytes: u64,
+    /// Seconds until every download covered has finished, `None` while no speed has been
+    /// measured yet.
+    pub seconds: Option<u64>,
+    /// When every download covered has finished as a unix timestamp in seconds.
+    pub finish_at: Option<u64>,
+    /// The scheduled downloads which have not started yet. They are not covered as their size is
+    /// unknown until they start.
+    pub scheduled: usize,
+    /// When the last scheduled download starts as a unix timestamp in seconds.
+    pub last_scheduled_at: Option<u64>,
+}
+
+/// This function returns the speed of a download once the limit of each download is applied.
+fn capped(speed: f64, limits: &Limits) -> f64 {
+    if limits.max_speed > 0 {
+        speed.min(limits.max_speed as f64)
+    } else {
+        speed
+    }
+}
+
+/// This function estimates how many seconds it takes until the running and queued downloads have
+/// all finished.
+///
+/// # Arguments
+/// - `running`: The running downloads.
+/// - `queued`: The bytes left of each queued download, next first.
+/// - `limits`: The limits of the settings.
+///
+/// # Returns
+/// `None` if a running download has not sent anything yet, or if downloads are queued while none
+/// is running to tell how fast they will be.
+pub fn estimate(running: &[Transfer], queued: &[u64], limits: &Limits) -> Option<u64> {
+    if running.iter().any(|t| t.remaining > 0 && t.speed == 0) {
+        return None;
+    }
+    let mut running: Vec<(f64, f64)> = running
+        .iter()
+        .filter(|t| t.remaining > 0)
+        .map(|t| (t.remaining as f64, capped(t.speed as f64, limits)))
+        .collect();
+    let mut queued: VecDeque<f64> = queued.iter().map(|r| *r as f64).collect();
+    if !queued.is_empty() && running.is_empty() {
+        return None;
+    }
+    let queued_speed = running.iter().map(|(_, s)| s).sum::<f64>() / running.len().max(1) as f64;
+    let slots = match limits.max_concurrent_downloads {
+        0 => usize::MAX,
+        n => n,
+    };
+
+    let mut elapsed = 0.0;
+    loop {
+        while running.len() < slots {
+            let Some(remaining) = queued.pop_front() else {
+                break;
+            };
+            running.push((remaining, queued_speed));
+        }
+        if running.is_empty() {
+            break;
+        }
+        // the downloads share the total limit in proportion to their speed
+        let total: f64 = running.iter().map(|(_, s)| s).sum();
+        let scale = if limits.max_total_speed > 0 && total > limits.max_total_speed as f64 {
+            limits.max_total_speed as f64 / total
+        } else {
+            1.0
+        };
+        let step = running
+            .iter()
+            .map(|(remaining, speed)| remaining / (speed * scale))
+            .fold(f64::INFINITY, f64::min);
+        elapsed += step;
+        for (remaining, speed) in &mut running {
+            *remaining -= *speed * scale * step;
+        }
+        // less than a byte left is rounding
+        running.retain(|(remaining, _)| *remaining >= 1.0);
+    }
+    Some(elapsed.ceil() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(remaining: u64, speed: u64) -> Transfer {
+        Transfer { remaining, speed }
+    }
+
+    #[test]
+    fn test_estimate_running() {
+        let limits = Limits::default();
+        assert_eq!(estimate(&[], &[], &limits), Some(0));
+        assert_eq!(
+            estimate(&[transfer(1000, 100), transfer(500, 100)], &[], &limits),
+            Some(10)
+        );
+        // nothing received yet
+        assert_eq!(estimate(&[transfer(1000, 0)], &[], &limits), None);
+        assert_eq!(estimate(&[], &[1000], &limits), None);
+    }
+
+    #[test]
+    fn test_estimate_queue_takes_free_slots() {
+        let limits = Limits {
+            max_concurrent_downloads: 2,
+            ..Limits::default()
+        };
+        // the 400 byte download finishes after 4 seconds, the queued one then runs at the
+        // average speed of 100 bytes per second until 4 + 10 seconds
+        let running = [transfer(400, 100), transfer(1000, 100)];
+        assert_eq!(estimate(&running, &[1000], &limits), Some(14));
+    }
+
+    #[test]
+    fn test_estimate_applies_speed_limits() {
+        let limits = Limits {
+            max_speed: 50,
+            ..Limits::default()
+        };
+        assert_eq!(estimate(&[transfer(1000, 100)], &[], &limits), Some(20));
+
+        let limits = Limits {
+            max_total_speed: 100,
+            ..Limits::default()
+        };
+        // two downloads at 100 bytes per second share 100 bytes per second
+        let running = [transfer(500, 100), transfer(500, 100)];
+        assert_eq!(estimate(&running, &[], &limits), Some(10));
+    }
+}
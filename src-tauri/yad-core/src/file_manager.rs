@@ -0,0 +1,313 @@
+//! This module finds the program used to show folders. `xdg-open` alone does not work everywhere,
+//! e.g. on tiling window managers without a desktop environment, so on Linux the default file
+//! manager is looked up with `xdg-mime` and its desktop entry, and on Windows in the registry. The
+//! user can also set a file manager in the settings, which always wins.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
+
+use serde::{Deserialize, Serialize};
+
+/// This struct represents a program and the arguments given before the path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Launcher {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl Launcher {
+    fn new(program: &str) -> Self {
+        Launcher {
+            program: program.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// This function runs the program with `path` as its last argument.
+    pub fn open(&self, path: &Path) -> Result<(), String> {
+        Command::new(&self.program)
+            .args(&self.args)
+            .arg(path)
+            .spawn()
+            .map(|_| ())
+            .map_err(|e| format!("Failed to run {}: {e}", self.program))
+    }
+}
+
+/// This function splits a command line into words, keeping quoted words together.
+fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut quote: Option<char> = None;
+    let mut in_word = false;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => word.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                word.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// This function turns a command line into a launcher. Placeholders for the path such as `%U`
+/// in desktop entries or `"%1"` in the Windows registry are dropped since the path is always
+/// added as the last argument.
+pub fn parse_command(command: &str) -> Option<Launcher> {
+    let mut words = split_command(command)
+        .into_iter()
+        .filter(|w| !(w.len() == 2 && w.starts_with('%')));
+    let program = words.next()?;
+    Some(Launcher {
+        program,
+        args: words.collect(),
+    })
+}
+
+/// This function returns the `Exec` line of the `[Desktop Entry]` group of a desktop entry.
+fn desktop_exec(contents: &str) -> Option<&str> {
+    let mut in_entry = false;
+    for line in contents.lines().map(str::trim) {
+        if line.starts_with('[') {
+            in_entry = line == "[Desktop Entry]";
+        } else if in_entry {
+            if let Some(exec) = line.strip_prefix("Exec=") {
+                return Some(exec);
+            }
+        }
+    }
+    None
+}
+
+/// This function returns the folders desktop entries are installed in, most specific first.
+fn application_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    match env::var("XDG_DATA_HOME") {
+        Ok(data_home) if !data_home.is_empty() => dirs.push(PathBuf::from(data_home)),
+        _ => {
+            if let Ok(home) = env::var("HOME") {
+                dirs.push(Path::new(&home).join(".local/share"));
+            }
+        }
+    }
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|d| !d.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+    dirs.extend(data_dirs.split(':').map(PathBuf::from));
+    dirs.into_iter().map(|d| d.join("applications")).collect()
+}
+
+/// This function looks up the default file manager on Linux through `xdg-mime`.
+fn detect_linux() -> Option<Launcher> {
+    let output = Command::new("xdg-mime")
+        .args(["query", "default", "inode/directory"])
+        .output()
+        .ok()?;
+    let desktop_file = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if desktop_file.is_empty() {
+        return None;
+    }
+    application_dirs().into_iter().find_map(|dir| {
+        let contents = std::fs::read_to_string(dir.join(&desktop_file)).ok()?;
+        parse_command(desktop_exec(&contents)?)
+    })
+}
+
+/// This function returns the default value printed by `reg query <key> /ve`.
+fn reg_default_value(output: &str) -> Option<&str> {
+    output.lines().find_map(|line| {
+        let (_, value) = line.split_once("REG_SZ")?;
+        Some(value.trim()).filter(|v| !v.is_empty())
+    })
+}
+
+/// This function looks up the program registered to open folders on Windows.
+fn detect_windows() -> Option<Launcher> {
+    let output = Command::new("reg")
+        .args(["query", r"HKCR\Directory\shell\open\command", "/ve"])
+        .output()
+        .ok()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    parse_command(reg_default_value(&stdout)?)
+}
+
+/// This function returns the program used to open folders.
+///
+/// # Arguments
+/// - `os`: The operating system, see `Config::os`.
+/// - `configured`: The file manager set in the settings, if any.
+pub fn folder_opener(os: &str, configured: Option<&str>) -> Launcher {
+    if let Some(launcher) = configured.and_then(parse_command) {
+        return launcher;
+    }
+    // the default file manager rarely changes, so it is only looked up once
+    static DETECTED: OnceLock<Launcher> = OnceLock::new();
+    DETECTED
+        .get_or_init(|| match os {
+            "Windows" => detect_windows().unwrap_or_else(|| Launcher::new("explorer")),
+            "Darwin" => Launcher::new("open"),
+            _ => detect_linux().unwrap_or_else(|| Launcher::new("xdg-open")),
+        })
+        .clone()
+}
+
+/// This function returns the program used to open a file with its default application.
+pub fn file_opener(os: &str) -> Launcher {
+    match os {
+        "Windows" => Launcher::new("explorer"),
+        "Darwin" => Launcher::new("open"),
+        _ => Launcher::new("xdg-open"),
+    }
+}
+
+/// This enum represents what is opened when a download finishes, see
+/// `DownloadRecord::open_when_done`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OpenWhenDone {
+    /// The file, with its default application.
+    File,
+    /// The folder, with the file selected where the platform supports it.
+    Folder,
+}
+
+impl OpenWhenDone {
+    /// This function returns the name it is saved as, e.g. `folder`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OpenWhenDone::File => "file",
+            OpenWhenDone::Folder => "folder",
+        }
+    }
+
+    /// This function reads a name saved with `as_str`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "file" => Some(OpenWhenDone::File),
+            "folder" => Some(OpenWhenDone::Folder),
+            _ => None,
+        }
+    }
+
+    /// This function opens the finished file at `path`, or its folder.
+    pub fn open(&self, path: &Path, os: &str, configured: Option<&str>) -> Result<(), String> {
+        match self {
+            OpenWhenDone::File => file_opener(os).open(path),
+            OpenWhenDone::Folder => reveal(path, os, configured),
+        }
+    }
+}
+
+/// This function shows a file in the file manager with the file selected where the platform
+/// supports it, otherwise it opens the folder containing the file.
+pub fn reveal(path: &Path, os: &str, configured: Option<&str>) -> Result<(), String> {
+    if configured.is_none() {
+        let selected = match os {
+            "Windows" => Command::new("explorer")
+                .arg(format!("/select,{}", path.display()))
+                .spawn()
+                .is_ok(),
+            "Darwin" => Command::new("open").arg("-R").arg(path).spawn().is_ok(),
+            // the FileManager1 interface is implemented by most Linux file managers
+            _ => Command::new("dbus-send")
+                .args([
+                    "--session",
+                    "--dest=org.freedesktop.FileManager1",
+                    "--type=method_call",
+                    "/org/freedesktop/FileManager1",
+                    "org.freedesktop.FileManager1.ShowItems",
+                ])
+                .arg(format!("array:string:file://{}", path.display()))
+                .arg("string:")
+                .status()
+                .is_ok_and(|s| s.success()),
+        };
+        if selected {
+            return Ok(());
+        }
+    }
+    let folder = path.parent().unwrap_or(path);
+    folder_opener(os, configured).open(folder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_when_done_names() {
+        for action in [OpenWhenDone::File, OpenWhenDone::Folder] {
+            assert_eq!(OpenWhenDone::parse(action.as_str()), Some(action));
+        }
+        assert_eq!(OpenWhenDone::parse("terminal"), None);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            parse_command("dolphin %u").unwrap(),
+            Launcher::new("dolphin")
+        );
+        assert_eq!(
+            parse_command("thunar --daemon %F").unwrap(),
+            Launcher {
+                program: "thunar".into(),
+                args: vec!["--daemon".into()],
+            }
+        );
+        assert_eq!(
+            parse_command(r#""C:\Program Files\Files\Files.exe" "%1""#).unwrap(),
+            Launcher::new(r"C:\Program Files\Files\Files.exe")
+        );
+        assert!(parse_command("   ").is_none());
+    }
+
+    #[test]
+    fn test_desktop_exec() {
+        let entry = "[Desktop Entry]\nName=Dolphin\nExec=dolphin %u\n\n[Desktop Action new]\nExec=dolphin --new-window\n";
+        assert_eq!(desktop_exec(entry), Some("dolphin %u"));
+        assert_eq!(desktop_exec("[Desktop Action x]\nExec=x\n"), None);
+    }
+
+    #[test]
+    fn test_reg_default_value() {
+        let output = "\r\nHKEY_CLASSES_ROOT\\Directory\\shell\\open\\command\r\n    (Default)    REG_SZ    \"C:\\Tools\\fm.exe\" \"%1\"\r\n\r\n";
+        assert_eq!(
+            reg_default_value(output),
+            Some("\"C:\\Tools\\fm.exe\" \"%1\"")
+        );
+        assert_eq!(reg_default_value("ERROR: not found"), None);
+    }
+
+    #[test]
+    fn test_configured_file_manager_wins() {
+        assert_eq!(
+            folder_opener("Linux", Some("nemo --existing-window")),
+            Launcher {
+                program: "nemo".into(),
+                args: vec!["--existing-window".into()],
+            }
+        );
+    }
+}
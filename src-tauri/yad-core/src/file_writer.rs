@@ -0,0 +1,421 @@
+//! This module writes the chunks of a download to disk from dedicated threads. Chunk workers send
+//! the bytes they downloaded instead of sharing the file, a writer takes whatever has queued up
+//! and sorts it by offset, so the disk is written mostly front to back. Each write is positioned,
+//! `write_at` on Unix and `seek_write` on Windows, instead of a seek followed by a write. `sync`
+//! makes sure everything has reached the disk before the file is verified.
+//!
+//! A download with several workers gets a writer thread, with a handle of its own to the file,
+//! for up to `MAX_HANDLES` of them, and the workers take turns between them, see `for_worker`. So
+//! the throughput of the disk grows with the number of connections instead of every write waiting
+//! for a single handle.
+//!
+//! Very large files can instead be mapped into memory, see `open_mapped`: the bytes are copied
+//! straight into the mapping on a blocking thread and the pages are written back in the
+//! background. Whether this is faster depends on the disk and the system, so it is off unless
+//! `Settings::mmap_min_size` is set. A write into a hole of a sparse file that the disk has no
+//! room for kills the process instead of failing, so only files whose space was reserved are
+//! mapped, see `allocation::allocate`.
+//!
+//! Workers do not have to wait for each write: `queue` hands the bytes over and returns a
+//! `PendingWrite`, so a worker can download on while the disk catches up, see
+//! `engine::PREFETCH_DEPTH`.
+
+use std::{
+    fs::File,
+    io,
+    ops::Range,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use memmap2::MmapMut;
+
+use tokio::sync::{mpsc, oneshot};
+
+/// The number of chunks that can be queued before workers wait for the writer.
+const QUEUE_SIZE: usize = 16;
+
+/// The most handles, and writer threads, a file is written through.
+pub const MAX_HANDLES: usize = 8;
+
+/// Answers a message once it has been handled.
+type Ack = oneshot::Sender<Result<(), String>>;
+
+enum Message {
+    Write {
+        offset: u64,
+        bytes: Vec<u8>,
+        done: Ack,
+    },
+    /// Answered once every chunk queued before it has been written and synced.
+    Sync(Ack),
+}
+
+/// This struct represents a write handed to the writer thread that may not have been written yet.
+pub struct PendingWrite(oneshot::Receiver<Result<(), String>>);
+
+impl PendingWrite {
+    /// This function waits until the bytes have been written.
+    pub async fn wait(self) -> Result<(), String> {
+        self.0
+            .await
+            .map_err(|_| "The file writer has stopped".to_string())?
+    }
+}
+
+/// This enum represents how the bytes reach the file.
+#[derive(Clone)]
+enum Backend {
+    /// The queue of each writer thread.
+    Threads(Arc<Vec<mpsc::Sender<Message>>>),
+    /// The file mapped into memory.
+    Mapped(Arc<Mutex<MmapMut>>),
+}
+
+/// This struct is the handle of the writer threads of a file, or of its mapping. The threads stop,
+/// and the mapping is released, once every handle is dropped.
+pub struct FileWriter {
+    backend: Backend,
+    /// The writer thread the writes of this handle go to.
+    lane: usize,
+    /// The lane of the next worker, see `for_worker`.
+    next_lane: Arc<AtomicUsize>,
+    /// The writes queued but not written yet.
+    pending: Arc<AtomicUsize>,
+}
+
+impl FileWriter {
+    /// This function starts a writer thread for `file`.
+    pub fn open(file: File) -> Result<Self, String> {
+        Self::open_handles(vec![file])
+    }
+
+    /// This function starts a writer thread for each of `files`, handles to the same file.
+    pub fn open_handles(files: Vec<File>) -> Result<Self, String> {
+        let pending = Arc::new(AtomicUsize::new(0));
+        let mut lanes = Vec::with_capacity(files.len());
+        for file in files {
+            let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+            let written = Arc::clone(&pending);
+            thread::Builder::new()
+                .name("yad-file-writer".into())
+                .spawn(move || run(rx, file, &written))
+                .map_err(|e| format!("Failed to start the file writer: {e}"))?;
+            lanes.push(tx);
+        }
+        if lanes.is_empty() {
+            return Err("The file writer needs a file".to_string());
+        }
+        Ok(FileWriter {
+            backend: Backend::Threads(Arc::new(lanes)),
+            lane: 0,
+            next_lane: Arc::new(AtomicUsize::new(0)),
+            pending,
+        })
+    }
+
+    /// This function maps `file` into memory, with the size it has now, so that the bytes are
+    /// copied into the mapping instead of being written. The file must be open for reading too,
+    /// and its space must be reserved.
+    pub fn open_mapped(file: &File) -> Result<Self, String> {
+        // SAFETY: the working file is hidden and named after its download, nothing else writes to
+        // it or shortens it while the download is running, see `files::working_path`
+        let map = unsafe { MmapMut::map_mut(file) }
+            .map_err(|e| format!("Failed to map the file: {e}"))?;
+        Ok(FileWriter {
+            backend: Backend::Mapped(Arc::new(Mutex::new(map))),
+            lane: 0,
+            next_lane: Arc::new(AtomicUsize::new(0)),
+            pending: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+
+    /// This function returns a handle for a chunk worker. Workers take turns between the writer
+    /// threads.
+    pub fn for_worker(&self) -> FileWriter {
+        let lanes = match &self.backend {
+            Backend::Threads(lanes) => lanes.len(),
+            Backend::Mapped(_) => 1,
+        };
+        FileWriter {
+            backend: self.backend.clone(),
+            lane: self.next_lane.fetch_add(1, Ordering::Relaxed) % lanes,
+            next_lane: Arc::clone(&self.next_lane),
+            pending: Arc::clone(&self.pending),
+        }
+    }
+
+    /// This function hands `bytes` to the writer thread to be written at `offset`. It only waits
+    /// when the queue of the writer is full.
+    pub async fn queue(&self, offset: u64, bytes: Vec<u8>) -> Result<PendingWrite, String> {
+        let (done, wait) = oneshot::channel();
+        let lanes = match &self.backend {
+            Backend::Threads(lanes) => lanes,
+            Backend::Mapped(map) => {
+                // copying may wait for pages to be read from the disk
+                let map = Arc::clone(map);
+                let pending = Arc::clone(&self.pending);
+                pending.fetch_add(1, Ordering::Relaxed);
+                tokio::task::spawn_blocking(move || {
+                    let copied = copy_into(&map, offset, &bytes);
+                    pending.fetch_sub(1, Ordering::Relaxed);
+                    let _ = done.send(copied);
+                });
+                return Ok(PendingWrite(wait));
+            }
+        };
+        let message = Message::Write {
+            offset,
+            bytes,
+            done,
+        };
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if lanes[self.lane].send(message).await.is_err() {
+            self.pending.fetch_sub(1, Ordering::Relaxed);
+            return Err("The file writer has stopped".to_string());
+        }
+        Ok(PendingWrite(wait))
+    }
+
+    /// This function writes `bytes` at `offset` and waits until they have been written.
+    pub async fn write(&self, offset: u64, bytes: Vec<u8>) -> Result<(), String> {
+        self.queue(offset, bytes).await?.wait().await
+    }
+
+    /// This function returns the number of writes waiting for the disk.
+    pub fn queue_depth(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    /// This function waits until every chunk written so far, through any handle, has reached the
+    /// disk.
+    pub async fn sync(&self) -> Result<(), String> {
+        let lanes = match &self.backend {
+            Backend::Threads(lanes) => lanes,
+            Backend::Mapped(map) => {
+                let map = Arc::clone(map);
+                return tokio::task::spawn_blocking(move || map.lock().unwrap().flush())
+                    .await
+                    .map_err(|e| format!("Failed to save the file: {e}"))?
+                    .map_err(|e| format!("Failed to save the file: {e}"));
+            }
+        };
+        for lane in lanes.iter() {
+            let (done, wait) = oneshot::channel();
+            lane.send(Message::Sync(done))
+                .await
+                .map_err(|_| "The file writer has stopped".to_string())?;
+            wait.await
+                .map_err(|_| "The file writer has stopped".to_string())??;
+        }
+        Ok(())
+    }
+}
+
+/// This function copies `bytes` into the mapping at `offset`. Writing the pages back to the disk
+/// is only started, `sync` waits for it.
+fn copy_into(map: &Mutex<MmapMut>, offset: u64, bytes: &[u8]) -> Result<(), String> {
+    let mut map = map.lock().unwrap();
+    let start = usize::try_from(offset)
+        .ok()
+        .filter(|start| start + bytes.len() <= map.len())
+        .ok_or_else(|| "write failed: past the end of the file".to_string())?;
+    map[start..start + bytes.len()].copy_from_slice(bytes);
+    map.flush_async_range(start, bytes.len())
+        .map_err(|e| format!("write failed: {e}"))
+}
+
+/// This function groups writes sorted by offset into runs where each write starts where the one
+/// before it ended.
+///
+/// # Arguments
+/// - `spans`: The offset and length of each write, sorted by offset.
+///
+/// # Returns
+/// The index ranges of `spans` making up each run.
+fn runs(spans: &[(u64, u64)]) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut first = 0;
+    for i in 1..=spans.len() {
+        let adjacent = i < spans.len() && spans[i - 1].0 + spans[i - 1].1 == spans[i].0;
+        if !adjacent {
+            runs.push(first..i);
+            first = i;
+        }
+    }
+    runs
+}
+
+/// This function writes all of `bytes` at `offset` without moving the cursor of the file.
+#[cfg(unix)]
+fn write_all_at(file: &File, bytes: &[u8], offset: u64) -> io::Result<()> {
+    use std::os::unix::fs::FileExt;
+
+    file.write_all_at(bytes, offset)
+}
+
+/// This function writes all of `bytes` at `offset`. `seek_write` moves the cursor on Windows,
+/// which nothing else uses.
+#[cfg(windows)]
+fn write_all_at(file: &File, mut bytes: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+
+    while !bytes.is_empty() {
+        match file.seek_write(bytes, offset) {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => {
+                bytes = &bytes[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+fn write_run(file: &File, writes: &[(u64, Vec<u8>)]) -> Result<(), String> {
+    for (offset, bytes) in writes {
+        write_all_at(file, bytes, *offset).map_err(|e| format!("write failed: {e}"))?;
+    }
+    Ok(())
+}
+
+fn write_batch(file: &File, mut batch: Vec<(u64, Vec<u8>, Ack)>, pending: &AtomicUsize) {
+    batch.sort_by_key(|(offset, _, _)| *offset);
+    let spans: Vec<(u64, u64)> = batch
+        .iter()
+        .map(|(offset, bytes, _)| (*offset, bytes.len() as u64))
+        .collect();
+    let mut batch = batch.into_iter();
+    for run in runs(&spans) {
+        let (writes, acks): (Vec<_>, Vec<_>) = batch
+            .by_ref()
+            .take(run.len())
+            .map(|(offset, bytes, done)| ((offset, bytes), done))
+            .unzip();
+        let result = write_run(file, &writes);
+        pending.fetch_sub(acks.len(), Ordering::Relaxed);
+        for done in acks {
+            let _ = done.send(result.clone());
+        }
+    }
+}
+
+fn run(mut rx: mpsc::Receiver<Message>, file: File, pending: &AtomicUsize) {
+    while let Some(first) = rx.blocking_recv() {
+        let mut batch = Vec::new();
+        let mut syncs = Vec::new();
+        let mut next = Some(first);
+        while let Some(message) = next {
+            match message {
+                Message::Write {
+                    offset,
+                    bytes,
+                    done,
+                } => batch.push((offset, bytes, done)),
+                Message::Sync(done) => syncs.push(done),
+            }
+            next = rx.try_recv().ok();
+        }
+        write_batch(&file, batch, pending);
+        if !syncs.is_empty() {
+            let result = file
+                .sync_all()
+                .map_err(|e| format!("Failed to save the file: {e}"));
+            for done in syncs {
+                let _ = done.send(result.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs() {
+        assert!(runs(&[]).is_empty());
+        assert_eq!(runs(&[(0, 10)]), vec![0..1]);
+        assert_eq!(
+            runs(&[(0, 10), (10, 5), (20, 5), (25, 5), (40, 1)]),
+            vec![0..2, 2..4, 4..5]
+        );
+    }
+
+    #[test]
+    fn test_writes_land_at_their_offsets() {
+        let path = std::env::temp_dir().join("yad_file_writer_test.bin");
+        let file = File::create(&path).unwrap();
+        file.set_len(12).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let writer = FileWriter::open(file).unwrap();
+            writer.write(8, b"9abc".to_vec()).await.unwrap();
+            let first = writer.queue(0, b"1234".to_vec()).await.unwrap();
+            let second = writer.queue(4, b"5678".to_vec()).await.unwrap();
+            first.wait().await.unwrap();
+            second.wait().await.unwrap();
+            assert_eq!(writer.queue_depth(), 0);
+            writer.sync().await.unwrap();
+        });
+        assert_eq!(std::fs::read(&path).unwrap(), b"123456789abc");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_workers_write_through_their_own_handles() {
+        let path = std::env::temp_dir().join("yad_file_writer_handles_test.bin");
+        let file = File::create(&path).unwrap();
+        file.set_len(8).unwrap();
+        let other = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let writer = FileWriter::open_handles(vec![file, other]).unwrap();
+            let (first, second) = (writer.for_worker(), writer.for_worker());
+            assert_ne!(first.lane, second.lane);
+            assert_eq!(writer.for_worker().lane, first.lane);
+            second.write(4, b"5678".to_vec()).await.unwrap();
+            first.write(0, b"1234".to_vec()).await.unwrap();
+            writer.sync().await.unwrap();
+        });
+        assert_eq!(std::fs::read(&path).unwrap(), b"12345678");
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_mapped_writes() {
+        let path = std::env::temp_dir().join("yad_file_writer_mapped_test.bin");
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .unwrap();
+        file.set_len(8).unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let writer = FileWriter::open_mapped(&file).unwrap();
+            let worker = writer.for_worker();
+            worker.write(4, b"5678".to_vec()).await.unwrap();
+            let pending = worker.queue(0, b"1234".to_vec()).await.unwrap();
+            pending.wait().await.unwrap();
+            assert_eq!(worker.queue_depth(), 0);
+            assert!(worker.write(6, b"789".to_vec()).await.is_err());
+            writer.sync().await.unwrap();
+        });
+        assert_eq!(std::fs::read(&path).unwrap(), b"12345678");
+        let _ = std::fs::remove_file(&path);
+    }
+}
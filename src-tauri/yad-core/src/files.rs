@@ -0,0 +1,769 @@
+use std::{collections::BTreeMap, fs, path::{Path, PathBuf}, time::{SystemTime, UNIX_EPOCH}};
+use crate::{config, storage::DownloadRecord};
+
+#[derive(Debug, Clone)]
+pub enum DownloadStatus {
+    Pending,
+    InProgress,
+    Failed,
+    Finished,
+    Cancelled,
+    /// The application crashed while the download was running.
+    Interrupted,
+    /// The download waits for one of the running downloads to end, see `queue`.
+    Queued,
+}
+
+impl DownloadStatus {
+    pub fn to_string(&self) -> String {
+        match self {
+            DownloadStatus::Pending => String::from("Pending"),
+            DownloadStatus::InProgress => String::from("InProgress"),
+            DownloadStatus::Failed => String::from("Failed"),
+            DownloadStatus::Finished => String::from("Finished"),
+            DownloadStatus::Cancelled => String::from("Cancelled"),
+            DownloadStatus::Interrupted => String::from("Interrupted"),
+            DownloadStatus::Queued => String::from("Queued"),
+        }
+    }
+
+    pub fn from_string(status: &str) -> Self {
+        match status {
+            "Pending" => DownloadStatus::Pending,
+            "InProgress" => DownloadStatus::InProgress,
+            "Failed" => DownloadStatus::Failed,
+            "Finished" => DownloadStatus::Finished,
+            "Cancelled" => DownloadStatus::Cancelled,
+            "Interrupted" => DownloadStatus::Interrupted,
+            "Queued" => DownloadStatus::Queued,
+            _ => DownloadStatus::Pending,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FileType {
+    Compressed,
+    Videos,
+    Audio,
+    Documents,
+    Programs,
+    Images,
+    Others,
+}
+
+impl FileType {
+    pub fn to_string(&self) -> String {
+        match self {
+            FileType::Compressed => String::from("Compressed"),
+            FileType::Videos => String::from("Videos"),
+            FileType::Audio => String::from("Audio"),
+            FileType::Documents => String::from("Documents"),
+            FileType::Programs => String::from("Programs"),
+            FileType::Images => String::from("Images"),
+            FileType::Others => String::from("Others"),
+        }
+    }
+
+    pub fn from_string(file_type: &str) -> Self {
+        match file_type {
+            "Compressed" => FileType::Compressed,
+            "Videos" => FileType::Videos,
+            "Audio" => FileType::Audio,
+            "Documents" => FileType::Documents,
+            "Programs" => FileType::Programs,
+            "Images" => FileType::Images,
+            _ => FileType::Others,
+        }
+    }
+
+    /// This function returns the file type named `file_type`, or `None` if there is no such type.
+    pub fn parse(file_type: &str) -> Option<Self> {
+        let parsed = FileType::from_string(file_type);
+        (parsed.to_string() == file_type).then_some(parsed)
+    }
+}
+
+impl From<DownloadRecord> for File {
+    fn from(dr: DownloadRecord) -> Self {
+        let stop = dr.download_stop_time.unwrap_or(0);
+        File {
+            id: dr.id,
+            file_url: dr.file_url,
+            file_name: dr.file_name,
+            file_type: FileType::from_string(&dr.file_type),
+            extension: dr.extension,
+            destination_dir: dr.destination_dir,
+            destination_path: dr.destination_path,
+            file_size: dr.file_size,
+            download_start_time: dr.download_start_time,
+            download_stop_time: stop,
+            download_duration: if stop > 0 { stop - dr.download_start_time } else { 0 },
+            download_status: DownloadStatus::from_string(&dr.download_status),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct File {
+    pub id: i64,
+    pub file_url: String,
+    pub file_name: String,
+    pub file_type: FileType,
+    pub extension: String,
+    pub destination_dir: String,
+    pub destination_path: String,
+    pub file_size: u64,
+    pub download_start_time: u64,
+    pub download_stop_time: u64,
+    pub download_duration: u64,
+    pub download_status: DownloadStatus,
+}
+
+fn get_file_type(extension: &str) -> FileType {
+    match extension {
+        "mp4" | "mkv" | "avi" | "mov" | "flv" | "webm" | "wmv" | "mpeg" | "mpg" | "3gp" => FileType::Videos,
+        "zip" | "rar" | "7z" | "tar" | "gz" | "targz" | "tarbz2" | "tarxz" | "iso" | "xz" => FileType::Compressed,
+        "mp3" | "flac" | "wav" | "aac" | "ogg" | "m4a" | "wma" | "alac" | "opus" | "amr" => FileType::Audio,
+        "pdf" | "docx" | "doc" | "txt" | "xlsx" | "pptx" | "ppt" | "odt" | "html" | "epub" | "csv" | "xml" => FileType::Documents,
+        "exe" | "msi" | "bat" | "apk" | "dmg" | "bin" | "deb" | "rpm" => FileType::Programs,
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "tiff" | "webp" | "svg" | "ico" => FileType::Images,
+        _ => FileType::Others,
+    }
+}
+
+/// This function learns the file type the user put a file in, so that later downloads with the same
+/// extension are put there too. A rule that only repeats the built in file type is removed.
+///
+/// # Arguments
+/// - `rules`: The file types by lowercase extension, see `Settings::category_rules`.
+/// - `extension`: The extension of the file.
+/// - `file_type`: The file type the user chose.
+///
+/// # Returns
+/// Whether the rules changed.
+pub fn learn_category(rules: &mut BTreeMap<String, String>, extension: &str, file_type: &FileType) -> bool {
+    let extension = extension.to_ascii_lowercase();
+    if extension.is_empty() || extension == "_" {
+        return false;
+    }
+    if get_file_type(&extension) == *file_type {
+        return rules.remove(&extension).is_some();
+    }
+    let file_type = file_type.to_string();
+    if rules.get(&extension) == Some(&file_type) {
+        return false;
+    }
+    rules.insert(extension, file_type);
+    true
+}
+
+fn get_destination_path(file_name: &str, cfg: &config::Config, file_type: &FileType) -> (String, String) {
+    let download_dir = Path::new(&cfg.download_dir);
+    let dir = download_dir.join(format!("{:?}", file_type));
+    let path = dir.join(file_name);
+    let dir = dir.to_str().unwrap_or("_").to_string();
+    let path = path.to_str().unwrap_or("_").to_string();
+    (dir, path)
+}
+
+/// Names that can not be used for files on Windows, regardless of the extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// This function makes a file name safe to use on all supported operating systems. Characters
+/// that are not allowed in file names are replaced with `_`.
+///
+/// # Arguments
+/// - `file_name`: The file name to sanitize.
+/// - `transliterate`: Whether to convert non-ASCII characters to ASCII, e.g. `Ünïcödé` to
+///   `Unicode`, for file systems and network shares that mangle Unicode.
+///
+/// # Example
+/// ```ignore
+/// assert_eq!(sanitize_file_name("a:b?.txt", false), "a_b_.txt");
+/// ```
+pub fn sanitize_file_name(file_name: &str, transliterate: bool) -> String {
+    let name = if transliterate {
+        deunicode::deunicode_with_tofu(file_name, "_")
+    } else {
+        file_name.to_string()
+    };
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let name = name.trim().trim_end_matches(['.', ' ']).to_string();
+
+    let stem = name.split('.').next().unwrap_or("");
+    if name.is_empty() {
+        "download".to_string()
+    } else if RESERVED_NAMES.contains(&stem.to_uppercase().as_str()) {
+        format!("_{name}")
+    } else {
+        name
+    }
+}
+
+/// This function marks the name of a file of which only the bytes `start` to `end` are downloaded,
+/// so that it is not mistaken for the whole file, e.g. `video.bytes-0-1023.mkv`.
+pub fn partial_file_name(file_name: &str, start: u64, end: u64) -> String {
+    let path = Path::new(file_name);
+    match (
+        path.file_stem().and_then(|s| s.to_str()),
+        path.extension().and_then(|e| e.to_str()),
+    ) {
+        (Some(stem), Some(ext)) => format!("{stem}.bytes-{start}-{end}.{ext}"),
+        _ => format!("{file_name}.bytes-{start}-{end}"),
+    }
+}
+
+/// This function returns the first name like `report (1).pdf` that is not taken, to save a file
+/// next to one with the same name.
+///
+/// # Arguments
+/// - `file_name`: The name that is taken.
+/// - `taken`: Whether a name is taken.
+pub fn free_file_name(file_name: &str, taken: impl Fn(&str) -> bool) -> String {
+    let path = Path::new(file_name);
+    let (stem, ext) = match (
+        path.file_stem().and_then(|s| s.to_str()),
+        path.extension().and_then(|e| e.to_str()),
+    ) {
+        (Some(stem), Some(ext)) => (stem, format!(".{ext}")),
+        _ => (file_name, String::new()),
+    };
+    (1..)
+        .map(|n| format!("{stem} ({n}){ext}"))
+        .find(|name| !taken(name))
+        .unwrap_or_else(|| file_name.to_string())
+}
+
+/// This function decodes `%XX` escapes, e.g. `my%20file.zip` to `my file.zip`. Invalid escapes
+/// are kept as they are.
+pub(crate) fn percent_decode(s: &str) -> String {
+    String::from_utf8_lossy(&percent_decode_bytes(s)).into_owned()
+}
+
+/// This function decodes `%XX` escapes to bytes, which may not be text, see `percent_decode`.
+pub(crate) fn percent_decode_bytes(s: &str) -> Vec<u8> {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// This function splits the parameters of a header such as `Content-Disposition` at `;`, keeping
+/// quoted values together.
+fn header_params(header: &str) -> Vec<(String, String)> {
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut quoted = false;
+    for c in header.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                part.push(c);
+            }
+            ';' if !quoted => parts.push(std::mem::take(&mut part)),
+            c => part.push(c),
+        }
+    }
+    parts.push(part);
+    parts
+        .iter()
+        .filter_map(|p| {
+            let (name, value) = p.split_once('=')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .unwrap_or(value);
+            Some((name.trim().to_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+/// This function returns the last part of a path, so that a name sent by a server can not point
+/// outside the download folder.
+fn last_segment(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or("")
+}
+
+/// This function returns the file name from a `Content-Disposition` header. The `filename*`
+/// parameter, which may hold non-ASCII names, wins over `filename`.
+///
+/// # Example
+/// ```ignore
+/// let name = files::content_disposition_name("attachment; filename=\"report.pdf\"");
+/// assert_eq!(name.as_deref(), Some("report.pdf"));
+/// ```
+pub fn content_disposition_name(header: &str) -> Option<String> {
+    let params = header_params(header);
+    let extended = params.iter().find(|(n, _)| n == "filename*").and_then(|(_, v)| {
+        // e.g. UTF-8''na%C3%AFve.txt, the language between the quotes is ignored
+        let mut parts = v.splitn(3, '\'');
+        let (_charset, _language, name) = (parts.next()?, parts.next()?, parts.next()?);
+        Some(percent_decode(name))
+    });
+    let plain = || {
+        params
+            .iter()
+            .find(|(n, _)| n == "filename")
+            .map(|(_, v)| v.clone())
+    };
+    extended
+        .or_else(plain)
+        .map(|name| last_segment(&name).trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+/// This function returns the file name from the path of a url, without the query or fragment.
+fn url_file_name(file_url: &str) -> String {
+    let path = file_url.split(['?', '#']).next().unwrap_or("");
+    percent_decode(path.split('/').next_back().unwrap_or(""))
+}
+
+impl File {
+    /// This function creates a file to download from `file_url`.
+    ///
+    /// # Arguments
+    /// - `file_url`: The url of the file.
+    /// - `content_disposition`: The `Content-Disposition` header sent by the server, if any. The
+    ///   file name in it is used instead of the name in the url, which is often something like
+    ///   `download?id=123`.
+    /// - `cfg`: An instance of `Config`.
+    pub fn new(file_url: &str, content_disposition: Option<&str>, cfg: &config::Config) -> Self {
+        let file_name = content_disposition
+            .and_then(content_disposition_name)
+            .unwrap_or_else(|| url_file_name(file_url));
+        Self::named(file_url, &file_name, cfg)
+    }
+
+    /// This function creates a file to download from `file_url` under another name than the one
+    /// in the url, e.g. the MP4 file a DASH manifest is downloaded to, see `dash::file_name`.
+    pub fn named(file_url: &str, file_name: &str, cfg: &config::Config) -> Self {
+        let extension = file_name.split('.').next_back().unwrap_or("_").to_string();
+        let file_type = get_file_type(&extension);
+        let (destination_dir, destination_path) = get_destination_path(file_name, cfg, &file_type);
+        let now = SystemTime::now();
+        let now = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let now = now.as_secs();
+
+        File {
+            id: 0,
+            file_url: file_url.to_string(),
+            file_name: file_name.to_string(),
+            file_type,
+            extension,
+            destination_dir,
+            destination_path,
+            file_size: 0,
+            download_start_time: now,
+            download_stop_time: 0,
+            download_duration: 0,
+            download_status: DownloadStatus::Pending,
+        }
+    }
+
+    /// This function creates a finished file for one that is already on disk, e.g. in a downloads
+    /// folder YAD did not fill, see `organize`. It is put in the folder of its type like a download
+    /// and its times are taken from when it was last modified.
+    ///
+    /// # Returns
+    /// `None` if `path` is not a file or has no name.
+    pub fn existing(path: &Path, cfg: &config::Config) -> Option<Self> {
+        let metadata = fs::metadata(path).ok().filter(|m| m.is_file())?;
+        let file_name = path.file_name()?.to_str()?;
+        let extension = file_name.split('.').next_back().unwrap_or("_").to_string();
+        let file_type = get_file_type(&extension.to_ascii_lowercase());
+        let (destination_dir, destination_path) = get_destination_path(file_name, cfg, &file_type);
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default()
+            .as_secs();
+
+        Some(File {
+            id: 0,
+            file_url: format!("file://{}", path.display()),
+            file_name: file_name.to_string(),
+            file_type,
+            extension,
+            destination_dir,
+            destination_path,
+            file_size: metadata.len(),
+            download_start_time: modified,
+            download_stop_time: modified,
+            download_duration: 0,
+            download_status: DownloadStatus::Finished,
+        })
+    }
+
+    /// This function changes the file type of the file, which puts it in the folder of that type.
+    pub fn set_file_type(&mut self, file_type: FileType, cfg: &config::Config) {
+        let (destination_dir, destination_path) = get_destination_path(&self.file_name, cfg, &file_type);
+        self.file_type = file_type;
+        self.destination_dir = destination_dir;
+        self.destination_path = destination_path;
+    }
+
+    /// This function applies the file types learned from the user, see `learn_category`.
+    pub fn apply_category_rules(&mut self, rules: &BTreeMap<String, String>, cfg: &config::Config) {
+        if let Some(file_type) = rules.get(&self.extension.to_ascii_lowercase()) {
+            self.set_file_type(FileType::from_string(file_type), cfg);
+        }
+    }
+}
+
+/// This function returns where a download is written to until every chunk has finished. It is a
+/// hidden file next to the destination, so that the downloads folder only ever holds whole files
+/// under their own name, the partial file outlives a restart of the machine, and moving it into
+/// place is a rename on the same drive.
+pub fn working_path(record_id: i64, destination_path: &str) -> PathBuf {
+    let destination = Path::new(destination_path);
+    let name = destination
+        .file_name()
+        .and_then(|n| n.to_str())
+        .unwrap_or("download");
+    destination.with_file_name(format!(".{name}.{record_id}.part"))
+}
+
+/// This function moves a finished download from its working path to its destination. A rename
+/// does not work across drives, then the file is copied next to the destination under a hidden
+/// name first and renamed from there, so the destination never holds half a file.
+pub fn move_into_place(from: &Path, to: &Path) -> Result<(), String> {
+    if let Some(dir) = to.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {e}", dir.display()))?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    let name = to.file_name().and_then(|n| n.to_str()).unwrap_or("download");
+    let copy = to.with_file_name(format!(".{name}.part"));
+    let moved = fs::copy(from, &copy)
+        .and_then(|_| fs::rename(&copy, to))
+        .and_then(|()| fs::remove_file(from));
+    if let Err(e) = moved {
+        let _ = fs::remove_file(&copy);
+        return Err(format!("Failed to move the file to {}: {e}", to.display()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn test_cfg() -> Config {
+        Config::default()
+    }
+
+    #[test]
+    fn test_download_status_round_trip() {
+        let variants = [
+            DownloadStatus::Pending,
+            DownloadStatus::InProgress,
+            DownloadStatus::Failed,
+            DownloadStatus::Finished,
+            DownloadStatus::Cancelled,
+            DownloadStatus::Interrupted,
+            DownloadStatus::Queued,
+        ];
+        for v in &variants {
+            let s = v.to_string();
+            let back = DownloadStatus::from_string(&s);
+            assert_eq!(v.to_string(), back.to_string(), "round-trip failed for {s}");
+        }
+    }
+
+    #[test]
+    fn test_download_status_unknown_defaults_to_pending() {
+        let s = DownloadStatus::from_string("UnknownStatus");
+        assert!(matches!(s, DownloadStatus::Pending));
+    }
+
+    #[test]
+    fn test_file_type_round_trip() {
+        let variants = [
+            FileType::Compressed,
+            FileType::Videos,
+            FileType::Audio,
+            FileType::Documents,
+            FileType::Programs,
+            FileType::Images,
+            FileType::Others,
+        ];
+        for v in &variants {
+            let s = v.to_string();
+            let back = FileType::from_string(&s);
+            assert_eq!(v.to_string(), back.to_string(), "round-trip failed for {s}");
+        }
+    }
+
+    #[test]
+    fn test_file_type_unknown_defaults_to_others() {
+        let t = FileType::from_string("UnknownType");
+        assert!(matches!(t, FileType::Others));
+    }
+
+    #[test]
+    fn test_get_file_type_videos() {
+        for ext in &["mp4", "mkv", "avi", "mov", "webm"] {
+            assert!(matches!(get_file_type(ext), FileType::Videos), "{ext} should be Videos");
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_compressed() {
+        for ext in &["zip", "rar", "7z", "tar", "gz", "iso"] {
+            assert!(matches!(get_file_type(ext), FileType::Compressed), "{ext} should be Compressed");
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_audio() {
+        for ext in &["mp3", "flac", "wav", "aac", "ogg"] {
+            assert!(matches!(get_file_type(ext), FileType::Audio), "{ext} should be Audio");
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_documents() {
+        for ext in &["pdf", "docx", "txt", "csv", "html", "epub"] {
+            assert!(matches!(get_file_type(ext), FileType::Documents), "{ext} should be Documents");
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_programs() {
+        for ext in &["exe", "dmg", "apk", "deb", "rpm"] {
+            assert!(matches!(get_file_type(ext), FileType::Programs), "{ext} should be Programs");
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_images() {
+        for ext in &["jpg", "png", "gif", "svg", "webp"] {
+            assert!(matches!(get_file_type(ext), FileType::Images), "{ext} should be Images");
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_unknown() {
+        assert!(matches!(get_file_type("xyz"), FileType::Others));
+    }
+
+    #[test]
+    fn test_file_new_extracts_name() {
+        let f = File::new("https://example.com/file.zip", None, &test_cfg());
+        assert_eq!(f.file_name, "file.zip");
+        assert_eq!(f.extension, "zip");
+        assert!(matches!(f.file_type, FileType::Compressed));
+        assert_eq!(f.file_url, "https://example.com/file.zip");
+        assert_eq!(f.download_status.to_string(), "Pending");
+        assert_eq!(f.id, 0);
+    }
+
+    #[test]
+    fn test_file_new_video_url() {
+        let f = File::new("https://example.com/movie.mp4", None, &test_cfg());
+        assert_eq!(f.file_name, "movie.mp4");
+        assert!(matches!(f.file_type, FileType::Videos));
+    }
+
+    #[test]
+    fn test_file_new_url_without_extension() {
+        let f = File::new("https://example.com/download", None, &test_cfg());
+        assert_eq!(f.file_name, "download");
+        assert_eq!(f.extension, "download");
+    }
+
+    #[test]
+    fn test_file_new_url_with_query_params() {
+        let f = File::new("https://example.com/file.pdf?token=abc", None, &test_cfg());
+        assert_eq!(f.file_name, "file.pdf");
+        assert_eq!(f.extension, "pdf");
+        let f = File::new("https://example.com/my%20file.zip#top", None, &test_cfg());
+        assert_eq!(f.file_name, "my file.zip");
+    }
+
+    #[test]
+    fn test_file_new_uses_content_disposition() {
+        let header = Some("attachment; filename=\"report 2024.pdf\"");
+        let f = File::new("https://site/download?id=123", header, &test_cfg());
+        assert_eq!(f.file_name, "report 2024.pdf");
+        assert!(matches!(f.file_type, FileType::Documents));
+
+        let f = File::new("https://site/download?id=123", Some("inline"), &test_cfg());
+        assert_eq!(f.file_name, "download");
+    }
+
+    #[test]
+    fn test_content_disposition_name() {
+        assert_eq!(
+            content_disposition_name("attachment; filename=plain.zip").as_deref(),
+            Some("plain.zip")
+        );
+        assert_eq!(
+            content_disposition_name(
+                "attachment; filename=\"fallback.txt\"; filename*=UTF-8''na%C3%AFve%20file.txt"
+            )
+            .as_deref(),
+            Some("naïve file.txt")
+        );
+        assert_eq!(
+            content_disposition_name("attachment; filename=\"a;b.txt\"").as_deref(),
+            Some("a;b.txt")
+        );
+        assert_eq!(
+            content_disposition_name("attachment; filename=\"../../etc/passwd\"").as_deref(),
+            Some("passwd")
+        );
+        assert_eq!(content_disposition_name("attachment"), None);
+        assert_eq!(content_disposition_name("attachment; filename=\"\""), None);
+    }
+
+    #[test]
+    fn test_file_new_stop_time_and_duration_are_zero() {
+        let f = File::new("https://example.com/file.zip", None, &test_cfg());
+        assert_eq!(f.download_stop_time, 0);
+        assert_eq!(f.download_duration, 0);
+    }
+
+    #[test]
+    fn test_file_new_start_time_is_recent() {
+        let f = File::new("https://example.com/file.zip", None, &test_cfg());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(f.download_start_time > 0);
+        assert!(f.download_start_time <= now);
+        assert!(f.download_start_time > now - 10);
+    }
+
+    #[test]
+    fn test_sanitize_file_name_replaces_reserved_characters() {
+        assert_eq!(sanitize_file_name("a:b?.txt", false), "a_b_.txt");
+        assert_eq!(sanitize_file_name("file.pdf?token=abc", false), "file.pdf_token=abc");
+        assert_eq!(sanitize_file_name("tab\there.txt", false), "tab_here.txt");
+        assert_eq!(sanitize_file_name(" name. ", false), "name");
+        assert_eq!(sanitize_file_name("...", false), "download");
+        assert_eq!(sanitize_file_name("con.txt", false), "_con.txt");
+    }
+
+    #[test]
+    fn test_partial_file_name() {
+        assert_eq!(partial_file_name("video.mkv", 0, 1023), "video.bytes-0-1023.mkv");
+        assert_eq!(partial_file_name("README", 5, 9), "README.bytes-5-9");
+    }
+
+    #[test]
+    fn test_sanitize_file_name_keeps_unicode() {
+        assert_eq!(sanitize_file_name("Привет мир.mp3", false), "Привет мир.mp3");
+    }
+
+    #[test]
+    fn test_sanitize_file_name_transliterates() {
+        assert_eq!(sanitize_file_name("Ünïcödé.txt", true), "Unicode.txt");
+        assert_eq!(sanitize_file_name("Привет.mp3", true), "Privet.mp3");
+        assert!(sanitize_file_name("北京.pdf", true).is_ascii());
+    }
+
+    #[test]
+    fn test_destination_path_includes_file_type_dir() {
+        let cfg = test_cfg();
+        let (dir, path) = get_destination_path("doc.pdf", &cfg, &FileType::Documents);
+        assert!(dir.contains("Documents"), "dir should contain Documents: {dir}");
+        assert!(path.ends_with("doc.pdf"), "path should end with filename: {path}");
+    }
+
+    #[test]
+    fn test_destination_path_for_different_types() {
+        let cfg = test_cfg();
+        let (dir_vid, _) = get_destination_path("v.mp4", &cfg, &FileType::Videos);
+        let (dir_aud, _) = get_destination_path("a.mp3", &cfg, &FileType::Audio);
+        assert_ne!(dir_vid, dir_aud);
+        assert!(dir_vid.contains("Videos"));
+        assert!(dir_aud.contains("Audio"));
+    }
+
+    #[test]
+    fn test_learn_category() {
+        let cfg = test_cfg();
+        let mut rules = BTreeMap::new();
+        assert!(learn_category(&mut rules, "APK", &FileType::Others));
+        assert!(!learn_category(&mut rules, "apk", &FileType::Others));
+        assert_eq!(rules.get("apk").map(String::as_str), Some("Others"));
+
+        let mut f = File::new("https://example.com/app.apk", None, &cfg);
+        f.apply_category_rules(&rules, &cfg);
+        assert!(matches!(f.file_type, FileType::Others));
+        assert!(f.destination_dir.ends_with("Others"));
+        assert!(f.destination_path.ends_with("app.apk"));
+
+        // going back to the built in file type forgets the rule
+        assert!(learn_category(&mut rules, "apk", &FileType::Programs));
+        assert!(rules.is_empty());
+        assert_eq!(FileType::parse("Videos"), Some(FileType::Videos));
+        assert_eq!(FileType::parse("videos"), None);
+    }
+
+    #[test]
+    fn test_working_path() {
+        assert_eq!(
+            working_path(7, "/home/user/Downloads/YAD/Compressed/file.zip"),
+            Path::new("/home/user/Downloads/YAD/Compressed/.file.zip.7.part")
+        );
+    }
+
+    #[test]
+    fn test_move_into_place() {
+        let dir = std::env::temp_dir().join("yad_move_into_place");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let from = dir.join("1-file.zip.part");
+        let to = dir.join("Compressed").join("file.zip");
+        fs::write(&from, b"whole file").unwrap();
+
+        move_into_place(&from, &to).unwrap();
+        assert!(!from.exists());
+        assert_eq!(fs::read(&to).unwrap(), b"whole file");
+        assert!(move_into_place(&from, &to).is_err());
+        assert!(!dir.join("Compressed").join(".file.zip.part").exists());
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_free_file_name() {
+        let taken = |name: &str| ["report (1).pdf", "archive.tar (1).gz"].contains(&name);
+        assert_eq!(free_file_name("report.pdf", taken), "report (2).pdf");
+        assert_eq!(free_file_name("README", taken), "README (1)");
+        assert_eq!(free_file_name("archive.tar.gz", taken), "archive.tar (2).gz");
+    }
+}
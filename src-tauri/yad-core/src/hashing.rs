@@ -0,0 +1,192 @@
+//! This module computes the checksum of a downloaded file. Hashing a file of many GB takes minutes,
+//! so the file is read on its own thread while the previous block is hashed, and progress is
+//! reported as the blocks are hashed so that verifying does not look like a hang. SHA-256 can
+//! only be computed one byte after the other, but BLAKE3 is a tree hash: each block is split over
+//! all cores, which makes it several times faster on big files.
+
+use std::{fmt, fs::File, io::Read, path::Path, sync::mpsc, thread};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The number of bytes read and hashed at once. BLAKE3 only spreads blocks of at least a few MB
+/// over the cores.
+const BLOCK_SIZE: usize = 16 * 1024 * 1024;
+
+/// The number of blocks read ahead of the one being hashed.
+const READ_AHEAD: usize = 2;
+
+/// This enum represents the algorithms a file can be hashed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// Hashed on all cores.
+    Blake3,
+}
+
+impl HashAlgorithm {
+    /// This function returns the name the checksum is prefixed with, e.g. `sha256`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+}
+
+/// This struct represents the checksum of a file, written as `algorithm:digest`, e.g.
+/// `blake3:af1349b9...`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: HashAlgorithm,
+    /// The digest as lowercase hex.
+    pub digest: String,
+}
+
+impl Checksum {
+    /// This function parses a checksum written as `algorithm:digest`. A digest without an
+    /// algorithm is taken to be SHA-256.
+    pub fn parse(checksum: &str) -> Option<Self> {
+        let checksum = checksum.trim();
+        let (algorithm, digest) = match checksum.split_once(':') {
+            Some((name, digest)) => {
+                let algorithm = [HashAlgorithm::Sha256, HashAlgorithm::Blake3]
+                    .into_iter()
+                    .find(|a| a.name().eq_ignore_ascii_case(name.trim()))?;
+                (algorithm, digest.trim())
+            }
+            None => (HashAlgorithm::Sha256, checksum),
+        };
+        // both algorithms have 32 byte digests
+        if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        Some(Checksum {
+            algorithm,
+            digest: digest.to_lowercase(),
+        })
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm.name(), self.digest)
+    }
+}
+
+enum Hasher {
+    Sha256(Box<Sha256>),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Hasher {
+    fn update(&mut self, block: &[u8]) {
+        match self {
+            Hasher::Sha256(h) => h.update(block),
+            Hasher::Blake3(h) => {
+                h.update_rayon(block);
+            }
+        }
+    }
+
+    fn finalize(self) -> String {
+        let bytes: Vec<u8> = match self {
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+            Hasher::Blake3(h) => h.finalize().as_bytes().to_vec(),
+        };
+        hex(&bytes)
+    }
+}
+
+/// This function returns bytes as lowercase hex, e.g. a digest.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// This function hashes the file at `path`.
+///
+/// # Arguments
+/// - `path`: The file.
+/// - `algorithm`: The algorithm to hash it with.
+/// - `on_progress`: Called after each block with the bytes hashed so far and the size of the file.
+pub fn hash_file(
+    path: &Path,
+    algorithm: HashAlgorithm,
+    mut on_progress: impl FnMut(u64, u64),
+) -> std::io::Result<Checksum> {
+    let mut file = File::open(path)?;
+    let total = file.metadata()?.len();
+    let mut hasher = match algorithm {
+        HashAlgorithm::Sha256 => Hasher::Sha256(Box::default()),
+        HashAlgorithm::Blake3 => Hasher::Blake3(Box::default()),
+    };
+    let (tx, rx) = mpsc::sync_channel::<std::io::Result<Vec<u8>>>(READ_AHEAD);
+    thread::scope(|scope| {
+        scope.spawn(move || loop {
+            let mut block = Vec::with_capacity(BLOCK_SIZE);
+            let read = (&mut file).take(BLOCK_SIZE as u64).read_to_end(&mut block);
+            let done = !matches!(read, Ok(n) if n > 0);
+            // the hashing side stops listening after an error
+            if tx.send(read.map(|_| block)).is_err() || done {
+                return;
+            }
+        });
+        let mut hashed = 0;
+        for block in rx {
+            let block = block?;
+            if block.is_empty() {
+                break;
+            }
+            hasher.update(&block);
+            hashed += block.len() as u64;
+            on_progress(hashed, total);
+        }
+        Ok(Checksum {
+            algorithm,
+            digest: hasher.finalize(),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_file() {
+        let path = std::env::temp_dir().join("yad_hashing.txt");
+        std::fs::write(&path, b"hello").unwrap();
+        let sha256 = hash_file(&path, HashAlgorithm::Sha256, |_, _| {}).unwrap();
+        assert_eq!(
+            sha256.to_string(),
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        let mut reported = Vec::new();
+        let blake3 = hash_file(&path, HashAlgorithm::Blake3, |done, total| {
+            reported.push((done, total))
+        })
+        .unwrap();
+        assert_eq!(
+            blake3.digest,
+            "ea8f163db38682925e4491c5e58d4bb3506ef8c14eb78a86e908c5624a67200f"
+        );
+        assert_eq!(reported, vec![(5, 5)]);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let digest = "AB".repeat(32);
+        let parsed = Checksum::parse(&format!("BLAKE3:{digest}")).unwrap();
+        assert_eq!(parsed.algorithm, HashAlgorithm::Blake3);
+        assert_eq!(parsed.digest, "ab".repeat(32));
+        assert_eq!(
+            Checksum::parse(&digest).unwrap().algorithm,
+            HashAlgorithm::Sha256
+        );
+        assert!(Checksum::parse(&format!("md5:{digest}")).is_none());
+        assert!(Checksum::parse("sha256:1234").is_none());
+    }
+}
@@ -0,0 +1,246 @@
+//! This module scores how healthy the running downloads are so that the ones that need attention
+//! can be shown first. The score goes from 0 (stuck) to 100 (healthy) and is computed from what
+//! the download engine reports for every chunk: how fast chunks finish compared to the expected
+//! speed, how many fail and how long it has been since any data arrived.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// Downloads with a score below this need the user's attention.
+pub const ATTENTION_THRESHOLD: u8 = 50;
+
+/// The window over which the current speed is measured.
+const SPEED_WINDOW: Duration = Duration::from_secs(10);
+
+/// A download with no data for this long is considered stalled.
+const STALL_AFTER: Duration = Duration::from_secs(30);
+
+/// A server that is slow to answer gets this many times its usual time to first byte before its
+/// downloads are considered stalled.
+const STALL_TTFB_FACTOR: u32 = 3;
+
+const ERROR_WEIGHT: f64 = 50.0;
+const STALL_WEIGHT: f64 = 40.0;
+const SPEED_WEIGHT: f64 = 30.0;
+
+/// This struct holds the per chunk telemetry of a running download.
+#[derive(Debug)]
+pub struct Telemetry {
+    started: Instant,
+    last_progress: Instant,
+    /// The configured speed limit, 0 if unlimited.
+    speed_limit: u64,
+    /// The highest speed measured so far, used as the expected speed when there is no limit.
+    peak_speed: f64,
+    recent: VecDeque<(Instant, u64)>,
+    finished_chunks: u32,
+    failed_chunks: u32,
+    /// How long the server usually takes to answer, see `latency`.
+    slow_ttfb: Duration,
+}
+
+impl Telemetry {
+    pub fn new(speed_limit: u64) -> Self {
+        Self::new_at(speed_limit, Instant::now())
+    }
+
+    fn new_at(speed_limit: u64, now: Instant) -> Self {
+        Telemetry {
+            started: now,
+            last_progress: now,
+            speed_limit,
+            peak_speed: 0.0,
+            recent: VecDeque::new(),
+            finished_chunks: 0,
+            failed_chunks: 0,
+            slow_ttfb: Duration::ZERO,
+        }
+    }
+
+    /// This function records a chunk of `bytes` that finished downloading.
+    pub fn record_chunk(&mut self, bytes: u64) {
+        self.record_chunk_at(bytes, Instant::now());
+    }
+
+    fn record_chunk_at(&mut self, bytes: u64, now: Instant) {
+        self.finished_chunks += 1;
+        self.last_progress = now;
+        self.recent.push_back((now, bytes));
+        let speed = self.speed_at(now);
+        if now.duration_since(self.started) >= SPEED_WINDOW && speed > self.peak_speed {
+            self.peak_speed = speed;
+        }
+    }
+
+    /// This function records a chunk that failed to download.
+    pub fn record_failure(&mut self) {
+        self.failed_chunks += 1;
+    }
+
+    /// This function sets how long the server usually takes to answer, e.g. the 90th percentile
+    /// of its time to first byte, so that waiting for a slow server is not taken for a stall.
+    pub fn set_slow_ttfb(&mut self, ttfb: Duration) {
+        self.slow_ttfb = ttfb;
+    }
+
+    /// This function returns how long the download can go without data before it is stalled.
+    fn stall_after(&self) -> Duration {
+        STALL_AFTER.max(self.slow_ttfb * STALL_TTFB_FACTOR)
+    }
+
+    /// This function changes the speed limit the download is compared against.
+    pub fn set_speed_limit(&mut self, speed_limit: u64) {
+        self.speed_limit = speed_limit;
+    }
+
+    /// The speed in bytes per second over the last `SPEED_WINDOW`.
+    fn speed_at(&mut self, now: Instant) -> f64 {
+        while let Some((t, _)) = self.recent.front() {
+            if now.duration_since(*t) > SPEED_WINDOW {
+                self.recent.pop_front();
+            } else {
+                break;
+            }
+        }
+        let window = now.duration_since(self.started).min(SPEED_WINDOW);
+        if window.is_zero() {
+            return 0.0;
+        }
+        let bytes: u64 = self.recent.iter().map(|(_, b)| b).sum();
+        bytes as f64 / window.as_secs_f64()
+    }
+
+    /// This function returns the health score of the download, from 0 to 100.
+    pub fn score(&mut self) -> u8 {
+        self.score_at(Instant::now())
+    }
+
+    fn score_at(&mut self, now: Instant) -> u8 {
+        let mut score = 100.0;
+
+        let attempted = self.finished_chunks + self.failed_chunks;
+        if attempted > 0 {
+            score -= ERROR_WEIGHT * self.failed_chunks as f64 / attempted as f64;
+        }
+
+        if now.duration_since(self.last_progress) >= self.stall_after() {
+            score -= STALL_WEIGHT;
+        }
+
+        let expected = if self.speed_limit > 0 {
+            self.speed_limit as f64
+        } else {
+            self.peak_speed
+        };
+        if expected > 0.0 && now.duration_since(self.started) >= SPEED_WINDOW {
+            let ratio = (self.speed_at(now) / expected).min(1.0);
+            score -= SPEED_WEIGHT * (1.0 - ratio);
+        }
+
+        score.clamp(0.0, 100.0).round() as u8
+    }
+}
+
+fn telemetry() -> &'static Mutex<HashMap<i64, Telemetry>> {
+    static MAP: OnceLock<Mutex<HashMap<i64, Telemetry>>> = OnceLock::new();
+    MAP.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// This function starts tracking a download.
+pub fn register(record_id: i64, speed_limit: u64) {
+    telemetry()
+        .lock()
+        .unwrap()
+        .insert(record_id, Telemetry::new(speed_limit));
+}
+
+/// This function stops tracking a download once it is no longer running.
+pub fn remove(record_id: i64) {
+    telemetry().lock().unwrap().remove(&record_id);
+}
+
+/// This function updates the telemetry of a running download.
+pub fn update(record_id: i64, f: impl FnOnce(&mut Telemetry)) {
+    if let Some(t) = telemetry().lock().unwrap().get_mut(&record_id) {
+        f(t);
+    }
+}
+
+/// This function returns the health score of a running download, `None` if it is not running.
+pub fn score(record_id: i64) -> Option<u8> {
+    telemetry()
+        .lock()
+        .unwrap()
+        .get_mut(&record_id)
+        .map(|t| t.score())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_new_download_is_healthy() {
+        let now = Instant::now();
+        let mut t = Telemetry::new_at(0, now);
+        assert_eq!(t.score_at(now), 100);
+    }
+
+    #[test]
+    fn test_failures_lower_the_score() {
+        let now = Instant::now();
+        let mut t = Telemetry::new_at(0, now);
+        t.record_chunk_at(MB, now);
+        t.record_failure();
+        assert_eq!(t.score_at(now), 75);
+    }
+
+    #[test]
+    fn test_stalled_download_needs_attention() {
+        let now = Instant::now();
+        let mut t = Telemetry::new_at(0, now);
+        for i in 0..20 {
+            t.record_chunk_at(MB, now + Duration::from_secs(i));
+        }
+        let later = now + Duration::from_secs(60);
+        assert!(t.score_at(later) < ATTENTION_THRESHOLD);
+    }
+
+    #[test]
+    fn test_slow_server_is_not_stalled() {
+        let now = Instant::now();
+        let mut t = Telemetry::new_at(0, now);
+        t.record_chunk_at(MB, now);
+        t.set_slow_ttfb(Duration::from_secs(20));
+        let later = now + Duration::from_secs(45);
+        assert_eq!(t.score_at(later), 100);
+        let much_later = now + Duration::from_secs(60);
+        assert_eq!(t.score_at(much_later), 60);
+    }
+
+    #[test]
+    fn test_slower_than_limit_lowers_the_score() {
+        let now = Instant::now();
+        let mut t = Telemetry::new_at(MB, now);
+        // 1 chunk of 1MB every 2 seconds, half the limit
+        for i in 0..10 {
+            t.record_chunk_at(MB, now + Duration::from_secs(i * 2));
+        }
+        let score = t.score_at(now + Duration::from_secs(19));
+        assert!((80..=90).contains(&score), "score was {score}");
+    }
+
+    #[test]
+    fn test_registry() {
+        register(-1, 0);
+        update(-1, |t| t.record_failure());
+        assert_eq!(score(-1), Some(50));
+        remove(-1);
+        assert_eq!(score(-1), None);
+    }
+}
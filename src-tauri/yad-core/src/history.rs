@@ -0,0 +1,170 @@
+//! This module keeps the history short for users with many downloads. Once there are more records
+//! than `Settings::max_history_records`, the finished downloads that finished the longest ago are
+//! moved out of the database into a gzip compressed JSON archive in the config directory, which
+//! keeps the database and `fetch_records` fast. Archived records can be restored on demand.
+
+use std::{
+    fs,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+use crate::{config::Config, storage, storage::DownloadRecord};
+
+/// The name of the archive in the config directory.
+pub const ARCHIVE_FILE: &str = "history-archive.json.gz";
+
+/// This function returns where the archive is kept.
+pub fn archive_path(cfg: &Config) -> PathBuf {
+    Path::new(&cfg.config_dir).join(ARCHIVE_FILE)
+}
+
+/// This function reads the records of an archive, oldest first. A missing archive is empty.
+pub fn read_archive(path: &Path) -> Result<Vec<DownloadRecord>, String> {
+    let file = match fs::File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(format!("Failed to open the archive: {e}")),
+    };
+    let mut json = String::new();
+    GzDecoder::new(file)
+        .read_to_string(&mut json)
+        .map_err(|e| format!("Failed to read the archive: {e}"))?;
+    serde_json::from_str(&json).map_err(|e| format!("The archive is damaged: {e}"))
+}
+
+/// This function writes the records of an archive. The archive is written next to the old one
+/// and then moved over it, so that it is never left half written.
+pub fn write_archive(path: &Path, records: &[DownloadRecord]) -> Result<(), String> {
+    let json = serde_json::to_vec(records).map_err(|e| format!("Failed to encode records: {e}"))?;
+    let tmp = path.with_extension("gz.tmp");
+    let write = || -> std::io::Result<()> {
+        let mut encoder = GzEncoder::new(fs::File::create(&tmp)?, Compression::default());
+        encoder.write_all(&json)?;
+        encoder.finish()?.sync_all()?;
+        fs::rename(&tmp, path)
+    };
+    write().map_err(|e| format!("Failed to write the archive: {e}"))
+}
+
+/// This function moves the oldest finished records into the archive while there are more than
+/// `max_records` records. Records which have not finished are never archived.
+///
+/// # Arguments
+/// - `max_records`: The number of records kept in the database, 0 means unlimited.
+/// - `cfg`: The config.
+///
+/// # Returns
+/// The number of records archived.
+pub fn archive_overflow(max_records: usize, cfg: &Config) -> Result<usize, String> {
+    if max_records == 0 {
+        return Ok(0);
+    }
+    let count = storage::count_records(cfg).map_err(|e| format!("Failed to count records: {e}"))?;
+    if count <= max_records {
+        return Ok(0);
+    }
+    let overflow = storage::oldest_finished_records(count - max_records, cfg)
+        .map_err(|e| format!("Failed to read records: {e}"))?;
+    if overflow.is_empty() {
+        return Ok(0);
+    }
+    // the archive is saved before the records are deleted, so a crash in between keeps them twice
+    // rather than losing them
+    let path = archive_path(cfg);
+    let mut archived = read_archive(&path)?;
+    archived.extend(overflow.iter().cloned());
+    write_archive(&path, &archived)?;
+    let ids: Vec<i64> = overflow.iter().map(|r| r.id).collect();
+    storage::delete_records(&ids, cfg).map_err(|e| format!("Failed to delete records: {e}"))?;
+    Ok(ids.len())
+}
+
+/// This function moves archived records back into the database. A record whose url or file is
+/// in the database again is left in the archive.
+///
+/// # Arguments
+/// - `ids`: The ids the records had when they were archived.
+/// - `cfg`: The config.
+///
+/// # Returns
+/// The ids of the records restored, as they were in the archive.
+pub fn restore(ids: &[i64], cfg: &Config) -> Result<Vec<i64>, String> {
+    let path = archive_path(cfg);
+    let mut archived = read_archive(&path)?;
+    let mut restored = Vec::new();
+    archived.retain(|record| {
+        if !ids.contains(&record.id) {
+            return true;
+        }
+        match storage::insert_record(record, record.file_size, cfg) {
+            Ok(_) => {
+                restored.push(record.id);
+                false
+            }
+            Err(e) => {
+                eprintln!(
+                    "failed to restore archived record {} because {e}",
+                    record.id
+                );
+                true
+            }
+        }
+    });
+    if !restored.is_empty() {
+        write_archive(&path, &archived)?;
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished(n: u64) -> DownloadRecord {
+        DownloadRecord {
+            file_url: format!("https://example.com/{n}.zip"),
+            file_name: format!("{n}.zip"),
+            destination_path: format!("/tmp/{n}.zip"),
+            download_status: "Finished".into(),
+            download_stop_time: Some(1000 + n),
+            file_size: 10,
+            ..DownloadRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_archive_and_restore() {
+        let cfg = storage::test_config("history_archive");
+        storage::create_tables(&cfg).unwrap();
+        for n in [3, 1, 2] {
+            storage::insert_record(&finished(n), 10, &cfg).unwrap();
+        }
+        let running = DownloadRecord {
+            download_status: "InProgress".into(),
+            download_stop_time: None,
+            ..finished(0)
+        };
+        storage::insert_record(&running, 10, &cfg).unwrap();
+
+        assert_eq!(archive_overflow(0, &cfg), Ok(0));
+        assert_eq!(archive_overflow(2, &cfg), Ok(2));
+        assert_eq!(storage::count_records(&cfg).unwrap(), 2);
+        let archived = read_archive(&archive_path(&cfg)).unwrap();
+        let names: Vec<&str> = archived.iter().map(|r| r.file_name.as_str()).collect();
+        assert_eq!(names, ["1.zip", "2.zip"]);
+
+        // the download that has not finished is never archived
+        assert_eq!(archive_overflow(1, &cfg), Ok(1));
+        assert_eq!(archive_overflow(1, &cfg), Ok(0));
+
+        let first = archived[0].id;
+        assert_eq!(restore(&[first], &cfg), Ok(vec![first]));
+        assert_eq!(storage::count_records(&cfg).unwrap(), 2);
+        assert_eq!(read_archive(&archive_path(&cfg)).unwrap().len(), 2);
+        let restored = storage::search_by_url("https://example.com/1.zip", &cfg).unwrap();
+        assert_eq!(restored.download_status, "Finished");
+    }
+}
@@ -0,0 +1,158 @@
+//! This module raises the limits while the user is away, so that downloads run at full speed
+//! overnight and stay out of the way while the computer is used. The time since the last input is
+//! asked from the operating system: `xprintidle` on Linux, which needs X11, `ioreg` on macOS and
+//! `GetLastInputInfo` through PowerShell on Windows. When it cannot be found out the user is taken
+//! to be present, and the limits of the settings apply.
+
+use std::{
+    process::Command,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use crate::settings::Settings;
+
+/// The PowerShell script that prints the milliseconds since the last input on Windows.
+const WINDOWS_SCRIPT: &str = "Add-Type 'using System; using System.Runtime.InteropServices; \
+    public static class YadIdle { \
+    [StructLayout(LayoutKind.Sequential)] struct Info { public uint Size; public uint Time; } \
+    [DllImport(\"user32.dll\")] static extern bool GetLastInputInfo(ref Info info); \
+    public static uint Millis() { Info i = new Info(); i.Size = 8; GetLastInputInfo(ref i); \
+    return (uint)Environment.TickCount - i.Time; } }'; [YadIdle]::Millis()";
+
+/// This function returns the program that prints the time since the last input, and its
+/// arguments.
+///
+/// # Arguments
+/// - `os`: The operating system, see `Config::os`.
+pub fn idle_command(os: &str) -> (&'static str, Vec<&'static str>) {
+    match os {
+        "Windows" => (
+            "powershell",
+            vec!["-NoProfile", "-NonInteractive", "-Command", WINDOWS_SCRIPT],
+        ),
+        "Darwin" => ("ioreg", vec!["-c", "IOHIDSystem", "-d", "4"]),
+        _ => ("xprintidle", Vec::new()),
+    }
+}
+
+/// This function reads the time since the last input from the output of `idle_command`.
+pub fn parse_idle(os: &str, output: &str) -> Option<Duration> {
+    match os {
+        // e.g. `    |   "HIDIdleTime" = 1234567890` in nanoseconds
+        "Darwin" => output
+            .lines()
+            .find_map(|line| line.split_once("\"HIDIdleTime\" = "))
+            .and_then(|(_, nanos)| nanos.trim().parse().ok())
+            .map(Duration::from_nanos),
+        _ => output.trim().parse().ok().map(Duration::from_millis),
+    }
+}
+
+/// This function returns the time since the last input, or `None` if it cannot be found out.
+pub fn idle_time(os: &str) -> Option<Duration> {
+    let (program, args) = idle_command(os);
+    let output = Command::new(program).args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    parse_idle(os, &String::from_utf8_lossy(&output.stdout))
+}
+
+fn idle() -> &'static AtomicBool {
+    static IDLE: AtomicBool = AtomicBool::new(false);
+    &IDLE
+}
+
+/// This function returns whether the user was away at the last check, see `check`.
+pub fn is_idle() -> bool {
+    idle().load(Ordering::Relaxed)
+}
+
+/// This function checks whether the user is away.
+///
+/// # Returns
+/// Whether this changed since the last check.
+pub fn check(os: &str, settings: &Settings) -> bool {
+    let now_idle = settings.idle_after_secs > 0
+        && idle_time(os).is_some_and(|t| t.as_secs() >= settings.idle_after_secs);
+    idle().swap(now_idle, Ordering::Relaxed) != now_idle
+}
+
+/// This function returns the higher of two limits where 0 means unlimited.
+fn raise(limit: u64, idle_limit: u64) -> u64 {
+    if limit == 0 || idle_limit == 0 {
+        0
+    } else {
+        limit.max(idle_limit)
+    }
+}
+
+/// This function returns how many downloads run at the same time. The limit is only ever
+/// raised while the user is away.
+pub fn concurrent_downloads(settings: &Settings, idle: bool) -> usize {
+    if !idle {
+        return settings.max_concurrent_downloads;
+    }
+    raise(
+        settings.max_concurrent_downloads as u64,
+        settings.idle_max_concurrent_downloads as u64,
+    ) as usize
+}
+
+/// This function returns the total speed limit.
+///
+/// # Arguments
+/// - `settings`: The settings.
+/// - `limit`: The total speed limit while the user is present, see `bandwidth::total_limit`.
+/// - `idle`: Whether the user is away.
+pub fn total_speed(settings: &Settings, limit: u64, idle: bool) -> u64 {
+    if !idle {
+        return limit;
+    }
+    raise(limit, settings.idle_max_total_speed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_idle() {
+        assert_eq!(
+            parse_idle("Linux", "1500\n"),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            parse_idle("Windows", "60000\r\n"),
+            Some(Duration::from_secs(60))
+        );
+        let ioreg = "  | |   \"HIDIdleTime\" = 3000000000\n  | |   \"HIDKeyboard\" = 1\n";
+        assert_eq!(parse_idle("Darwin", ioreg), Some(Duration::from_secs(3)));
+        assert_eq!(parse_idle("Linux", "couldn't open display"), None);
+    }
+
+    #[test]
+    fn test_limits_are_only_raised() {
+        let settings = Settings {
+            max_concurrent_downloads: 2,
+            idle_max_concurrent_downloads: 6,
+            idle_max_total_speed: 0,
+            ..Settings::default()
+        };
+        assert_eq!(concurrent_downloads(&settings, false), 2);
+        assert_eq!(concurrent_downloads(&settings, true), 6);
+        assert_eq!(total_speed(&settings, 500_000, false), 500_000);
+        assert_eq!(total_speed(&settings, 500_000, true), 0);
+
+        let settings = Settings {
+            max_concurrent_downloads: 8,
+            idle_max_concurrent_downloads: 6,
+            idle_max_total_speed: 100_000,
+            ..Settings::default()
+        };
+        assert_eq!(concurrent_downloads(&settings, true), 8);
+        assert_eq!(total_speed(&settings, 500_000, true), 500_000);
+        assert_eq!(total_speed(&settings, 0, true), 0);
+    }
+}
@@ -0,0 +1,165 @@
+//! This module spot checks large downloads while they are running. Every few chunks, a small
+//! random sample of a chunk that was just written is requested from the server again and compared
+//! with what is on disk, so that corruption (a bad proxy, a mirror serving a different file) is
+//! caught before the whole file has been downloaded.
+//!
+//! Spot checks are off unless `Settings::spot_check_min_size` is set. They compare a few samples
+//! and cost a request each, so they catch a source serving another file or a proxy mangling it,
+//! not every corrupt byte: only a checksum in the success criteria guarantees the whole file. The
+//! samples that did not match are counted on the record, see `storage::add_spot_check_failure`.
+
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use reqwest::{Client, StatusCode};
+
+use crate::{
+    chunks::range_len,
+    hashing::{self, HashAlgorithm},
+};
+
+/// The number of bytes compared in each spot check.
+pub const SAMPLE_LEN: u64 = 4096;
+
+/// One in this many chunks is spot checked.
+pub const CHECK_EVERY: u64 = 32;
+
+/// The smallest size worth spot checking, suggested when spot checks are turned on.
+pub const DEFAULT_MIN_SIZE: u64 = 256 * 1024 * 1024;
+
+/// This function checks whether the chunk starting at `start` should be spot checked.
+pub fn should_check(start: u64, chunk_size: u64, total_size: u64, min_size: u64) -> bool {
+    min_size > 0
+        && total_size >= min_size
+        && (start / chunk_size.max(1)).is_multiple_of(CHECK_EVERY)
+}
+
+/// This function picks the byte range to compare inside the chunk `[start, end]`.
+///
+/// # Arguments
+/// - `start`, `end`: The chunk, inclusive on both ends.
+/// - `seed`: Any number, used to pick where the sample starts.
+pub fn sample_range(start: u64, end: u64, seed: u64) -> (u64, u64) {
+    let len = range_len(start, end);
+    if len <= SAMPLE_LEN {
+        return (start, end);
+    }
+    // xorshift so that consecutive seeds are spread over the chunk
+    let mut x = seed ^ 0x9E37_79B9_7F4A_7C15;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    let offset = x % (len - SAMPLE_LEN + 1);
+    (start + offset, start + offset + SAMPLE_LEN - 1)
+}
+
+/// This function returns a seed for `sample_range`.
+pub fn seed(start: u64) -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos() as u64;
+    start ^ nanos.rotate_left(32)
+}
+
+/// This function reads the bytes `[start, end]` of the file at `path`.
+fn read_range(path: &Path, start: u64, end: u64) -> std::io::Result<Vec<u8>> {
+    let mut f = File::open(path)?;
+    f.seek(SeekFrom::Start(start))?;
+    let mut buf = vec![0u8; range_len(start, end) as usize];
+    f.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// This function returns the SHA-256 checksum of the file at `path` as lowercase hex.
+pub fn sha256_file(path: &Path) -> std::io::Result<String> {
+    hashing::hash_file(path, HashAlgorithm::Sha256, |_, _| {}).map(|c| c.digest)
+}
+
+/// This function downloads the bytes `[start, end]` again and compares them with the bytes on
+/// disk.
+///
+/// # Returns
+/// - `Ok(true)`: The bytes match.
+/// - `Ok(false)`: The bytes differ, the chunk should be downloaded again.
+/// - `Err(String)`: The check could not be done, e.g. the request failed.
+pub async fn verify_sample(
+    client: &Client,
+    url: &str,
+    headers: &[(String, String)],
+    path: &Path,
+    (start, end): (u64, u64),
+) -> Result<bool, String> {
+    let mut request = client
+        .get(url)
+        .header("Range", format!("bytes={start}-{end}"));
+    for (name, value) in headers {
+        request = request.header(name.as_str(), value);
+    }
+    let response = request
+        .send()
+        .await
+        .map_err(|e| format!("spot check request failed: {e}"))?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(format!("spot check got status {}", response.status()));
+    }
+    let remote = response
+        .bytes()
+        .await
+        .map_err(|e| format!("spot check body failed: {e}"))?;
+    let local = read_range(path, start, end).map_err(|e| format!("spot check read failed: {e}"))?;
+    Ok(remote.as_ref() == local.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MB: u64 = 1024 * 1024;
+
+    #[test]
+    fn test_should_check() {
+        let total = 512 * MB;
+        assert!(should_check(0, MB, total, DEFAULT_MIN_SIZE));
+        assert!(!should_check(MB, MB, total, DEFAULT_MIN_SIZE));
+        assert!(should_check(CHECK_EVERY * MB, MB, total, DEFAULT_MIN_SIZE));
+        assert!(!should_check(0, MB, 10 * MB, DEFAULT_MIN_SIZE));
+        assert!(!should_check(0, MB, total, 0), "0 disables spot checks");
+    }
+
+    #[test]
+    fn test_sample_range_stays_inside_the_chunk() {
+        for seed in 0..1000 {
+            let (s, e) = sample_range(MB, 2 * MB - 1, seed);
+            assert!(s >= MB && e < 2 * MB);
+            assert_eq!(range_len(s, e), SAMPLE_LEN);
+        }
+        assert_eq!(sample_range(10, 20, 5), (10, 20));
+    }
+
+    #[test]
+    fn test_read_range() {
+        let dir = std::env::temp_dir().join("yad_test").join("integrity");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("file.bin");
+        std::fs::write(&path, b"0123456789").unwrap();
+        assert_eq!(read_range(&path, 2, 5).unwrap(), b"2345");
+        assert!(read_range(&path, 8, 12).is_err());
+    }
+
+    #[test]
+    fn test_sha256_file() {
+        let dir = std::env::temp_dir().join("yad_test").join("integrity");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("hello.txt");
+        std::fs::write(&path, b"hello").unwrap();
+        assert_eq!(
+            sha256_file(&path).unwrap(),
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+}
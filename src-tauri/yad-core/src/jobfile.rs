@@ -0,0 +1,151 @@
+//! This module handles `.yad` job files. A job file describes a single download, the url, the
+//! template whose headers it needs, the expected checksum and mirrors, so that a user can share
+//! "download this exact thing, verified" with teammates. Opening a job file adds the download.
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::engine::is_supported_url;
+
+/// The extension of job files, registered with the operating system in `tauri.conf.json`.
+pub const EXTENSION: &str = "yad";
+
+/// The newest job file format this version understands.
+pub const VERSION: u32 = 1;
+
+/// This struct represents the content of a job file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JobFile {
+    pub version: u32,
+    pub url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    /// The name of the template providing the headers, e.g. "Work VPN". Templates hold secrets
+    /// such as tokens, so only the name is shared.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+    /// The SHA-256 checksum of the file as hex.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Other urls serving the same file, tried in order when the url fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mirrors: Vec<String>,
+}
+
+impl JobFile {
+    pub fn new(url: &str) -> Self {
+        JobFile {
+            version: VERSION,
+            url: url.to_string(),
+            file_name: None,
+            template: None,
+            sha256: None,
+            mirrors: Vec::new(),
+        }
+    }
+
+    /// This function parses and validates the content of a job file.
+    ///
+    /// # Returns
+    /// - `Ok(JobFile)`: The job.
+    /// - `Err(String)`: If the content is not a valid job file.
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let job: JobFile =
+            serde_json::from_str(content).map_err(|e| format!("Invalid job file: {e}"))?;
+        if job.version > VERSION {
+            return Err(format!(
+                "The job file was made by a newer version of YAD (format {})",
+                job.version
+            ));
+        }
+        if let Some(url) = job.urls().find(|u| !is_supported_url(u)) {
+            return Err(format!("Invalid URL in job file: {url}"));
+        }
+        if let Some(sha256) = &job.sha256 {
+            let hex = sha256.strip_prefix("sha256:").unwrap_or(sha256);
+            if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(format!("Invalid SHA-256 checksum in job file: {sha256}"));
+            }
+        }
+        Ok(job)
+    }
+
+    /// This function returns the url followed by the mirrors.
+    pub fn urls(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.url.as_str()).chain(self.mirrors.iter().map(String::as_str))
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap_or_default()
+    }
+}
+
+/// This function checks whether `path` looks like a job file.
+pub fn is_job_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(EXTENSION))
+}
+
+/// This function reads a job file.
+pub fn read(path: &Path) -> Result<JobFile, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read job file: {e}"))?;
+    JobFile::parse(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_round_trip() {
+        let job = JobFile {
+            file_name: Some("ubuntu.iso".into()),
+            template: Some("Linux ISO".into()),
+            sha256: Some(SHA256.into()),
+            mirrors: vec!["https://mirror.example.com/ubuntu.iso".into()],
+            ..JobFile::new("https://example.com/ubuntu.iso")
+        };
+        assert_eq!(JobFile::parse(&job.to_json()).unwrap(), job);
+        assert_eq!(
+            job.urls().collect::<Vec<_>>(),
+            vec![
+                "https://example.com/ubuntu.iso",
+                "https://mirror.example.com/ubuntu.iso"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_minimal_job() {
+        let job = JobFile::parse(r#"{"version": 1, "url": "https://example.com/a.zip"}"#).unwrap();
+        assert_eq!(job, JobFile::new("https://example.com/a.zip"));
+        assert_eq!(
+            job.to_json(),
+            "{\n  \"version\": 1,\n  \"url\": \"https://example.com/a.zip\"\n}"
+        );
+    }
+
+    #[test]
+    fn test_invalid_jobs() {
+        assert!(JobFile::parse("not json").is_err());
+        assert!(JobFile::parse(r#"{"version": 2, "url": "https://example.com/a"}"#).is_err());
+        assert!(JobFile::parse(r#"{"version": 1, "url": "file:///etc/passwd"}"#).is_err());
+        assert!(JobFile::parse(r#"{"version": 1, "url": "s3://bucket/a.zip"}"#).is_ok());
+        let bad_mirror = r#"{"version": 1, "url": "https://a.com/a", "mirrors": ["a.com/a"]}"#;
+        assert!(JobFile::parse(bad_mirror).is_err());
+        let bad_sha = r#"{"version": 1, "url": "https://a.com/a", "sha256": "abc"}"#;
+        assert!(JobFile::parse(bad_sha).is_err());
+    }
+
+    #[test]
+    fn test_is_job_file() {
+        assert!(is_job_file(Path::new("/tmp/ubuntu.yad")));
+        assert!(is_job_file(Path::new("C:\\jobs\\ISO.YAD")));
+        assert!(!is_job_file(Path::new("/tmp/ubuntu.iso")));
+    }
+}
@@ -0,0 +1,257 @@
+//! This module keeps the time to first byte and the duration of the latest chunks, per download
+//! and per host. A server that is slow to answer looks the same as a stalled one from the bytes
+//! alone, so stall detection allows for it, mirrors are tried fastest first and the numbers are
+//! shown when a user asks why a download is slow. Per host numbers are kept until the application
+//! is closed.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Mutex, OnceLock},
+    time::Duration,
+};
+
+use serde::Serialize;
+
+/// How many of the latest chunks are kept per download and per host.
+pub const RING_SIZE: usize = 256;
+
+/// This struct represents one downloaded chunk.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sample {
+    /// From sending the request until the response arrived.
+    pub ttfb: Duration,
+    /// From sending the request until the last byte was written.
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+/// This struct holds the latest samples, dropping the oldest once it is full.
+#[derive(Debug, Default)]
+pub struct Ring {
+    samples: VecDeque<Sample>,
+}
+
+impl Ring {
+    pub fn push(&mut self, sample: Sample) {
+        if self.samples.len() == RING_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// This function sums up the samples, `None` if there are none.
+    pub fn stats(&self) -> Option<LatencyStats> {
+        if self.samples.is_empty() {
+            return None;
+        }
+        let mut ttfb: Vec<u64> = self
+            .samples
+            .iter()
+            .map(|s| s.ttfb.as_millis() as u64)
+            .collect();
+        ttfb.sort_unstable();
+        let total_ms: u64 = self
+            .samples
+            .iter()
+            .map(|s| s.duration.as_millis() as u64)
+            .sum();
+        let bytes: u64 = self.samples.iter().map(|s| s.bytes).sum();
+        Some(LatencyStats {
+            samples: ttfb.len(),
+            median_ttfb_ms: percentile(&ttfb, 50),
+            p90_ttfb_ms: percentile(&ttfb, 90),
+            max_ttfb_ms: ttfb[ttfb.len() - 1],
+            mean_chunk_ms: total_ms / ttfb.len() as u64,
+            speed: bytes * 1000 / total_ms.max(1),
+        })
+    }
+}
+
+/// This function returns the `p`th percentile of `sorted`, which must not be empty.
+fn percentile(sorted: &[u64], p: usize) -> u64 {
+    sorted[(sorted.len() - 1) * p / 100]
+}
+
+/// This struct sums up the latest chunks of a download or a host.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyStats {
+    pub samples: usize,
+    pub median_ttfb_ms: u64,
+    pub p90_ttfb_ms: u64,
+    pub max_ttfb_ms: u64,
+    pub mean_chunk_ms: u64,
+    /// Bytes per second while the chunks were downloading, ignoring the time between them.
+    pub speed: u64,
+}
+
+/// This struct represents the latency of a host.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HostLatency {
+    pub host: String,
+    pub stats: LatencyStats,
+}
+
+#[derive(Default)]
+struct Registry {
+    downloads: HashMap<i64, Ring>,
+    hosts: HashMap<String, Ring>,
+}
+
+fn registry() -> &'static Mutex<Registry> {
+    static REGISTRY: OnceLock<Mutex<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(|| Mutex::new(Registry::default()))
+}
+
+/// This function returns the host of `url`, the key the per host numbers are kept under.
+pub fn host_of(url: &str) -> Option<String> {
+    reqwest::Url::parse(url)
+        .ok()?
+        .host_str()
+        .map(str::to_ascii_lowercase)
+}
+
+/// This function records a chunk of a download from `host`.
+pub fn record(download_id: i64, host: &str, sample: Sample) {
+    let mut registry = registry().lock().unwrap();
+    registry
+        .downloads
+        .entry(download_id)
+        .or_default()
+        .push(sample);
+    registry
+        .hosts
+        .entry(host.to_string())
+        .or_default()
+        .push(sample);
+}
+
+/// This function forgets the chunks of a download once it is no longer running. The host keeps
+/// them.
+pub fn remove(download_id: i64) {
+    registry().lock().unwrap().downloads.remove(&download_id);
+}
+
+/// This function returns the latency of a running download.
+pub fn for_download(download_id: i64) -> Option<LatencyStats> {
+    registry()
+        .lock()
+        .unwrap()
+        .downloads
+        .get(&download_id)
+        .and_then(Ring::stats)
+}
+
+/// This function returns the latency of a host, `None` if nothing was downloaded from it yet.
+pub fn for_host(host: &str) -> Option<LatencyStats> {
+    registry()
+        .lock()
+        .unwrap()
+        .hosts
+        .get(host)
+        .and_then(Ring::stats)
+}
+
+/// This function returns the latency of every host downloaded from, slowest first.
+pub fn hosts() -> Vec<HostLatency> {
+    let registry = registry().lock().unwrap();
+    let mut hosts: Vec<HostLatency> = registry
+        .hosts
+        .iter()
+        .filter_map(|(host, ring)| {
+            ring.stats().map(|stats| HostLatency {
+                host: host.clone(),
+                stats,
+            })
+        })
+        .collect();
+    hosts.sort_by_key(|h| std::cmp::Reverse(h.stats.median_ttfb_ms));
+    hosts
+}
+
+/// This function orders urls of the same file by how fast their hosts answered. Hosts nothing was
+/// downloaded from yet are tried first so that they get measured, in the order given.
+pub fn rank<'a>(urls: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut urls: Vec<&str> = urls.into_iter().collect();
+    urls.sort_by_cached_key(|url| {
+        host_of(url)
+            .and_then(|h| for_host(&h))
+            .map(|s| s.median_ttfb_ms)
+    });
+    urls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(ttfb_ms: u64, duration_ms: u64, bytes: u64) -> Sample {
+        Sample {
+            ttfb: Duration::from_millis(ttfb_ms),
+            duration: Duration::from_millis(duration_ms),
+            bytes,
+        }
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut ring = Ring::default();
+        assert_eq!(ring.stats(), None);
+        for ttfb in 1..=10 {
+            ring.push(sample(ttfb * 100, 1000, 1024 * 1024));
+        }
+        let stats = ring.stats().unwrap();
+        assert_eq!(stats.samples, 10);
+        assert_eq!(stats.median_ttfb_ms, 500);
+        assert_eq!(stats.p90_ttfb_ms, 900);
+        assert_eq!(stats.max_ttfb_ms, 1000);
+        assert_eq!(stats.mean_chunk_ms, 1000);
+        assert_eq!(stats.speed, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_ring_keeps_the_latest() {
+        let mut ring = Ring::default();
+        for i in 0..RING_SIZE as u64 + 10 {
+            ring.push(sample(i, i, 1));
+        }
+        let stats = ring.stats().unwrap();
+        assert_eq!(stats.samples, RING_SIZE);
+        assert_eq!(stats.max_ttfb_ms, RING_SIZE as u64 + 9);
+    }
+
+    #[test]
+    fn test_hosts_outlive_downloads() {
+        record(-1, "slow.latency.test", sample(900, 1000, 10));
+        record(-1, "slow.latency.test", sample(700, 1000, 10));
+        record(-2, "fast.latency.test", sample(20, 100, 10));
+        assert_eq!(for_download(-1).unwrap().samples, 2);
+        remove(-1);
+        assert_eq!(for_download(-1), None);
+        assert_eq!(for_host("slow.latency.test").unwrap().samples, 2);
+        remove(-2);
+
+        let ranked = rank([
+            "https://slow.latency.test/a.iso",
+            "https://fast.latency.test/a.iso",
+            "https://new.latency.test/a.iso",
+        ]);
+        assert_eq!(
+            ranked,
+            vec![
+                "https://new.latency.test/a.iso",
+                "https://fast.latency.test/a.iso",
+                "https://slow.latency.test/a.iso",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_host_of() {
+        assert_eq!(
+            host_of("https://Example.com:8080/a?b=c").as_deref(),
+            Some("example.com")
+        );
+        assert_eq!(host_of("not a url"), None);
+    }
+}
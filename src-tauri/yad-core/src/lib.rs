@@ -0,0 +1,67 @@
+//! The download engine of yad and everything it needs: storage, settings, file naming and the
+//! network helpers. Nothing here depends on a user interface; `engine` reports what happens as
+//! events which the desktop app, or any other frontend, turns into whatever it shows.
+
+pub mod allocation;
+pub mod bandwidth;
+pub mod binding;
+pub mod bundle;
+pub mod chunks;
+pub mod config;
+pub mod connectivity;
+pub mod crash;
+pub mod criteria;
+pub mod dash;
+pub mod data_url;
+pub mod decisions;
+pub mod db_writer;
+pub mod diagnosis;
+pub mod engine;
+pub mod eta;
+pub mod file_manager;
+pub mod file_writer;
+pub mod files;
+pub mod ftp;
+pub mod hashing;
+pub mod health;
+pub mod history;
+pub mod idle;
+pub mod integrity;
+pub mod jobfile;
+pub mod latency;
+pub mod metalink;
+pub mod metrics;
+pub mod mirrors;
+pub mod music;
+pub mod mux;
+pub mod onboarding;
+pub mod organize;
+pub mod pins;
+pub mod post_processing;
+pub mod power;
+pub mod presets;
+pub mod privacy;
+pub mod progress;
+pub mod proxy;
+pub mod push;
+pub mod queue;
+pub mod record_query;
+pub mod redirects;
+pub mod report;
+pub mod retry;
+pub mod s3;
+pub mod scheduler;
+pub mod settings;
+pub mod sftp;
+pub mod simulation;
+pub mod ssh;
+pub mod storage;
+pub mod subtitles;
+pub mod templates;
+pub mod throttle;
+pub mod trust;
+pub mod units;
+pub mod upgrade;
+pub mod validators;
+pub mod watch_folders;
+pub mod watchdog;
@@ -0,0 +1,256 @@
+//! This module reads Metalink files (RFC 5854, `.meta4`), which list the urls serving a file
+//! together with its size and checksums. The urls of a file are downloaded from in parallel as
+//! mirrors, see `mirrors`, and the file is checked against its SHA-256 checksum once downloaded,
+//! see `criteria`. The older Metalink 3 format is not supported.
+
+use std::path::Path;
+
+use roxmltree::{Document, Node};
+
+use crate::{criteria::SuccessCriteria, ftp, sftp};
+
+/// The extension of Metalink files, registered with the operating system in `tauri.conf.json`.
+pub const EXTENSION: &str = "meta4";
+
+/// The namespace of the elements of a Metalink file.
+const NAMESPACE: &str = "urn:ietf:params:xml:ns:metalink";
+
+/// The priority of urls without one, the lowest there is.
+const LOWEST_PRIORITY: u32 = 999_999;
+
+/// This struct represents a file of a Metalink file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetalinkFile {
+    /// The name of the file, without the folders it may be in.
+    pub name: String,
+    pub size: Option<u64>,
+    /// The SHA-256 checksum of the file as hex.
+    pub sha256: Option<String>,
+    /// The urls serving the file, the preferred first.
+    pub urls: Vec<String>,
+}
+
+impl MetalinkFile {
+    /// This function returns the url the file is downloaded from and its mirrors. Only HTTP urls
+    /// are downloaded from together; a file without any is downloaded from its first FTP or SFTP
+    /// url alone.
+    pub fn sources(&self) -> Option<(&str, Vec<String>)> {
+        let mut http = self
+            .urls
+            .iter()
+            .filter(|url| url.starts_with("http://") || url.starts_with("https://"));
+        match http.next() {
+            Some(url) => Some((url, http.cloned().collect())),
+            None => self
+                .urls
+                .iter()
+                .find(|url| ftp::is_ftp(url) || sftp::is_sftp(url))
+                .map(|url| (url.as_str(), Vec::new())),
+        }
+    }
+
+    /// This function returns the conditions the downloaded file must meet: exactly its size, and
+    /// its checksum.
+    pub fn criteria(&self) -> SuccessCriteria {
+        SuccessCriteria {
+            min_size: self.size,
+            max_size: self.size,
+            sha256: self.sha256.clone(),
+            ..SuccessCriteria::default()
+        }
+    }
+}
+
+/// This function returns the child elements of the Metalink namespace named `name`.
+fn children<'a, 'input>(
+    node: Node<'a, 'input>,
+    name: &'static str,
+) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children()
+        .filter(move |child| child.has_tag_name((NAMESPACE, name)))
+}
+
+/// This function reads the `file` element of a Metalink file.
+fn read_file(node: Node) -> Result<MetalinkFile, String> {
+    let declared = node
+        .attribute("name")
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .ok_or("A file of the Metalink file has no name")?;
+    // the name may hold folders, but none that leave the folder it is downloaded to
+    let relative = Path::new(declared);
+    if relative.is_absolute() || declared.split(['/', '\\']).any(|part| part == "..") {
+        return Err(format!("Invalid file name in Metalink file: {declared}"));
+    }
+    let name = relative
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| format!("Invalid file name in Metalink file: {declared}"))?;
+
+    let size = match children(node, "size").next().and_then(|n| n.text()) {
+        Some(size) => Some(
+            size.trim()
+                .parse()
+                .map_err(|_| format!("Invalid size of {name} in Metalink file: {size}"))?,
+        ),
+        None => None,
+    };
+    let sha256 = children(node, "hash")
+        .find(|n| {
+            n.attribute("type")
+                .is_some_and(|t| t.eq_ignore_ascii_case("sha-256"))
+        })
+        .and_then(|n| n.text())
+        .map(|hex| hex.trim().to_ascii_lowercase());
+    if let Some(hex) = &sha256 {
+        if hex.len() != 64 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!(
+                "Invalid SHA-256 checksum of {name} in Metalink file: {hex}"
+            ));
+        }
+    }
+
+    let mut urls: Vec<(u32, String)> = children(node, "url")
+        .filter_map(|n| {
+            let url = n.text()?.trim();
+            let priority = n.attribute("priority").and_then(|p| p.trim().parse().ok());
+            Some((priority.unwrap_or(LOWEST_PRIORITY), url.to_string()))
+        })
+        .filter(|(_, url)| !url.is_empty())
+        .collect();
+    // a stable sort keeps the order of the file among urls of the same priority
+    urls.sort_by_key(|(priority, _)| *priority);
+    let mut unique: Vec<String> = Vec::new();
+    for (_, url) in urls {
+        if !unique.contains(&url) {
+            unique.push(url);
+        }
+    }
+
+    let file = MetalinkFile {
+        name: name.to_string(),
+        size,
+        sha256,
+        urls: unique,
+    };
+    if file.sources().is_none() {
+        return Err(format!(
+            "The Metalink file has no url YAD can download {name} from"
+        ));
+    }
+    Ok(file)
+}
+
+/// This function parses the content of a Metalink file.
+///
+/// # Returns
+/// - `Ok(Vec<MetalinkFile>)`: The files it lists, at least one.
+/// - `Err(String)`: If the content is not a valid Metalink file.
+pub fn parse(content: &str) -> Result<Vec<MetalinkFile>, String> {
+    let document = Document::parse(content).map_err(|e| format!("Invalid Metalink file: {e}"))?;
+    let root = document.root_element();
+    if !root.has_tag_name((NAMESPACE, "metalink")) {
+        return Err("Invalid Metalink file: only Metalink 4 (.meta4) files are supported".into());
+    }
+    let files = children(root, "file")
+        .map(read_file)
+        .collect::<Result<Vec<_>, _>>()?;
+    if files.is_empty() {
+        return Err("The Metalink file lists no files".into());
+    }
+    Ok(files)
+}
+
+/// This function checks whether `path` looks like a Metalink file.
+pub fn is_metalink(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case(EXTENSION))
+}
+
+/// This function checks whether `url` points to a Metalink file, going by its extension.
+pub fn is_metalink_url(url: &str) -> bool {
+    reqwest::Url::parse(url).is_ok_and(|url| {
+        matches!(url.scheme(), "http" | "https") && is_metalink(Path::new(url.path()))
+    })
+}
+
+/// This function reads a Metalink file.
+pub fn read(path: &Path) -> Result<Vec<MetalinkFile>, String> {
+    let content =
+        std::fs::read_to_string(path).map_err(|e| format!("Failed to read Metalink file: {e}"))?;
+    parse(&content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_parse() {
+        let content = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <metalink xmlns="urn:ietf:params:xml:ns:metalink">
+              <file name="iso/ubuntu.iso">
+                <size>14471447</size>
+                <hash type="sha-1">a9993e364706816aba3e25717850c26c9cd0d89d</hash>
+                <hash type="sha-256">{}</hash>
+                <url location="de">ftp://ftp.example.de/ubuntu.iso</url>
+                <url priority="2">https://b.example.com/ubuntu.iso</url>
+                <url priority="1">https://a.example.com/ubuntu.iso</url>
+                <url>https://c.example.com/ubuntu.iso</url>
+                <metaurl mediatype="torrent">https://example.com/ubuntu.torrent</metaurl>
+              </file>
+              <file name="notes.txt">
+                <url>sftp://example.com/notes.txt</url>
+              </file>
+            </metalink>"#,
+            SHA256.to_uppercase()
+        );
+        let files = parse(&content).unwrap();
+        assert_eq!(files.len(), 2);
+        let iso = &files[0];
+        assert_eq!(iso.name, "ubuntu.iso");
+        assert_eq!(iso.size, Some(14471447));
+        assert_eq!(iso.sha256.as_deref(), Some(SHA256));
+        let (url, mirrors) = iso.sources().unwrap();
+        assert_eq!(url, "https://a.example.com/ubuntu.iso");
+        assert_eq!(
+            mirrors,
+            [
+                "https://b.example.com/ubuntu.iso",
+                "https://c.example.com/ubuntu.iso"
+            ]
+        );
+        assert_eq!(iso.criteria().min_size, Some(14471447));
+        assert_eq!(iso.criteria().max_size, Some(14471447));
+        assert_eq!(
+            files[1].sources(),
+            Some(("sftp://example.com/notes.txt", Vec::new()))
+        );
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        let metalink = |file: &str| {
+            format!(r#"<metalink xmlns="urn:ietf:params:xml:ns:metalink">{file}</metalink>"#)
+        };
+        assert!(parse("not xml").is_err());
+        let version3 = r#"<metalink xmlns="http://www.metalinker.org/" version="3.0"/>"#;
+        assert!(parse(version3).unwrap_err().contains("Metalink 4"));
+        assert!(parse(&metalink("")).is_err());
+        let escape = r#"<file name="../.bashrc"><url>https://a.com/a</url></file>"#;
+        assert!(parse(&metalink(escape)).is_err());
+        let no_url = r#"<file name="a"><url>file:///etc/passwd</url></file>"#;
+        assert!(parse(&metalink(no_url)).is_err());
+        let bad_hash =
+            r#"<file name="a"><hash type="sha-256">abc</hash><url>https://a.com/a</url></file>"#;
+        assert!(parse(&metalink(bad_hash)).is_err());
+        assert!(is_metalink_url(
+            "https://example.com/ubuntu.meta4?mirrorlist"
+        ));
+        assert!(!is_metalink_url("https://example.com/ubuntu.iso"));
+    }
+}
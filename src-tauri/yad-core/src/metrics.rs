@@ -0,0 +1,218 @@
+//! This module serves metrics about the downloads in the text format of Prometheus, so that YAD
+//! running on a server without anyone looking at it can be watched from existing dashboards. The
+//! endpoint is off unless a port is set in the settings, and only listens on the local machine.
+//! Counters start from zero every time YAD starts, which Prometheus handles as a restart.
+
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+
+use crate::{progress, queue};
+
+/// How long a client may take to send its request before it is dropped, so that one stuck
+/// client does not block the scrapes after it.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+static DOWNLOADS_FINISHED: AtomicU64 = AtomicU64::new(0);
+static DOWNLOADS_FAILED: AtomicU64 = AtomicU64::new(0);
+static CHUNK_ERRORS: AtomicU64 = AtomicU64::new(0);
+static DB_WRITES: AtomicU64 = AtomicU64::new(0);
+static DB_WRITE_MICROS: AtomicU64 = AtomicU64::new(0);
+
+/// This function counts a download that finished.
+pub fn download_finished() {
+    DOWNLOADS_FINISHED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// This function counts a download that failed.
+pub fn download_failed() {
+    DOWNLOADS_FAILED.fetch_add(1, Ordering::Relaxed);
+}
+
+/// This function counts a failed attempt at a chunk, whether it is retried or not.
+pub fn chunk_error() {
+    CHUNK_ERRORS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// This function records how long a write to the database took.
+pub fn db_write(took: Duration) {
+    DB_WRITES.fetch_add(1, Ordering::Relaxed);
+    DB_WRITE_MICROS.fetch_add(took.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// This struct represents the values of the metrics at one moment.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snapshot {
+    pub active_downloads: usize,
+    /// The sum of the speeds of the running downloads.
+    pub bytes_per_second: u64,
+    pub queued_downloads: usize,
+    pub downloads_finished: u64,
+    pub downloads_failed: u64,
+    pub chunk_errors: u64,
+    pub db_writes: u64,
+    pub db_write_seconds: f64,
+}
+
+/// This function reads the current values of the metrics.
+pub fn snapshot() -> Snapshot {
+    let active = progress::active_downloads();
+    Snapshot {
+        active_downloads: active.len(),
+        bytes_per_second: active.iter().map(|a| a.speed).sum(),
+        queued_downloads: queue::global().queued().len(),
+        downloads_finished: DOWNLOADS_FINISHED.load(Ordering::Relaxed),
+        downloads_failed: DOWNLOADS_FAILED.load(Ordering::Relaxed),
+        chunk_errors: CHUNK_ERRORS.load(Ordering::Relaxed),
+        db_writes: DB_WRITES.load(Ordering::Relaxed),
+        db_write_seconds: DB_WRITE_MICROS.load(Ordering::Relaxed) as f64 / 1_000_000.0,
+    }
+}
+
+/// This function writes the metrics in the text format of Prometheus.
+pub fn render(s: &Snapshot) -> String {
+    let metrics: [(&str, &str, &str, String); 8] = [
+        (
+            "yad_active_downloads",
+            "gauge",
+            "Downloads running now.",
+            s.active_downloads.to_string(),
+        ),
+        (
+            "yad_download_bytes_per_second",
+            "gauge",
+            "The total speed of the running downloads.",
+            s.bytes_per_second.to_string(),
+        ),
+        (
+            "yad_queued_downloads",
+            "gauge",
+            "Downloads waiting for a free slot.",
+            s.queued_downloads.to_string(),
+        ),
+        (
+            "yad_downloads_finished_total",
+            "counter",
+            "Downloads that finished.",
+            s.downloads_finished.to_string(),
+        ),
+        (
+            "yad_downloads_failed_total",
+            "counter",
+            "Downloads that failed.",
+            s.downloads_failed.to_string(),
+        ),
+        (
+            "yad_chunk_errors_total",
+            "counter",
+            "Failed attempts at chunks, retried or not.",
+            s.chunk_errors.to_string(),
+        ),
+        (
+            "yad_db_writes_total",
+            "counter",
+            "Batches of chunk updates written to the database.",
+            s.db_writes.to_string(),
+        ),
+        (
+            "yad_db_write_seconds_total",
+            "counter",
+            "Time spent writing chunk updates to the database.",
+            s.db_write_seconds.to_string(),
+        ),
+    ];
+    let mut out = String::new();
+    for (name, kind, help, value) in metrics {
+        let _ = write!(
+            out,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+        );
+    }
+    out
+}
+
+fn respond(mut stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            ("200 OK", "text/plain; version=0.0.4", render(&snapshot()))
+        }
+        _ => ("404 Not Found", "text/plain", "Not found\n".to_string()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// This function serves the metrics at `http://127.0.0.1:{port}/metrics` from a thread of its own.
+///
+/// # Returns
+/// - `Ok(())`: if the port could be listened on.
+/// - `Err(io::Error)`: if it is taken, for example.
+pub fn serve(port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+    thread::Builder::new()
+        .name("yad-metrics".into())
+        .spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = respond(stream) {
+                    eprintln!("failed to answer a metrics request because {e}");
+                }
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_render() {
+        let s = Snapshot {
+            active_downloads: 2,
+            bytes_per_second: 1500,
+            db_write_seconds: 0.25,
+            ..Snapshot::default()
+        };
+        let text = render(&s);
+        assert!(text.contains("# TYPE yad_active_downloads gauge\nyad_active_downloads 2\n"));
+        assert!(text.contains("\nyad_download_bytes_per_second 1500\n"));
+        assert!(text.contains("\nyad_db_write_seconds_total 0.25\n"));
+        assert_eq!(text.lines().filter(|l| l.starts_with("# HELP")).count(), 8);
+    }
+
+    #[test]
+    fn test_serve() {
+        // a free port picked by the system
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        serve(port).unwrap();
+        let get = |path: &str| {
+            let mut stream = TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+            write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        };
+        let metrics = get("/metrics");
+        assert!(metrics.starts_with("HTTP/1.1 200 OK"));
+        assert!(metrics.contains("yad_downloads_failed_total "));
+        assert!(get("/").starts_with("HTTP/1.1 404"));
+    }
+}
@@ -0,0 +1,222 @@
+//! This module spreads the chunks of a download over mirrors, other urls serving the same file,
+//! so that they are downloaded from several servers in parallel. Every chunk starts at the next
+//! source in turn and a retry moves on to the following one, so a mirror that fails is not asked
+//! twice in a row. Mirrors are only used when they announce the same size as the file and can
+//! send parts of it.
+//!
+//! A source that keeps failing, e.g. the url once its server goes down in the middle of a
+//! download, is failed over: the chunks still to come and the retries of the others skip it while
+//! another source works, instead of running out of retries and failing the whole download.
+
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+/// How many failures in a row make a source be skipped.
+pub const FAILOVER_AFTER: u32 = 2;
+
+/// This struct represents the urls the chunks of a download are requested from.
+#[derive(Debug)]
+pub struct Sources {
+    urls: Vec<String>,
+    /// The failures in a row of each source.
+    failures: Vec<AtomicU32>,
+    next: AtomicUsize,
+}
+
+impl Sources {
+    /// This function creates the sources of a download. Mirrors equal to the url, or to another
+    /// mirror, are left out.
+    ///
+    /// # Arguments
+    /// - `url`: The url the file was requested from.
+    /// - `mirrors`: The mirrors which serve the same file.
+    pub fn new(url: &str, mirrors: Vec<String>) -> Self {
+        let mut urls = vec![url.to_string()];
+        for mirror in mirrors {
+            if !urls.contains(&mirror) {
+                urls.push(mirror);
+            }
+        }
+        let failures = urls.iter().map(|_| AtomicU32::new(0)).collect();
+        Sources {
+            urls,
+            failures,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// This function returns whether there are no mirrors, only the url itself.
+    pub fn is_single(&self) -> bool {
+        self.urls.len() == 1
+    }
+
+    /// This function returns whether `url` is the url the file was requested from rather than a
+    /// mirror. The validator of a download, see `validators`, only holds for it: mirrors send
+    /// their own ETag for the same bytes.
+    pub fn is_primary(&self, url: &str) -> bool {
+        self.urls[0] == url
+    }
+
+    /// This function returns the source of the next chunk.
+    pub fn next_index(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % self.urls.len()
+    }
+
+    /// This function returns whether a source has been failed over.
+    fn is_down(&self, index: usize) -> bool {
+        self.failures[index].load(Ordering::Relaxed) >= FAILOVER_AFTER
+    }
+
+    /// This function returns the url of a chunk. Sources which have been failed over are skipped,
+    /// unless all of them have.
+    ///
+    /// # Arguments
+    /// - `first`: The source the chunk started at, see `next_index`.
+    /// - `attempt`: The number of retries so far.
+    pub fn url(&self, first: usize, attempt: u32) -> &str {
+        let len = self.urls.len();
+        let turns: Vec<usize> = (0..len).map(|k| (first + k) % len).collect();
+        let working: Vec<usize> = turns
+            .iter()
+            .copied()
+            .filter(|&i| !self.is_down(i))
+            .collect();
+        let turns = if working.is_empty() { turns } else { working };
+        &self.urls[turns[attempt as usize % turns.len()]]
+    }
+
+    /// This function returns how many times a chunk is retried, enough for it to try every
+    /// source at least once.
+    pub fn retries(&self, chunk_retries: u32) -> u32 {
+        chunk_retries.max(self.urls.len() as u32 - 1)
+    }
+
+    /// This function records that a request to `url` failed.
+    ///
+    /// # Returns
+    /// Whether this failure made the source be failed over.
+    pub fn failed(&self, url: &str) -> bool {
+        let Some(index) = self.urls.iter().position(|u| u == url) else {
+            return false;
+        };
+        self.failures[index].fetch_add(1, Ordering::Relaxed) + 1 == FAILOVER_AFTER
+            && !self.is_single()
+    }
+
+    /// This function records that a request to `url` succeeded, which brings a source that was
+    /// failed over back.
+    pub fn succeeded(&self, url: &str) {
+        if let Some(index) = self.urls.iter().position(|u| u == url) {
+            self.failures[index].store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+/// This function checks that a mirror can be downloaded from.
+pub fn validate(url: &str) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid mirror {url}: {e}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err(format!("Mirror {url} must start with http:// or https://"));
+    }
+    Ok(())
+}
+
+/// This function checks that a mirror serves the same file, going by its response to a `HEAD`
+/// request.
+///
+/// # Arguments
+/// - `total_size`: The size of the file.
+/// - `size`: The size the mirror announces.
+/// - `accept_ranges`: The `Accept-Ranges` header of the mirror.
+pub fn serves_same_file(
+    total_size: u64,
+    size: Option<u64>,
+    accept_ranges: Option<&str>,
+) -> Result<(), String> {
+    if size != Some(total_size) {
+        return Err(match size {
+            Some(size) => format!("announces {size} bytes instead of {total_size}"),
+            None => "does not announce the size of the file".to_string(),
+        });
+    }
+    if crate::chunks::ranges_refused(accept_ranges) {
+        return Err("cannot send parts of the file".to_string());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sources_take_turns() {
+        let sources = Sources::new(
+            "https://a.example/f.iso",
+            vec![
+                "https://b.example/f.iso".into(),
+                "https://a.example/f.iso".into(),
+                "https://c.example/f.iso".into(),
+            ],
+        );
+        assert!(!sources.is_single());
+        let firsts: Vec<usize> = (0..4).map(|_| sources.next_index()).collect();
+        assert_eq!(firsts, [0, 1, 2, 0]);
+        // a retry moves on to the next mirror
+        assert_eq!(sources.url(2, 0), "https://c.example/f.iso");
+        assert_eq!(sources.url(2, 1), "https://a.example/f.iso");
+        assert_eq!(sources.url(2, 2), "https://b.example/f.iso");
+        assert!(sources.is_primary("https://a.example/f.iso"));
+        assert!(!sources.is_primary("https://b.example/f.iso"));
+
+        let single = Sources::new("https://a.example/f.iso", Vec::new());
+        assert!(single.is_single());
+        assert_eq!(
+            single.url(single.next_index(), 3),
+            "https://a.example/f.iso"
+        );
+    }
+
+    #[test]
+    fn test_failover() {
+        let sources = Sources::new(
+            "https://a.example/f.iso",
+            vec![
+                "https://b.example/f.iso".into(),
+                "https://c.example/f.iso".into(),
+            ],
+        );
+        assert_eq!(sources.retries(0), 2);
+        assert_eq!(sources.retries(5), 5);
+
+        assert!(!sources.failed("https://a.example/f.iso"));
+        assert_eq!(sources.url(0, 0), "https://a.example/f.iso");
+        assert!(sources.failed("https://a.example/f.iso"));
+        // the chunks starting at the url move on to the mirrors
+        assert_eq!(sources.url(0, 0), "https://b.example/f.iso");
+        assert_eq!(sources.url(0, 1), "https://c.example/f.iso");
+        assert_eq!(sources.url(2, 1), "https://b.example/f.iso");
+
+        // with every source down, they are all tried again
+        for url in ["https://b.example/f.iso", "https://c.example/f.iso"] {
+            sources.failed(url);
+            sources.failed(url);
+        }
+        assert_eq!(sources.url(0, 0), "https://a.example/f.iso");
+
+        sources.succeeded("https://c.example/f.iso");
+        assert_eq!(sources.url(0, 0), "https://c.example/f.iso");
+    }
+
+    #[test]
+    fn test_mirrors_must_serve_the_same_file() {
+        assert!(validate("https://b.example/f.iso").is_ok());
+        assert!(validate("ftp://b.example/f.iso").is_err());
+        assert!(validate("not a url").is_err());
+
+        assert!(serves_same_file(100, Some(100), Some("bytes")).is_ok());
+        assert!(serves_same_file(100, Some(100), None).is_ok());
+        assert!(serves_same_file(100, Some(99), Some("bytes")).is_err());
+        assert!(serves_same_file(100, None, Some("bytes")).is_err());
+        assert!(serves_same_file(100, Some(100), Some("none")).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+//! This module files downloaded audio into a music library. The artist and album are read from
+//! the ID3 tags of the file and it is moved, or copied, to `<library>/<artist>/<album>/`, the
+//! layout music players and podcast apps expect.
+
+use std::{
+    fs,
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+};
+
+use crate::files;
+
+/// The folder used when a file has no artist tag.
+pub const UNKNOWN_ARTIST: &str = "Unknown Artist";
+/// The folder used when a file has no album tag.
+pub const UNKNOWN_ALBUM: &str = "Unknown Album";
+
+/// This struct represents the tags a file is filed by.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Tags {
+    pub artist: Option<String>,
+    pub album: Option<String>,
+}
+
+/// This function decodes a size stored in 7 bit bytes, as used by ID3v2.
+fn syncsafe(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |size, b| (size << 7) | (*b as usize & 0x7f))
+}
+
+/// This function decodes a size stored in plain big endian bytes.
+fn big_endian(bytes: &[u8]) -> usize {
+    bytes.iter().fold(0, |size, b| (size << 8) | *b as usize)
+}
+
+/// This function decodes a UTF-16 text, big endian unless a byte order mark says otherwise.
+fn utf16(bytes: &[u8]) -> String {
+    let (little_endian, bytes) = match bytes {
+        [0xff, 0xfe, rest @ ..] => (true, rest),
+        [0xfe, 0xff, rest @ ..] => (false, rest),
+        _ => (false, bytes),
+    };
+    let units: Vec<u16> = bytes
+        .chunks_exact(2)
+        .map(|u| {
+            if little_endian {
+                u16::from_le_bytes([u[0], u[1]])
+            } else {
+                u16::from_be_bytes([u[0], u[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// This function decodes the content of an ID3v2 text frame. The first byte is the encoding.
+fn text_frame(data: &[u8]) -> Option<String> {
+    let (encoding, text) = data.split_first()?;
+    let text = match encoding {
+        0 => text.iter().map(|b| *b as char).collect(),
+        1 | 2 => utf16(text),
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+    // several values are separated by null characters, only the first is used
+    let text = text.split('\0').next().unwrap_or("").trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// This function reads the artist and album frames of an ID3v2 tag, without its 10 byte header.
+fn parse_id3v2(version: u8, flags: u8, tag: &[u8]) -> Tags {
+    let mut tags = Tags::default();
+    // the album artist is only used when there is no artist
+    let mut album_artist = None;
+    let mut pos = 0;
+    // the extended header is skipped, its size includes itself in version 4 only
+    if flags & 0x40 != 0 && tag.len() >= 4 {
+        pos = match version {
+            4 => syncsafe(&tag[..4]),
+            _ => big_endian(&tag[..4]) + 4,
+        };
+    }
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    while pos + header_len <= tag.len() {
+        let id = &tag[pos..pos + id_len];
+        if id[0] == 0 {
+            // padding
+            break;
+        }
+        let size = match version {
+            2 => big_endian(&tag[pos + 3..pos + 6]),
+            3 => big_endian(&tag[pos + 4..pos + 8]),
+            _ => syncsafe(&tag[pos + 4..pos + 8]),
+        };
+        let start = pos + header_len;
+        let Some(data) = tag.get(start..start + size) else {
+            break;
+        };
+        match id {
+            b"TPE1" | b"TP1" => tags.artist = text_frame(data),
+            b"TPE2" | b"TP2" => album_artist = text_frame(data),
+            b"TALB" | b"TAL" => tags.album = text_frame(data),
+            _ => {}
+        }
+        pos = start + size;
+    }
+    tags.artist = tags.artist.or(album_artist);
+    tags
+}
+
+/// This function reads a field of an ID3v1 tag, which is padded with nulls or spaces.
+fn id3v1_field(bytes: &[u8]) -> Option<String> {
+    let text: String = bytes
+        .iter()
+        .take_while(|b| **b != 0)
+        .map(|b| *b as char)
+        .collect();
+    let text = text.trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// This function reads the artist and album of an audio file from its ID3v2 tag, or from the
+/// older ID3v1 tag at the end of the file when there is none.
+pub fn read_tags(path: &Path) -> io::Result<Tags> {
+    let mut file = fs::File::open(path)?;
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_ok() && header.starts_with(b"ID3") {
+        let mut tag = vec![0u8; syncsafe(&header[6..10])];
+        file.read_exact(&mut tag)?;
+        let tags = parse_id3v2(header[3], header[5], &tag);
+        if tags != Tags::default() {
+            return Ok(tags);
+        }
+    }
+    if file.metadata()?.len() < 128 {
+        return Ok(Tags::default());
+    }
+    let mut tag = [0u8; 128];
+    file.seek(SeekFrom::End(-128))?;
+    file.read_exact(&mut tag)?;
+    if !tag.starts_with(b"TAG") {
+        return Ok(Tags::default());
+    }
+    Ok(Tags {
+        artist: id3v1_field(&tag[33..63]),
+        album: id3v1_field(&tag[63..93]),
+    })
+}
+
+/// This function returns where a file is filed in the library, e.g.
+/// `<library>/Artist/Album/song.mp3`.
+pub fn library_path(library: &Path, tags: &Tags, file_name: &str) -> PathBuf {
+    let artist = tags.artist.as_deref().unwrap_or(UNKNOWN_ARTIST);
+    let album = tags.album.as_deref().unwrap_or(UNKNOWN_ALBUM);
+    library
+        .join(files::sanitize_file_name(artist, false))
+        .join(files::sanitize_file_name(album, false))
+        .join(file_name)
+}
+
+/// This function files an audio file into the library.
+///
+/// # Arguments
+/// - `path`: The downloaded file.
+/// - `library`: The root folder of the library.
+/// - `keep_original`: Whether the file is copied instead of moved.
+///
+/// # Returns
+/// - `Ok(PathBuf)`: Where the file is now.
+/// - `Err(String)`: If the file cannot be read or moved, or the library already has a file with
+///   its name.
+pub fn import(path: &Path, library: &Path, keep_original: bool) -> Result<PathBuf, String> {
+    let tags = read_tags(path).map_err(|e| format!("Failed to read tags: {e}"))?;
+    let file_name = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or("The file has no name")?;
+    let target = library_path(library, &tags, file_name);
+    if target.exists() {
+        return Err(format!("{} already exists", target.display()));
+    }
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Failed to create folder: {e}"))?;
+    }
+    // renaming fails when the library is on another drive, so the file is copied instead
+    if keep_original || fs::rename(path, &target).is_err() {
+        fs::copy(path, &target).map_err(|e| format!("Failed to copy file: {e}"))?;
+        if !keep_original {
+            let _ = fs::remove_file(path);
+        }
+    }
+    Ok(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id3v2_frame(id: &[u8], text: &[u8]) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        frame.extend_from_slice(&((text.len() + 1) as u32).to_be_bytes());
+        frame.extend_from_slice(&[0, 0, 3]);
+        frame.extend_from_slice(text);
+        frame
+    }
+
+    fn id3v2_tag(frames: &[u8]) -> Vec<u8> {
+        let size = frames.len() + 16;
+        let mut tag = b"ID3\x03\x00\x00".to_vec();
+        tag.extend((0..4).rev().map(|i| ((size >> (7 * i)) & 0x7f) as u8));
+        tag.extend_from_slice(frames);
+        // padding
+        tag.extend_from_slice(&[0; 16]);
+        tag
+    }
+
+    #[test]
+    fn test_read_id3v2_tags() {
+        let dir = std::env::temp_dir().join("yad_test_music_id3v2");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+
+        let mut frames = id3v2_frame(b"TIT2", b"Song");
+        frames.extend(id3v2_frame(b"TPE1", "Sigur Rós".as_bytes()));
+        frames.extend(id3v2_frame(b"TALB", b"Takk..."));
+        let mut content = id3v2_tag(&frames);
+        content.extend_from_slice(&[0xff; 64]);
+        fs::write(&path, content).unwrap();
+
+        let tags = read_tags(&path).unwrap();
+        assert_eq!(tags.artist.as_deref(), Some("Sigur Rós"));
+        assert_eq!(tags.album.as_deref(), Some("Takk..."));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_read_id3v1_tags() {
+        let dir = std::env::temp_dir().join("yad_test_music_id3v1");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("song.mp3");
+
+        let mut tag = [0u8; 128];
+        tag[..3].copy_from_slice(b"TAG");
+        tag[33..39].copy_from_slice(b"Artist");
+        tag[63..68].copy_from_slice(b"Album");
+        let mut content = vec![0xff; 256];
+        content.extend_from_slice(&tag);
+        fs::write(&path, content).unwrap();
+
+        let tags = read_tags(&path).unwrap();
+        assert_eq!(tags.artist.as_deref(), Some("Artist"));
+        assert_eq!(tags.album.as_deref(), Some("Album"));
+
+        fs::write(&path, b"no tags").unwrap();
+        assert_eq!(read_tags(&path).unwrap(), Tags::default());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_library_path() {
+        let tags = Tags {
+            artist: Some("AC/DC".into()),
+            album: None,
+        };
+        assert_eq!(
+            library_path(Path::new("/music"), &tags, "song.mp3"),
+            Path::new("/music/AC_DC/Unknown Album/song.mp3")
+        );
+    }
+
+    #[test]
+    fn test_import() {
+        let dir = std::env::temp_dir().join("yad_test_music_import");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("episode.mp3");
+        let mut content = id3v2_tag(&id3v2_frame(b"TPE1", b"Podcast"));
+        content.extend_from_slice(&[0xff; 64]);
+        fs::write(&path, &content).unwrap();
+
+        let library = dir.join("library");
+        let target = import(&path, &library, true).unwrap();
+        assert_eq!(target, library.join("Podcast/Unknown Album/episode.mp3"));
+        assert!(path.exists());
+        // the library already has the file
+        assert!(import(&path, &library, false).is_err());
+
+        fs::remove_file(&target).unwrap();
+        import(&path, &library, false).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read(&target).unwrap(), content);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,561 @@
+//! This module muxes the video and the audio of a DASH stream, see `dash`, into a single MP4
+//! file. Both are fragmented MP4 files: an initialization part (`ftyp` and `moov`) describing
+//! the tracks, followed by fragments (`moof` and `mdat`) holding their samples.
+//!
+//! The fragments are kept as they are, only their track and sequence numbers change, and are
+//! interleaved by time, so no sample is decoded and the file never has to fit in memory: the
+//! samples (`mdat`) are copied from one file to the other.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+/// The flag of `tfhd` telling that the data offsets of a fragment are relative to the start of
+/// the file, which would change once it is muxed.
+const BASE_DATA_OFFSET_PRESENT: u32 = 0x000001;
+
+/// This struct represents a box inside a buffer.
+struct Mp4Box {
+    kind: [u8; 4],
+    start: usize,
+    /// Where the content of the box starts, after its header.
+    content: usize,
+    end: usize,
+}
+
+impl Mp4Box {
+    fn content(&self) -> Range<usize> {
+        self.content..self.end
+    }
+}
+
+/// This function lists the boxes found in `range` of `data`.
+fn boxes(data: &[u8], range: Range<usize>) -> Result<Vec<Mp4Box>, String> {
+    let invalid = || "Invalid MP4 box".to_string();
+    let mut found = Vec::new();
+    let mut position = range.start;
+    while position + 8 <= range.end {
+        let size = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as u64;
+        let kind: [u8; 4] = data[position + 4..position + 8].try_into().unwrap();
+        let (header, size) = match size {
+            0 => (8, (range.end - position) as u64),
+            1 => {
+                let large = data.get(position + 8..position + 16).ok_or_else(invalid)?;
+                (16, u64::from_be_bytes(large.try_into().unwrap()))
+            }
+            size => (8, size),
+        };
+        let end = usize::try_from(size)
+            .ok()
+            .and_then(|size| position.checked_add(size))
+            .filter(|end| *end <= range.end && size >= header as u64)
+            .ok_or_else(invalid)?;
+        found.push(Mp4Box {
+            kind,
+            start: position,
+            content: position + header,
+            end,
+        });
+        position = end;
+    }
+    Ok(found)
+}
+
+/// This function returns the first box of `kind` in `range` of `data`.
+fn find(data: &[u8], range: Range<usize>, kind: &[u8; 4]) -> Result<Option<Mp4Box>, String> {
+    Ok(boxes(data, range)?.into_iter().find(|b| &b.kind == kind))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32, String> {
+    data.get(at..at + 4)
+        .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()))
+        .ok_or_else(|| "Invalid MP4 box".to_string())
+}
+
+fn write_u32(data: &mut [u8], at: usize, value: u32) -> Result<(), String> {
+    data.get_mut(at..at + 4)
+        .map(|bytes| bytes.copy_from_slice(&value.to_be_bytes()))
+        .ok_or_else(|| "Invalid MP4 box".to_string())
+}
+
+/// This function returns where a field follows the times of a full box (`tkhd`, `mdhd`), which
+/// are 32 bits in version 0 and 64 bits in version 1.
+fn after_times(data: &[u8], full_box: &Mp4Box) -> usize {
+    let times = if data[full_box.content] == 1 { 16 } else { 8 };
+    full_box.content + 4 + times
+}
+
+fn push_box(out: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+    out.extend_from_slice(&(content.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(kind);
+    out.extend_from_slice(content);
+}
+
+/// This struct represents a track of a `moov`.
+struct Track {
+    /// The `trak` box.
+    bytes: Vec<u8>,
+    id: u32,
+    /// The units per second of the times of the track.
+    timescale: u32,
+}
+
+/// This function reads the `trak` boxes of a `moov`.
+fn read_tracks(moov: &[u8]) -> Result<Vec<Track>, String> {
+    let mut tracks = Vec::new();
+    for trak in boxes(moov, 8..moov.len())?
+        .iter()
+        .filter(|b| &b.kind == b"trak")
+    {
+        let missing = || "An MP4 track has no header".to_string();
+        let tkhd = find(moov, trak.content(), b"tkhd")?.ok_or_else(missing)?;
+        let mdia = find(moov, trak.content(), b"mdia")?.ok_or_else(missing)?;
+        let mdhd = find(moov, mdia.content(), b"mdhd")?.ok_or_else(missing)?;
+        tracks.push(Track {
+            bytes: moov[trak.start..trak.end].to_vec(),
+            id: read_u32(moov, after_times(moov, &tkhd))?,
+            timescale: read_u32(moov, after_times(moov, &mdhd))?.max(1),
+        });
+    }
+    Ok(tracks)
+}
+
+/// This struct represents a box of a file, which is read or copied when needed.
+#[derive(Clone, Copy)]
+struct FileBox {
+    kind: [u8; 4],
+    start: u64,
+    size: u64,
+}
+
+/// This function lists the boxes of a file, without reading their content.
+fn file_boxes(file: &mut File) -> Result<Vec<FileBox>, String> {
+    let length = file.metadata().map_err(|e| e.to_string())?.len();
+    let mut found = Vec::new();
+    let mut position = 0;
+    while position + 8 <= length {
+        let mut header = [0; 16];
+        file.seek(SeekFrom::Start(position))
+            .and_then(|_| file.read_exact(&mut header[..8]))
+            .map_err(|e| e.to_string())?;
+        let size = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => length - position,
+            1 => {
+                file.read_exact(&mut header[8..])
+                    .map_err(|e| e.to_string())?;
+                u64::from_be_bytes(header[8..].try_into().unwrap())
+            }
+            size => size as u64,
+        };
+        if size < 8 || position + size > length {
+            return Err("The downloaded stream is not a valid MP4 file".into());
+        }
+        found.push(FileBox {
+            kind: header[4..8].try_into().unwrap(),
+            start: position,
+            size,
+        });
+        position += size;
+    }
+    Ok(found)
+}
+
+fn read_box(file: &mut File, found: &FileBox) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![0; usize::try_from(found.size).map_err(|e| e.to_string())?];
+    file.seek(SeekFrom::Start(found.start))
+        .and_then(|_| file.read_exact(&mut bytes))
+        .map_err(|e| e.to_string())?;
+    Ok(bytes)
+}
+
+/// This struct represents a fragment of a track about to be written: its `moof`, already edited
+/// but for its sequence number, and the `mdat`s following it.
+struct Fragment {
+    moof: Vec<u8>,
+    /// Where the sequence number is in `moof`.
+    sequence: usize,
+    /// The time of the first sample of the fragment, in seconds.
+    time: f64,
+    mdats: Vec<FileBox>,
+}
+
+/// This struct represents one of the muxed files.
+struct Source {
+    file: File,
+    boxes: Vec<FileBox>,
+    /// The box of the next fragment.
+    next: usize,
+    /// The new id and the timescale of each track of the file, by its id in the file.
+    tracks: Vec<(u32, u32, u32)>,
+    /// The time of the last fragment, which fragments without one follow.
+    time: f64,
+}
+
+impl Source {
+    fn open(path: &Path) -> Result<Self, String> {
+        let mut file = File::open(path).map_err(|e| format!("Failed to read the stream: {e}"))?;
+        let boxes = file_boxes(&mut file)?;
+        Ok(Self {
+            file,
+            boxes,
+            next: 0,
+            tracks: Vec::new(),
+            time: 0.0,
+        })
+    }
+
+    /// This function reads the first box of `kind`, which must come before the fragments.
+    fn read(&mut self, kind: &[u8; 4]) -> Result<Option<Vec<u8>>, String> {
+        match self
+            .boxes
+            .iter()
+            .take_while(|b| &b.kind != b"moof")
+            .find(|b| &b.kind == kind)
+        {
+            Some(found) => read_box(&mut self.file, &found.clone()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// This function reads the next fragment, renumbering its tracks.
+    fn fragment(&mut self) -> Result<Option<Fragment>, String> {
+        let Some(offset) = self.boxes[self.next..]
+            .iter()
+            .position(|b| &b.kind == b"moof")
+        else {
+            return Ok(None);
+        };
+        let start = self.next + offset;
+        let moof_box = self.boxes[start];
+        let mdats: Vec<FileBox> = self.boxes[start + 1..]
+            .iter()
+            .take_while(|b| &b.kind != b"moof")
+            .filter(|b| &b.kind == b"mdat")
+            .copied()
+            .collect();
+        self.next = start + 1;
+
+        let mut moof = read_box(&mut self.file, &moof_box)?;
+        let children = boxes(&moof, 8..moof.len())?;
+        let mfhd = children
+            .iter()
+            .find(|b| &b.kind == b"mfhd")
+            .ok_or("An MP4 fragment has no header")?;
+        let sequence = mfhd.content + 4;
+        let mut time = None;
+        for traf in children.iter().filter(|b| &b.kind == b"traf") {
+            let tfhd =
+                find(&moof, traf.content(), b"tfhd")?.ok_or("An MP4 fragment has no track")?;
+            if read_u32(&moof, tfhd.content)? & 0xffffff & BASE_DATA_OFFSET_PRESENT != 0 {
+                return Err("The stream has fragments that cannot be muxed".into());
+            }
+            let id = read_u32(&moof, tfhd.content + 4)?;
+            let &(_, new_id, timescale) = self
+                .tracks
+                .iter()
+                .find(|(old, _, _)| *old == id)
+                .ok_or("An MP4 fragment belongs to an unknown track")?;
+            write_u32(&mut moof, tfhd.content + 4, new_id)?;
+            if let (None, Some(tfdt)) = (time, find(&moof, traf.content(), b"tfdt")?) {
+                let decode_time = match moof[tfdt.content] {
+                    1 => moof
+                        .get(tfdt.content + 4..tfdt.content + 12)
+                        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+                        .ok_or("Invalid MP4 box")?,
+                    _ => read_u32(&moof, tfdt.content + 4)? as u64,
+                };
+                time = Some(decode_time as f64 / timescale as f64);
+            }
+        }
+        self.time = time.unwrap_or(self.time);
+        Ok(Some(Fragment {
+            moof,
+            sequence,
+            time: self.time,
+            mdats,
+        }))
+    }
+}
+
+/// This function builds the `moov` of the muxed file: the one of the video, with the tracks of
+/// the audio added after its own under their new ids.
+///
+/// # Arguments
+/// - `ids`: The id of each audio track in the audio file, its new id and its timescale.
+fn merge_moov(
+    video: &[u8],
+    audio: &[u8],
+    audio_tracks: &[Track],
+    ids: &[(u32, u32, u32)],
+) -> Result<Vec<u8>, String> {
+    let next_id = ids.iter().map(|(_, id, _)| *id).max().unwrap_or(0) + 1;
+    let mut content = Vec::new();
+    let mut mvex = None;
+    for child in boxes(video, 8..video.len())? {
+        let mut bytes = video[child.start..child.end].to_vec();
+        match &child.kind {
+            b"mvhd" => {
+                // the id of the next track is the last field of the header
+                write_u32(&mut bytes, child.end - child.start - 4, next_id)?;
+            }
+            b"mvex" => {
+                mvex = Some(child);
+                continue;
+            }
+            _ => {}
+        }
+        content.extend_from_slice(&bytes);
+    }
+    for (track, (_, new_id, _)) in audio_tracks.iter().zip(ids) {
+        let mut bytes = track.bytes.clone();
+        let trak = &boxes(&bytes, 0..bytes.len())?[0];
+        let tkhd = find(&bytes, trak.content(), b"tkhd")?.ok_or("An MP4 track has no header")?;
+        let at = after_times(&bytes, &tkhd);
+        write_u32(&mut bytes, at, *new_id)?;
+        content.extend_from_slice(&bytes);
+    }
+
+    let mvex = mvex.ok_or("The video of the stream is not fragmented")?;
+    let mut extends = video[mvex.content()].to_vec();
+    let audio_mvex =
+        find(audio, 8..audio.len(), b"mvex")?.ok_or("The audio of the stream is not fragmented")?;
+    for trex in boxes(audio, audio_mvex.content())?
+        .iter()
+        .filter(|b| &b.kind == b"trex")
+    {
+        let mut bytes = audio[trex.start..trex.end].to_vec();
+        let at = trex.content - trex.start + 4;
+        let id = read_u32(&bytes, at)?;
+        if let Some((_, new_id, _)) = ids.iter().find(|(old, _, _)| *old == id) {
+            write_u32(&mut bytes, at, *new_id)?;
+            extends.extend_from_slice(&bytes);
+        }
+    }
+    push_box(&mut content, b"mvex", &extends);
+
+    let mut moov = Vec::new();
+    push_box(&mut moov, b"moov", &content);
+    Ok(moov)
+}
+
+/// This function muxes the video and the audio of a stream into `output`.
+///
+/// # Returns
+/// - `Ok(())`: If the video and the audio were muxed.
+/// - `Err(String)`: If either is not a fragmented MP4 file, or the muxed file cannot be written.
+pub fn mux(video: &Path, audio: &Path, output: &Path) -> Result<(), String> {
+    let mut video = Source::open(video)?;
+    let mut audio = Source::open(audio)?;
+    let not_mp4 = || "The downloaded stream is not a valid MP4 file".to_string();
+    let video_moov = video.read(b"moov")?.ok_or_else(not_mp4)?;
+    let audio_moov = audio.read(b"moov")?.ok_or_else(not_mp4)?;
+
+    let video_tracks = read_tracks(&video_moov)?;
+    video.tracks = video_tracks
+        .iter()
+        .map(|t| (t.id, t.id, t.timescale))
+        .collect();
+    let mut next_id = video_tracks.iter().map(|t| t.id).max().unwrap_or(0);
+    let audio_tracks = read_tracks(&audio_moov)?;
+    for track in &audio_tracks {
+        next_id += 1;
+        audio.tracks.push((track.id, next_id, track.timescale));
+    }
+    let moov = merge_moov(&video_moov, &audio_moov, &audio_tracks, &audio.tracks)?;
+
+    let file = File::create(output).map_err(|e| format!("Failed to create the file: {e}"))?;
+    let mut out = BufWriter::new(file);
+    let write_error = |e: io::Error| format!("Failed to write the file: {e}");
+    if let Some(ftyp) = video.read(b"ftyp")? {
+        out.write_all(&ftyp).map_err(write_error)?;
+    }
+    out.write_all(&moov).map_err(write_error)?;
+
+    let mut sequence = 0;
+    let mut next_video = video.fragment()?;
+    let mut next_audio = audio.fragment()?;
+    loop {
+        let audio_first = match (&next_video, &next_audio) {
+            (None, None) => break,
+            (Some(v), Some(a)) => a.time < v.time,
+            (v, _) => v.is_none(),
+        };
+        let (source, fragment) = if audio_first {
+            let fragment = next_audio.take();
+            next_audio = audio.fragment()?;
+            (&mut audio, fragment)
+        } else {
+            let fragment = next_video.take();
+            next_video = video.fragment()?;
+            (&mut video, fragment)
+        };
+        let mut fragment = fragment.unwrap();
+        sequence += 1;
+        write_u32(&mut fragment.moof, fragment.sequence, sequence)?;
+        out.write_all(&fragment.moof).map_err(write_error)?;
+        for mdat in &fragment.mdats {
+            copy_box(&mut source.file, mdat, &mut out)?;
+        }
+    }
+    out.into_inner()
+        .map_err(|e| write_error(e.into_error()))?
+        .sync_all()
+        .map_err(write_error)
+}
+
+/// This function copies a box of a file to `out`.
+fn copy_box(file: &mut File, found: &FileBox, out: &mut impl Write) -> Result<(), String> {
+    file.seek(SeekFrom::Start(found.start))
+        .map_err(|e| e.to_string())?;
+    let mut header = [0; 8];
+    file.read_exact(&mut header).map_err(|e| e.to_string())?;
+    if header[..4] == [0; 4] {
+        // a box lasting until the end of the file needs its size once other boxes follow it
+        let size = u32::try_from(found.size).map_err(|_| "The stream is too large to mux")?;
+        header[..4].copy_from_slice(&size.to_be_bytes());
+    }
+    out.write_all(&header).map_err(|e| e.to_string())?;
+    io::copy(&mut file.take(found.size - 8), out)
+        .map_err(|e| format!("Failed to write the file: {e}"))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mp4_box(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        push_box(&mut out, kind, content);
+        out
+    }
+
+    fn full_box(kind: &[u8; 4], version: u8, flags: u32, fields: &[u32]) -> Vec<u8> {
+        let mut content = ((version as u32) << 24 | flags).to_be_bytes().to_vec();
+        fields
+            .iter()
+            .for_each(|f| content.extend_from_slice(&f.to_be_bytes()));
+        mp4_box(kind, &content)
+    }
+
+    /// This function returns the initialization part of a file with a single track.
+    fn init(id: u32, timescale: u32) -> Vec<u8> {
+        // the fields of the movie header, the id of the next track last
+        let mut mvhd = vec![0; 24];
+        mvhd.push(id + 1);
+        let tkhd = full_box(b"tkhd", 0, 3, &[0, 0, id, 0, 0]);
+        let mdhd = full_box(b"mdhd", 0, 0, &[0, 0, timescale, 0, 0]);
+        let trak = mp4_box(b"trak", &[tkhd, mp4_box(b"mdia", &mdhd)].concat());
+        let mvex = mp4_box(b"mvex", &full_box(b"trex", 0, 0, &[id, 1, 0, 0, 0]));
+        let mvhd = full_box(b"mvhd", 0, 0, &mvhd);
+        [
+            mp4_box(b"ftyp", b"iso6\0\0\0\0"),
+            mp4_box(b"moov", &[mvhd, trak, mvex].concat()),
+        ]
+        .concat()
+    }
+
+    fn fragment(id: u32, flags: u32, time: u32, sample: &[u8]) -> Vec<u8> {
+        let traf = [
+            full_box(b"tfhd", 0, flags, &[id]),
+            full_box(b"tfdt", 0, 0, &[time]),
+        ]
+        .concat();
+        let moof = [full_box(b"mfhd", 0, 0, &[7]), mp4_box(b"traf", &traf)].concat();
+        [
+            mp4_box(b"styp", b"msdh"),
+            mp4_box(b"moof", &moof),
+            mp4_box(b"mdat", sample),
+        ]
+        .concat()
+    }
+
+    /// This function returns the track id and the sequence number of the fragments of a file,
+    /// with their samples.
+    fn fragments(data: &[u8]) -> Vec<(u32, u32, Vec<u8>)> {
+        let mut found = Vec::new();
+        let top = boxes(data, 0..data.len()).unwrap();
+        for (i, moof) in top.iter().enumerate().filter(|(_, b)| &b.kind == b"moof") {
+            let mfhd = find(data, moof.content(), b"mfhd").unwrap().unwrap();
+            let traf = find(data, moof.content(), b"traf").unwrap().unwrap();
+            let tfhd = find(data, traf.content(), b"tfhd").unwrap().unwrap();
+            found.push((
+                read_u32(data, tfhd.content + 4).unwrap(),
+                read_u32(data, mfhd.content + 4).unwrap(),
+                data[top[i + 1].content()].to_vec(),
+            ));
+        }
+        found
+    }
+
+    #[test]
+    fn test_mux() {
+        let dir = std::env::temp_dir().join(format!("yad-mux-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (video, audio, output) = (dir.join("v"), dir.join("a"), dir.join("out.mp4"));
+        let video_data = [
+            init(1, 1000),
+            fragment(1, 0x020000, 0, b"v0"),
+            fragment(1, 0x020000, 2000, b"v1"),
+        ]
+        .concat();
+        let audio_data = [
+            init(1, 48000),
+            fragment(1, 0x020000, 0, b"a0"),
+            fragment(1, 0x020000, 48000, b"a1"),
+            fragment(1, 0x020000, 96000, b"a2"),
+        ]
+        .concat();
+        std::fs::write(&video, video_data).unwrap();
+        std::fs::write(&audio, audio_data).unwrap();
+
+        mux(&video, &audio, &output).unwrap();
+        let data = std::fs::read(&output).unwrap();
+        let kinds: Vec<[u8; 4]> = boxes(&data, 0..data.len())
+            .unwrap()
+            .iter()
+            .map(|b| b.kind)
+            .collect();
+        assert_eq!(&kinds[..2], [*b"ftyp", *b"moov"]);
+        let moov = find(&data, 0..data.len(), b"moov").unwrap().unwrap();
+        let moov = &data[moov.start..moov.end];
+        let ids: Vec<u32> = read_tracks(moov).unwrap().iter().map(|t| t.id).collect();
+        assert_eq!(ids, [1, 2]);
+        let mvhd = find(moov, 8..moov.len(), b"mvhd").unwrap().unwrap();
+        assert_eq!(read_u32(moov, mvhd.end - 4).unwrap(), 3);
+        let mvex = find(moov, 8..moov.len(), b"mvex").unwrap().unwrap();
+        let trex: Vec<u32> = boxes(moov, mvex.content())
+            .unwrap()
+            .iter()
+            .map(|b| read_u32(moov, b.content + 4).unwrap())
+            .collect();
+        assert_eq!(trex, [1, 2]);
+        assert_eq!(
+            fragments(&data),
+            [
+                (1, 1, b"v0".to_vec()),
+                (2, 2, b"a0".to_vec()),
+                (2, 3, b"a1".to_vec()),
+                (1, 4, b"v1".to_vec()),
+                (2, 5, b"a2".to_vec()),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsupported_streams() {
+        let dir = std::env::temp_dir().join(format!("yad-mux-bad-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (video, audio, output) = (dir.join("v"), dir.join("a"), dir.join("out.mp4"));
+        std::fs::write(&video, [init(1, 1000), fragment(1, 1, 0, b"v")].concat()).unwrap();
+        std::fs::write(&audio, [init(1, 1000), fragment(1, 0, 0, b"a")].concat()).unwrap();
+        assert!(mux(&video, &audio, &output)
+            .unwrap_err()
+            .contains("cannot be muxed"));
+        std::fs::write(&video, b"not an mp4 file").unwrap();
+        assert!(mux(&video, &audio, &output).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,197 @@
+//! This module gathers what the setup wizard shows on the first run: where files are saved, how
+//! much space is left and how fast the connection is, together with settings suggested from them.
+//! The suggestions are made here rather than in the frontend so that every client of the API
+//! gets the same defaults.
+
+use std::time::{Duration, Instant};
+
+use reqwest::Client;
+use serde::Serialize;
+
+use crate::{chunks, config::Config, settings::Settings, storage::DownloadRecord};
+
+/// How long the speed test downloads for.
+pub const SPEED_TEST_DURATION: Duration = Duration::from_secs(3);
+
+/// The speed test stops after this many bytes even if the time is not up.
+const SPEED_TEST_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Finished downloads smaller than this say more about latency than speed and are ignored.
+const MIN_SAMPLE_SIZE: u64 = 1024 * 1024;
+
+/// How many of the latest finished downloads the speed is estimated from.
+const MAX_SAMPLES: usize = 10;
+
+/// This struct represents what was detected on the first run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FirstRunInfo {
+    /// Whether the setup wizard has not been completed yet.
+    pub first_run: bool,
+    /// The folder downloads are saved in.
+    pub download_dir: String,
+    /// The free space on the system disk in bytes, if it could be read.
+    pub free_space: Option<u64>,
+    /// The estimated download speed in bytes per second, if it could be measured.
+    pub speed_estimate: Option<u64>,
+    /// The smallest and biggest size of the chunks files are split into, chosen per file from its
+    /// size.
+    pub chunk_sizes: (u64, u64),
+    /// The settings suggested for this machine, to be confirmed with `complete_onboarding`.
+    pub suggested: Settings,
+}
+
+/// This function returns the free space on the system disk in bytes.
+pub fn free_space() -> Option<u64> {
+    sys_info::disk_info().ok().map(|d| d.free * 1024)
+}
+
+/// This function estimates the download speed from the latest finished downloads, as the median
+/// of their average speeds.
+///
+/// # Returns
+/// - `Some(u64)`: The speed in bytes per second.
+/// - `None`: If no download is big enough to tell.
+pub fn speed_from_history(records: &[DownloadRecord]) -> Option<u64> {
+    let mut finished: Vec<&DownloadRecord> = records
+        .iter()
+        .filter(|r| r.download_status == "Finished" && r.file_size >= MIN_SAMPLE_SIZE)
+        .collect();
+    finished.sort_by_key(|r| std::cmp::Reverse(r.download_start_time));
+    let mut speeds: Vec<u64> = finished
+        .into_iter()
+        .filter_map(|r| {
+            let seconds = r.download_stop_time?.checked_sub(r.download_start_time)?;
+            Some(r.file_size / seconds.max(1))
+        })
+        .take(MAX_SAMPLES)
+        .collect();
+    if speeds.is_empty() {
+        return None;
+    }
+    speeds.sort_unstable();
+    Some(speeds[speeds.len() / 2])
+}
+
+/// This function measures the download speed by downloading from `url` for
+/// `SPEED_TEST_DURATION`. Nothing is written to disk.
+///
+/// # Returns
+/// - `Ok(u64)`: The speed in bytes per second.
+/// - `Err(String)`: If the request failed.
+pub async fn speed_test(client: &Client, url: &str) -> Result<u64, String> {
+    let started = Instant::now();
+    let mut response = client
+        .get(url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Speed test failed: {e}"))?;
+    let mut received: u64 = 0;
+    while started.elapsed() < SPEED_TEST_DURATION && received < SPEED_TEST_MAX_BYTES {
+        let next = tokio::time::timeout(SPEED_TEST_DURATION, response.chunk()).await;
+        match next {
+            Ok(Ok(Some(bytes))) => received += bytes.len() as u64,
+            Ok(Ok(None)) | Err(_) => break,
+            Ok(Err(e)) => return Err(format!("Speed test failed: {e}")),
+        }
+    }
+    let millis = started.elapsed().as_millis().max(1) as u64;
+    Ok(received * 1000 / millis)
+}
+
+/// This function returns how many chunks should be downloaded at the same time at `speed` bytes
+/// per second. Slow connections gain little from more connections and fast ones need several to
+/// fill the line.
+pub fn suggested_concurrency(speed: Option<u64>) -> usize {
+    const MB: u64 = 1024 * 1024;
+    match speed {
+        None => crate::settings::DEFAULT_MAX_CONCURRENT_CHUNKS,
+        Some(s) if s < MB => 2,
+        Some(s) if s < 10 * MB => 4,
+        Some(s) if s < 50 * MB => 8,
+        Some(_) => 16,
+    }
+}
+
+/// This function puts together the first run information.
+///
+/// # Arguments
+/// - `current`: The current settings, the suggestions are based on them.
+/// - `first_run`: Whether the setup wizard has not been completed yet.
+/// - `speed_estimate`: The measured download speed, if any.
+/// - `cfg`: An instance of `Config`.
+pub fn first_run_info(
+    current: &Settings,
+    first_run: bool,
+    speed_estimate: Option<u64>,
+    cfg: &Config,
+) -> FirstRunInfo {
+    FirstRunInfo {
+        first_run,
+        download_dir: cfg.download_dir.clone(),
+        free_space: free_space(),
+        speed_estimate,
+        chunk_sizes: (chunks::MIN_CHUNK_SIZE, chunks::MAX_CHUNK_SIZE),
+        suggested: Settings {
+            max_concurrent_chunks: suggested_concurrency(speed_estimate),
+            ..current.clone()
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(status: &str, size: u64, start: u64, stop: Option<u64>) -> DownloadRecord {
+        DownloadRecord {
+            download_status: status.into(),
+            file_size: size,
+            download_start_time: start,
+            download_stop_time: stop,
+            ..DownloadRecord::default()
+        }
+    }
+
+    #[test]
+    fn test_speed_from_history() {
+        const MB: u64 = 1024 * 1024;
+        let records = vec![
+            record("Finished", 100 * MB, 1000, Some(1010)),
+            record("Finished", 100 * MB, 2000, Some(2050)),
+            record("Finished", 100 * MB, 3000, Some(3004)),
+            record("Failed", 100 * MB, 4000, Some(4001)),
+            record("Finished", 1024, 5000, Some(5001)),
+            record("InProgress", 100 * MB, 6000, None),
+        ];
+        assert_eq!(speed_from_history(&records), Some(10 * MB));
+        assert_eq!(speed_from_history(&records[3..]), None);
+    }
+
+    #[test]
+    fn test_suggested_concurrency() {
+        assert_eq!(
+            suggested_concurrency(None),
+            crate::settings::DEFAULT_MAX_CONCURRENT_CHUNKS
+        );
+        assert_eq!(suggested_concurrency(Some(500 * 1024)), 2);
+        assert_eq!(suggested_concurrency(Some(20 * 1024 * 1024)), 8);
+        assert_eq!(suggested_concurrency(Some(1024 * 1024 * 1024)), 16);
+    }
+
+    #[test]
+    fn test_first_run_info_keeps_other_settings() {
+        let current = Settings {
+            max_speed: 1000,
+            ..Settings::default()
+        };
+        let info = first_run_info(&current, true, Some(100 * 1024 * 1024), &Config::default());
+        assert!(info.first_run);
+        assert_eq!(info.suggested.max_concurrent_chunks, 16);
+        assert_eq!(info.suggested.max_speed, 1000);
+        assert_eq!(
+            info.chunk_sizes,
+            (chunks::MIN_CHUNK_SIZE, chunks::MAX_CHUNK_SIZE)
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! This module brings an existing downloads folder, filled before YAD was used, into the folders
+//! YAD keeps its downloads in. Every file is given a file type with the same rules as a download,
+//! including the ones learned from the user, is moved into the folder of that type, and gets a
+//! finished record marked as imported, so that the history shows every download of the user.
+//!
+//! Only the files directly in the folder are moved. Sub folders are left alone, since their files
+//! usually belong together, and so are hidden files and files a browser is still writing.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    files::{self, File},
+    settings,
+    storage::{self, DownloadRecord},
+};
+
+/// The extensions of files that are still being downloaded by a browser or by YAD.
+const PARTIAL_EXTENSIONS: [&str; 5] = ["part", "crdownload", "download", "partial", "tmp"];
+
+/// This struct represents what organizing a folder did.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Summary {
+    /// The files moved and added to the history.
+    pub imported: usize,
+    /// The files left where they are, e.g. because they already are in the history.
+    pub skipped: usize,
+    /// The files that could not be moved, with the reason.
+    pub failed: Vec<String>,
+}
+
+/// This function returns the files in `folder` that can be organized, sorted by name.
+pub fn candidates(folder: &Path) -> Result<Vec<PathBuf>, String> {
+    let entries =
+        fs::read_dir(folder).map_err(|e| format!("Failed to read {}: {e}", folder.display()))?;
+    let mut files: Vec<PathBuf> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file())
+        .filter(|path| {
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or(".");
+            let extension = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            !name.starts_with('.')
+                && !PARTIAL_EXTENSIONS.contains(&extension.to_ascii_lowercase().as_str())
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// This function decides where the file at `path` goes. A file with the same name already in
+/// that folder, or kept for another download, is not replaced; the file gets a name like
+/// `report (1).pdf` instead.
+///
+/// # Arguments
+/// - `path`: The file.
+/// - `rules`: The file types learned from the user, see `Settings::category_rules`.
+/// - `taken`: Whether a destination path is in use by a download.
+/// - `cfg`: An instance of `Config`.
+pub fn plan(
+    path: &Path,
+    rules: &BTreeMap<String, String>,
+    taken: impl Fn(&str) -> bool,
+    cfg: &Config,
+) -> Option<File> {
+    let mut file = File::existing(path, cfg)?;
+    file.apply_category_rules(rules, cfg);
+    let dir = PathBuf::from(&file.destination_dir);
+    let in_use = |p: &Path| p.exists() || taken(p.to_str().unwrap_or_default());
+    if in_use(Path::new(&file.destination_path)) {
+        let name = files::free_file_name(&file.file_name, |n| in_use(&dir.join(n)));
+        file.destination_path = dir.join(&name).to_str()?.to_string();
+        file.file_name = name;
+    }
+    file.file_url = format!("file://{}", file.destination_path);
+    Some(file)
+}
+
+/// This function moves one file into its folder and adds it to the history.
+///
+/// # Returns
+/// - `Ok(true)`: if the file was imported.
+/// - `Ok(false)`: if it already is a download of YAD.
+/// - `Err(String)`: why it could not be imported. The file is left where it was.
+fn import(path: &Path, rules: &BTreeMap<String, String>, cfg: &Config) -> Result<bool, String> {
+    let in_history = |p: &str| storage::path_in_use(p, cfg).unwrap_or(true);
+    if in_history(path.to_str().unwrap_or_default()) {
+        return Ok(false);
+    }
+    let file = plan(path, rules, in_history, cfg)
+        .ok_or_else(|| format!("{} is not a file", path.display()))?;
+    let destination = PathBuf::from(&file.destination_path);
+    files::move_into_place(path, &destination)?;
+
+    let size = file.file_size;
+    let record = DownloadRecord {
+        imported: true,
+        ..DownloadRecord::from(file)
+    };
+    if let Err(e) = storage::insert_record(&record, size, cfg) {
+        // the file goes back so that it is not hidden in a folder without a record
+        let _ = files::move_into_place(&destination, path);
+        return Err(format!("Failed to save {}: {e}", record.file_name));
+    }
+    Ok(true)
+}
+
+/// This function organizes the files in `folder`, see the module documentation. A file that
+/// cannot be moved does not stop the others.
+pub fn organize_existing(folder: &Path, cfg: &Config) -> Result<Summary, String> {
+    let rules = settings::current().category_rules;
+    let mut summary = Summary::default();
+    for path in candidates(folder)? {
+        match import(&path, &rules, cfg) {
+            Ok(true) => summary.imported += 1,
+            Ok(false) => summary.skipped += 1,
+            Err(e) => summary.failed.push(e),
+        }
+    }
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{create_tables, read_download_records, test_config};
+
+    #[test]
+    fn test_plan() {
+        let cfg = test_config("organize_plan");
+        let messy = Path::new(&cfg.config_dir).join("messy");
+        fs::create_dir_all(&messy).unwrap();
+        fs::write(messy.join("report.pdf"), b"pdf").unwrap();
+        fs::write(messy.join("song.opus"), b"opus").unwrap();
+
+        let rules = BTreeMap::from([("opus".to_string(), "Documents".to_string())]);
+        let report = plan(&messy.join("report.pdf"), &rules, |_| false, &cfg).unwrap();
+        let documents = Path::new(&cfg.download_dir).join("Documents");
+        assert_eq!(
+            report.destination_path,
+            documents.join("report.pdf").to_str().unwrap()
+        );
+        let song = plan(&messy.join("song.opus"), &rules, |_| false, &cfg).unwrap();
+        assert_eq!(song.destination_dir, documents.to_str().unwrap());
+
+        let taken = plan(
+            &messy.join("report.pdf"),
+            &rules,
+            |p| p.ends_with("report.pdf"),
+            &cfg,
+        );
+        assert_eq!(taken.unwrap().file_name, "report (1).pdf");
+        assert!(plan(&messy, &rules, |_| false, &cfg).is_none(), "a folder");
+    }
+
+    #[test]
+    fn test_organize_existing() {
+        let cfg = test_config("organize_existing");
+        create_tables(&cfg).unwrap();
+        let messy = Path::new(&cfg.config_dir).join("messy");
+        fs::create_dir_all(messy.join("photos")).unwrap();
+        fs::write(messy.join("movie.mkv"), b"mkv").unwrap();
+        fs::write(messy.join("setup.exe.crdownload"), b"ex").unwrap();
+        fs::write(messy.join(".hidden"), b"").unwrap();
+
+        let summary = organize_existing(&messy, &cfg).unwrap();
+        assert_eq!(
+            summary,
+            Summary {
+                imported: 1,
+                ..Summary::default()
+            }
+        );
+        let moved = Path::new(&cfg.download_dir)
+            .join("Videos")
+            .join("movie.mkv");
+        assert!(moved.exists());
+        assert!(!messy.join("movie.mkv").exists());
+        assert!(messy.join("setup.exe.crdownload").exists());
+
+        let records = read_download_records(&cfg).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(records[0].imported);
+        assert_eq!(records[0].download_status, "Finished");
+        assert_eq!(records[0].file_size, 3);
+
+        // organizing the folder YAD keeps them in leaves them alone
+        let videos = Path::new(&cfg.download_dir).join("Videos");
+        assert_eq!(organize_existing(&videos, &cfg).unwrap().skipped, 1);
+    }
+}
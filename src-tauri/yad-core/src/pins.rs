@@ -0,0 +1,169 @@
+//! This module pins the certificates of sensitive hosts, e.g. internal artifact servers. A pinned
+//! host must present a certificate whose SHA-256 fingerprint is in its pin, otherwise the
+//! download fails instead of silently accepting an intercepted connection. The clients of the
+//! settings refuse such a certificate during the TLS handshake, see `trust::apply`, and responses
+//! are checked again before any byte of their body is written.
+
+use reqwest::{tls::TlsInfo, Response};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::hashing;
+
+/// This struct represents the certificates a host is expected to present.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Default)]
+pub struct CertPin {
+    /// The host name, e.g. `artifacts.example.com`.
+    pub host: String,
+    /// The accepted SHA-256 fingerprints of the leaf certificate as hex, with or without colons.
+    /// More than one can be given so that a certificate can be rotated without downtime.
+    pub fingerprints: Vec<String>,
+}
+
+impl CertPin {
+    /// This function checks that the pin has a host and only well formed fingerprints.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.host.trim().is_empty() {
+            return Err("Certificate pin has no host".into());
+        }
+        if self.fingerprints.is_empty() {
+            return Err(format!(
+                "Certificate pin for {} has no fingerprint",
+                self.host
+            ));
+        }
+        if let Some(f) = self.fingerprints.iter().find(|f| normalize(f).is_none()) {
+            return Err(format!(
+                "Certificate pin for {} has an invalid SHA-256 fingerprint {f}",
+                self.host
+            ));
+        }
+        Ok(())
+    }
+
+    fn matches(&self, host: &str) -> bool {
+        self.host.trim().eq_ignore_ascii_case(host)
+    }
+}
+
+/// This function returns a fingerprint as 64 lowercase hex characters, or `None` if it is not a
+/// SHA-256 fingerprint.
+fn normalize(fingerprint: &str) -> Option<String> {
+    let hex: String = fingerprint
+        .chars()
+        .filter(|c| *c != ':' && !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    (hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit())).then_some(hex)
+}
+
+/// This function returns the SHA-256 fingerprint of a DER encoded certificate as lowercase hex.
+pub fn fingerprint(der: &[u8]) -> String {
+    hashing::hex(&Sha256::digest(der))
+}
+
+/// This function checks the certificate presented by `host` against the pins.
+///
+/// # Arguments
+/// - `pins`: The certificate pins from the settings.
+/// - `host`: The host that was connected to.
+/// - `certificate`: The DER encoded leaf certificate, `None` if the connection was not TLS.
+///
+/// # Returns
+/// - `Ok(())`: If the host is not pinned or the certificate matches its pin.
+/// - `Err(String)`: If the host is pinned and the certificate is missing or does not match.
+pub fn check_certificate(
+    pins: &[CertPin],
+    host: &str,
+    certificate: Option<&[u8]>,
+) -> Result<(), String> {
+    let Some(pin) = pins.iter().find(|p| p.matches(host)) else {
+        return Ok(());
+    };
+    let Some(certificate) = certificate else {
+        return Err(format!(
+            "{host} is pinned but the connection has no TLS certificate, refusing to download"
+        ));
+    };
+    let actual = fingerprint(certificate);
+    if pin
+        .fingerprints
+        .iter()
+        .filter_map(|f| normalize(f))
+        .any(|f| f == actual)
+    {
+        Ok(())
+    } else {
+        Err(format!(
+            "Certificate of {host} does not match its pin (got SHA-256 {actual}), the connection \
+             may be intercepted"
+        ))
+    }
+}
+
+/// This function checks the certificate a response was received over against the pins. The
+/// client must be built with `tls_info(true)`, see `settings::build_client`.
+pub fn check(pins: &[CertPin], response: &Response) -> Result<(), String> {
+    if pins.is_empty() {
+        return Ok(());
+    }
+    let Some(host) = response.url().host_str() else {
+        return Ok(());
+    };
+    let certificate = response
+        .extensions()
+        .get::<TlsInfo>()
+        .and_then(|info| info.peer_certificate());
+    check_certificate(pins, host, certificate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CERT: &[u8] = b"not really a certificate";
+
+    fn pin(fingerprint: &str) -> CertPin {
+        CertPin {
+            host: "Artifacts.Example.com".into(),
+            fingerprints: vec![fingerprint.into()],
+        }
+    }
+
+    #[test]
+    fn test_normalize() {
+        let hex = "ab".repeat(32);
+        let colons = vec!["AB"; 32].join(":");
+        assert_eq!(normalize(&colons), Some(hex.clone()));
+        assert_eq!(normalize(&hex), Some(hex));
+        assert_eq!(normalize("abcd"), None);
+        assert_eq!(normalize(&"zz".repeat(32)), None);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(pin(&fingerprint(CERT)).validate().is_ok());
+        assert!(pin("abcd").validate().is_err());
+        let no_host = CertPin {
+            host: " ".into(),
+            ..pin(&fingerprint(CERT))
+        };
+        assert!(no_host.validate().is_err());
+    }
+
+    #[test]
+    fn test_check_certificate() {
+        let pins = vec![pin(&fingerprint(CERT))];
+        let pinned = "artifacts.example.com";
+        assert!(check_certificate(&pins, pinned, Some(CERT)).is_ok());
+
+        let err = check_certificate(&pins, pinned, Some(b"intercepted")).unwrap_err();
+        assert!(err.contains("does not match"), "{err}");
+
+        // a connection without TLS
+        assert!(check_certificate(&pins, pinned, None).is_err());
+
+        let other = "example.com";
+        assert!(check_certificate(&pins, other, Some(b"anything")).is_ok());
+    }
+}
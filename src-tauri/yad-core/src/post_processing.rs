@@ -0,0 +1,48 @@
+//! This module holds the steps run after a download finishes. They are configured per file type,
+//! e.g. subtitles are only looked for when a video finishes.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// This struct represents the steps run after a file of one file type has downloaded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct PostProcessing {
+    /// Whether subtitles are looked for with the subtitle providers of the settings. Only used
+    /// for videos.
+    pub fetch_subtitles: bool,
+    /// The languages of the subtitles to download e.g. `en`, one subtitle per language. The first
+    /// subtitle found is downloaded when empty.
+    pub subtitle_languages: Vec<String>,
+    /// Whether audio is filed into the music library of the settings by artist and album.
+    pub import_music: bool,
+    /// Whether audio filed into the music library is copied there instead of moved.
+    pub keep_original: bool,
+}
+
+/// This function returns the steps configured for a file type, see `files::FileType`. A file type
+/// without steps runs none.
+pub fn for_file_type(steps: &BTreeMap<String, PostProcessing>, file_type: &str) -> PostProcessing {
+    steps.get(file_type).cloned().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_file_type() {
+        let mut steps = BTreeMap::new();
+        steps.insert(
+            "Videos".to_string(),
+            PostProcessing {
+                fetch_subtitles: true,
+                subtitle_languages: vec!["en".into()],
+                ..PostProcessing::default()
+            },
+        );
+        assert!(for_file_type(&steps, "Videos").fetch_subtitles);
+        assert_eq!(for_file_type(&steps, "Audio"), PostProcessing::default());
+    }
+}
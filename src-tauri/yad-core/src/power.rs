@@ -0,0 +1,221 @@
+//! This module wakes the computer from sleep for scheduled downloads and can put it back to sleep
+//! once they are done. The wake timer is registered with the tools of the operating system:
+//! `rtcwake` on Linux and `pmset` on macOS, both of which usually need administrator rights, and
+//! a task of the task scheduler with "wake the computer to run this task" on Windows. A single
+//! timer is kept, set shortly before the next scheduled download.
+
+use std::{process::Command, sync::Mutex};
+
+/// How many seconds before a scheduled download the computer is woken, so that the network is
+/// back when it starts.
+pub const WAKE_LEAD: u64 = 60;
+
+/// How many seconds after the wake timer the scheduler may notice it woke the computer. A wake
+/// later than that is taken to be the user's, and the computer is not put back to sleep.
+pub const WAKE_WINDOW: u64 = 300;
+
+/// The name of the task of the Windows task scheduler that wakes the computer.
+const WINDOWS_TASK: &str = "yad wake for scheduled downloads";
+
+/// This struct represents a program of the operating system and its arguments.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PowerCommand {
+    pub program: String,
+    pub args: Vec<String>,
+}
+
+impl PowerCommand {
+    fn new(program: &str, args: &[&str]) -> Self {
+        PowerCommand {
+            program: program.to_string(),
+            args: args.iter().map(|a| a.to_string()).collect(),
+        }
+    }
+
+    fn powershell(script: &str) -> Self {
+        PowerCommand::new(
+            "powershell",
+            &["-NoProfile", "-NonInteractive", "-Command", script],
+        )
+    }
+
+    /// This function runs the program and waits for it to exit.
+    fn run(&self) -> Result<(), String> {
+        let output = Command::new(&self.program)
+            .args(&self.args)
+            .output()
+            .map_err(|e| format!("Failed to run {}: {e}", self.program))?;
+        if output.status.success() {
+            return Ok(());
+        }
+        Err(format!(
+            "{} failed: {}",
+            self.program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+    }
+}
+
+/// This function formats a unix timestamp in the local time zone.
+fn local_time(at: u64, format: &str) -> Option<String> {
+    let at = chrono::DateTime::from_timestamp(at as i64, 0)?;
+    Some(at.with_timezone(&chrono::Local).format(format).to_string())
+}
+
+/// This function returns the command that wakes the computer at `at`.
+///
+/// # Arguments
+/// - `os`: The operating system, see `Config::os`.
+/// - `at`: When to wake as a unix timestamp in seconds.
+pub fn wake_command(os: &str, at: u64) -> Option<PowerCommand> {
+    match os {
+        "Windows" => {
+            // the task does nothing, it is only there to wake the computer
+            let script = format!(
+                "$t = New-ScheduledTaskTrigger -Once -At ([DateTimeOffset]::FromUnixTimeSeconds({at}).LocalDateTime); \
+                 $a = New-ScheduledTaskAction -Execute 'cmd.exe' -Argument '/c exit'; \
+                 $s = New-ScheduledTaskSettingsSet -WakeToRun; \
+                 Register-ScheduledTask -TaskName '{WINDOWS_TASK}' -Trigger $t -Action $a -Settings $s -Force"
+            );
+            Some(PowerCommand::powershell(&script))
+        }
+        "Darwin" => {
+            let date = local_time(at, "%m/%d/%y %H:%M:%S")?;
+            Some(PowerCommand::new("pmset", &["schedule", "wake", &date]))
+        }
+        _ => Some(PowerCommand::new(
+            "rtcwake",
+            &["-m", "no", "-t", &at.to_string()],
+        )),
+    }
+}
+
+/// This function returns the command that removes the wake timer set for `at`.
+pub fn cancel_command(os: &str, at: u64) -> Option<PowerCommand> {
+    match os {
+        "Windows" => Some(PowerCommand::powershell(&format!(
+            "Unregister-ScheduledTask -TaskName '{WINDOWS_TASK}' -Confirm:$false"
+        ))),
+        "Darwin" => {
+            let date = local_time(at, "%m/%d/%y %H:%M:%S")?;
+            Some(PowerCommand::new(
+                "pmset",
+                &["schedule", "cancel", "wake", &date],
+            ))
+        }
+        _ => Some(PowerCommand::new("rtcwake", &["-m", "disable"])),
+    }
+}
+
+/// This function returns the command that puts the computer to sleep.
+pub fn sleep_command(os: &str) -> PowerCommand {
+    match os {
+        "Windows" => PowerCommand::new("rundll32.exe", &["powrprof.dll,SetSuspendState", "0,1,0"]),
+        "Darwin" => PowerCommand::new("pmset", &["sleepnow"]),
+        _ => PowerCommand::new("systemctl", &["suspend"]),
+    }
+}
+
+/// The wake timer currently set, as a unix timestamp in seconds.
+fn armed() -> &'static Mutex<Option<u64>> {
+    static ARMED: Mutex<Option<u64>> = Mutex::new(None);
+    &ARMED
+}
+
+/// This function sets the wake timer to `at`, replacing the one set before. Nothing is run when
+/// the timer is already set to `at`, so a timer which could not be set is not tried again until
+/// the time changes.
+///
+/// # Arguments
+/// - `os`: The operating system, see `Config::os`.
+/// - `at`: When to wake as a unix timestamp in seconds, `None` removes the timer.
+pub fn arm(os: &str, at: Option<u64>) -> Result<(), String> {
+    let mut armed = armed().lock().unwrap();
+    if *armed == at {
+        return Ok(());
+    }
+    if let Some(old) = armed.take() {
+        if let Some(command) = cancel_command(os, old) {
+            command.run()?;
+        }
+    }
+    let Some(at) = at else {
+        return Ok(());
+    };
+    *armed = Some(at);
+    wake_command(os, at)
+        .ok_or("The wake time cannot be represented")?
+        .run()
+}
+
+/// This function puts the computer to sleep.
+pub fn sleep(os: &str) -> Result<(), String> {
+    sleep_command(os).run()
+}
+
+/// This function tells whether the computer slept between two checks of the scheduler, going by
+/// the wall clock, which keeps running while the computer sleeps. A check more than twice the
+/// interval late is taken as sleep.
+///
+/// # Arguments
+/// - `last`: The unix timestamp of the previous check.
+/// - `now`: The unix timestamp of this check.
+/// - `interval`: The seconds the scheduler waits between checks.
+pub fn slept(last: u64, now: u64, interval: u64) -> bool {
+    now.saturating_sub(last) > 2 * interval
+}
+
+/// This function tells whether the computer was woken by the wake timer, i.e. it slept and the
+/// timer went off shortly before `now`.
+pub fn woken_by_timer(slept: bool, now: u64) -> bool {
+    let armed = *armed().lock().unwrap();
+    slept && armed.is_some_and(|at| at <= now && now - at <= WAKE_WINDOW)
+}
+
+/// This function returns when to wake the computer for the next scheduled download.
+///
+/// # Arguments
+/// - `starts`: When the scheduled downloads start as unix timestamps in seconds.
+/// - `now`: The current unix timestamp.
+///
+/// # Returns
+/// `None` if nothing is scheduled or the next download starts too soon to sleep before it.
+pub fn next_wake(starts: impl IntoIterator<Item = u64>, now: u64) -> Option<u64> {
+    starts
+        .into_iter()
+        .min()
+        .map(|start| start.saturating_sub(WAKE_LEAD))
+        .filter(|at| *at > now)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands() {
+        let linux = wake_command("Linux", 1_700_000_000).unwrap();
+        assert_eq!(linux.program, "rtcwake");
+        assert_eq!(linux.args, ["-m", "no", "-t", "1700000000"]);
+        let windows = wake_command("Windows", 1_700_000_000).unwrap();
+        assert!(windows.args[3].contains("FromUnixTimeSeconds(1700000000)"));
+        assert!(windows.args[3].contains("-WakeToRun"));
+        let mac = wake_command("Darwin", 1_700_000_000).unwrap();
+        assert_eq!(mac.args[..2], ["schedule", "wake"]);
+        assert_eq!(sleep_command("Darwin").args, ["sleepnow"]);
+    }
+
+    #[test]
+    fn test_next_wake() {
+        assert_eq!(next_wake([5000, 2000, 9000], 1000), Some(2000 - WAKE_LEAD));
+        // too soon to sleep in between
+        assert_eq!(next_wake([1030], 1000), None);
+        assert_eq!(next_wake([], 1000), None);
+    }
+
+    #[test]
+    fn test_slept() {
+        assert!(!slept(1000, 1030, 30));
+        assert!(slept(1000, 5000, 30));
+    }
+}
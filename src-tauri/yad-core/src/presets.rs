@@ -0,0 +1,108 @@
+//! This module handles the per host request presets. Some file hosts refuse downloads that do not
+//! come from their own pages (hotlink protection), so a preset adds the `Referer` and `Origin`
+//! headers they expect. Presets are stored in the settings so that the user can edit them, and a
+//! few known hosts are shipped by default.
+
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+/// This struct represents the headers to send to a host and its sub domains.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct HostPreset {
+    /// A name shown to the user and saved on the download record when the preset is applied.
+    pub name: String,
+    /// The host the preset applies to e.g. `example.com`. Sub domains are matched as well.
+    pub host: String,
+    pub referer: Option<String>,
+    pub origin: Option<String>,
+}
+
+impl HostPreset {
+    fn new(name: &str, host: &str, referer: &str) -> Self {
+        HostPreset {
+            name: name.to_string(),
+            host: host.to_string(),
+            referer: Some(referer.to_string()),
+            origin: None,
+        }
+    }
+
+    /// This function checks whether the preset applies to `host`.
+    pub fn matches(&self, host: &str) -> bool {
+        let host = host.to_lowercase();
+        let preset_host = self.host.trim_start_matches("*.").to_lowercase();
+        host == preset_host || host.ends_with(&format!(".{preset_host}"))
+    }
+
+    /// This function returns the headers to add to requests.
+    pub fn headers(&self) -> Vec<(String, String)> {
+        let mut headers = Vec::new();
+        if let Some(referer) = &self.referer {
+            headers.push(("Referer".to_string(), referer.clone()));
+        }
+        if let Some(origin) = &self.origin {
+            headers.push(("Origin".to_string(), origin.clone()));
+        }
+        headers
+    }
+}
+
+/// This function returns the presets shipped with YAD.
+pub fn default_presets() -> Vec<HostPreset> {
+    vec![
+        HostPreset::new("Pixiv", "i.pximg.net", "https://www.pixiv.net/"),
+        HostPreset::new("Bilibili images", "hdslb.com", "https://www.bilibili.com/"),
+        HostPreset::new("Bilibili videos", "bilivideo.com", "https://www.bilibili.com/"),
+    ]
+}
+
+/// This function finds the first preset that applies to the host of `url`.
+pub fn find<'a>(presets: &'a [HostPreset], url: &str) -> Option<&'a HostPreset> {
+    let url = Url::parse(url).ok()?;
+    let host = url.host_str()?;
+    presets.iter().find(|p| p.matches(host))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_matches_host_and_sub_domains() {
+        let presets = default_presets();
+        let p = find(&presets, "https://i.pximg.net/img-original/1.png").unwrap();
+        assert_eq!(p.name, "Pixiv");
+        let p = find(&presets, "https://i0.HDSLB.com/bfs/archive/1.jpg").unwrap();
+        assert_eq!(p.name, "Bilibili images");
+        assert!(find(&presets, "https://example.com/file.zip").is_none());
+    }
+
+    #[test]
+    fn test_find_does_not_match_partial_host() {
+        let presets = vec![HostPreset::new("Example", "example.com", "https://example.com/")];
+        assert!(find(&presets, "https://notexample.com/file.zip").is_none());
+        assert!(find(&presets, "https://dl.example.com/file.zip").is_some());
+        assert!(find(&presets, "not a url").is_none());
+    }
+
+    #[test]
+    fn test_wildcard_host() {
+        let p = HostPreset::new("Example", "*.example.com", "https://example.com/");
+        assert!(p.matches("cdn.example.com"));
+    }
+
+    #[test]
+    fn test_headers() {
+        let p = HostPreset {
+            origin: Some("https://example.com".into()),
+            ..HostPreset::new("Example", "example.com", "https://example.com/page")
+        };
+        assert_eq!(
+            p.headers(),
+            vec![
+                ("Referer".to_string(), "https://example.com/page".to_string()),
+                ("Origin".to_string(), "https://example.com".to_string())
+            ]
+        );
+    }
+}
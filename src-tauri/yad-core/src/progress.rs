@@ -0,0 +1,442 @@
+//! This module keeps the live progress of the running downloads in memory. The frontend receives
+//! progress as events, but a window that is reloaded or reopened has missed them, so it asks for
+//! the current state instead of waiting for the next event.
+
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+
+use crate::{engine::DownloadProgress, settings, units::Units};
+
+/// How quickly the speed follows changes. The speed is an exponential moving average in which a
+/// rate measured this long ago weighs about a third of one measured now.
+const SPEED_SMOOTHING: Duration = Duration::from_secs(3);
+
+/// The shortest time a rate is measured over. Bytes arriving sooner are added to the next rate,
+/// so that a chunk finishing in a burst does not count as a very high rate.
+const MIN_RATE_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The percentages announced to screen readers.
+pub const MILESTONES: [u8; 4] = [25, 50, 75, 100];
+
+/// This struct represents the progress of a running download as sent to the frontend.
+#[derive(Debug, Clone, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ActiveDownload {
+    pub download_id: i64,
+    pub file_name: String,
+    pub total_size: u64,
+    pub downloaded: u64,
+    /// Bytes per second, smoothed over the last few seconds.
+    pub speed: u64,
+    /// Seconds until the download finishes at the current speed, `None` if unknown. Downloads
+    /// whose size the server did not announce have a `total_size` of 0 and no eta.
+    pub eta: Option<u64>,
+    /// Milliseconds since the unix epoch, the same as in `download-progress` events.
+    pub timestamp: u64,
+    /// The reads downloaded but still waiting for the disk, see `file_writer`. A queue that stays
+    /// full means the disk is slower than the network.
+    pub write_queue: usize,
+}
+
+#[derive(Debug)]
+struct LiveProgress {
+    file_name: String,
+    total_size: u64,
+    downloaded: u64,
+    /// When the rate being measured started and the bytes downloaded then.
+    since: (Instant, u64),
+    /// The smoothed speed in bytes per second, `None` until a rate has been measured.
+    smoothed: Option<f64>,
+    write_queue: usize,
+}
+
+/// This function returns the speed once a rate measured over `elapsed` is taken into account.
+/// The longer the rate was measured over the more it weighs, so that the result does not depend
+/// on how often progress is reported.
+fn smooth(smoothed: Option<f64>, rate: f64, elapsed: Duration) -> f64 {
+    let Some(smoothed) = smoothed else {
+        return rate;
+    };
+    let weight = 1.0 - (-elapsed.as_secs_f64() / SPEED_SMOOTHING.as_secs_f64()).exp();
+    smoothed + weight * (rate - smoothed)
+}
+
+impl LiveProgress {
+    fn new(file_name: &str, total_size: u64, downloaded: u64, now: Instant) -> Self {
+        LiveProgress {
+            file_name: file_name.to_string(),
+            total_size,
+            downloaded,
+            since: (now, downloaded),
+            smoothed: None,
+            write_queue: 0,
+        }
+    }
+
+    /// This function returns the rate since the last one was measured, `None` if it has not
+    /// been measured for long enough.
+    fn rate(&self, now: Instant) -> Option<(f64, Duration)> {
+        let (since, bytes) = self.since;
+        let elapsed = now.duration_since(since);
+        if elapsed < MIN_RATE_INTERVAL {
+            return None;
+        }
+        let rate = self.downloaded.saturating_sub(bytes) as f64 / elapsed.as_secs_f64();
+        Some((rate, elapsed))
+    }
+
+    fn update(&mut self, downloaded: u64, now: Instant) {
+        self.downloaded = downloaded;
+        if let Some((rate, elapsed)) = self.rate(now) {
+            self.smoothed = Some(smooth(self.smoothed, rate, elapsed));
+            self.since = (now, downloaded);
+        }
+    }
+
+    /// This function returns the smoothed speed. A download that has not reported progress for
+    /// a while slows down as if it had reported no bytes.
+    fn speed(&self, now: Instant) -> u64 {
+        let speed = match self.rate(now) {
+            Some((rate, elapsed)) => smooth(self.smoothed, rate, elapsed),
+            None => self.smoothed.unwrap_or(0.0),
+        };
+        speed as u64
+    }
+
+    fn snapshot(&self, download_id: i64, now: Instant) -> ActiveDownload {
+        let speed = self.speed(now);
+        let remaining = self.total_size.saturating_sub(self.downloaded);
+        ActiveDownload {
+            download_id,
+            file_name: self.file_name.clone(),
+            total_size: self.total_size,
+            downloaded: self.downloaded,
+            speed,
+            eta: if speed > 0 && self.total_size > 0 {
+                Some(remaining.div_ceil(speed))
+            } else {
+                None
+            },
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+            write_queue: self.write_queue,
+        }
+    }
+}
+
+fn registry() -> &'static Mutex<HashMap<i64, LiveProgress>> {
+    static MAP: OnceLock<Mutex<HashMap<i64, LiveProgress>>> = OnceLock::new();
+    MAP.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// This function starts tracking a download.
+///
+/// # Arguments
+/// - `download_id`: The download record id.
+/// - `file_name`: The name of the file being downloaded.
+/// - `total_size`: The size of the file.
+/// - `downloaded`: The bytes already downloaded, e.g. when resuming.
+pub fn start(download_id: i64, file_name: &str, total_size: u64, downloaded: u64) {
+    registry().lock().unwrap().insert(
+        download_id,
+        LiveProgress::new(file_name, total_size, downloaded, Instant::now()),
+    );
+}
+
+/// This function records how many bytes of a download have been downloaded so far.
+pub fn update(download_id: i64, downloaded: u64) {
+    if let Some(p) = registry().lock().unwrap().get_mut(&download_id) {
+        p.update(downloaded, Instant::now());
+    }
+}
+
+/// This function records how many reads of a download are waiting for the disk.
+pub fn set_write_queue(download_id: i64, depth: usize) {
+    if let Some(p) = registry().lock().unwrap().get_mut(&download_id) {
+        p.write_queue = depth;
+    }
+}
+
+/// This function stops tracking a download.
+pub fn finish(download_id: i64) {
+    registry().lock().unwrap().remove(&download_id);
+}
+
+/// This function returns the progress of a running download.
+pub fn get(download_id: i64) -> Option<ActiveDownload> {
+    registry()
+        .lock()
+        .unwrap()
+        .get(&download_id)
+        .map(|p| p.snapshot(download_id, Instant::now()))
+}
+
+/// This function returns how much of a download is done as a whole percentage, rounded down so
+/// that 100 means finished.
+pub fn percent(downloaded: u64, total_size: u64) -> u8 {
+    if total_size == 0 {
+        return 0;
+    }
+    (downloaded.min(total_size) as u128 * 100 / total_size as u128) as u8
+}
+
+/// This function returns the highest milestone reached going from `previous` to `current` bytes.
+///
+/// # Example
+/// ```ignore
+/// assert_eq!(progress::milestone(20, 55, 100), Some(50));
+/// assert_eq!(progress::milestone(55, 60, 100), None);
+/// ```
+pub fn milestone(previous: u64, current: u64, total_size: u64) -> Option<u8> {
+    let (before, now) = (percent(previous, total_size), percent(current, total_size));
+    MILESTONES
+        .iter()
+        .rev()
+        .find(|m| before < **m && now >= **m)
+        .copied()
+}
+
+/// This function describes a remaining time in words, e.g. `about 3 minutes`.
+fn format_eta(seconds: u64) -> String {
+    match seconds {
+        0..=59 => "less than a minute".to_string(),
+        60..=119 => "about 1 minute".to_string(),
+        120..=3599 => format!("about {} minutes", seconds.div_ceil(60)),
+        3600..=7199 => "about 1 hour".to_string(),
+        _ => format!("about {} hours", seconds.div_ceil(3600)),
+    }
+}
+
+/// This function returns a sentence describing the progress of a download which a screen reader
+/// can announce as is.
+///
+/// # Example
+/// ```ignore
+/// let s = progress::summary("file.zip", 512, 1024, 0, Some(150), Units::default());
+/// assert_eq!(s, "file.zip: 50% downloaded, 512 B of 1 KiB, about 3 minutes left");
+/// ```
+pub fn summary(
+    file_name: &str,
+    downloaded: u64,
+    total_size: u64,
+    speed: u64,
+    eta: Option<u64>,
+    units: Units,
+) -> String {
+    if total_size == 0 {
+        // the size of the file is not known until it has been downloaded
+        return format!("{file_name}: {} downloaded", units.size(downloaded));
+    }
+    let pct = percent(downloaded, total_size);
+    if total_size > 0 && pct == 100 {
+        return format!("{file_name}: download complete, {}", units.size(total_size));
+    }
+    let mut s = format!(
+        "{file_name}: {pct}% downloaded, {} of {}",
+        units.size(downloaded),
+        units.size(total_size)
+    );
+    if speed > 0 {
+        s.push_str(&format!(" at {}", units.speed(speed)));
+    }
+    if let Some(eta) = eta {
+        s.push_str(&format!(", {} left", format_eta(eta)));
+    }
+    s
+}
+
+/// This function returns the progress event of a download, with the speed and eta of its live
+/// progress, the milestone reached since the last event and the summary for screen readers.
+///
+/// # Arguments
+/// - `download_id`: The download record id.
+/// - `downloaded`: The bytes downloaded.
+/// - `total_size`: The size of the file, 0 if unknown.
+/// - `announced`: The bytes downloaded when the last event was emitted.
+pub fn event(
+    download_id: i64,
+    downloaded: u64,
+    total_size: u64,
+    announced: u64,
+) -> DownloadProgress {
+    let live = get(download_id);
+    let (speed, eta) = live.as_ref().map_or((0, None), |l| (l.speed, l.eta));
+    let file_name = live.as_ref().map_or("", |l| l.file_name.as_str());
+    let units = settings::units();
+    DownloadProgress {
+        download_id,
+        total_size,
+        downloaded,
+        timestamp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64,
+        percent: percent(downloaded, total_size),
+        milestone: milestone(announced, downloaded, total_size),
+        speed,
+        eta,
+        summary: summary(file_name, downloaded, total_size, speed, eta, units),
+    }
+}
+
+/// This function returns the progress of all running downloads.
+pub fn active_downloads() -> Vec<ActiveDownload> {
+    let now = Instant::now();
+    let mut active: Vec<ActiveDownload> = registry()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(id, p)| p.snapshot(*id, now))
+        .collect();
+    active.sort_by_key(|a| a.download_id);
+    active
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::UnitSystem;
+
+    #[test]
+    fn test_speed_and_eta() {
+        let now = Instant::now();
+        let mut p = LiveProgress::new("file.zip", 10_000, 1000, now);
+        p.update(3000, now + Duration::from_secs(1));
+        p.update(5000, now + Duration::from_secs(2));
+
+        let a = p.snapshot(1, now + Duration::from_secs(2));
+        assert_eq!(a.speed, 2000);
+        assert_eq!(a.eta, Some(3));
+        assert_eq!(a.downloaded, 5000);
+    }
+
+    #[test]
+    fn test_speed_follows_a_slowdown() {
+        let now = Instant::now();
+        let mut p = LiveProgress::new("file.zip", 100_000, 0, now);
+        // fast at first, then slow
+        p.update(50_000, now + Duration::from_secs(1));
+        for i in 2..=30 {
+            p.update(50_000 + (i - 1) * 100, now + Duration::from_secs(i));
+        }
+        let a = p.snapshot(1, now + Duration::from_secs(30));
+        assert!(a.speed <= 110, "speed was {}", a.speed);
+
+        // nothing arrives any more
+        let a = p.snapshot(1, now + Duration::from_secs(60));
+        assert!(a.speed < 5, "speed was {}", a.speed);
+    }
+
+    #[test]
+    fn test_speed_smooths_bursts() {
+        let now = Instant::now();
+        let mut p = LiveProgress::new("file.zip", 1_000_000, 0, now);
+        // 1000 bytes per second arriving at once every second, reported every 100 ms
+        let mut downloaded = 0;
+        for i in 1..=200 {
+            if i % 10 == 0 {
+                downloaded += 1000;
+            }
+            let at = now + Duration::from_millis(i * 100);
+            p.update(downloaded, at);
+            if i > 50 {
+                let speed = p.speed(at);
+                assert!((700..=1300).contains(&speed), "speed was {speed} at {i}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_no_progress_has_no_eta() {
+        let now = Instant::now();
+        let p = LiveProgress::new("file.zip", 100, 0, now);
+        let a = p.snapshot(1, now + Duration::from_secs(1));
+        assert_eq!(a.speed, 0);
+        assert_eq!(a.eta, None);
+
+        let mut unknown_size = LiveProgress::new("file.zip", 0, 0, now);
+        unknown_size.update(1000, now + Duration::from_secs(1));
+        let a = unknown_size.snapshot(1, now + Duration::from_secs(1));
+        assert_eq!(a.speed, 1000);
+        assert_eq!(a.eta, None);
+    }
+
+    #[test]
+    fn test_percent_and_milestones() {
+        assert_eq!(percent(0, 0), 0);
+        assert_eq!(percent(999, 1000), 99);
+        assert_eq!(percent(2000, 1000), 100);
+
+        assert_eq!(milestone(0, 24, 100), None);
+        assert_eq!(milestone(0, 25, 100), Some(25));
+        assert_eq!(milestone(20, 80, 100), Some(75), "only the highest milestone is reported");
+        assert_eq!(milestone(25, 49, 100), None, "a milestone is reported once");
+        assert_eq!(milestone(99, 100, 100), Some(100));
+        assert_eq!(milestone(0, 10, 0), None);
+    }
+
+    #[test]
+    fn test_summary() {
+        let units = Units::default();
+        assert_eq!(
+            summary("file.zip", 512, 1024, 0, Some(150), units),
+            "file.zip: 50% downloaded, 512 B of 1 KiB, about 3 minutes left"
+        );
+        assert_eq!(
+            summary("file.zip", 3 * 1024 * 1024, 10 * 1024 * 1024, 0, None, units),
+            "file.zip: 30% downloaded, 3 MiB of 10 MiB"
+        );
+        assert_eq!(
+            summary("file.zip", 1536, 1536, 0, Some(0), units),
+            "file.zip: download complete, 1.5 KiB"
+        );
+        assert_eq!(
+            summary("stream.bin", 2048, 0, 0, None, units),
+            "stream.bin: 2 KiB downloaded"
+        );
+        let si_bits = Units {
+            system: UnitSystem::Si,
+            speed_in_bits: true,
+        };
+        assert_eq!(
+            summary("iso", 2_000_000, 8_000_000, 1_250_000, None, si_bits),
+            "iso: 25% downloaded, 2 MB of 8 MB at 10 Mbit/s"
+        );
+        assert_eq!(format_eta(30), "less than a minute");
+        assert_eq!(format_eta(7200), "about 2 hours");
+    }
+
+    #[test]
+    fn test_event() {
+        start(-11, "file.zip", 100, 0);
+        update(-11, 50);
+        let e = event(-11, 50, 100, 20);
+        assert_eq!((e.percent, e.milestone), (50, Some(50)));
+        assert!(e.summary.starts_with("file.zip: 50% downloaded"), "{}", e.summary);
+        assert_eq!(event(-11, 60, 100, 50).milestone, None);
+        finish(-11);
+    }
+
+    #[test]
+    fn test_registry() {
+        start(-10, "file.zip", 100, 10);
+        update(-10, 50);
+        let a = active_downloads()
+            .into_iter()
+            .find(|a| a.download_id == -10)
+            .unwrap();
+        assert_eq!(a.downloaded, 50);
+        assert_eq!(a.file_name, "file.zip");
+        assert_eq!(get(-10).unwrap().downloaded, 50);
+        finish(-10);
+        assert!(get(-10).is_none());
+        assert!(active_downloads().iter().all(|a| a.download_id != -10));
+    }
+}
@@ -0,0 +1,264 @@
+//! This module finds the proxy configured in the operating system so that downloads work behind
+//! corporate proxies without any setup. On Windows the proxy is read from the Internet Settings in
+//! the registry, on macOS from `scutil --proxy` and everywhere else from the `http_proxy` family of
+//! environment variables. A proxy set in the settings always wins.
+
+use std::{env, process::Command};
+
+use reqwest::{ClientBuilder, NoProxy, Proxy};
+use serde::Serialize;
+
+/// This struct represents the proxy configured in the operating system.
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+pub struct SystemProxy {
+    /// The proxy used for `http` urls.
+    pub http: Option<String>,
+    /// The proxy used for `https` urls.
+    pub https: Option<String>,
+    /// Hosts and networks reached without the proxy, e.g. `.corp.example.com` or `10.0.0.0/8`.
+    pub bypass: Vec<String>,
+}
+
+impl SystemProxy {
+    fn is_empty(&self) -> bool {
+        self.http.is_none() && self.https.is_none()
+    }
+}
+
+/// This function reads the first non-empty environment variable of `names`.
+fn first_var(lookup: &impl Fn(&str) -> Option<String>, names: &[&str]) -> Option<String> {
+    names
+        .iter()
+        .filter_map(|name| lookup(name))
+        .map(|v| v.trim().to_string())
+        .find(|v| !v.is_empty())
+}
+
+/// This function turns a list of hosts to bypass into the form understood by `NoProxy`. A leading
+/// `*` is dropped so that `*.local` matches every host ending in `.local`, and the `<local>` entry
+/// of Windows becomes the loopback names.
+fn bypass_list(list: &str) -> Vec<String> {
+    list.split([',', ';'])
+        .map(str::trim)
+        .filter(|h| !h.is_empty())
+        .flat_map(|h| match h {
+            "<local>" => vec!["localhost".to_string(), "127.0.0.1".to_string()],
+            h => vec![h.strip_prefix('*').unwrap_or(h).to_string()],
+        })
+        .collect()
+}
+
+/// This function reads the proxy from the `http_proxy`, `https_proxy`, `all_proxy` and `no_proxy`
+/// environment variables, in lower or upper case.
+fn from_env(lookup: impl Fn(&str) -> Option<String>) -> SystemProxy {
+    let all = first_var(&lookup, &["all_proxy", "ALL_PROXY"]);
+    SystemProxy {
+        http: first_var(&lookup, &["http_proxy", "HTTP_PROXY"]).or_else(|| all.clone()),
+        https: first_var(&lookup, &["https_proxy", "HTTPS_PROXY"]).or(all),
+        bypass: first_var(&lookup, &["no_proxy", "NO_PROXY"])
+            .map(|list| bypass_list(&list))
+            .unwrap_or_default(),
+    }
+}
+
+/// This function reads the proxy from the output of `scutil --proxy` on macOS.
+fn parse_scutil(output: &str) -> SystemProxy {
+    let mut values = std::collections::HashMap::new();
+    let mut bypass = Vec::new();
+    let mut in_exceptions = false;
+    for line in output.lines().map(str::trim) {
+        if line.starts_with("ExceptionsList") {
+            in_exceptions = true;
+        } else if in_exceptions && line == "}" {
+            in_exceptions = false;
+        } else if let Some((key, value)) = line.split_once(" : ") {
+            if in_exceptions {
+                bypass.extend(bypass_list(value));
+            } else {
+                values.insert(key.trim(), value.trim());
+            }
+        }
+    }
+    let proxy = |kind: &str| {
+        if values.get(format!("{kind}Enable").as_str()) != Some(&"1") {
+            return None;
+        }
+        let host = values.get(format!("{kind}Proxy").as_str())?;
+        Some(match values.get(format!("{kind}Port").as_str()) {
+            Some(port) => format!("http://{host}:{port}"),
+            None => format!("http://{host}"),
+        })
+    };
+    SystemProxy {
+        http: proxy("HTTP"),
+        https: proxy("HTTPS"),
+        bypass,
+    }
+}
+
+/// This function adds a scheme to a proxy address from the registry, which leaves it out.
+fn with_scheme(address: &str) -> String {
+    if address.contains("://") {
+        address.to_string()
+    } else {
+        format!("http://{address}")
+    }
+}
+
+/// This function reads the proxy from the output of `reg query` on the Internet Settings key.
+/// `ProxyServer` is either one address for every protocol or a list such as
+/// `http=proxy:80;https=proxy:443`.
+fn parse_internet_settings(output: &str) -> SystemProxy {
+    let value = |name: &str| {
+        output.lines().find_map(|line| {
+            let line = line.trim();
+            let rest = line.strip_prefix(name)?;
+            let (_, value) = rest.split_once("REG_")?;
+            let (_, value) = value.split_once(char::is_whitespace)?;
+            Some(value.trim().to_string())
+        })
+    };
+    let enabled = value("ProxyEnable").is_some_and(|v| v == "0x1");
+    let Some(server) = value("ProxyServer").filter(|_| enabled) else {
+        return SystemProxy::default();
+    };
+    let mut proxy = SystemProxy {
+        bypass: value("ProxyOverride")
+            .map(|list| bypass_list(&list))
+            .unwrap_or_default(),
+        ..SystemProxy::default()
+    };
+    if server.contains('=') {
+        for entry in server.split(';') {
+            match entry.trim().split_once('=') {
+                Some(("http", address)) => proxy.http = Some(with_scheme(address)),
+                Some(("https", address)) => proxy.https = Some(with_scheme(address)),
+                _ => {}
+            }
+        }
+    } else {
+        proxy.http = Some(with_scheme(&server));
+        proxy.https = proxy.http.clone();
+    }
+    proxy
+}
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// This function returns the proxy configured in the operating system, if any. The environment
+/// variables are checked on every system since they are also set by shells and containers.
+///
+/// # Arguments
+/// - `os`: The operating system, see `Config::os`.
+pub fn detect(os: &str) -> Option<SystemProxy> {
+    let from_os = match os {
+        "Windows" => command_output(
+            "reg",
+            &[
+                "query",
+                r"HKCU\Software\Microsoft\Windows\CurrentVersion\Internet Settings",
+            ],
+        )
+        .map(|o| parse_internet_settings(&o)),
+        "Darwin" => command_output("scutil", &["--proxy"]).map(|o| parse_scutil(&o)),
+        _ => None,
+    };
+    from_os
+        .filter(|p| !p.is_empty())
+        .or_else(|| Some(from_env(|name| env::var(name).ok())))
+        .filter(|p| !p.is_empty())
+}
+
+/// This function sets the proxy of an http client.
+///
+/// # Arguments
+/// - `configured`: The proxy set in the settings, which wins over the system proxy.
+/// - `use_system`: Whether the system proxy is used when none is configured. Without it
+///   requests go straight to the server.
+/// - `os`: The operating system, see `Config::os`.
+pub fn apply(
+    builder: ClientBuilder,
+    configured: Option<&str>,
+    use_system: bool,
+    os: &str,
+) -> Result<ClientBuilder, String> {
+    if let Some(proxy) = configured {
+        let proxy = Proxy::all(proxy).map_err(|e| format!("Invalid proxy: {e}"))?;
+        return Ok(builder.proxy(proxy));
+    }
+    if !use_system {
+        return Ok(builder.no_proxy());
+    }
+    let Some(system) = detect(os) else {
+        return Ok(builder.no_proxy());
+    };
+    let bypass = NoProxy::from_string(&system.bypass.join(","));
+    let mut builder = builder;
+    if let Some(http) = &system.http {
+        let proxy = Proxy::http(http).map_err(|e| format!("Invalid system proxy: {e}"))?;
+        builder = builder.proxy(proxy.no_proxy(bypass.clone()));
+    }
+    if let Some(https) = &system.https {
+        let proxy = Proxy::https(https).map_err(|e| format!("Invalid system proxy: {e}"))?;
+        builder = builder.proxy(proxy.no_proxy(bypass));
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_env() {
+        let vars = [
+            ("HTTP_PROXY", "http://proxy.corp:3128"),
+            ("all_proxy", "socks5://127.0.0.1:1080"),
+            ("no_proxy", "localhost, .corp.example.com,*.local"),
+        ];
+        let proxy = from_env(|name| {
+            vars.iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, v)| v.to_string())
+        });
+        assert_eq!(proxy.http.as_deref(), Some("http://proxy.corp:3128"));
+        assert_eq!(proxy.https.as_deref(), Some("socks5://127.0.0.1:1080"));
+        assert_eq!(proxy.bypass, ["localhost", ".corp.example.com", ".local"]);
+        assert!(from_env(|_| None).is_empty());
+    }
+
+    #[test]
+    fn test_parse_scutil() {
+        let output = "<dictionary> {\n  ExceptionsList : <array> {\n    0 : *.local\n    1 : 169.254/16\n  }\n  HTTPEnable : 1\n  HTTPPort : 8080\n  HTTPProxy : proxy.corp\n  HTTPSEnable : 0\n  HTTPSProxy : other.corp\n}\n";
+        let proxy = parse_scutil(output);
+        assert_eq!(proxy.http.as_deref(), Some("http://proxy.corp:8080"));
+        assert_eq!(proxy.https, None);
+        assert_eq!(proxy.bypass, [".local", "169.254/16"]);
+    }
+
+    #[test]
+    fn test_parse_internet_settings() {
+        let output = "\r\nHKEY_CURRENT_USER\\Software\\Microsoft\\Windows\\CurrentVersion\\Internet Settings\r\n    ProxyEnable    REG_DWORD    0x1\r\n    ProxyServer    REG_SZ    http=proxy.corp:80;https=proxy.corp:443\r\n    ProxyOverride    REG_SZ    *.corp;<local>\r\n";
+        let proxy = parse_internet_settings(output);
+        assert_eq!(proxy.http.as_deref(), Some("http://proxy.corp:80"));
+        assert_eq!(proxy.https.as_deref(), Some("http://proxy.corp:443"));
+        assert_eq!(proxy.bypass, [".corp", "localhost", "127.0.0.1"]);
+
+        let single = "    ProxyEnable    REG_DWORD    0x1\r\n    ProxyServer    REG_SZ    proxy.corp:8080\r\n";
+        let proxy = parse_internet_settings(single);
+        assert_eq!(proxy.http.as_deref(), Some("http://proxy.corp:8080"));
+        assert_eq!(proxy.https, proxy.http);
+
+        let disabled = "    ProxyEnable    REG_DWORD    0x0\r\n    ProxyServer    REG_SZ    proxy.corp:8080\r\n";
+        assert!(parse_internet_settings(disabled).is_empty());
+    }
+
+    #[test]
+    fn test_configured_proxy_must_be_valid() {
+        assert!(apply(reqwest::Client::builder(), Some("not a proxy"), true, "Linux").is_err());
+        assert!(apply(reqwest::Client::builder(), None, false, "Linux").is_ok());
+    }
+}
@@ -0,0 +1,273 @@
+//! This module sends notifications about downloads to push services, so that a download left
+//! running on a computer at home can be followed from a phone. Nothing is sent unless the user
+//! adds a service in the settings, and each service can be limited to the downloads that finish
+//! or to the ones that fail.
+
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::{privacy, units::Units};
+
+/// The url messages are posted to on Pushover.
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+
+/// This enum represents the push services notifications can be sent to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PushService {
+    #[default]
+    Ntfy,
+    Gotify,
+    Pushover,
+}
+
+/// This enum represents what happened to a download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PushEvent {
+    Finished,
+    Failed,
+}
+
+/// This struct represents a push service notifications are sent to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PushTarget {
+    pub service: PushService,
+    /// The url of the server, e.g. `https://ntfy.sh`. Not used by Pushover.
+    pub server: String,
+    /// The topic posted to on ntfy.
+    pub topic: String,
+    /// The user key on Pushover.
+    pub user: String,
+    /// The access token of ntfy, the application token of Gotify or Pushover.
+    pub token: Option<String>,
+    /// Whether downloads that finish are sent.
+    pub on_finished: bool,
+    /// Whether downloads that fail are sent.
+    pub on_failed: bool,
+}
+
+impl Default for PushTarget {
+    fn default() -> Self {
+        PushTarget {
+            service: PushService::Ntfy,
+            server: "https://ntfy.sh".to_string(),
+            topic: String::new(),
+            user: String::new(),
+            token: None,
+            on_finished: true,
+            on_failed: true,
+        }
+    }
+}
+
+impl PushTarget {
+    /// This function checks that the service has what it needs to be posted to.
+    pub fn validate(&self) -> Result<(), String> {
+        let server = self.server.trim();
+        let has_server = server.starts_with("http://") || server.starts_with("https://");
+        let has_token = self.token.as_deref().is_some_and(|t| !t.trim().is_empty());
+        match self.service {
+            PushService::Ntfy if !has_server || self.topic.trim().is_empty() => {
+                Err("ntfy needs a http server url and a topic".into())
+            }
+            PushService::Gotify if !has_server || !has_token => {
+                Err("Gotify needs a http server url and an application token".into())
+            }
+            PushService::Pushover if self.user.trim().is_empty() || !has_token => {
+                Err("Pushover needs a user key and an application token".into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// This function returns whether the service wants to hear about `event`.
+    pub fn wants(&self, event: PushEvent) -> bool {
+        match event {
+            PushEvent::Finished => self.on_finished,
+            PushEvent::Failed => self.on_failed,
+        }
+    }
+}
+
+/// This struct represents a notification about a download.
+#[derive(Debug, Clone)]
+pub struct Push {
+    pub event: PushEvent,
+    pub file_name: String,
+    /// The size of the file, 0 if unknown.
+    pub size: u64,
+    /// Why the download failed.
+    pub reason: Option<String>,
+}
+
+impl Push {
+    /// This function returns the title of the notification, the same as the one shown on the
+    /// desktop.
+    pub fn title(&self) -> &'static str {
+        match self.event {
+            PushEvent::Finished => "YAD — Download complete",
+            PushEvent::Failed => "YAD — Download failed",
+        }
+    }
+
+    /// This function returns the text of the notification, e.g. `ubuntu.iso (5.7 GB)`. The urls
+    /// a failure names are sent without their secrets, the push service is someone else's server.
+    pub fn message(&self, units: Units) -> String {
+        let mut message = self.file_name.clone();
+        if self.size > 0 {
+            message.push_str(&format!(" ({})", units.size(self.size)));
+        }
+        if let Some(reason) = &self.reason {
+            message.push_str(&format!(" — {}", privacy::strip_urls_in(reason)));
+        }
+        message
+    }
+}
+
+/// This function builds the request posting `push` to `target`.
+pub fn request(client: &Client, target: &PushTarget, push: &Push, units: Units) -> RequestBuilder {
+    let server = target.server.trim().trim_end_matches('/');
+    let token = target.token.as_deref().unwrap_or_default().trim();
+    let (url, body) = match target.service {
+        PushService::Ntfy => (
+            server.to_string(),
+            json!({
+                "topic": target.topic.trim(),
+                "title": push.title(),
+                "message": push.message(units),
+            }),
+        ),
+        PushService::Gotify => (
+            format!("{server}/message"),
+            json!({"title": push.title(), "message": push.message(units)}),
+        ),
+        PushService::Pushover => (
+            PUSHOVER_URL.to_string(),
+            json!({
+                "token": token,
+                "user": target.user.trim(),
+                "title": push.title(),
+                "message": push.message(units),
+            }),
+        ),
+    };
+    let mut request = client
+        .post(url)
+        .header("Content-Type", "application/json")
+        .body(body.to_string());
+    match target.service {
+        PushService::Ntfy if !token.is_empty() => {
+            request = request.bearer_auth(token);
+        }
+        PushService::Gotify => {
+            request = request.header("X-Gotify-Key", token);
+        }
+        _ => {}
+    }
+    request
+}
+
+/// This function posts `push` to every target that wants it. A service that cannot be reached is
+/// only logged, the download is not affected.
+pub async fn send(client: &Client, targets: &[PushTarget], push: &Push, units: Units) {
+    for target in targets.iter().filter(|t| t.wants(push.event)) {
+        let sent = request(client, target, push, units)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status());
+        if let Err(e) = sent {
+            eprintln!(
+                "failed to send a push notification to {:?} because {}",
+                target.service,
+                privacy::strip_urls_in(&e.to_string())
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::UnitSystem;
+
+    fn push() -> Push {
+        Push {
+            event: PushEvent::Finished,
+            file_name: "ubuntu.iso".into(),
+            size: 5_700_000_000,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let ntfy = PushTarget {
+            topic: "downloads".into(),
+            ..PushTarget::default()
+        };
+        assert!(ntfy.validate().is_ok());
+        assert!(PushTarget::default().validate().is_err(), "no topic");
+        let gotify = PushTarget {
+            service: PushService::Gotify,
+            server: "https://push.example.com".into(),
+            ..PushTarget::default()
+        };
+        assert!(gotify.validate().is_err(), "no token");
+        let pushover = PushTarget {
+            service: PushService::Pushover,
+            server: String::new(),
+            user: "u1".into(),
+            token: Some("t1".into()),
+            ..PushTarget::default()
+        };
+        assert!(pushover.validate().is_ok());
+    }
+
+    #[test]
+    fn test_request() {
+        let units = Units {
+            system: UnitSystem::Si,
+            speed_in_bits: false,
+        };
+        let target = PushTarget {
+            service: PushService::Gotify,
+            server: "https://push.example.com/".into(),
+            token: Some("secret".into()),
+            on_finished: true,
+            ..PushTarget::default()
+        };
+        let built = request(&Client::new(), &target, &push(), units)
+            .build()
+            .unwrap();
+        assert_eq!(built.url().as_str(), "https://push.example.com/message");
+        assert_eq!(built.headers()["X-Gotify-Key"], "secret");
+        let body: serde_json::Value =
+            serde_json::from_slice(built.body().unwrap().as_bytes().unwrap()).unwrap();
+        assert_eq!(body["message"], "ubuntu.iso (5.7 GB)");
+
+        let failed = Push {
+            event: PushEvent::Failed,
+            reason: Some("checksum mismatch".into()),
+            size: 0,
+            ..push()
+        };
+        assert_eq!(failed.message(units), "ubuntu.iso — checksum mismatch");
+        let leaky = Push {
+            event: PushEvent::Failed,
+            reason: Some("request failed for https://cdn.example.com/f.iso?sig=abc".into()),
+            size: 0,
+            ..push()
+        };
+        assert_eq!(
+            leaky.message(units),
+            "ubuntu.iso — request failed for https://cdn.example.com/f.iso"
+        );
+        let finished_only = PushTarget {
+            on_failed: false,
+            ..target
+        };
+        assert!(!finished_only.wants(PushEvent::Failed));
+    }
+}
@@ -0,0 +1,341 @@
+//! This module limits how many downloads run at the same time. A download added while every slot
+//! is taken waits in the queue, saved with the `Queued` status, and starts by itself as soon as a
+//! running download ends. Queued downloads with a higher priority start first, and downloads of
+//! the same priority in the order they were added. The limit can be changed while downloads are
+//! running.
+
+use std::{
+    collections::VecDeque,
+    sync::{Mutex, OnceLock},
+};
+
+use tokio::sync::oneshot;
+
+/// The number of downloads running at the same time when nothing is configured.
+pub const DEFAULT_MAX_CONCURRENT_DOWNLOADS: usize = 3;
+
+/// This struct represents the downloads running and the downloads waiting for a slot.
+#[derive(Debug, Default)]
+pub struct Queue {
+    state: Mutex<QueueState>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    /// The number of downloads running at the same time. 0 means unlimited.
+    limit: usize,
+    running: Vec<i64>,
+    /// Ordered by priority, highest first.
+    waiting: VecDeque<Waiter>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: i64,
+    priority: i64,
+    start: oneshot::Sender<()>,
+}
+
+impl QueueState {
+    fn has_room(&self) -> bool {
+        self.limit == 0 || self.running.len() < self.limit
+    }
+
+    /// This function starts waiting downloads while there are free slots. A waiter which is gone
+    /// does not take a slot.
+    fn start_waiting(&mut self) {
+        while self.has_room() {
+            let Some(waiter) = self.waiting.pop_front() else {
+                break;
+            };
+            if waiter.start.send(()).is_ok() {
+                self.running.push(waiter.id);
+            }
+        }
+    }
+
+    /// This function queues a download behind the downloads of the same or a higher priority.
+    fn push(&mut self, waiter: Waiter) {
+        let at = self
+            .waiting
+            .iter()
+            .position(|w| w.priority < waiter.priority)
+            .unwrap_or(self.waiting.len());
+        self.waiting.insert(at, waiter);
+    }
+
+    fn take(&mut self, id: i64) -> Option<Waiter> {
+        let at = self.waiting.iter().position(|w| w.id == id)?;
+        self.waiting.remove(at)
+    }
+
+    fn release(&mut self, id: i64) {
+        self.running.retain(|r| *r != id);
+        self.start_waiting();
+    }
+}
+
+/// This struct is the slot of a running download. The slot is freed, and the next queued download
+/// started, when it is dropped.
+#[derive(Debug)]
+pub struct Slot {
+    id: i64,
+    queue: &'static Queue,
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.queue.state.lock().unwrap().release(self.id);
+    }
+}
+
+/// This struct is the place of a download waiting in the queue.
+#[derive(Debug)]
+pub struct Ticket {
+    id: i64,
+    queue: &'static Queue,
+    start: Option<oneshot::Receiver<()>>,
+    /// Whether the slot was handed to a `Slot`, which then frees it.
+    started: bool,
+}
+
+impl Ticket {
+    /// This function waits until the download gets a slot.
+    ///
+    /// # Returns
+    /// - `Some(Slot)`: The slot of the download, held while it runs.
+    /// - `None`: If the download was removed from the queue, see `Queue::remove`.
+    pub async fn wait(mut self) -> Option<Slot> {
+        let start = self.start.take()?;
+        start.await.ok()?;
+        self.started = true;
+        Some(Slot {
+            id: self.id,
+            queue: self.queue,
+        })
+    }
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        if self.started {
+            return;
+        }
+        // the download stopped waiting, it leaves the queue or frees the slot it was just given
+        let mut state = self.queue.state.lock().unwrap();
+        state.take(self.id);
+        if state.running.contains(&self.id) {
+            state.release(self.id);
+        }
+    }
+}
+
+impl Queue {
+    /// This function creates a queue running up to `limit` downloads at the same time. 0 means
+    /// unlimited.
+    pub fn new(limit: usize) -> Self {
+        Queue {
+            state: Mutex::new(QueueState {
+                limit,
+                ..QueueState::default()
+            }),
+        }
+    }
+
+    /// This function returns the number of downloads running at the same time. 0 means
+    /// unlimited.
+    pub fn limit(&self) -> usize {
+        self.state.lock().unwrap().limit
+    }
+
+    /// This function changes the limit. Queued downloads start right away when it is raised,
+    /// running downloads are not stopped when it is lowered.
+    pub fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit;
+        state.start_waiting();
+    }
+
+    /// This function takes a slot for a download.
+    ///
+    /// # Returns
+    /// - `Ok(Slot)`: If a slot was free and the download can start.
+    /// - `Err(Ticket)`: If every slot is taken, the download is queued behind the others of the
+    ///   same or a higher priority.
+    pub fn enter(&'static self, id: i64, priority: i64) -> Result<Slot, Ticket> {
+        let mut state = self.state.lock().unwrap();
+        if state.waiting.is_empty() && state.has_room() {
+            state.running.push(id);
+            return Ok(Slot { id, queue: self });
+        }
+        let (tx, rx) = oneshot::channel();
+        state.push(Waiter {
+            id,
+            priority,
+            start: tx,
+        });
+        Err(Ticket {
+            id,
+            queue: self,
+            start: Some(rx),
+            started: false,
+        })
+    }
+
+    /// This function returns whether a download is waiting for a slot.
+    pub fn is_queued(&self, id: i64) -> bool {
+        self.state
+            .lock()
+            .unwrap()
+            .waiting
+            .iter()
+            .any(|w| w.id == id)
+    }
+
+    /// This function returns the downloads waiting for a slot, next first.
+    pub fn queued(&self) -> Vec<i64> {
+        let state = self.state.lock().unwrap();
+        state.waiting.iter().map(|w| w.id).collect()
+    }
+
+    /// This function changes the priority of a queued download, moving it behind the downloads of
+    /// the same or a higher priority.
+    ///
+    /// # Returns
+    /// Whether the download was waiting.
+    pub fn set_priority(&self, id: i64, priority: i64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let Some(waiter) = state.take(id) else {
+            return false;
+        };
+        state.push(Waiter { priority, ..waiter });
+        true
+    }
+
+    /// This function moves a queued download to `position` in the queue, 0 being next. It takes
+    /// the priority of the download it is moved in front of, or behind when it is moved to the
+    /// end, so that the queue stays ordered by priority.
+    ///
+    /// # Returns
+    /// - `Some(i64)`: The new priority of the download.
+    /// - `None`: If the download is not waiting.
+    pub fn move_to(&self, id: i64, position: usize) -> Option<i64> {
+        let mut state = self.state.lock().unwrap();
+        let mut waiter = state.take(id)?;
+        let position = position.min(state.waiting.len());
+        if let Some(next) = state.waiting.get(position) {
+            waiter.priority = next.priority;
+        } else if let Some(last) = state.waiting.back() {
+            waiter.priority = last.priority;
+        }
+        let priority = waiter.priority;
+        state.waiting.insert(position, waiter);
+        Some(priority)
+    }
+
+    /// This function removes a download from the queue, its `Ticket::wait` returns `None`.
+    ///
+    /// # Returns
+    /// Whether the download was waiting.
+    pub fn remove(&self, id: i64) -> bool {
+        self.state.lock().unwrap().take(id).is_some()
+    }
+}
+
+/// This function returns the queue shared by every download, set to
+/// `Settings::max_concurrent_downloads`.
+pub fn global() -> &'static Queue {
+    static GLOBAL: OnceLock<Queue> = OnceLock::new();
+    GLOBAL.get_or_init(|| Queue::new(DEFAULT_MAX_CONCURRENT_DOWNLOADS))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leak(limit: usize) -> &'static Queue {
+        Box::leak(Box::new(Queue::new(limit)))
+    }
+
+    #[test]
+    fn test_slots_start_queued_downloads_in_order() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let queue = leak(2);
+            let first = queue.enter(1, 0).unwrap();
+            let second = queue.enter(2, 0).unwrap();
+            let third = queue.enter(3, 0).unwrap_err();
+            let fourth = queue.enter(4, 0).unwrap_err();
+            assert_eq!(queue.queued(), [3, 4]);
+
+            drop(first);
+            let third = third.wait().await.unwrap();
+            assert_eq!(queue.queued(), [4]);
+            drop(second);
+            let _fourth = fourth.wait().await.unwrap();
+            let fifth = queue.enter(5, 0).unwrap_err();
+            assert_eq!(queue.queued(), [5]);
+            drop(third);
+            assert!(fifth.wait().await.is_some());
+            assert!(queue.queued().is_empty());
+        });
+    }
+
+    #[test]
+    fn test_remove_and_set_limit() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let queue = leak(1);
+            let _running = queue.enter(1, 0).unwrap();
+            let removed = queue.enter(2, 0).unwrap_err();
+            let raised = queue.enter(3, 0).unwrap_err();
+            assert!(queue.is_queued(2));
+            assert!(queue.remove(2));
+            assert!(!queue.remove(2));
+            assert!(removed.wait().await.is_none());
+
+            queue.set_limit(0);
+            assert!(raised.wait().await.is_some());
+            assert!(queue.enter(4, 0).is_ok());
+        });
+    }
+
+    #[test]
+    fn test_priorities_and_moves() {
+        let queue = leak(1);
+        let _running = queue.enter(1, 0).unwrap();
+        let _tickets = [
+            queue.enter(2, 0).unwrap_err(),
+            queue.enter(3, 0).unwrap_err(),
+            queue.enter(4, 5).unwrap_err(),
+            queue.enter(5, -1).unwrap_err(),
+        ];
+        assert_eq!(queue.queued(), [4, 2, 3, 5]);
+
+        assert!(queue.set_priority(3, 9));
+        assert_eq!(queue.queued(), [3, 4, 2, 5]);
+        assert!(!queue.set_priority(1, 9));
+
+        // moved in front of 4, it takes its priority
+        assert_eq!(queue.move_to(5, 1), Some(5));
+        assert_eq!(queue.queued(), [3, 5, 4, 2]);
+        assert_eq!(queue.move_to(3, 10), Some(0));
+        assert_eq!(queue.queued(), [5, 4, 2, 3]);
+        assert_eq!(queue.move_to(1, 0), None);
+    }
+
+    #[test]
+    fn test_dropped_ticket_frees_its_slot() {
+        let queue = leak(1);
+        let running = queue.enter(1, 0).unwrap();
+        let gone = queue.enter(2, 0).unwrap_err();
+        drop(running);
+        // the slot was handed to the ticket, which is dropped before it is used
+        drop(gone);
+        assert!(queue.enter(3, 0).is_ok());
+    }
+}
@@ -0,0 +1,142 @@
+//! This module lets the frontend ask for only the fields of the download records it shows, and
+//! for details that cost more to compute, in one call. A history of tens of thousands of records
+//! is sent as a few columns instead of every field, and a detail view asks for the chunk map and
+//! speed of a few records instead of calling several commands.
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    config::Config,
+    health, progress,
+    storage::{self, DownloadRecord},
+};
+
+/// A record with the fields asked for, keyed by their names in `DownloadRecord`.
+pub type Row = Map<String, Value>;
+
+/// This struct represents what to return of the download records.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct RecordQuery {
+    /// The fields of `DownloadRecord` to return, e.g. `["file_name", "download_status"]`. Every
+    /// field when empty. The `id` is always returned.
+    pub fields: Vec<String>,
+    /// Only the records with these ids, every record when `None`.
+    pub ids: Option<Vec<i64>>,
+    /// Whether the chunks of each record are added as `chunk_map`.
+    pub with_chunk_map: bool,
+    /// Whether the speed and eta of running downloads are added as `speed` and `eta`, `null` for
+    /// the others.
+    pub with_speed: bool,
+}
+
+/// This function keeps the fields of `record` asked for by `query`.
+///
+/// # Returns
+/// - `Ok(Row)`: The fields asked for.
+/// - `Err(String)`: If a field asked for is not a field of `DownloadRecord`.
+pub fn select(record: &DownloadRecord, query: &RecordQuery) -> Result<Row, String> {
+    let Ok(Value::Object(mut all)) = serde_json::to_value(record) else {
+        return Err("Failed to serialize the download record".into());
+    };
+    if query.fields.is_empty() {
+        return Ok(all);
+    }
+    let mut row = Row::new();
+    row.insert("id".into(), record.id.into());
+    for field in &query.fields {
+        let value = all
+            .remove(field)
+            .ok_or_else(|| format!("{field} is not a field of a download"))?;
+        row.insert(field.clone(), value);
+    }
+    Ok(row)
+}
+
+/// This function returns the download records as asked for by `query`, see `RecordQuery`.
+pub fn fetch(query: &RecordQuery, cfg: &Config) -> Result<Vec<Row>, String> {
+    let records = match &query.ids {
+        Some(ids) => storage::read_records_by_ids(ids, cfg),
+        None => storage::read_download_records(cfg),
+    }
+    .map_err(|e| format!("Failed to read the downloads: {e}"))?;
+    records
+        .into_iter()
+        .map(|mut record| {
+            record.health = health::score(record.id);
+            let mut row = select(&record, query)?;
+            if query.with_chunk_map {
+                let chunks = storage::get_chunks_by_record(record.id, cfg).unwrap_or_default();
+                row.insert(
+                    "chunk_map".into(),
+                    serde_json::to_value(chunks).unwrap_or_default(),
+                );
+            }
+            if query.with_speed {
+                let live = progress::get(record.id);
+                row.insert("speed".into(), live.as_ref().map(|l| l.speed).into());
+                row.insert("eta".into(), live.and_then(|l| l.eta).into());
+            }
+            Ok(row)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let record = DownloadRecord {
+            id: 4,
+            file_name: "a.zip".into(),
+            file_size: 10,
+            ..DownloadRecord::default()
+        };
+        let all = select(&record, &RecordQuery::default()).unwrap();
+        assert!(all.contains_key("destination_path"));
+
+        let query = RecordQuery {
+            fields: vec!["file_name".into(), "file_size".into()],
+            ..RecordQuery::default()
+        };
+        let row = select(&record, &query).unwrap();
+        assert_eq!(
+            Value::Object(row),
+            serde_json::json!({"id": 4, "file_name": "a.zip", "file_size": 10})
+        );
+
+        let query = RecordQuery {
+            fields: vec!["password".into()],
+            ..RecordQuery::default()
+        };
+        assert!(select(&record, &query).is_err());
+    }
+
+    #[test]
+    fn test_fetch_with_extras() {
+        let cfg = storage::test_config("record_query");
+        storage::create_tables(&cfg).unwrap();
+        let record = DownloadRecord {
+            file_url: "https://example.com/a.zip".into(),
+            file_name: "a.zip".into(),
+            destination_path: "/tmp/a.zip".into(),
+            ..DownloadRecord::default()
+        };
+        let id =
+            storage::insert_record_with_chunks(&record, 20, &[(0, 9), (10, 19)], &cfg).unwrap();
+        let query = RecordQuery {
+            fields: vec!["file_name".into()],
+            ids: Some(vec![id]),
+            with_chunk_map: true,
+            with_speed: true,
+        };
+        let rows = fetch(&query, &cfg).unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["chunk_map"].as_array().unwrap().len(), 2);
+        assert_eq!(rows[0]["speed"], Value::Null, "not running");
+        assert!(!rows[0].contains_key("file_url"));
+    }
+}
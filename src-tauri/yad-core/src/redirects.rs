@@ -0,0 +1,183 @@
+//! This module follows redirects when a download starts so that every hop can be recorded on the
+//! download record. Mirrors often bounce users through trackers before serving the file, and the
+//! recorded chain shows where the file actually came from. The number of redirects is limited by
+//! the settings and redirect loops are reported instead of being followed until the limit.
+
+use reqwest::{redirect::Policy, Client, Response, Url};
+use serde::{Deserialize, Serialize};
+
+use crate::pins::{self, CertPin};
+
+/// The number of redirects followed when nothing is configured.
+pub const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// This struct represents one redirect: `url` answered with `status` and sent us to `location`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct RedirectHop {
+    pub url: String,
+    pub status: u16,
+    pub location: String,
+}
+
+/// This function returns the redirect policy for requests that follow redirects on their own.
+pub fn policy(max_redirects: usize) -> Policy {
+    Policy::custom(move |attempt| {
+        if attempt.previous().iter().any(|u| u == attempt.url()) {
+            let message = format!("Redirect loop detected at {}", attempt.url());
+            attempt.error(message)
+        } else if attempt.previous().len() > max_redirects {
+            let message = format!("Too many redirects (more than {max_redirects})");
+            attempt.error(message)
+        } else {
+            attempt.follow()
+        }
+    })
+}
+
+/// This function resolves the `Location` header of a redirect against the url that sent it.
+fn next_location(base: &Url, location: &str) -> Result<Url, String> {
+    base.join(location)
+        .map_err(|e| format!("Invalid redirect location {location}: {e}"))
+}
+
+/// This function checks that following a redirect to `next` is allowed.
+///
+/// # Arguments
+/// - `hops`: The redirects followed so far.
+/// - `start`: The url the download started from.
+/// - `next`: The url being redirected to.
+/// - `max_redirects`: The maximum number of redirects.
+fn check_hop(
+    hops: &[RedirectHop],
+    start: &str,
+    next: &Url,
+    max_redirects: usize,
+) -> Result<(), String> {
+    let next = next.as_str();
+    let mut visited = std::iter::once(start).chain(hops.iter().map(|h| h.location.as_str()));
+    if visited.any(|u| u == next) {
+        let chain: Vec<&str> = std::iter::once(start)
+            .chain(hops.iter().map(|h| h.location.as_str()))
+            .chain(std::iter::once(next))
+            .collect();
+        return Err(format!("Redirect loop detected: {}", chain.join(" -> ")));
+    }
+    if hops.len() >= max_redirects {
+        return Err(format!(
+            "Too many redirects (more than {max_redirects}), last location was {next}"
+        ));
+    }
+    Ok(())
+}
+
+/// This function sends a `HEAD` request to `url`, following redirects one by one.
+///
+/// # Arguments
+/// - `client`: A client that does not follow redirects on its own.
+/// - `url`: The url of the download.
+/// - `headers`: Extra headers to send with every request.
+/// - `max_redirects`: The maximum number of redirects to follow.
+/// - `cert_pins`: The certificate pins every hop is checked against.
+///
+/// # Returns
+/// - `Ok((Response, String, Vec<RedirectHop>))`: The response of the final url, the final url and
+///   the redirects that were followed.
+/// - `Err(String)`: If a request fails, there are too many redirects, a loop is detected or a
+///   certificate does not match its pin.
+pub async fn probe(
+    client: &Client,
+    url: &str,
+    headers: &[(String, String)],
+    max_redirects: usize,
+    cert_pins: &[CertPin],
+) -> Result<(Response, String, Vec<RedirectHop>), String> {
+    let mut hops: Vec<RedirectHop> = Vec::new();
+    let mut current = Url::parse(url).map_err(|e| format!("Invalid URL: {e}"))?;
+    let start = current.to_string();
+    loop {
+        let mut request = client.head(current.clone());
+        for (name, value) in headers {
+            request = request.header(name.as_str(), value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("HEAD request failed: {e}"))?;
+        pins::check(cert_pins, &response)?;
+
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|v| v.to_str().ok());
+        let location = match location {
+            Some(l) if response.status().is_redirection() => l.to_string(),
+            _ => return Ok((response, current.to_string(), hops)),
+        };
+
+        let next = next_location(&current, &location)?;
+        check_hop(&hops, &start, &next, max_redirects)?;
+        hops.push(RedirectHop {
+            url: current.to_string(),
+            status: response.status().as_u16(),
+            location: next.to_string(),
+        });
+        current = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hop(url: &str, location: &str) -> RedirectHop {
+        RedirectHop {
+            url: url.into(),
+            status: 302,
+            location: location.into(),
+        }
+    }
+
+    #[test]
+    fn test_next_location_resolves_relative_urls() {
+        let base = Url::parse("https://example.com/dl/file.zip").unwrap();
+        assert_eq!(
+            next_location(&base, "/mirror/file.zip").unwrap().as_str(),
+            "https://example.com/mirror/file.zip"
+        );
+        assert_eq!(
+            next_location(&base, "https://cdn.example.com/file.zip")
+                .unwrap()
+                .as_str(),
+            "https://cdn.example.com/file.zip"
+        );
+    }
+
+    #[test]
+    fn test_check_hop_detects_loops() {
+        let start = "https://a.example.com/";
+        let hops = vec![
+            hop(start, "https://b.example.com/"),
+            hop("https://b.example.com/", "https://c.example.com/"),
+        ];
+        let next = Url::parse("https://b.example.com/").unwrap();
+        let err = check_hop(&hops, start, &next, 10).unwrap_err();
+        assert!(err.contains("loop"), "{err}");
+        assert!(err.contains("https://c.example.com/ -> https://b.example.com/"), "{err}");
+
+        let back_to_start = Url::parse(start).unwrap();
+        assert!(check_hop(&hops, start, &back_to_start, 10).is_err());
+
+        let to_itself = Url::parse("https://c.example.com/").unwrap();
+        assert!(check_hop(&hops, start, &to_itself, 10).is_err());
+    }
+
+    #[test]
+    fn test_check_hop_enforces_max_depth() {
+        let start = "https://a.example.com/";
+        let hops = vec![hop(start, "https://b.example.com/")];
+        let next = Url::parse("https://c.example.com/").unwrap();
+        assert!(check_hop(&hops, start, &next, 2).is_ok());
+        assert!(check_hop(&hops, start, &next, 1).is_err());
+        assert!(check_hop(&[], start, &next, 0).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! This module decides how long to wait before a failed chunk request is retried. A single
+//! transient network error should not fail a chunk, and with it the whole download, so chunks are
+//! retried a few times with an exponentially growing delay before they are marked as failed. The
+//! same goes for the server errors of busy servers, see `is_transient_status`. A server that
+//! limits the requests it is sent and says for how long is left alone by every chunk of the
+//! download for that long, see `Cooldown`.
+
+use std::{
+    sync::{Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+/// The number of times a failed chunk is retried when nothing is configured.
+pub const DEFAULT_CHUNK_RETRIES: u32 = 3;
+
+/// The delay before the first retry in milliseconds when nothing is configured.
+pub const DEFAULT_BACKOFF_MS: u64 = 500;
+
+/// The number of attempts after which a chunk is given up when nothing is configured. 0 means
+/// chunks are never given up.
+pub const DEFAULT_MAX_CHUNK_ATTEMPTS: u32 = 0;
+
+/// The longest delay between two attempts in milliseconds when nothing is configured.
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 30_000;
+
+/// The longest a server is left alone when it asks for it, so that a wrong `Retry-After` cannot
+/// stop a download for days.
+pub const MAX_RETRY_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// This struct represents the retry settings of a single download, used instead of the ones of
+/// the settings, e.g. to keep trying harder for a large file on a flaky connection. A field left
+/// `None` keeps the setting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetryOverrides {
+    /// See `Settings::chunk_retries`.
+    pub retries: Option<u32>,
+    /// See `Settings::retry_backoff_ms`.
+    pub base_delay_ms: Option<u64>,
+    /// See `Settings::retry_max_delay_ms`.
+    pub max_delay_ms: Option<u64>,
+}
+
+impl RetryOverrides {
+    /// This function returns whether nothing is overridden.
+    pub fn is_empty(&self) -> bool {
+        *self == RetryOverrides::default()
+    }
+
+    /// This function checks the overrides against the settings they leave in place, e.g. a
+    /// longest delay shorter than the first delay of the settings.
+    pub fn validate(&self, settings: &Settings) -> Result<(), String> {
+        let mut overridden = settings.clone();
+        self.apply(&mut overridden);
+        if overridden.retry_max_delay_ms < overridden.retry_backoff_ms {
+            return Err(format!(
+                "The longest delay between retries ({} ms) must not be shorter than the first \
+                 one ({} ms)",
+                overridden.retry_max_delay_ms, overridden.retry_backoff_ms
+            ));
+        }
+        Ok(())
+    }
+
+    /// This function replaces the retry settings in `settings` with the ones overridden.
+    pub fn apply(&self, settings: &mut Settings) {
+        settings.chunk_retries = self.retries.unwrap_or(settings.chunk_retries);
+        settings.retry_backoff_ms = self.base_delay_ms.unwrap_or(settings.retry_backoff_ms);
+        settings.retry_max_delay_ms = self.max_delay_ms.unwrap_or(settings.retry_max_delay_ms);
+    }
+}
+
+/// This function returns the delay before retry number `attempt`, starting at 0. The delay doubles
+/// with every attempt and never exceeds `max_ms`.
+///
+/// # Example
+/// ```ignore
+/// assert_eq!(retry::backoff(0, 500, 30_000), Duration::from_millis(500));
+/// assert_eq!(retry::backoff(2, 500, 30_000), Duration::from_millis(2000));
+/// ```
+pub fn backoff(attempt: u32, base_ms: u64, max_ms: u64) -> Duration {
+    let factor = 1u64.checked_shl(attempt).unwrap_or(u64::MAX);
+    Duration::from_millis(base_ms.saturating_mul(factor).min(max_ms))
+}
+
+/// This function tells whether an HTTP error status is likely to go away on its own, e.g. a mirror
+/// answering 503 while it is busy. A chunk that gets one is retried instead of failed.
+pub fn is_transient_status(status: u16) -> bool {
+    matches!(status, 500 | 502 | 503 | 504)
+}
+
+/// This function tells whether an HTTP error status means that the server limits the requests it
+/// is sent. Servers usually say for how long in `Retry-After`.
+pub fn is_rate_limited(status: u16) -> bool {
+    matches!(status, 429 | 503)
+}
+
+/// This function returns how long a server asked to be left alone in its `Retry-After` header,
+/// never more than `MAX_RETRY_AFTER`.
+///
+/// # Arguments
+/// - `value`: The header, a number of seconds or an HTTP date, e.g.
+///   `Wed, 21 Oct 2015 07:28:00 GMT`.
+/// - `now`: The current time in seconds since the epoch, a date is counted from it.
+pub fn retry_after(value: &str, now: u64) -> Option<Duration> {
+    let value = value.trim();
+    let secs = match value.parse::<u64>() {
+        Ok(secs) => secs,
+        Err(_) => {
+            let at = chrono::DateTime::parse_from_rfc2822(value).ok()?.timestamp();
+            u64::try_from(at).ok()?.saturating_sub(now)
+        }
+    };
+    Some(Duration::from_secs(secs).min(MAX_RETRY_AFTER))
+}
+
+/// This struct represents a break from sending requests that a server asked a download for. Each
+/// chunk waits for it before it sends its next request, so the server is not asked again by the
+/// other chunks while one of them is told to wait.
+#[derive(Debug, Default)]
+pub struct Cooldown {
+    until: Mutex<Option<Instant>>,
+}
+
+impl Cooldown {
+    /// This function makes the break last at least `wait` from now.
+    ///
+    /// # Returns
+    /// Whether the break was made longer.
+    pub fn extend(&self, wait: Duration) -> bool {
+        let at = Instant::now() + wait;
+        let mut until = self.until.lock().unwrap_or_else(PoisonError::into_inner);
+        if until.is_some_and(|u| u >= at) {
+            return false;
+        }
+        *until = Some(at);
+        true
+    }
+
+    /// This function returns how much of the break is left, zero when there is none.
+    pub fn remaining(&self) -> Duration {
+        let until = self.until.lock().unwrap_or_else(PoisonError::into_inner);
+        until.map_or(Duration::ZERO, |u| u.saturating_duration_since(Instant::now()))
+    }
+}
+
+/// This function tells whether a chunk status update ends an attempt to download the chunk and is
+/// counted in the `attempts` of the chunk. A chunk paused before it started was not attempted.
+pub fn counts_as_attempt(status: &str) -> bool {
+    status != "Cancelled"
+}
+
+/// This function tells whether a chunk has been attempted too often to be tried again.
+///
+/// # Arguments
+/// - `attempts`: The attempts saved for the chunk.
+/// - `max_attempts`: The attempts allowed, 0 for no limit.
+pub fn gave_up(attempts: u32, max_attempts: u32) -> bool {
+    max_attempts > 0 && attempts >= max_attempts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gave_up() {
+        assert!(!gave_up(100, 0));
+        assert!(!gave_up(4, 5));
+        assert!(gave_up(5, 5));
+    }
+
+    #[test]
+    fn test_transient_status() {
+        assert!(is_transient_status(503));
+        assert!(is_transient_status(502));
+        assert!(!is_transient_status(501), "not implemented does not go away");
+        assert!(!is_transient_status(404));
+        assert!(is_rate_limited(429));
+        assert!(!is_rate_limited(500));
+    }
+
+    #[test]
+    fn test_retry_after() {
+        // Wed, 21 Oct 2015 07:28:00 GMT
+        let now = 1_445_412_480 - 120;
+        assert_eq!(retry_after(" 5", now), Some(Duration::from_secs(5)));
+        assert_eq!(retry_after("86400", now), Some(MAX_RETRY_AFTER));
+        let date = retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now);
+        assert_eq!(date, Some(Duration::from_secs(120)));
+        let past = retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now + 600);
+        assert_eq!(past, Some(Duration::ZERO));
+        assert_eq!(retry_after("soon", now), None);
+
+        let cooldown = Cooldown::default();
+        assert_eq!(cooldown.remaining(), Duration::ZERO);
+        assert!(cooldown.extend(Duration::from_secs(60)));
+        assert!(!cooldown.extend(Duration::from_secs(1)), "a shorter break is kept");
+        assert!(cooldown.remaining() > Duration::from_secs(59));
+    }
+
+    #[test]
+    fn test_backoff_doubles() {
+        assert_eq!(backoff(0, 500, 30_000), Duration::from_millis(500));
+        assert_eq!(backoff(1, 500, 30_000), Duration::from_millis(1000));
+        assert_eq!(backoff(3, 500, 30_000), Duration::from_millis(4000));
+        assert_eq!(backoff(0, 0, 30_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        assert_eq!(backoff(10, 500, 30_000), Duration::from_secs(30));
+        assert_eq!(backoff(200, u64::MAX, 30_000), Duration::from_secs(30));
+        assert_eq!(backoff(2, 500, 1_000), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_overrides() {
+        let mut settings = Settings::default();
+        let overrides = RetryOverrides {
+            retries: Some(10),
+            max_delay_ms: Some(120_000),
+            ..RetryOverrides::default()
+        };
+        overrides.apply(&mut settings);
+        assert_eq!(settings.chunk_retries, 10);
+        assert_eq!(settings.retry_backoff_ms, DEFAULT_BACKOFF_MS);
+        assert_eq!(settings.retry_max_delay_ms, 120_000);
+        assert!(!overrides.is_empty());
+        assert!(RetryOverrides::default().is_empty());
+
+        let settings = Settings::default();
+        assert!(overrides.validate(&settings).is_ok());
+        let shorter = RetryOverrides {
+            max_delay_ms: Some(DEFAULT_BACKOFF_MS - 1),
+            ..RetryOverrides::default()
+        };
+        assert!(shorter.validate(&settings).is_err());
+        let longer = RetryOverrides {
+            base_delay_ms: Some(DEFAULT_MAX_BACKOFF_MS + 1),
+            ..RetryOverrides::default()
+        };
+        assert!(longer.validate(&settings).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+//! This module handles downloads scheduled to start at a later time, optionally repeating at a
+//! fixed interval. Scheduled jobs are stored in the database and started by a background loop.
+//! The schedule can be exported as an iCalendar file so that upcoming downloads show up in
+//! calendar applications.
+
+use serde::{Deserialize, Serialize};
+
+/// How often the scheduler checks for jobs that are due.
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// This struct represents a download scheduled to start at `start_at`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct ScheduledJob {
+    pub id: i64,
+    pub file_url: String,
+    pub file_name: Option<String>,
+    pub destination_dir: Option<String>,
+    /// When the download should start as a unix timestamp in seconds.
+    pub start_at: u64,
+    /// If set, the job is scheduled again this many seconds after it starts.
+    pub repeat_every: Option<u64>,
+}
+
+impl ScheduledJob {
+    /// This function checks whether the job should be started.
+    pub fn is_due(&self, now: u64) -> bool {
+        self.start_at <= now
+    }
+
+    /// This function returns when a recurring job should run next, skipping any runs missed while
+    /// the application was closed. One-off jobs return `None`.
+    pub fn next_run(&self, now: u64) -> Option<u64> {
+        let every = self.repeat_every.filter(|e| *e > 0)?;
+        if self.start_at > now {
+            return Some(self.start_at);
+        }
+        let missed = (now - self.start_at) / every + 1;
+        Some(self.start_at + missed * every)
+    }
+}
+
+/// This function exports the scheduled jobs as an iCalendar (RFC 5545) document.
+///
+/// # Arguments
+/// - `jobs`: The scheduled jobs.
+/// - `now`: The current unix timestamp, used as the `DTSTAMP` of every event.
+///
+/// # Returns
+/// - `String`: The calendar. Every job is an event and recurring jobs have a `RRULE`.
+pub fn to_ics(jobs: &[ScheduledJob], now: u64) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        "PRODID:-//YAD//Scheduled downloads//EN".to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+    ];
+    for job in jobs {
+        let name = job
+            .file_name
+            .clone()
+            .unwrap_or_else(|| job.file_url.rsplit('/').next().unwrap_or("").to_string());
+        lines.push("BEGIN:VEVENT".to_string());
+        lines.push(format!("UID:yad-job-{}@yad", job.id));
+        lines.push(format!("DTSTAMP:{}", format_utc(now)));
+        lines.push(format!("DTSTART:{}", format_utc(job.start_at)));
+        lines.push(format!("SUMMARY:{}", escape_text(&format!("Download {name}"))));
+        lines.push(format!("DESCRIPTION:{}", escape_text(&job.file_url)));
+        if let Some(rule) = job.repeat_every.and_then(recurrence_rule) {
+            lines.push(format!("RRULE:{rule}"));
+        }
+        lines.push("END:VEVENT".to_string());
+    }
+    lines.push("END:VCALENDAR".to_string());
+
+    let mut ics = String::new();
+    for line in lines {
+        ics.push_str(&fold_line(&line));
+        ics.push_str("\r\n");
+    }
+    ics
+}
+
+/// This function converts an interval in seconds to the coarsest `RRULE` that represents it.
+fn recurrence_rule(every: u64) -> Option<String> {
+    const UNITS: [(u64, &str); 4] = [
+        (7 * 24 * 3600, "WEEKLY"),
+        (24 * 3600, "DAILY"),
+        (3600, "HOURLY"),
+        (60, "MINUTELY"),
+    ];
+    UNITS
+        .iter()
+        .find(|(secs, _)| every >= *secs && every.is_multiple_of(*secs))
+        .map(|(secs, freq)| format!("FREQ={freq};INTERVAL={}", every / secs))
+}
+
+/// This function formats a unix timestamp as an iCalendar UTC date-time e.g. `20240131T235959Z`.
+fn format_utc(ts: u64) -> String {
+    let days = (ts / 86400) as i64;
+    let secs = ts % 86400;
+
+    // civil date from days since the epoch, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        secs / 3600,
+        (secs % 3600) / 60,
+        secs % 60
+    )
+}
+
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// Lines longer than 75 octets are folded onto continuation lines starting with a space.
+fn fold_line(line: &str) -> String {
+    let mut folded = String::new();
+    let mut len = 0;
+    for c in line.chars() {
+        if len + c.len_utf8() > 75 {
+            folded.push_str("\r\n ");
+            len = 1;
+        }
+        folded.push(c);
+        len += c.len_utf8();
+    }
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(id: i64, start_at: u64, repeat_every: Option<u64>) -> ScheduledJob {
+        ScheduledJob {
+            id,
+            file_url: "https://example.com/big.iso".into(),
+            start_at,
+            repeat_every,
+            ..ScheduledJob::default()
+        }
+    }
+
+    #[test]
+    fn test_format_utc() {
+        assert_eq!(format_utc(0), "19700101T000000Z");
+        assert_eq!(format_utc(951_782_400), "20000229T000000Z");
+        assert_eq!(format_utc(1_706_745_599), "20240131T235959Z");
+    }
+
+    #[test]
+    fn test_next_run_skips_missed_runs() {
+        let j = job(1, 1000, Some(100));
+        assert_eq!(j.next_run(500), Some(1000));
+        assert_eq!(j.next_run(1000), Some(1100));
+        assert_eq!(j.next_run(1350), Some(1400));
+        assert_eq!(job(2, 1000, None).next_run(1000), None);
+    }
+
+    #[test]
+    fn test_recurrence_rule() {
+        assert_eq!(recurrence_rule(86400).unwrap(), "FREQ=DAILY;INTERVAL=1");
+        assert_eq!(recurrence_rule(2 * 7 * 86400).unwrap(), "FREQ=WEEKLY;INTERVAL=2");
+        assert_eq!(recurrence_rule(5400).unwrap(), "FREQ=MINUTELY;INTERVAL=90");
+        assert!(recurrence_rule(45).is_none());
+    }
+
+    #[test]
+    fn test_to_ics() {
+        let jobs = vec![job(1, 0, None), job(2, 86400, Some(86400))];
+        let ics = to_ics(&jobs, 0);
+        assert!(ics.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ics.ends_with("END:VCALENDAR\r\n"));
+        assert_eq!(ics.matches("BEGIN:VEVENT").count(), 2);
+        assert!(ics.contains("UID:yad-job-2@yad\r\n"));
+        assert!(ics.contains("DTSTART:19700102T000000Z\r\n"));
+        assert!(ics.contains("RRULE:FREQ=DAILY;INTERVAL=1\r\n"));
+        assert!(ics.contains("SUMMARY:Download big.iso\r\n"));
+    }
+
+    #[test]
+    fn test_long_lines_are_folded() {
+        let line = "DESCRIPTION:".to_string() + &"a".repeat(100);
+        let folded = fold_line(&line);
+        let parts: Vec<&str> = folded.split("\r\n").collect();
+        assert_eq!(parts.len(), 2);
+        assert_eq!(parts[0].len(), 75);
+        assert!(parts[1].starts_with(' '));
+    }
+}
@@ -0,0 +1,556 @@
+//! This module holds the settings the user can change while the application is running.
+//! Unlike `Config`, which is derived from the operating system, settings are persisted in the
+//! database and every change is broadcast to the downloads that are already running so that new
+//! limits take effect without restarting the application.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    sync::{Mutex, OnceLock},
+};
+
+use reqwest::{redirect::Policy, Client, ClientBuilder};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    allocation::Allocation,
+    bandwidth::{self, BandwidthRule},
+    binding, chunks,
+    config::Config,
+    file_manager,
+    hashing::HashAlgorithm,
+    idle,
+    pins::CertPin,
+    post_processing::PostProcessing,
+    presets::{self, HostPreset},
+    proxy,
+    push::PushTarget,
+    queue, redirects, retry, s3,
+    simulation::Simulation,
+    ssh, storage,
+    subtitles::SubtitleProvider,
+    throttle, trust,
+    units::{UnitSystem, Units},
+    watchdog,
+};
+
+/// The number of chunks of a single file downloaded at the same time when nothing is configured.
+pub const DEFAULT_MAX_CONCURRENT_CHUNKS: usize = 4;
+
+/// How many seconds a deleted record can be restored when nothing is configured.
+pub const DEFAULT_UNDO_WINDOW_SECS: u64 = 30;
+
+/// This struct represents the user adjustable settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// How many chunks of a single file are downloaded at the same time.
+    pub max_concurrent_chunks: usize,
+    /// Files up to this size in bytes are downloaded over a single connection instead of in
+    /// chunks. 0 means every file is split.
+    pub single_stream_max_size: u64,
+    /// Files that one connection to their host downloads within this many seconds, going by the
+    /// speed measured before, are downloaded over a single connection. 0 turns this off.
+    pub single_stream_max_secs: u64,
+    /// How many downloads run at the same time, the others wait in the queue. 0 means unlimited.
+    pub max_concurrent_downloads: usize,
+    /// The maximum speed of each download in bytes per second. 0 means unlimited.
+    pub max_speed: u64,
+    /// The maximum speed of all downloads together in bytes per second. 0 means unlimited.
+    pub max_total_speed: u64,
+    /// Total speed limits by the time of day, used instead of `max_total_speed` while they
+    /// apply. The first rule that applies wins.
+    pub bandwidth_rules: Vec<BandwidthRule>,
+    /// An optional proxy url e.g. `http://127.0.0.1:8080` or `socks5://127.0.0.1:1080`. It
+    /// overrides the proxy of the operating system.
+    pub proxy: Option<String>,
+    /// Whether the proxy of the operating system is used when no proxy is set, see `proxy`.
+    pub use_system_proxy: bool,
+    /// How many seconds without input make the user count as away, which raises the limits to
+    /// the ones below, see `idle`. 0 turns this off.
+    pub idle_after_secs: u64,
+    /// How many downloads run at the same time while the user is away. 0 means unlimited.
+    pub idle_max_concurrent_downloads: usize,
+    /// The maximum speed of all downloads together while the user is away. 0 means unlimited.
+    pub idle_max_total_speed: u64,
+    /// The network interface, e.g. `eth0` or `tun0`, or the local address downloads are sent
+    /// from. The operating system picks the route when not set, see `binding`.
+    pub bind_to: Option<String>,
+    /// The number of records kept in the history, the oldest finished downloads beyond it are
+    /// archived, see `history`. 0 means unlimited.
+    pub max_history_records: usize,
+    /// How many seconds a deleted record can be restored before it is purged, see
+    /// `engine::delete`.
+    pub undo_window_secs: u64,
+    /// Whether the downloads interrupted when the application last closed are resumed on start.
+    pub resume_on_start: bool,
+    /// Whether the computer is woken from sleep for scheduled downloads, see `power`.
+    pub wake_for_scheduled: bool,
+    /// Whether the computer is put back to sleep once the scheduled downloads it was woken for
+    /// are done.
+    pub sleep_after_scheduled: bool,
+    /// Folders scanned for dropped url lists and metalink files.
+    pub watch_folders: Vec<String>,
+    /// Whether files picked up from a watch folder are moved into a `processed` sub folder
+    /// instead of being deleted.
+    pub archive_watched_files: bool,
+    /// Headers sent to hosts that refuse hotlinked downloads.
+    pub host_presets: Vec<HostPreset>,
+    /// The maximum number of redirects followed before a download fails.
+    pub max_redirects: usize,
+    /// Downloads bigger than this many bytes are only started once the user confirms them, see
+    /// `decisions`. 0 never asks.
+    pub confirm_size_above: u64,
+    /// How the space of a file is reserved before its chunks are written, see `allocation`.
+    pub allocation: Allocation,
+    /// Files at least this many bytes big are mapped into memory instead of being written, see
+    /// `file_writer`. Only files whose space was reserved are, so it needs `allocation` to be
+    /// `Fallocate` or `ZeroFill`. 0 never maps them.
+    pub mmap_min_size: u64,
+    /// Whether sizes are shown in SI units, e.g. MB, or IEC units, e.g. MiB, see `units`.
+    pub size_units: UnitSystem,
+    /// Whether speeds are shown in bits per second, the way internet plans are sold.
+    pub speed_in_bits: bool,
+    /// Whether non-ASCII characters in file names are converted to ASCII.
+    pub transliterate_file_names: bool,
+    /// Whether the query strings and credentials are removed from the urls of finished downloads,
+    /// so that signed urls do not stay in the history.
+    pub strip_finished_urls: bool,
+    /// Downloads at least this big are spot checked while they run, see `integrity`. 0, the
+    /// default, disables spot checks.
+    pub spot_check_min_size: u64,
+    /// The algorithm finished downloads are hashed with, the checksum is stored on the record.
+    /// `None` only hashes downloads with an expected checksum, see `criteria`.
+    pub checksum_algorithm: Option<HashAlgorithm>,
+    /// Whether the chunks of a download from a server speaking HTTP/2 share a single multiplexed
+    /// connection instead of opening one each.
+    pub http2_multiplexing: bool,
+    /// Hosts whose certificates must match a known fingerprint.
+    pub cert_pins: Vec<CertPin>,
+    /// PEM files of certificate authorities trusted besides the ones of the system, for HTTPS and
+    /// FTPS, e.g. the one of a company signing the certificates of its internal servers.
+    pub ca_certificates: Vec<String>,
+    /// The private key SFTP downloads log in with, the default keys of OpenSSH in `~/.ssh` when
+    /// not set.
+    pub ssh_key: Option<String>,
+    /// The passphrase of the private key, if it has one.
+    pub ssh_key_passphrase: Option<String>,
+    /// The account `s3://` urls are downloaded with, see `s3`.
+    pub s3: s3::S3Settings,
+    /// How many times a failed chunk is retried before it is marked as failed.
+    pub chunk_retries: u32,
+    /// The delay before the first retry in milliseconds, doubled for every further retry.
+    pub retry_backoff_ms: u64,
+    /// The longest delay between two retries in milliseconds. A server asking for a longer break
+    /// in `Retry-After` is still given it, see `retry::Cooldown`.
+    pub retry_max_delay_ms: u64,
+    /// How many times a chunk is attempted over all runs of a download before it is given up and
+    /// left failed. 0 means chunks are never given up.
+    pub max_chunk_attempts: u32,
+    /// How many seconds a running download may go without any chunk starting an attempt or
+    /// writing before it is restarted, see `watchdog`. 0 turns the watchdog off.
+    pub stuck_after_secs: u64,
+    /// The program used to show folders, e.g. `nemo --no-desktop`. The default file manager of
+    /// the system is used when not set.
+    pub file_manager: Option<String>,
+    /// The file types downloads are put in by lowercase extension, e.g. `apk` to `Others`,
+    /// learned when the user changes the category of a download, see `files::learn_category`.
+    pub category_rules: BTreeMap<String, String>,
+    /// The steps run after a download finishes, by file type e.g. `Videos`.
+    pub post_processing: BTreeMap<String, PostProcessing>,
+    /// The services subtitles of finished videos are looked up from, in order.
+    pub subtitle_providers: Vec<SubtitleProvider>,
+    /// The folder audio is filed into when the post processing imports music.
+    pub music_library: Option<String>,
+    /// Simulated network conditions for testing, see `simulation`. Not set in normal use.
+    pub simulation: Option<Simulation>,
+    /// The local port metrics are served on for monitoring, see `metrics`. Off when not set, and
+    /// applied when YAD starts.
+    pub metrics_port: Option<u16>,
+    /// The push services notifications about downloads are sent to, see `push`.
+    pub push_targets: Vec<PushTarget>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            max_concurrent_chunks: DEFAULT_MAX_CONCURRENT_CHUNKS,
+            single_stream_max_size: chunks::DEFAULT_SINGLE_STREAM_SIZE,
+            single_stream_max_secs: chunks::DEFAULT_SINGLE_STREAM_SECS,
+            max_concurrent_downloads: queue::DEFAULT_MAX_CONCURRENT_DOWNLOADS,
+            max_speed: 0,
+            max_total_speed: 0,
+            bandwidth_rules: Vec::new(),
+            proxy: None,
+            use_system_proxy: true,
+            idle_after_secs: 0,
+            idle_max_concurrent_downloads: 0,
+            idle_max_total_speed: 0,
+            bind_to: None,
+            max_history_records: 0,
+            undo_window_secs: DEFAULT_UNDO_WINDOW_SECS,
+            resume_on_start: true,
+            wake_for_scheduled: false,
+            sleep_after_scheduled: false,
+            watch_folders: Vec::new(),
+            archive_watched_files: true,
+            host_presets: presets::default_presets(),
+            max_redirects: redirects::DEFAULT_MAX_REDIRECTS,
+            confirm_size_above: 0,
+            allocation: Allocation::default(),
+            mmap_min_size: 0,
+            size_units: UnitSystem::default(),
+            speed_in_bits: false,
+            transliterate_file_names: false,
+            strip_finished_urls: false,
+            spot_check_min_size: 0,
+            checksum_algorithm: None,
+            http2_multiplexing: true,
+            cert_pins: Vec::new(),
+            ca_certificates: Vec::new(),
+            ssh_key: None,
+            ssh_key_passphrase: None,
+            s3: s3::S3Settings::default(),
+            chunk_retries: retry::DEFAULT_CHUNK_RETRIES,
+            retry_backoff_ms: retry::DEFAULT_BACKOFF_MS,
+            retry_max_delay_ms: retry::DEFAULT_MAX_BACKOFF_MS,
+            max_chunk_attempts: retry::DEFAULT_MAX_CHUNK_ATTEMPTS,
+            stuck_after_secs: watchdog::DEFAULT_STUCK_AFTER_SECS,
+            file_manager: None,
+            category_rules: BTreeMap::new(),
+            post_processing: BTreeMap::new(),
+            subtitle_providers: Vec::new(),
+            music_library: None,
+            simulation: None,
+            metrics_port: None,
+            push_targets: Vec::new(),
+        }
+    }
+}
+
+impl Settings {
+    /// This function checks that the settings can be applied.
+    ///
+    /// # Returns
+    /// - `Ok(())`: if the settings are valid.
+    /// - `Err(String)`: a message describing the invalid setting.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_concurrent_chunks == 0 {
+            return Err("max_concurrent_chunks must be at least 1".into());
+        }
+        if self.retry_max_delay_ms < self.retry_backoff_ms {
+            return Err("retry_max_delay_ms must not be lower than retry_backoff_ms".into());
+        }
+        if let Some(proxy) = &self.proxy {
+            reqwest::Proxy::all(proxy).map_err(|e| format!("Invalid proxy: {e}"))?;
+        }
+        for rule in &self.bandwidth_rules {
+            rule.validate()?;
+        }
+        if let Some(bind_to) = self.bind_to.as_deref().filter(|b| !b.trim().is_empty()) {
+            binding::parse(bind_to)?;
+        }
+        if let Some(p) = self.host_presets.iter().find(|p| p.host.trim().is_empty()) {
+            return Err(format!("Host preset {} has no host", p.name));
+        }
+        for pin in &self.cert_pins {
+            pin.validate()?;
+        }
+        trust::read_authorities(&self.ca_certificates)?;
+        if let Some(key) = self.ssh_key.as_deref().filter(|k| !k.trim().is_empty()) {
+            ssh::check_private_key(std::path::Path::new(key))?;
+        }
+        self.s3.validate()?;
+        if let Some(fm) = &self.file_manager {
+            if file_manager::parse_command(fm).is_none() {
+                return Err("The file manager is empty".into());
+            }
+        }
+        for provider in &self.subtitle_providers {
+            provider.validate()?;
+        }
+        for target in &self.push_targets {
+            target.validate()?;
+        }
+        let imports_music = self.post_processing.values().any(|p| p.import_music);
+        if imports_music && self.music_library.as_deref().unwrap_or("").trim().is_empty() {
+            return Err("Importing music needs a music library folder".into());
+        }
+        if let Some(simulation) = &self.simulation {
+            simulation.validate()?;
+        }
+        for folder in &self.watch_folders {
+            if !std::path::Path::new(folder).is_dir() {
+                return Err(format!("Watch folder {folder} is not a directory"));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The single source of truth for the current settings. Downloads subscribe to it to pick up
+/// changes while they are running.
+fn store() -> &'static watch::Sender<Settings> {
+    static STORE: OnceLock<watch::Sender<Settings>> = OnceLock::new();
+    STORE.get_or_init(|| {
+        let cfg = Config::default();
+        let settings = storage::read_settings(&cfg).unwrap_or_else(|e| {
+            eprintln!("failed to read settings because {e}, using defaults");
+            Settings::default()
+        });
+        throttle::global().set_rate(bandwidth::current_limit(&settings));
+        queue::global().set_limit(idle::concurrent_downloads(&settings, idle::is_idle()));
+        watch::channel(settings).0
+    })
+}
+
+/// This function returns a copy of the current settings.
+pub fn current() -> Settings {
+    store().borrow().clone()
+}
+
+/// This function returns the units sizes and speeds are shown in.
+pub fn units() -> Units {
+    let settings = store().borrow();
+    Units {
+        system: settings.size_units,
+        speed_in_bits: settings.speed_in_bits,
+    }
+}
+
+/// This function returns a receiver which is notified every time the settings change.
+pub fn subscribe() -> watch::Receiver<Settings> {
+    store().subscribe()
+}
+
+/// This function validates and persists new settings, then broadcasts them to all running
+/// downloads.
+///
+/// # Arguments
+/// - `settings`: The new settings.
+/// - `cfg`: An instance of `Config`.
+///
+/// # Example
+/// ```ignore
+/// let cfg = config::Config::default();
+/// let mut s = settings::current();
+/// s.max_speed = 512 * 1024;
+/// settings::update(s, &cfg)?;
+/// ```
+pub fn update(settings: Settings, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    settings.validate()?;
+    storage::save_settings(&settings, cfg)?;
+    let total_limit = bandwidth::current_limit(&settings);
+    if total_limit != throttle::global().rate() {
+        throttle::global().set_rate(total_limit);
+    }
+    let max_downloads = idle::concurrent_downloads(&settings, idle::is_idle());
+    if max_downloads != queue::global().limit() {
+        queue::global().set_limit(max_downloads);
+    }
+    // clients of the old network settings are dropped once the downloads using them end
+    clients().lock().unwrap().clear();
+    store().send_replace(settings);
+    Ok(())
+}
+
+fn client_builder(settings: &Settings) -> Result<ClientBuilder, String> {
+    let cfg = Config::default();
+    let builder = Client::builder()
+        .pool_max_idle_per_host(cfg.pool_max_idle_per_host)
+        .pool_idle_timeout(cfg.pool_idle_timeout)
+        .tcp_keepalive(cfg.tcp_keepalive)
+        .tcp_nodelay(cfg.tcp_nodelay)
+        .connect_timeout(cfg.connect_timeout)
+        .read_timeout(cfg.read_timeout);
+    let builder = proxy::apply(
+        builder,
+        settings.proxy.as_deref(),
+        settings.use_system_proxy,
+        &cfg.os,
+    )?;
+    let builder = trust::apply(builder, settings)?;
+    binding::apply(builder, settings.bind_to.as_deref())
+}
+
+/// This function builds the http client used for downloads from the settings.
+pub fn build_client(settings: &Settings) -> Result<Client, String> {
+    client_builder(settings)?
+        .redirect(redirects::policy(settings.max_redirects))
+        .build()
+        .map_err(|e| format!("Failed to build http client: {e}"))
+}
+
+/// This function builds a client that only speaks HTTP/2, for servers known to speak it. All
+/// requests to a host are multiplexed over a single connection, which saves a handshake per
+/// chunk and keeps clear of servers limiting the connections of each address. A client that may
+/// also speak HTTP/1.1 opens a connection for every request started before the first one has
+/// found out the server speaks HTTP/2.
+pub fn build_http2_client(settings: &Settings) -> Result<Client, String> {
+    client_builder(settings)?
+        .redirect(redirects::policy(settings.max_redirects))
+        .http2_prior_knowledge()
+        .http2_adaptive_window(true)
+        .build()
+        .map_err(|e| format!("Failed to build http client: {e}"))
+}
+
+/// This function builds a client that does not follow redirects, used to record every redirect
+/// with `redirects::probe`.
+pub fn build_probe_client(settings: &Settings) -> Result<Client, String> {
+    client_builder(settings)?
+        .redirect(Policy::none())
+        .build()
+        .map_err(|e| format!("Failed to build http client: {e}"))
+}
+
+/// This struct represents the settings a client is built from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ClientKey {
+    proxy: Option<String>,
+    use_system_proxy: bool,
+    bind_to: Option<String>,
+    cert_pins: Vec<CertPin>,
+    ca_certificates: Vec<String>,
+    /// The redirects followed, `None` for a client that does not follow them.
+    max_redirects: Option<usize>,
+    http2_only: bool,
+}
+
+impl ClientKey {
+    fn new(settings: &Settings, follow_redirects: bool, http2_only: bool) -> Self {
+        ClientKey {
+            proxy: settings.proxy.clone(),
+            use_system_proxy: settings.use_system_proxy,
+            bind_to: settings.bind_to.clone(),
+            cert_pins: settings.cert_pins.clone(),
+            ca_certificates: settings.ca_certificates.clone(),
+            max_redirects: follow_redirects.then_some(settings.max_redirects),
+            http2_only,
+        }
+    }
+}
+
+/// The clients shared by all downloads. Cloning a client shares its connection pool.
+fn clients() -> &'static Mutex<HashMap<ClientKey, Client>> {
+    static CLIENTS: OnceLock<Mutex<HashMap<ClientKey, Client>>> = OnceLock::new();
+    CLIENTS.get_or_init(Mutex::default)
+}
+
+fn shared(settings: &Settings, follow_redirects: bool, http2_only: bool) -> Result<Client, String> {
+    let key = ClientKey::new(settings, follow_redirects, http2_only);
+    let mut clients = clients().lock().unwrap();
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+    let client = match (follow_redirects, http2_only) {
+        (true, true) => build_http2_client(settings)?,
+        (true, false) => build_client(settings)?,
+        (false, _) => build_probe_client(settings)?,
+    };
+    clients.insert(key, client.clone());
+    Ok(client)
+}
+
+/// This function returns the http client used for downloads, shared with every other download
+/// of the same settings so that connections to a host are reused, see `build_client`.
+pub fn shared_client(settings: &Settings) -> Result<Client, String> {
+    shared(settings, true, false)
+}
+
+/// This function returns the shared client for servers known to speak HTTP/2, see
+/// `build_http2_client`.
+pub fn shared_http2_client(settings: &Settings) -> Result<Client, String> {
+    shared(settings, true, true)
+}
+
+/// This function returns the shared client that does not follow redirects, see
+/// `build_probe_client`.
+pub fn shared_probe_client(settings: &Settings) -> Result<Client, String> {
+    shared(settings, false, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_settings_are_valid() {
+        let s = Settings::default();
+        assert_eq!(s.max_concurrent_chunks, DEFAULT_MAX_CONCURRENT_CHUNKS);
+        assert_eq!(s.max_speed, 0);
+        assert!(s.validate().is_ok());
+    }
+
+    #[test]
+    fn test_zero_concurrency_is_invalid() {
+        let s = Settings {
+            max_concurrent_chunks: 0,
+            ..Settings::default()
+        };
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn test_invalid_proxy_is_rejected() {
+        let s = Settings {
+            proxy: Some("not a proxy".into()),
+            ..Settings::default()
+        };
+        assert!(s.validate().is_err());
+        assert!(build_client(&s).is_err());
+        assert!(shared_client(&s).is_err());
+    }
+
+    #[test]
+    fn test_clients_are_shared() {
+        let s = Settings {
+            proxy: Some("http://127.0.0.1:3128".into()),
+            ..Settings::default()
+        };
+        shared_client(&s).unwrap();
+        shared_client(&s).unwrap();
+        shared_probe_client(&s).unwrap();
+        shared_http2_client(&s).unwrap();
+        let clients = clients().lock().unwrap();
+        let built = clients.keys().filter(|k| k.proxy == s.proxy).count();
+        assert_eq!(built, 3);
+    }
+
+    #[test]
+    fn test_invalid_cert_pin_is_rejected() {
+        let s = Settings {
+            cert_pins: vec![CertPin {
+                host: "artifacts.example.com".into(),
+                fingerprints: vec!["not a fingerprint".into()],
+            }],
+            ..Settings::default()
+        };
+        assert!(s.validate().is_err());
+    }
+
+    #[test]
+    fn test_music_import_needs_a_library() {
+        let mut s = Settings::default();
+        s.post_processing.insert(
+            "Audio".into(),
+            PostProcessing {
+                import_music: true,
+                ..PostProcessing::default()
+            },
+        );
+        assert!(s.validate().is_err());
+        s.music_library = Some("/home/user/Music".into());
+        assert!(s.validate().is_ok());
+    }
+
+    #[test]
+    fn test_missing_fields_use_defaults() {
+        let s: Settings = serde_json::from_str(r#"{"max_speed": 1024}"#).unwrap();
+        assert_eq!(s.max_speed, 1024);
+        assert_eq!(s.max_concurrent_chunks, DEFAULT_MAX_CONCURRENT_CHUNKS);
+        assert!(s.proxy.is_none());
+        assert!(s.use_system_proxy);
+    }
+}
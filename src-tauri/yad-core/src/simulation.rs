@@ -0,0 +1,109 @@
+//! This module simulates bad networks for testing. With `Settings::simulation` set, every chunk
+//! request is delayed, downloads are capped to a bandwidth and chunk attempts fail at a given
+//! rate, so that resume, retry and stall handling can be tried out without a flaky server.
+//! Failures are picked from a seed, so the same seed fails the same chunk attempts every time.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::settings::Settings;
+
+/// This struct represents the simulated network conditions. It is a developer option and is not
+/// set in normal use.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Simulation {
+    /// Added before every chunk request, in milliseconds.
+    pub latency_ms: u64,
+    /// The speed of each download in bytes per second. 0 means no cap.
+    pub bandwidth: u64,
+    /// The percentage of chunk attempts that fail, from 0 to 100.
+    pub failure_rate: u8,
+    /// Picks which attempts fail.
+    pub seed: u64,
+}
+
+/// This function mixes the bits of `x`, see splitmix64.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+impl Simulation {
+    /// This function checks that the simulation can be applied.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.failure_rate > 100 {
+            return Err("The simulated failure rate must be between 0 and 100".into());
+        }
+        Ok(())
+    }
+
+    /// This function returns the delay added before a chunk request.
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency_ms)
+    }
+
+    /// This function decides whether an attempt of the chunk starting at `start` fails.
+    ///
+    /// # Arguments
+    /// - `record_id`: The download.
+    /// - `start`: The first byte of the chunk.
+    /// - `attempt`: 0 for the first attempt, 1 for the first retry and so on.
+    pub fn fails(&self, record_id: i64, start: u64, attempt: u32) -> bool {
+        if self.failure_rate == 0 {
+            return false;
+        }
+        let roll = mix(self.seed ^ mix(record_id as u64 ^ mix(start ^ mix(attempt as u64))));
+        roll % 100 < self.failure_rate as u64
+    }
+}
+
+/// This function returns the simulated bandwidth cap, 0 if there is none.
+pub fn bandwidth(settings: &Settings) -> u64 {
+    settings.simulation.as_ref().map_or(0, |s| s.bandwidth)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failures_follow_the_seed() {
+        let sim = Simulation {
+            failure_rate: 30,
+            seed: 7,
+            ..Simulation::default()
+        };
+        let first: Vec<bool> = (0..1000).map(|i| sim.fails(1, i * 1024, 0)).collect();
+        let again: Vec<bool> = (0..1000).map(|i| sim.fails(1, i * 1024, 0)).collect();
+        assert_eq!(first, again);
+
+        let failed = first.iter().filter(|f| **f).count();
+        assert!((200..400).contains(&failed), "{failed} of 1000 failed");
+
+        let other_seed = Simulation { seed: 8, ..sim };
+        let other: Vec<bool> = (0..1000)
+            .map(|i| other_seed.fails(1, i * 1024, 0))
+            .collect();
+        assert_ne!(first, other);
+    }
+
+    #[test]
+    fn test_failure_rate_bounds() {
+        let never = Simulation::default();
+        assert!((0..100).all(|i| !never.fails(1, i, 0)));
+        let always = Simulation {
+            failure_rate: 100,
+            ..Simulation::default()
+        };
+        assert!((0..100).all(|i| always.fails(1, i, 0)));
+        let invalid = Simulation {
+            failure_rate: 101,
+            ..Simulation::default()
+        };
+        assert!(invalid.validate().is_err());
+    }
+}
@@ -0,0 +1,2435 @@
+//! This module deals with data storage, retrieval and update in the database.
+use std::fs;
+use std::{
+    collections::HashMap,
+    error::Error,
+    path::{Path, PathBuf},
+    sync::{Mutex, OnceLock, PoisonError},
+    time::Duration,
+};
+
+use r2d2::PooledConnection;
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{
+    params, Connection, ErrorCode, OptionalExtension, Transaction, TransactionBehavior,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    chunks::ByteRange, config::Config, criteria::SuccessCriteria, file_manager::OpenWhenDone,
+    files::File,
+    redirects::RedirectHop, retry, scheduler::ScheduledJob, settings::Settings,
+    templates::Template,
+};
+
+/// This struct represents a download record as stored in the database and used in the frontend.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct DownloadRecord {
+    pub id: i64,
+    pub file_url: String,
+    pub file_name: String,
+    pub file_type: String,
+    pub extension: String,
+    pub destination_dir: String,
+    pub destination_path: String,
+    pub file_size: u64,
+    pub download_start_time: u64,
+    pub download_stop_time: Option<u64>,
+    pub download_status: String,
+    pub downloaded_percentage: f32,
+    /// The name of the host preset whose headers were sent, if any.
+    pub applied_preset: Option<String>,
+    /// The redirects followed when the download started.
+    pub redirect_chain: Vec<RedirectHop>,
+    /// The download this one belongs to, e.g. the video of a subtitle.
+    pub parent_id: Option<i64>,
+    /// The part of the file that was downloaded, when only a part of it was asked for.
+    pub byte_range: Option<ByteRange>,
+    /// The url the redirects ended at, which the parts of the file are requested from. `None` for
+    /// downloads saved before it was stored.
+    pub final_url: Option<String>,
+    /// The file name before it was sanitized, if sanitizing changed it.
+    pub original_file_name: Option<String>,
+    /// When the record was deleted, if it is waiting to be purged. Deleted records can be restored
+    /// until they are purged.
+    pub deleted_at: Option<u64>,
+    /// The size of the chunks the file is split into, see `chunks::chunk_size`. `None` for
+    /// downloads saved before it was chosen per file, which used `chunks::MIN_CHUNK_SIZE`.
+    pub chunk_size: Option<u64>,
+    /// Downloads with a higher priority leave the queue first, see `queue`.
+    pub priority: i64,
+    /// How to fix the download when it failed, see `diagnosis::suggest`. Cleared when it starts
+    /// again.
+    pub failure_note: Option<String>,
+    /// The checksum of the file as `algorithm:digest`, computed when the download finished, see
+    /// `hashing::Checksum`.
+    pub checksum: Option<String>,
+    /// The ETag or `Last-Modified` date of the file when the download started, see
+    /// `validators::validator`. A resume whose server sends another one starts over.
+    pub validator: Option<String>,
+    /// Whether the file of a finished download changed on the server since, see
+    /// `engine::check_for_update`.
+    pub outdated: bool,
+    /// What is opened once the download has finished and its file was verified.
+    pub open_when_done: Option<OpenWhenDone>,
+    /// Whether the file was already on disk and brought in by `organize::organize_existing`
+    /// rather than downloaded by YAD.
+    pub imported: bool,
+    /// The retry settings used for this download instead of the ones of the settings.
+    pub retry_overrides: Option<retry::RetryOverrides>,
+    /// The highest video quality, in pixels of height, of a download of a DASH stream, see
+    /// `dash::Manifest::pick`. The best quality when `None`.
+    pub video_height: Option<u32>,
+    /// How many samples of the file did not match the server, see `integrity`. The chunks they
+    /// were in were downloaded again.
+    pub spot_check_failures: u32,
+    /// The template the download was started with, see `engine::retry`.
+    pub template_id: Option<i64>,
+    /// The conditions the download was started with, e.g. its checksum.
+    pub criteria: Option<SuccessCriteria>,
+    /// The referer sent instead of the headers of the host presets.
+    pub referer: Option<String>,
+    /// The health score of a running download, see `health::score`. Not stored in the database.
+    pub health: Option<u8>,
+}
+
+impl From<File> for DownloadRecord {
+    fn from(f: File) -> Self {
+        DownloadRecord {
+            id: 0,
+            file_url: f.file_url,
+            file_name: f.file_name,
+            file_type: f.file_type.to_string(),
+            extension: f.extension,
+            destination_dir: f.destination_dir,
+            destination_path: f.destination_path,
+            file_size: f.file_size,
+            download_start_time: f.download_start_time,
+            download_stop_time: if f.download_stop_time == 0 { None } else { Some(f.download_stop_time) },
+            download_status: f.download_status.to_string(),
+            downloaded_percentage: 0.0,
+            applied_preset: None,
+            redirect_chain: Vec::new(),
+            final_url: None,
+            parent_id: None,
+            byte_range: None,
+            original_file_name: None,
+            deleted_at: None,
+            chunk_size: None,
+            priority: 0,
+            failure_note: None,
+            checksum: None,
+            validator: None,
+            outdated: false,
+            open_when_done: None,
+            imported: false,
+            retry_overrides: None,
+            video_height: None,
+            spot_check_failures: 0,
+            template_id: None,
+            criteria: None,
+            referer: None,
+            health: None,
+        }
+    }
+}
+
+/// This struct represents a chunk. One file will have 1 or more chunks depending on its size, see
+/// `chunks::chunk_size`.
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct Chunk {
+    pub id: i64,
+    pub record_id: i64,
+    pub start: u64,
+    pub end: u64,
+    pub status: String,
+    /// How many times the chunk was attempted over all runs of its download, see
+    /// `retry::counts_as_attempt`.
+    pub attempts: u32,
+}
+
+impl Chunk {
+    pub fn new(record_id: i64, start: u64, end: u64) -> Self {
+        let status = "Pending".to_string();
+        let id = 0;
+        Chunk {
+            id,
+            record_id,
+            start,
+            end,
+            status,
+            attempts: 0,
+        }
+    }
+}
+
+/// This struct represents the number of chunks depending on their status.
+/// There are three possible statuses for each chunk: Pending, Finished and Failed.
+/// A fully downloaded file will have all its chunks with the Finished status.
+/// In case any is Failed, then the download is failed and in future, we can restart that chunk
+/// along.
+/// In case there are pending chunks, they can be restarted later.
+#[derive(Debug)]
+struct ChunkCount {
+    count: i32,
+    status: String,
+}
+
+/// The columns selected for a `DownloadRecord`, in the order expected by `record_from_row`.
+const RECORD_COLUMNS: &str = r#"
+            id, file_url, file_name, file_type, extension,
+            destination_dir, destination_path, file_size,
+            download_start_time, download_stop_time,
+            download_status, applied_preset, redirect_chain,
+            original_file_name, deleted_at, chunk_size, final_url,
+            parent_id, byte_range, priority, failure_note, checksum,
+            validator, outdated, open_when_done, imported, retry_overrides,
+            video_height, spot_check_failures, template_id, criteria, referer"#;
+
+/// This function maps a row selected with `RECORD_COLUMNS` to a `DownloadRecord`.
+fn record_from_row(row: &rusqlite::Row) -> rusqlite::Result<DownloadRecord> {
+    let redirect_chain: Option<String> = row.get(12)?;
+    let byte_range: Option<String> = row.get(18)?;
+    let open_when_done: Option<String> = row.get(24)?;
+    let retry_overrides: Option<String> = row.get(26)?;
+    let criteria: Option<String> = row.get(30)?;
+    Ok(DownloadRecord {
+        id: row.get(0)?,
+        file_url: row.get(1)?,
+        file_name: row.get(2)?,
+        file_type: row.get(3)?,
+        extension: row.get(4)?,
+        destination_dir: row.get(5)?,
+        destination_path: row.get(6)?,
+        file_size: row.get(7)?,
+        download_start_time: row.get(8)?,
+        download_stop_time: row.get(9)?,
+        download_status: row.get(10)?,
+        downloaded_percentage: 0.0,
+        applied_preset: row.get(11)?,
+        redirect_chain: redirect_chain
+            .and_then(|c| serde_json::from_str(&c).ok())
+            .unwrap_or_default(),
+        original_file_name: row.get(13)?,
+        deleted_at: row.get(14)?,
+        chunk_size: row.get(15)?,
+        final_url: row.get(16)?,
+        parent_id: row.get(17)?,
+        byte_range: byte_range.and_then(|r| serde_json::from_str(&r).ok()),
+        priority: row.get(19)?,
+        failure_note: row.get(20)?,
+        checksum: row.get(21)?,
+        validator: row.get(22)?,
+        outdated: row.get(23)?,
+        open_when_done: open_when_done.as_deref().and_then(OpenWhenDone::parse),
+        imported: row.get(25)?,
+        retry_overrides: retry_overrides.and_then(|r| serde_json::from_str(&r).ok()),
+        video_height: row.get(27)?,
+        spot_check_failures: row.get(28)?,
+        template_id: row.get(29)?,
+        criteria: criteria.and_then(|c| serde_json::from_str(&c).ok()),
+        referer: row.get(31)?,
+        health: None,
+    })
+}
+
+/// This function adds a column to an existing table. Tables created by older versions of YAD do
+/// not have the columns added since, and `CREATE TABLE IF NOT EXISTS` does not add them.
+fn add_column_if_missing(
+    conn: &Connection,
+    table: &str,
+    column: &str,
+    definition: &str,
+) -> Result<(), Box<dyn Error>> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let exists = stmt
+        .query_map([], |row| row.get::<_, String>(1))?
+        .collect::<Result<Vec<_>, _>>()?
+        .iter()
+        .any(|c| c == column);
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {definition}"),
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+/// The most connections kept open to one database. Chunk workers write through `db_writer`, so
+/// only a few connections are in use at the same time.
+const POOL_SIZE: u32 = 8;
+
+/// How long a connection waits for another one to finish writing before it gives up.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How many times a write is tried again when the database stays locked past `BUSY_TIMEOUT`.
+const BUSY_RETRIES: u32 = 4;
+
+type Pool = r2d2::Pool<SqliteConnectionManager>;
+
+/// This function returns the pools of connections, one per database file. There is only one
+/// outside of tests, which each use a database of their own.
+fn pools() -> &'static Mutex<HashMap<PathBuf, Pool>> {
+    static POOLS: OnceLock<Mutex<HashMap<PathBuf, Pool>>> = OnceLock::new();
+    POOLS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// This function checks whether an error means that another connection holds the lock.
+fn is_busy(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
+/// This function runs a write, trying it again with a growing pause while the database is locked,
+/// e.g. by a long transaction of another connection.
+fn retry_busy<T>(mut write: impl FnMut() -> rusqlite::Result<T>) -> rusqlite::Result<T> {
+    let mut attempt = 0;
+    loop {
+        match write() {
+            Err(e) if is_busy(&e) && attempt < BUSY_RETRIES => {
+                attempt += 1;
+                std::thread::sleep(Duration::from_millis(50 << attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+/// This function begins a transaction that writes. It takes the write lock up front, as a
+/// transaction that reads first and writes later fails at once when another connection wrote in
+/// between, without waiting for `BUSY_TIMEOUT`.
+fn write_transaction(conn: &mut Connection) -> rusqlite::Result<Transaction<'_>> {
+    conn.transaction_with_behavior(TransactionBehavior::Immediate)
+}
+
+/// This function gets a db connection for use in all functions. Connections are taken from a pool
+/// and go back to it when dropped, so that commands and workers do not open the database on
+/// every call.
+///
+/// # Arguments
+/// - `cfg`: A `Config` instance.
+///
+/// # Returns
+/// This function returns a `Result` containing either:
+/// - `Ok(PooledConnection)`: The connection to the db, used like a `rusqlite::Connection`.
+/// - `Err(dyn std::error::Error)`: An error if any error occurs.
+///
+/// # Example
+/// ```ignore
+/// let cfg = config::Config::default();
+/// let conn = match get_db(&cfg)?;
+/// ```
+fn get_db(cfg: &Config) -> Result<PooledConnection<SqliteConnectionManager>, Box<dyn Error>> {
+    let db_path = Path::new(&cfg.config_dir).join("yad.db");
+    let pool = {
+        let mut pools = pools().lock().unwrap_or_else(PoisonError::into_inner);
+        match pools.get(&db_path) {
+            Some(pool) => pool.clone(),
+            None => {
+                fs::create_dir_all(&cfg.config_dir)?;
+                println!("db path: {}", db_path.display());
+                let manager = SqliteConnectionManager::file(&db_path).with_init(|conn| {
+                    // enable relationships in sqlite3
+                    conn.execute("PRAGMA foreign_keys = ON;", [])?;
+                    // readers do not block the writer and the other way round
+                    conn.pragma_update_and_check(None, "journal_mode", "WAL", |_| Ok(()))?;
+                    conn.pragma_update(None, "synchronous", "NORMAL")?;
+                    conn.busy_timeout(BUSY_TIMEOUT)
+                });
+                let pool = r2d2::Pool::builder()
+                    .max_size(POOL_SIZE)
+                    // connections are opened when needed rather than all up front
+                    .min_idle(Some(0))
+                    .build(manager)?;
+                pools.insert(db_path, pool.clone());
+                pool
+            }
+        }
+    };
+    Ok(pool.get()?)
+}
+
+/// This function creates the two tables and creates the relationships.
+/// Ideally, it should create the database tables ones. If this function fails, stop the
+/// application.
+///
+/// # Arguments
+/// - `cfg`: An instance of `Config`
+///
+/// # Returns
+/// This function returns a `Result` containing either:
+/// - `Ok(())`: An emptu tuple if everything is ok.
+/// - `Err(Box<dyn std::error::Error)`: An error in case it occurs.
+///
+/// # Example
+/// ```ignore
+/// let cfg = config::Config::default();
+/// match create_tables(&cfg) {
+///     Ok(()) => println!("tables created"),
+///     Err(e) => panic!("failed to create tables because {e}")
+/// };
+/// ```
+pub fn create_tables(cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS download_record (
+            id                  INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_url            TEXT NOT NULL UNIQUE,
+            file_name           TEXT NOT NULL,
+            file_type           TEXT NOT NULL,
+            extension           TEXT NOT NULL,
+            destination_dir     TEXT NOT NULL,
+            destination_path    TEXT NOT NULL UNIQUE,
+            file_size           INTEGER NULL,
+            download_start_time INTEGER NOT NULL,
+            download_stop_time  INTEGER NULL,
+            download_status     TEXT NOT NULL    
+        )"#;
+    conn.execute(sql, [])?;
+    add_column_if_missing(&conn, "download_record", "applied_preset", "TEXT NULL")?;
+    // json array of `RedirectHop`
+    add_column_if_missing(&conn, "download_record", "redirect_chain", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "original_file_name", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "deleted_at", "INTEGER NULL")?;
+    add_column_if_missing(&conn, "download_record", "chunk_size", "INTEGER NULL")?;
+    add_column_if_missing(&conn, "download_record", "final_url", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "parent_id", "INTEGER NULL")?;
+    // json `ByteRange`
+    add_column_if_missing(&conn, "download_record", "byte_range", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "priority", "INTEGER NOT NULL DEFAULT 0")?;
+    add_column_if_missing(&conn, "download_record", "failure_note", "TEXT NULL")?;
+    // `algorithm:digest`, see `hashing::Checksum`
+    add_column_if_missing(&conn, "download_record", "checksum", "TEXT NULL")?;
+    // quoted ETag or http date, see `validators`
+    add_column_if_missing(&conn, "download_record", "validator", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "outdated", "INTEGER NOT NULL DEFAULT 0")?;
+    // see `OpenWhenDone::as_str`
+    add_column_if_missing(&conn, "download_record", "open_when_done", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "imported", "INTEGER NOT NULL DEFAULT 0")?;
+    // json `RetryOverrides`
+    add_column_if_missing(&conn, "download_record", "retry_overrides", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "video_height", "INTEGER NULL")?;
+    let failures = "INTEGER NOT NULL DEFAULT 0";
+    add_column_if_missing(&conn, "download_record", "spot_check_failures", failures)?;
+    add_column_if_missing(&conn, "download_record", "template_id", "INTEGER NULL")?;
+    // json `SuccessCriteria`
+    add_column_if_missing(&conn, "download_record", "criteria", "TEXT NULL")?;
+    add_column_if_missing(&conn, "download_record", "referer", "TEXT NULL")?;
+
+    // create the child table for chunks
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS chunk (
+           id               INTEGER PRIMARY KEY AUTOINCREMENT,
+           record_id        INTEGER NOT NULL,
+           start            INTEGER NOT NULL,
+           end              INTEGER NOT NULL,
+           status           TEXT NOT NULL,
+
+           FOREIGN KEY (record_id) 
+                REFERENCES download_record(id)
+                ON DELETE CASCADE
+        );
+        "#;
+    conn.execute(sql, [])?;
+    add_column_if_missing(&conn, "chunk", "attempts", "INTEGER NOT NULL DEFAULT 0")?;
+
+    // other urls the file of a record is downloaded from, see `mirrors`
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS mirror (
+           id               INTEGER PRIMARY KEY AUTOINCREMENT,
+           record_id        INTEGER NOT NULL,
+           url              TEXT NOT NULL,
+
+           UNIQUE (record_id, url),
+           FOREIGN KEY (record_id)
+                REFERENCES download_record(id)
+                ON DELETE CASCADE
+        );
+        "#;
+    conn.execute(sql, [])?;
+
+    // values about the database itself, e.g. the version of YAD that last opened it
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS meta (
+            key     TEXT PRIMARY KEY,
+            value   TEXT NOT NULL
+        );
+        "#;
+    conn.execute(sql, [])?;
+
+    // the settings are stored as a single json document so that new settings do not need a
+    // migration
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS settings (
+            id      INTEGER PRIMARY KEY CHECK (id = 1),
+            value   TEXT NOT NULL
+        );
+        "#;
+    conn.execute(sql, [])?;
+
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS scheduled_job (
+            id              INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_url        TEXT NOT NULL,
+            file_name       TEXT NULL,
+            destination_dir TEXT NULL,
+            start_at        INTEGER NOT NULL,
+            repeat_every    INTEGER NULL
+        );
+        "#;
+    conn.execute(sql, [])?;
+
+    let sql = r#"
+        CREATE TABLE IF NOT EXISTS template (
+            id              INTEGER PRIMARY KEY AUTOINCREMENT,
+            name            TEXT NOT NULL UNIQUE,
+            destination_dir TEXT NULL,
+            headers         TEXT NOT NULL DEFAULT '[]',
+            max_speed       INTEGER NULL,
+            segments        INTEGER NULL,
+            post_command    TEXT NULL
+        );
+        "#;
+    conn.execute(sql, [])?;
+    Ok(())
+}
+
+/// This function fetches the saved download records to be shown on the UI. It also verifies how
+/// many chunks have been downloaded and if any are pending/ have failed.
+///
+/// # Arguments
+/// - `cfg`: An instance of Configs
+///
+/// # Returns
+/// This function returns a `Result` containing either:
+/// - `Ok(Vec<DownloadRecord>)`: an array of download records.
+/// - `Err(Bix<dyn std::error::Error>)`: An error if it occurred.
+///
+/// # Example
+/// ```ignore
+/// let cfg = config::Config::default();
+/// let download_records = match storage::read_download_records(&cfg) {
+///     Ok(records) => records,
+///     Err(e) => {
+///         println!("failed to read download records because {e}");
+///         let r: Vec<storage::DownloadRecord> = Vec::new();
+///         r
+///     }
+/// };
+/// ```
+pub fn read_download_records(cfg: &Config) -> Result<Vec<DownloadRecord>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+
+    let sql = format!(
+        r#"
+        SELECT {RECORD_COLUMNS}
+        FROM download_record
+        WHERE deleted_at IS NULL
+        ORDER BY id DESC
+        "#
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let record_iter = stmt.query_map([], record_from_row)?;
+    let mut records = Vec::new();
+    for r in record_iter {
+        let mut _r = r?;
+
+        // check chunks and their statuses if the status == 'Pending'
+        let (pending, finished, failed) = count_chunks(_r.id, cfg).unwrap();
+
+        let total = pending + finished + failed;
+        let downloaded_percentage: f32 = if total == 0 {
+            0.0
+        } else {
+            (finished as f32 / total as f32) * 100.0
+        };
+        let mut status = "Pending";
+
+        if failed > 0 {
+            status = "Failed";
+        } else if pending == 0 {
+            status = "Finished";
+        }
+
+        // a queued download keeps its status until it starts, and a download without chunks has
+        // not been planned yet
+        if total > 0 && _r.download_status != "Queued" {
+            _r.download_status = status.to_string();
+        }
+        _r.downloaded_percentage = downloaded_percentage;
+
+        // update the download record with the new status.
+        // update_download_record(_r.id, status, _r.download_stop_time, _r.file_size, cfg).unwrap();
+
+        records.push(_r);
+    }
+    Ok(records)
+}
+
+/// This function checks whether a file exists in the db from its url. This is to prevent duplicate
+/// downloads. IN future updates, the user should be able to delete the file from the list of
+/// downloads.
+///
+/// # Arguments
+/// - `url`: The url pointing to the file.
+/// - `cfg`: An instance of configs.
+///
+/// # Returns
+/// This function returns a `Result` containing either:
+/// - `Ok(storage::DownloadRecord)`: A download record after selecting.
+/// - `Err(Box<dyn std::error::Error)`: an error in case the select fails or the record does not
+/// exist in the database.
+///
+/// # Example
+/// ```ignore
+/// let cfg = config::Config::default();
+/// let file_url = "https://example.com/super-secret-file.pdf";
+///
+/// let download_record = storage::search_by_url(file_url, &cfg).unwrap_or_default();
+/// ```
+pub fn search_by_url(url: &str, cfg: &Config) -> Result<DownloadRecord, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = format!(
+        r#"
+        SELECT {RECORD_COLUMNS}
+        FROM download_record
+        WHERE file_url=?1
+        LIMIT 1;
+    "#
+    );
+    let record = conn.query_row(&sql, params![url], record_from_row)?;
+    Ok(record)
+}
+
+/// This function returns whether a record, deleted or not, saves its file at `path`.
+pub fn path_in_use(path: &str, cfg: &Config) -> Result<bool, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "SELECT EXISTS(SELECT 1 FROM download_record WHERE destination_path=?1)";
+    Ok(conn.query_row(sql, params![path], |row| row.get(0))?)
+}
+
+/// This function creates a new download record in the db before the download begins.
+pub fn insert_record(
+    record: &DownloadRecord,
+    file_size: u64,
+    cfg: &Config,
+) -> Result<i64, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    insert_record_row(&conn, record, file_size)
+}
+
+/// This function creates a new download record together with the chunks its file is split into,
+/// in one transaction, so that a crash in between cannot leave a record without chunks.
+///
+/// # Arguments
+/// - `record`: The download record.
+/// - `file_size`: The size of the file.
+/// - `ranges`: The first and last byte of each chunk.
+/// - `cfg`: An instance of configs.
+pub fn insert_record_with_chunks(
+    record: &DownloadRecord,
+    file_size: u64,
+    ranges: &[(u64, u64)],
+    cfg: &Config,
+) -> Result<i64, Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    let id = insert_record_row(&tx, record, file_size)?;
+    for (start, end) in ranges {
+        tx.execute(
+            "INSERT INTO chunk (record_id, start, end, status) VALUES (?1, ?2, ?3, 'Pending')",
+            params![id, start, end],
+        )?;
+    }
+    tx.commit()?;
+    Ok(id)
+}
+
+fn insert_record_row(
+    conn: &Connection,
+    record: &DownloadRecord,
+    file_size: u64,
+) -> Result<i64, Box<dyn Error>> {
+    let sql = r#"
+        INSERT INTO download_record (
+            file_url, file_name, file_type, extension, destination_dir, 
+            destination_path, file_size, download_start_time, 
+            download_stop_time, download_status, applied_preset,
+            redirect_chain, original_file_name, chunk_size, final_url,
+            parent_id, byte_range, validator, open_when_done, imported,
+            retry_overrides, video_height, template_id, criteria, referer
+            )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18,
+            ?19, ?20, ?21, ?22, ?23, ?24, ?25)
+        "#;
+    conn.execute(
+        sql,
+        params![
+            record.file_url,
+            record.file_name,
+            record.file_type,
+            record.extension,
+            record.destination_dir,
+            record.destination_path,
+            file_size,
+            record.download_start_time,
+            record.download_stop_time,
+            record.download_status,
+            record.applied_preset,
+            serde_json::to_string(&record.redirect_chain)?,
+            record.original_file_name,
+            record.chunk_size,
+            record.final_url,
+            record.parent_id,
+            record
+                .byte_range
+                .map(|r| serde_json::to_string(&r))
+                .transpose()?,
+            record.validator,
+            record.open_when_done.map(|o| o.as_str()),
+            record.imported,
+            record
+                .retry_overrides
+                .map(|r| serde_json::to_string(&r))
+                .transpose()?,
+            record.video_height,
+            record.template_id,
+            record
+                .criteria
+                .as_ref()
+                .map(serde_json::to_string)
+                .transpose()?,
+            record.referer,
+        ],
+    )?;
+    let id: i64 = conn.last_insert_rowid();
+
+    Ok(id)
+}
+
+/// This function updates the download record in the database.
+pub fn update_download_record(
+    id: i64,
+    download_status: &str,
+    download_stop_time: Option<u64>,
+    file_size: u64,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        UPDATE download_record 
+        SET download_status=?1, download_stop_time=?2, file_size=?3
+        WHERE id = ?4
+        LIMIT 1;"#;
+    match conn.execute(
+        sql,
+        params![download_status, download_stop_time, file_size, id,],
+    ) {
+        Ok(_) => {
+            println!("UPDATED SUCCESSFULLY");
+        }
+        Err(e) => {
+            eprintln!("FAILED TO UPDATE BECAUSE {}", e);
+        }
+    };
+    Ok(())
+}
+
+/// This function saves the redirects followed by a download and the url they ended at, replacing
+/// the ones saved before.
+pub fn update_redirect_chain(
+    id: i64,
+    final_url: &str,
+    redirect_chain: &[RedirectHop],
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE download_record SET final_url=?1, redirect_chain=?2 WHERE id=?3";
+    conn.execute(
+        sql,
+        params![final_url, serde_json::to_string(redirect_chain)?, id],
+    )?;
+    Ok(())
+}
+
+/// This function saves the file type of a download record.
+pub fn update_record_file_type(id: i64, file_type: &str, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let updated = conn.execute(
+        "UPDATE download_record SET file_type=?1 WHERE id=?2 AND deleted_at IS NULL",
+        params![file_type, id],
+    )?;
+    if updated == 0 {
+        return Err("No download record found with this id".into());
+    }
+    Ok(())
+}
+
+/// This function saves the priority of a download record, see `queue`.
+pub fn update_record_priority(id: i64, priority: i64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let updated = conn.execute(
+        "UPDATE download_record SET priority=?1 WHERE id=?2 AND deleted_at IS NULL",
+        params![priority, id],
+    )?;
+    if updated == 0 {
+        return Err("No download record found with this id".into());
+    }
+    Ok(())
+}
+
+/// This function saves how to fix a failed download, `None` clears it.
+pub fn update_failure_note(id: i64, note: Option<&str>, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    conn.execute(
+        "UPDATE download_record SET failure_note=?1 WHERE id=?2",
+        params![note, id],
+    )?;
+    Ok(())
+}
+
+/// This function saves the checksum of the file of a download record, see `hashing::Checksum`.
+pub fn update_record_checksum(
+    id: i64,
+    checksum: Option<&str>,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    conn.execute(
+        "UPDATE download_record SET checksum=?1 WHERE id=?2",
+        params![checksum, id],
+    )?;
+    Ok(())
+}
+
+/// This function saves where the file of a download record is after it was moved.
+pub fn update_record_path(
+    id: i64,
+    destination_dir: &str,
+    destination_path: &str,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE download_record SET destination_dir=?1, destination_path=?2 WHERE id=?3";
+    conn.execute(sql, params![destination_dir, destination_path, id])?;
+    Ok(())
+}
+
+/// This function replaces the urls of a download record and of its redirects, e.g. with the ones
+/// from `privacy::strip_url`. When another record already has `file_url`, e.g. an earlier
+/// download of the same file from another signed url, the id of the record is added as the
+/// fragment of the url, which is never sent to servers.
+pub fn update_record_urls(
+    id: i64,
+    file_url: &str,
+    final_url: Option<&str>,
+    redirect_chain: &[RedirectHop],
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE download_record SET file_url=?1, final_url=?2, redirect_chain=?3 WHERE id=?4";
+    let redirect_chain = serde_json::to_string(redirect_chain)?;
+    let updated = conn.execute(sql, params![file_url, final_url, redirect_chain, id]);
+    if let Err(e) = &updated {
+        if e.sqlite_error_code() == Some(ErrorCode::ConstraintViolation) {
+            let file_url = format!("{}#{id}", file_url.split('#').next().unwrap_or(file_url));
+            conn.execute(sql, params![file_url, final_url, redirect_chain, id])?;
+            return Ok(());
+        }
+    }
+    updated?;
+    Ok(())
+}
+
+/// This function marks a download record as deleted. It is hidden from the list of downloads and
+/// can be restored with `restore_record` until it is purged with `purge_deleted_records`.
+pub fn soft_delete_record(id: i64, deleted_at: u64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE download_record SET deleted_at=?1 WHERE id=?2";
+    conn.execute(sql, params![deleted_at, id])?;
+    Ok(())
+}
+
+/// This function marks several download records as deleted in one transaction.
+///
+/// # Returns
+/// - `Ok(Vec<i64>)`: The ids of the records that were deleted, ids that do not exist or were
+///   already deleted are left out.
+pub fn soft_delete_records(
+    ids: &[i64],
+    deleted_at: u64,
+    cfg: &Config,
+) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    let mut deleted = Vec::new();
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE download_record SET deleted_at=?1 WHERE id=?2 AND deleted_at IS NULL",
+        )?;
+        for id in ids {
+            if stmt.execute(params![deleted_at, id])? > 0 {
+                deleted.push(*id);
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(deleted)
+}
+
+/// This function sets the status of several download records in one transaction.
+///
+/// # Returns
+/// - `Ok(Vec<i64>)`: The ids of the records that were updated.
+pub fn update_records_status(
+    ids: &[i64],
+    download_status: &str,
+    cfg: &Config,
+) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    let mut updated = Vec::new();
+    {
+        let mut stmt = tx.prepare(
+            "UPDATE download_record SET download_status=?1 WHERE id=?2 AND deleted_at IS NULL",
+        )?;
+        for id in ids {
+            if stmt.execute(params![download_status, id])? > 0 {
+                updated.push(*id);
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// This function changes the status of every record with status `from` to `to` and returns how
+/// many were changed.
+pub fn replace_status(from: &str, to: &str, cfg: &Config) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE download_record SET download_status=?2 WHERE download_status=?1";
+    Ok(conn.execute(sql, params![from, to])?)
+}
+
+/// This function returns the ids of the records with the given status that are not deleted, oldest
+/// first.
+pub fn record_ids_with_status(download_status: &str, cfg: &Config) -> Result<Vec<i64>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        SELECT id FROM download_record
+        WHERE download_status=?1 AND deleted_at IS NULL
+        ORDER BY id ASC
+    "#;
+    let mut stmt = conn.prepare(sql)?;
+    let ids = stmt
+        .query_map(params![download_status], |row| row.get(0))?
+        .collect::<Result<Vec<i64>, _>>()?;
+    Ok(ids)
+}
+
+/// This function reads several download records at once. Deleted records and ids that do not
+/// exist are left out.
+pub fn read_records_by_ids(ids: &[i64], cfg: &Config) -> Result<Vec<DownloadRecord>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = format!(
+        r#"
+        SELECT {RECORD_COLUMNS}
+        FROM download_record
+        WHERE id=?1 AND deleted_at IS NULL;
+    "#
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut records = Vec::new();
+    for id in ids {
+        if let Some(record) = stmt.query_row(params![id], record_from_row).optional()? {
+            records.push(record);
+        }
+    }
+    Ok(records)
+}
+
+/// This function restores a download record deleted with `soft_delete_record`.
+///
+/// # Returns
+/// - `Ok(true)`: If the record was restored.
+/// - `Ok(false)`: If there was no deleted record with that id, e.g. it was already purged.
+pub fn restore_record(id: i64, cfg: &Config) -> Result<bool, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE download_record SET deleted_at=NULL WHERE id=?1 AND deleted_at IS NOT NULL";
+    Ok(conn.execute(sql, params![id])? > 0)
+}
+
+/// This function counts the records in the history, leaving out deleted records.
+pub fn count_records(cfg: &Config) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "SELECT COUNT(*) FROM download_record WHERE deleted_at IS NULL";
+    Ok(conn.query_row(sql, [], |row| row.get(0))?)
+}
+
+/// This function reads the finished records that finished the longest ago, see `history`.
+pub fn oldest_finished_records(limit: usize, cfg: &Config) -> Result<Vec<DownloadRecord>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = format!(
+        r#"
+        SELECT {RECORD_COLUMNS}
+        FROM download_record
+        WHERE download_status='Finished' AND deleted_at IS NULL
+        ORDER BY download_stop_time ASC, id ASC
+        LIMIT ?1
+        "#
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let records = stmt
+        .query_map(params![limit as i64], record_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(records)
+}
+
+/// This function marks the records which have no chunks as interrupted, so that they are planned
+/// again when they resume. Older versions saved the chunks apart from the record, and a crash in
+/// between left a record without chunks. Finished records are left alone.
+///
+/// # Returns
+/// The number of records marked.
+pub fn interrupt_records_without_chunks(status: &str, cfg: &Config) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        UPDATE download_record SET download_status=?1
+        WHERE deleted_at IS NULL
+            AND download_status NOT IN ('Finished', ?1)
+            AND NOT EXISTS (SELECT 1 FROM chunk WHERE chunk.record_id = download_record.id)
+        "#;
+    Ok(conn.execute(sql, params![status])?)
+}
+
+/// This function permanently deletes several records together with their chunks in one
+/// transaction.
+pub fn delete_records(ids: &[i64], cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    for id in ids {
+        tx.execute("DELETE FROM chunk WHERE record_id=?1", params![id])?;
+        tx.execute("DELETE FROM mirror WHERE record_id=?1", params![id])?;
+        tx.execute("DELETE FROM download_record WHERE id=?1", params![id])?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// This function permanently deletes the records deleted at or before `before` together with
+/// their chunks.
+pub fn purge_deleted_records(before: u64, cfg: &Config) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        DELETE FROM chunk
+        WHERE record_id IN (
+            SELECT id FROM download_record WHERE deleted_at IS NOT NULL AND deleted_at <= ?1
+        );
+        "#;
+    conn.execute(sql, params![before])?;
+    let sql = r#"
+        DELETE FROM mirror
+        WHERE record_id IN (
+            SELECT id FROM download_record WHERE deleted_at IS NOT NULL AND deleted_at <= ?1
+        );
+        "#;
+    conn.execute(sql, params![before])?;
+    let sql = "DELETE FROM download_record WHERE deleted_at IS NOT NULL AND deleted_at <= ?1";
+    Ok(conn.execute(sql, params![before])?)
+}
+
+/// This function deletes a download record from the database.
+pub fn delete_record(id: i64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        DELETE FROM download_record 
+        WHERE id=?1 LIMIT 1;
+    "#;
+    conn.execute(sql, params![id])?;
+
+    let sql = r#"
+        DELETE FROM chunk
+        WHERE record_id=?1;
+        "#;
+    conn.execute(sql, params![id])?;
+    conn.execute("DELETE FROM mirror WHERE record_id=?1", params![id])?;
+    Ok(())
+}
+
+/// This function saves other urls the file of a download record is downloaded from. Urls saved
+/// before are skipped.
+pub fn add_mirrors(record_id: i64, urls: &[String], cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    for url in urls {
+        tx.execute(
+            "INSERT OR IGNORE INTO mirror (record_id, url) VALUES (?1, ?2)",
+            params![record_id, url],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// This function reads the mirrors of a download record in the order they were added.
+pub fn read_mirrors(record_id: i64, cfg: &Config) -> Result<Vec<String>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let mut stmt = conn.prepare("SELECT url FROM mirror WHERE record_id=?1 ORDER BY id ASC")?;
+    let urls = stmt
+        .query_map(params![record_id], |row| row.get(0))?
+        .collect::<Result<Vec<String>, _>>()?;
+    Ok(urls)
+}
+
+/// This function saves each chunk of the file being downloaded.
+pub fn save_chunk(chunk: &Chunk, cfg: &Config) -> Result<i64, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        INSERT INTO chunk (
+            record_id, start, end, status
+        )
+        VALUES (?, ?, ?, ?)
+        "#;
+    conn.execute(
+        sql,
+        params![chunk.record_id, chunk.start, chunk.end, chunk.status],
+    )?;
+    let id: i64 = conn.last_insert_rowid();
+    Ok(id)
+}
+
+/// This function removes chunks saved more than once for the same byte range, keeping a finished
+/// one if there is any. Older versions saved the unfinished chunks again on every resume.
+pub fn delete_duplicate_chunks(record_id: i64, cfg: &Config) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        DELETE FROM chunk
+        WHERE record_id = ?1
+            AND id NOT IN (
+                SELECT id FROM (
+                    SELECT id, ROW_NUMBER() OVER (
+                        PARTITION BY start, end
+                        ORDER BY status = 'Finished' DESC, id ASC
+                    ) AS n
+                    FROM chunk
+                    WHERE record_id = ?1
+                )
+                WHERE n = 1
+            );
+        "#;
+    let deleted = conn.execute(sql, params![record_id])?;
+    Ok(deleted)
+}
+
+/// This function splits the chunk of `record_id` starting at `start` in two. The chunk ends before
+/// `at` and a new pending chunk covers the bytes from `at` to where the chunk ended before.
+pub fn split_chunk(record_id: i64, start: u64, at: u64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    let end: u64 = tx.query_row(
+        "SELECT end FROM chunk WHERE record_id = ?1 AND start = ?2",
+        params![record_id, start],
+        |row| row.get(0),
+    )?;
+    if at <= start || at > end {
+        return Err(format!("Cannot split chunk {start}-{end} at {at}").into());
+    }
+    tx.execute(
+        "UPDATE chunk SET end = ?1 WHERE record_id = ?2 AND start = ?3",
+        params![at - 1, record_id, start],
+    )?;
+    tx.execute(
+        "INSERT INTO chunk (record_id, start, end, status) VALUES (?1, ?2, ?3, 'Pending')",
+        params![record_id, at, end],
+    )?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// This function replaces the chunks of `record_id` with pending chunks covering `ranges`, e.g.
+/// when a server turns out not to support ranges and the file is downloaded in one piece.
+pub fn replace_chunks(
+    record_id: i64,
+    ranges: &[(u64, u64)],
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    tx.execute("DELETE FROM chunk WHERE record_id = ?1", params![record_id])?;
+    for (start, end) in ranges {
+        tx.execute(
+            "INSERT INTO chunk (record_id, start, end, status) VALUES (?1, ?2, ?3, 'Pending')",
+            params![record_id, start, end],
+        )?;
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+/// This function sets the retry settings used for a download instead of the ones of the settings.
+pub fn update_retry_overrides(
+    id: i64,
+    overrides: Option<retry::RetryOverrides>,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let overrides = overrides.map(|r| serde_json::to_string(&r)).transpose()?;
+    conn.execute(
+        "UPDATE download_record SET retry_overrides=?1 WHERE id=?2",
+        params![overrides, id],
+    )?;
+    Ok(())
+}
+
+/// This function sets what is opened when a download finishes, `None` opens nothing.
+pub fn update_open_when_done(
+    id: i64,
+    open_when_done: Option<OpenWhenDone>,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let updated = conn.execute(
+        "UPDATE download_record SET open_when_done=?1 WHERE id=?2",
+        params![open_when_done.map(|o| o.as_str()), id],
+    )?;
+    if updated == 0 {
+        return Err("No download record found with this id".into());
+    }
+    Ok(())
+}
+
+/// This function marks whether the file of a finished download changed on the server.
+pub fn update_record_outdated(id: i64, outdated: bool, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let updated = conn.execute(
+        "UPDATE download_record SET outdated=?1 WHERE id=?2",
+        params![outdated, id],
+    )?;
+    if updated == 0 {
+        return Err("No download record found with this id".into());
+    }
+    Ok(())
+}
+
+/// This function counts a spot check of a download that did not match the server, see
+/// `integrity`.
+pub fn add_spot_check_failure(id: i64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    conn.execute(
+        "UPDATE download_record SET spot_check_failures=spot_check_failures+1 WHERE id=?1",
+        params![id],
+    )?;
+    Ok(())
+}
+
+/// This function starts a download record over, e.g. when its file changed on the server since it
+/// started: its size, chunk size and validator become those of the new file, and its chunks are
+/// deleted so that they are planned again.
+pub fn restart_record(
+    id: i64,
+    file_size: u64,
+    chunk_size: Option<u64>,
+    validator: Option<&str>,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    let tx = write_transaction(&mut conn)?;
+    tx.execute(
+        "UPDATE download_record SET file_size=?1, chunk_size=?2, validator=?3 WHERE id=?4",
+        params![file_size, chunk_size, validator, id],
+    )?;
+    tx.execute("DELETE FROM chunk WHERE record_id = ?1", params![id])?;
+    tx.commit()?;
+    Ok(())
+}
+
+/// This function updates the status of each chunk once it has been downloaded or in case an error
+/// occurs.
+pub fn update_chunk(
+    record_id: i64,
+    start: u64,
+    status: &str,
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        UPDATE chunk 
+        SET status=?1, attempts=attempts+?4
+        WHERE record_id = ?2
+            AND start = ?3
+        LIMIT 1;
+        "#;
+    let attempts = u32::from(retry::counts_as_attempt(status));
+    retry_busy(|| conn.execute(sql, params![status, record_id, start, attempts]))?;
+    Ok(())
+}
+
+/// This function sets the chunks of a record that are not finished back to `Pending` so that they
+/// are downloaded again, without counting an attempt. Chunks attempted `max_attempts` times are
+/// left as they are, see `retry::gave_up`.
+///
+/// # Returns
+/// - `Ok(usize)`: The number of chunks set back to `Pending`.
+pub fn reset_unfinished_chunks(
+    record_id: i64,
+    max_attempts: u32,
+    cfg: &Config,
+) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        UPDATE chunk
+        SET status='Pending'
+        WHERE record_id = ?1
+            AND status != 'Finished'
+            AND (?2 = 0 OR attempts < ?2)
+        "#;
+    Ok(conn.execute(sql, params![record_id, max_attempts])?)
+}
+
+/// This function sets the finished chunks of a record that end at or after the byte `from` back to
+/// `Pending`, e.g. when the file was cut short and lost them.
+///
+/// # Returns
+/// - `Ok(usize)`: The number of chunks set back to `Pending`.
+pub fn reset_chunks_from(record_id: i64, from: u64, cfg: &Config) -> Result<usize, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        UPDATE chunk
+        SET status='Pending'
+        WHERE record_id = ?1
+            AND status = 'Finished'
+            AND end >= ?2
+        "#;
+    Ok(conn.execute(sql, params![record_id, from])?)
+}
+
+/// This function updates the status of several chunks in one transaction.
+///
+/// # Arguments
+/// - `updates`: The record id, chunk start, new status and number of attempts to add of each
+///   chunk.
+/// - `cfg`: An instance of `Config`.
+pub fn update_chunks(
+    updates: &[(i64, u64, String, u32)],
+    cfg: &Config,
+) -> Result<(), Box<dyn Error>> {
+    let mut conn = get_db(cfg)?;
+    retry_busy(|| {
+        let tx = write_transaction(&mut conn)?;
+        {
+            let mut stmt = tx.prepare(
+                "UPDATE chunk SET status=?1, attempts=attempts+?4 WHERE record_id = ?2 AND start = ?3",
+            )?;
+            for (record_id, start, status, attempts) in updates {
+                stmt.execute(params![status, record_id, start, attempts])?;
+            }
+        }
+        tx.commit()
+    })?;
+    Ok(())
+}
+
+/// Count summaries of chunks for the files. We count how many chunks are pending, successful and
+/// failed to determine the status and final state of the download.
+///
+/// # Arguments
+/// - `record_id`: The download record id.
+/// - `cfg`: Configs.
+///
+/// # Return
+/// - `(i32, i32, i32)`: number of pending, successful and failed chunks.
+pub fn count_chunks(record_id: i64, cfg: &Config) -> Result<(i32, i32, i32), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        SELECT COUNT(id), status
+        FROM chunk
+        WHERE record_id = ?1
+        GROUP BY status;
+        "#;
+    let mut stmt = conn.prepare(sql)?;
+    let record_iter = stmt.query_map(params![record_id], |row| {
+        Ok(ChunkCount {
+            count: row.get(0)?,
+            status: row.get(1)?,
+        })
+    })?;
+
+    let mut pending: i32 = 0;
+    let mut finished: i32 = 0;
+    let mut failed: i32 = 0;
+    for record in record_iter {
+        let r = record?;
+        println!("status={}, count: {}", r.status, r.count);
+        if r.status == "Pending" || r.status == "InProgress" || r.status == "Cancelled" {
+            pending += r.count;
+        } else if r.status == "Finished" {
+            finished = r.count;
+        } else {
+            failed = r.count;
+        }
+    }
+    println!("---- pending: {pending}, finished: {finished}, failed: {failed}");
+    Ok((pending, finished, failed))
+}
+
+/// This function reads the saved settings. If none have been saved yet, the defaults are returned.
+pub fn read_settings(cfg: &Config) -> Result<Settings, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "SELECT value FROM settings WHERE id = 1";
+    let value: Option<String> = match conn.query_row(sql, [], |row| row.get(0)) {
+        Ok(v) => Some(v),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e.into()),
+    };
+    match value {
+        Some(v) => Ok(serde_json::from_str(&v)?),
+        None => Ok(Settings::default()),
+    }
+}
+
+/// This function checks whether settings have been saved, i.e. the application has been set up.
+pub fn settings_saved(cfg: &Config) -> Result<bool, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "SELECT EXISTS(SELECT 1 FROM settings WHERE id = 1)";
+    Ok(conn.query_row(sql, [], |row| row.get(0))?)
+}
+
+/// This function saves the settings, replacing the previously saved ones.
+pub fn save_settings(settings: &Settings, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        INSERT INTO settings (id, value) VALUES (1, ?1)
+        ON CONFLICT(id) DO UPDATE SET value=excluded.value;
+        "#;
+    conn.execute(sql, params![serde_json::to_string(settings)?])?;
+    Ok(())
+}
+
+/// This function reads a value about the database, see the `meta` table.
+pub fn read_meta(key: &str, cfg: &Config) -> Result<Option<String>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "SELECT value FROM meta WHERE key = ?1";
+    Ok(conn
+        .query_row(sql, params![key], |row| row.get(0))
+        .optional()?)
+}
+
+/// This function saves a value about the database, replacing the previous one.
+pub fn write_meta(key: &str, value: &str, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        INSERT INTO meta (key, value) VALUES (?1, ?2)
+        ON CONFLICT(key) DO UPDATE SET value=excluded.value;
+        "#;
+    conn.execute(sql, params![key, value])?;
+    Ok(())
+}
+
+/// This function saves a scheduled download and returns its id.
+pub fn insert_scheduled_job(job: &ScheduledJob, cfg: &Config) -> Result<i64, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        INSERT INTO scheduled_job (
+            file_url, file_name, destination_dir, start_at, repeat_every
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5)
+        "#;
+    conn.execute(
+        sql,
+        params![
+            job.file_url,
+            job.file_name,
+            job.destination_dir,
+            job.start_at,
+            job.repeat_every,
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// This function fetches all scheduled downloads ordered by when they start.
+pub fn read_scheduled_jobs(cfg: &Config) -> Result<Vec<ScheduledJob>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        SELECT id, file_url, file_name, destination_dir, start_at, repeat_every
+        FROM scheduled_job
+        ORDER BY start_at ASC
+        "#;
+    let mut stmt = conn.prepare(sql)?;
+    let jobs = stmt
+        .query_map([], |row| {
+            Ok(ScheduledJob {
+                id: row.get(0)?,
+                file_url: row.get(1)?,
+                file_name: row.get(2)?,
+                destination_dir: row.get(3)?,
+                start_at: row.get(4)?,
+                repeat_every: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(jobs)
+}
+
+/// This function moves a recurring scheduled download to its next run.
+pub fn reschedule_job(id: i64, start_at: u64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "UPDATE scheduled_job SET start_at=?1 WHERE id=?2";
+    conn.execute(sql, params![start_at, id])?;
+    Ok(())
+}
+
+/// This function deletes a scheduled download.
+pub fn delete_scheduled_job(id: i64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "DELETE FROM scheduled_job WHERE id=?1";
+    conn.execute(sql, params![id])?;
+    Ok(())
+}
+
+const TEMPLATE_COLUMNS: &str =
+    "id, name, destination_dir, headers, max_speed, segments, post_command";
+
+fn template_from_row(row: &rusqlite::Row) -> rusqlite::Result<Template> {
+    let headers: String = row.get(3)?;
+    Ok(Template {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        destination_dir: row.get(2)?,
+        headers: serde_json::from_str(&headers).unwrap_or_default(),
+        max_speed: row.get(4)?,
+        segments: row.get(5)?,
+        post_command: row.get(6)?,
+    })
+}
+
+/// This function saves a new download template.
+///
+/// # Returns
+/// - `Ok(i64)`: The id of the template.
+/// - `Err`: If the query fails, e.g. a template with the same name exists.
+pub fn insert_template(template: &Template, cfg: &Config) -> Result<i64, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        INSERT INTO template (
+            name, destination_dir, headers, max_speed, segments, post_command
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        "#;
+    conn.execute(
+        sql,
+        params![
+            template.name.trim(),
+            template.destination_dir,
+            serde_json::to_string(&template.headers)?,
+            template.max_speed,
+            template.segments,
+            template.post_command,
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// This function updates a download template.
+pub fn update_template(template: &Template, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = r#"
+        UPDATE template
+        SET name=?1, destination_dir=?2, headers=?3, max_speed=?4, segments=?5, post_command=?6
+        WHERE id=?7
+        "#;
+    let updated = conn.execute(
+        sql,
+        params![
+            template.name.trim(),
+            template.destination_dir,
+            serde_json::to_string(&template.headers)?,
+            template.max_speed,
+            template.segments,
+            template.post_command,
+            template.id,
+        ],
+    )?;
+    if updated == 0 {
+        return Err(format!("No template with id {}", template.id).into());
+    }
+    Ok(())
+}
+
+/// This function fetches the download templates ordered by name.
+pub fn read_templates(cfg: &Config) -> Result<Vec<Template>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = format!("SELECT {TEMPLATE_COLUMNS} FROM template ORDER BY name COLLATE NOCASE");
+    let mut stmt = conn.prepare(&sql)?;
+    let templates = stmt
+        .query_map([], template_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(templates)
+}
+
+/// This function fetches a download template.
+pub fn read_template(id: i64, cfg: &Config) -> Result<Template, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = format!("SELECT {TEMPLATE_COLUMNS} FROM template WHERE id=?1");
+    Ok(conn.query_row(&sql, params![id], template_from_row)?)
+}
+
+pub fn delete_template(id: i64, cfg: &Config) -> Result<(), Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    conn.execute("DELETE FROM template WHERE id=?1", params![id])?;
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) fn test_config(tmp_name: &str) -> Config {
+    let tmp = std::env::temp_dir().join("yad_test").join(tmp_name);
+    let _ = fs::remove_dir_all(&tmp);
+    fs::create_dir_all(&tmp).unwrap();
+    Config {
+        config_dir: tmp.to_str().unwrap().to_string(),
+        download_dir: tmp.join("Downloads").to_str().unwrap().to_string(),
+        tmp_dir: tmp.join("tmp").to_str().unwrap().to_string(),
+        os: "Linux".to_string(),
+        user: "test".to_string(),
+        db_name: "yad.db".to_string(),
+        ..Config::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_tables_creates_db() {
+        let cfg = test_config("create_tables");
+        assert!(create_tables(&cfg).is_ok());
+        // DB file should exist
+        let db_path = Path::new(&cfg.config_dir).join("yad.db");
+        assert!(db_path.exists());
+    }
+
+    #[test]
+    fn test_connections_are_reused() {
+        let cfg = test_config("pool");
+        create_tables(&cfg).unwrap();
+        for _ in 0..10 {
+            let _ = search_by_url("https://example.com/missing.zip", &cfg);
+        }
+        let db_path = Path::new(&cfg.config_dir).join("yad.db");
+        let pool = pools().lock().unwrap()[&db_path].clone();
+        assert_eq!(pool.state().connections, 1);
+        // foreign keys are on for pooled connections too
+        let on: bool = get_db(&cfg)
+            .unwrap()
+            .query_row("PRAGMA foreign_keys", [], |r| r.get(0))
+            .unwrap();
+        assert!(on);
+    }
+
+    #[test]
+    fn test_create_tables_idempotent() {
+        let cfg = test_config("create_tables_idempotent");
+        assert!(create_tables(&cfg).is_ok());
+        assert!(create_tables(&cfg).is_ok()); // second call should not fail
+    }
+
+    #[test]
+    fn test_insert_and_search_by_url() {
+        let cfg = test_config("insert_search");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            id: 0,
+            file_url: "https://example.com/file.zip".into(),
+            file_name: "file.zip".into(),
+            file_type: "Compressed".into(),
+            extension: "zip".into(),
+            destination_dir: "/tmp/yad_test/Compressed".into(),
+            destination_path: "/tmp/yad_test/Compressed/file.zip".into(),
+            file_size: 1024,
+            download_start_time: 1000,
+            download_stop_time: None,
+            download_status: "Pending".into(),
+            downloaded_percentage: 0.0,
+            ..DownloadRecord::default()
+        };
+
+        let id = insert_record(&record, 1024, &cfg).unwrap();
+        assert!(id > 0, "insert should return a positive id");
+
+        let found = search_by_url("https://example.com/file.zip", &cfg).unwrap();
+        assert_eq!(found.id, id);
+        assert_eq!(found.file_name, "file.zip");
+        assert_eq!(found.file_size, 1024);
+        assert_eq!(found.download_status, "Pending");
+        assert!(found.download_stop_time.is_none());
+    }
+
+    #[test]
+    fn test_update_record_urls() {
+        let cfg = test_config("update_record_urls");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/file.zip?token=abc".into(),
+            file_name: "file.zip".into(),
+            redirect_chain: vec![RedirectHop {
+                url: "https://example.com/file.zip?token=abc".into(),
+                status: 302,
+                location: "https://cdn.example.com/file.zip?sig=def".into(),
+            }],
+            ..DownloadRecord::default()
+        };
+        let id = insert_record(&record, 1024, &cfg).unwrap();
+        let hops = vec![RedirectHop {
+            url: "https://example.com/file.zip".into(),
+            status: 302,
+            location: "https://cdn.example.com/file.zip".into(),
+        }];
+        update_record_urls(
+            id,
+            "https://example.com/file.zip",
+            Some("https://cdn.example.com/file.zip"),
+            &hops,
+            &cfg,
+        )
+        .unwrap();
+
+        assert!(search_by_url("https://example.com/file.zip?token=abc", &cfg).is_err());
+        let found = search_by_url("https://example.com/file.zip", &cfg).unwrap();
+        assert_eq!(found.id, id);
+        assert_eq!(found.redirect_chain, hops);
+        assert_eq!(found.final_url.as_deref(), Some("https://cdn.example.com/file.zip"));
+
+        // another signed url of the same file strips to the same url
+        let other = DownloadRecord {
+            file_url: "https://example.com/file.zip?token=xyz".into(),
+            destination_path: "file (1).zip".into(),
+            ..record
+        };
+        let other_id = insert_record(&other, 1024, &cfg).unwrap();
+        update_record_urls(other_id, "https://example.com/file.zip", None, &[], &cfg).unwrap();
+        let url = format!("https://example.com/file.zip#{other_id}");
+        assert_eq!(search_by_url(&url, &cfg).unwrap().id, other_id);
+    }
+
+    #[test]
+    fn test_insert_duplicate_url_fails() {
+        let cfg = test_config("insert_duplicate");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            id: 0,
+            file_url: "https://example.com/dup.zip".into(),
+            file_name: "dup.zip".into(),
+            file_type: "Compressed".into(),
+            extension: "zip".into(),
+            destination_dir: "/tmp".into(),
+            destination_path: "/tmp/dup.zip".into(),
+            file_size: 512,
+            download_start_time: 1000,
+            download_stop_time: None,
+            download_status: "Pending".into(),
+            downloaded_percentage: 0.0,
+            ..DownloadRecord::default()
+        };
+
+        insert_record(&record, 512, &cfg).unwrap();
+        let dup = insert_record(&record, 512, &cfg);
+        assert!(dup.is_err(), "duplicate URL should be rejected");
+    }
+
+    #[test]
+    fn test_update_download_record() {
+        let cfg = test_config("update_record");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            id: 0,
+            file_url: "https://example.com/file.zip".into(),
+            file_name: "file.zip".into(),
+            file_type: "Compressed".into(),
+            extension: "zip".into(),
+            destination_dir: "/tmp".into(),
+            destination_path: "/tmp/file.zip".into(),
+            file_size: 1024,
+            download_start_time: 1000,
+            download_stop_time: None,
+            download_status: "Pending".into(),
+            downloaded_percentage: 0.0,
+            ..DownloadRecord::default()
+        };
+
+        let id = insert_record(&record, 1024, &cfg).unwrap();
+
+        update_download_record(id, "Finished", Some(2000), 1024, &cfg).unwrap();
+
+        let found = search_by_url("https://example.com/file.zip", &cfg).unwrap();
+        assert_eq!(found.download_status, "Finished");
+        assert_eq!(found.download_stop_time, Some(2000));
+    }
+
+    #[test]
+    fn test_chunk_lifecycle() {
+        let cfg = test_config("chunk_lifecycle");
+        create_tables(&cfg).unwrap();
+
+        // Insert parent record
+        let record = DownloadRecord {
+            id: 0,
+            file_url: "https://example.com/big.zip".into(),
+            file_name: "big.zip".into(),
+            file_type: "Compressed".into(),
+            extension: "zip".into(),
+            destination_dir: "/tmp".into(),
+            destination_path: "/tmp/big.zip".into(),
+            file_size: 5_000_000,
+            download_start_time: 1000,
+            download_stop_time: None,
+            download_status: "Pending".into(),
+            downloaded_percentage: 0.0,
+            ..DownloadRecord::default()
+        };
+        let record_id = insert_record(&record, 5_000_000, &cfg).unwrap();
+
+        // Save chunks
+        let chunk1 = Chunk::new(record_id, 0, 1023);
+        let chunk2 = Chunk::new(record_id, 1024, 2047);
+        let id1 = save_chunk(&chunk1, &cfg).unwrap();
+        let id2 = save_chunk(&chunk2, &cfg).unwrap();
+        assert!(id1 > 0);
+        assert!(id2 > 0);
+
+        // Count (both Pending)
+        let (pending, finished, failed) = count_chunks(record_id, &cfg).unwrap();
+        assert_eq!(pending, 2, "both chunks should be Pending");
+        assert_eq!(finished, 0);
+        assert_eq!(failed, 0);
+
+        // Update chunk1 to Finished
+        update_chunk(record_id, 0, "Finished", &cfg).unwrap();
+        let (pending, finished, failed) = count_chunks(record_id, &cfg).unwrap();
+        assert_eq!(pending, 1);
+        assert_eq!(finished, 1);
+        assert_eq!(failed, 0);
+
+        // Update chunk2 to Failed
+        update_chunk(record_id, 1024, "Failed", &cfg).unwrap();
+        let (pending, finished, failed) = count_chunks(record_id, &cfg).unwrap();
+        assert_eq!(pending, 0);
+        assert_eq!(finished, 1);
+        assert_eq!(failed, 1);
+
+        // Update both at once
+        let updates = vec![
+            (record_id, 0, "Pending".to_string(), 2),
+            (record_id, 1024, "Finished".to_string(), 1),
+        ];
+        update_chunks(&updates, &cfg).unwrap();
+        let (pending, finished, failed) = count_chunks(record_id, &cfg).unwrap();
+        assert_eq!((pending, finished, failed), (1, 1, 0));
+
+        let attempts: Vec<u32> = get_chunks_by_record(record_id, &cfg)
+            .unwrap()
+            .iter()
+            .map(|c| c.attempts)
+            .collect();
+        assert_eq!(attempts, vec![3, 2]);
+    }
+
+    #[test]
+    fn test_reset_unfinished_chunks() {
+        let cfg = test_config("reset_unfinished_chunks");
+        create_tables(&cfg).unwrap();
+        let record = DownloadRecord {
+            file_url: "https://example.com/flaky.zip".into(),
+            destination_path: "/tmp/flaky.zip".into(),
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record(&record, 3072, &cfg).unwrap();
+        for start in [0, 1024, 2048] {
+            save_chunk(&Chunk::new(rid, start, start + 1023), &cfg).unwrap();
+        }
+        update_chunk(rid, 0, "Finished", &cfg).unwrap();
+        update_chunk(rid, 1024, "Failed", &cfg).unwrap();
+        update_chunk(rid, 2048, "Failed", &cfg).unwrap();
+        update_chunk(rid, 2048, "Failed", &cfg).unwrap();
+        // pausing is not an attempt
+        update_chunk(rid, 2048, "Cancelled", &cfg).unwrap();
+
+        assert_eq!(reset_unfinished_chunks(rid, 2, &cfg).unwrap(), 1);
+        let statuses: Vec<(String, u32)> = get_chunks_by_record(rid, &cfg)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.status, c.attempts))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("Finished".to_string(), 1),
+                ("Pending".to_string(), 1),
+                ("Cancelled".to_string(), 2),
+            ]
+        );
+        assert_eq!(reset_unfinished_chunks(rid, 0, &cfg).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_delete_duplicate_chunks_keeps_finished() {
+        let cfg = test_config("duplicate_chunks");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/dup-chunks.zip".into(),
+            destination_path: "/tmp/dup-chunks.zip".into(),
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record(&record, 2000, &cfg).unwrap();
+
+        save_chunk(&Chunk::new(rid, 0, 999), &cfg).unwrap();
+        save_chunk(&Chunk::new(rid, 0, 999), &cfg).unwrap();
+        let finished = Chunk {
+            status: "Finished".into(),
+            ..Chunk::new(rid, 0, 999)
+        };
+        save_chunk(&finished, &cfg).unwrap();
+        save_chunk(&Chunk::new(rid, 1000, 1999), &cfg).unwrap();
+
+        assert_eq!(delete_duplicate_chunks(rid, &cfg).unwrap(), 2);
+        let chunks = get_chunks_by_record(rid, &cfg).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks.iter().any(|c| c.start == 0 && c.status == "Finished"));
+        assert_eq!(delete_duplicate_chunks(rid, &cfg).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_get_chunks_by_record() {
+        let cfg = test_config("get_chunks");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            id: 0,
+            file_url: "https://example.com/data.zip".into(),
+            file_name: "data.zip".into(),
+            file_type: "Compressed".into(),
+            extension: "zip".into(),
+            destination_dir: "/tmp".into(),
+            destination_path: "/tmp/data.zip".into(),
+            file_size: 3_000_000,
+            download_start_time: 1000,
+            download_stop_time: None,
+            download_status: "Pending".into(),
+            downloaded_percentage: 0.0,
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record(&record, 3_000_000, &cfg).unwrap();
+
+        let c1 = Chunk::new(rid, 0, 999);
+        let c2 = Chunk::new(rid, 1000, 1999);
+        save_chunk(&c1, &cfg).unwrap();
+        save_chunk(&c2, &cfg).unwrap();
+
+        let chunks = get_chunks_by_record(rid, &cfg).unwrap();
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(chunks[0].record_id, rid);
+        assert_eq!(chunks[1].record_id, rid);
+    }
+
+    #[test]
+    fn test_split_chunk() {
+        let cfg = test_config("split_chunk");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/split.iso".into(),
+            destination_path: "/tmp/split.iso".into(),
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record(&record, 2000, &cfg).unwrap();
+        save_chunk(&Chunk::new(rid, 0, 999), &cfg).unwrap();
+        save_chunk(&Chunk::new(rid, 1000, 1999), &cfg).unwrap();
+
+        split_chunk(rid, 1000, 1500, &cfg).unwrap();
+        let mut ranges: Vec<(u64, u64)> = get_chunks_by_record(rid, &cfg)
+            .unwrap()
+            .iter()
+            .map(|c| (c.start, c.end))
+            .collect();
+        ranges.sort();
+        assert_eq!(ranges, vec![(0, 999), (1000, 1499), (1500, 1999)]);
+        assert!(split_chunk(rid, 1000, 1500, &cfg).is_err(), "nothing left to split");
+        assert!(split_chunk(rid, 42, 50, &cfg).is_err(), "no such chunk");
+    }
+
+    #[test]
+    fn test_replace_chunks() {
+        let cfg = test_config("replace_chunks");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/replace.iso".into(),
+            destination_path: "/tmp/replace.iso".into(),
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record(&record, 2000, &cfg).unwrap();
+        save_chunk(&Chunk::new(rid, 0, 999), &cfg).unwrap();
+        save_chunk(&Chunk::new(rid, 1000, 1999), &cfg).unwrap();
+        update_chunk(rid, 0, "Finished", &cfg).unwrap();
+
+        replace_chunks(rid, &[(0, 1999)], &cfg).unwrap();
+        let chunks = get_chunks_by_record(rid, &cfg).unwrap();
+        assert_eq!(chunks.len(), 1);
+        assert_eq!((chunks[0].start, chunks[0].end), (0, 1999));
+        assert_eq!(chunks[0].status, "Pending");
+    }
+
+    #[test]
+    fn test_restart_record() {
+        let cfg = test_config("restart_record");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/nightly.iso".into(),
+            destination_path: "/tmp/nightly.iso".into(),
+            chunk_size: Some(1000),
+            validator: Some("\"v1\"".into()),
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record_with_chunks(&record, 2000, &[(0, 999), (1000, 1999)], &cfg).unwrap();
+        update_chunk(rid, 0, "Finished", &cfg).unwrap();
+
+        restart_record(rid, 3000, Some(1500), Some("\"v2\""), &cfg).unwrap();
+        let read = read_records_by_ids(&[rid], &cfg).unwrap().pop().unwrap();
+        assert_eq!((read.file_size, read.chunk_size), (3000, Some(1500)));
+        assert_eq!(read.validator.as_deref(), Some("\"v2\""));
+        assert!(get_chunks_by_record(rid, &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_delete_record_cascades_to_chunks() {
+        let cfg = test_config("delete_cascade");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            id: 0,
+            file_url: "https://example.com/delete.zip".into(),
+            file_name: "delete.zip".into(),
+            file_type: "Compressed".into(),
+            extension: "zip".into(),
+            destination_dir: "/tmp".into(),
+            destination_path: "/tmp/delete.zip".into(),
+            file_size: 1000,
+            download_start_time: 1000,
+            download_stop_time: None,
+            download_status: "Pending".into(),
+            downloaded_percentage: 0.0,
+            ..DownloadRecord::default()
+        };
+        let rid = insert_record(&record, 1000, &cfg).unwrap();
+
+        let c = Chunk::new(rid, 0, 999);
+        save_chunk(&c, &cfg).unwrap();
+
+        delete_record(rid, &cfg).unwrap();
+
+        // After delete, search should fail
+        let found = search_by_url("https://example.com/delete.zip", &cfg);
+        assert!(found.is_err(), "record should be deleted");
+
+        // Chunks should also be gone
+        let chunks = get_chunks_by_record(rid, &cfg).unwrap();
+        assert!(chunks.is_empty(), "chunks should cascade on delete");
+    }
+
+    #[test]
+    fn test_settings_round_trip() {
+        let cfg = test_config("settings_round_trip");
+        create_tables(&cfg).unwrap();
+
+        let defaults = read_settings(&cfg).unwrap();
+        assert_eq!(defaults, Settings::default());
+        assert!(!settings_saved(&cfg).unwrap());
+
+        let s = Settings {
+            max_concurrent_chunks: 8,
+            max_speed: 1024,
+            proxy: Some("http://127.0.0.1:8080".into()),
+            ..Settings::default()
+        };
+        save_settings(&s, &cfg).unwrap();
+        save_settings(&s, &cfg).unwrap(); // saving twice should overwrite
+        assert_eq!(read_settings(&cfg).unwrap(), s);
+        assert!(settings_saved(&cfg).unwrap());
+    }
+
+    #[test]
+    fn test_scheduled_job_lifecycle() {
+        let cfg = test_config("scheduled_jobs");
+        create_tables(&cfg).unwrap();
+
+        let later = ScheduledJob {
+            file_url: "https://example.com/later.iso".into(),
+            start_at: 2000,
+            repeat_every: Some(86400),
+            ..ScheduledJob::default()
+        };
+        let sooner = ScheduledJob {
+            file_url: "https://example.com/sooner.iso".into(),
+            file_name: Some("renamed.iso".into()),
+            start_at: 1000,
+            ..ScheduledJob::default()
+        };
+        let later_id = insert_scheduled_job(&later, &cfg).unwrap();
+        let sooner_id = insert_scheduled_job(&sooner, &cfg).unwrap();
+
+        let jobs = read_scheduled_jobs(&cfg).unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert_eq!(jobs[0].id, sooner_id, "jobs should be ordered by start time");
+        assert_eq!(jobs[0].file_name.as_deref(), Some("renamed.iso"));
+        assert_eq!(jobs[1].repeat_every, Some(86400));
+
+        reschedule_job(later_id, 500, &cfg).unwrap();
+        assert_eq!(read_scheduled_jobs(&cfg).unwrap()[0].id, later_id);
+
+        delete_scheduled_job(sooner_id, &cfg).unwrap();
+        assert_eq!(read_scheduled_jobs(&cfg).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_template_crud() {
+        let cfg = test_config("templates");
+        create_tables(&cfg).unwrap();
+
+        let mut iso = Template {
+            name: "Linux ISO".into(),
+            destination_dir: Some("/tmp/isos".into()),
+            segments: Some(8),
+            ..Template::default()
+        };
+        let vpn = Template {
+            name: "Work VPN".into(),
+            headers: vec![("Authorization".into(), "Bearer token".into())],
+            max_speed: Some(1024),
+            post_command: Some("/usr/bin/true".into()),
+            ..Template::default()
+        };
+        iso.id = insert_template(&iso, &cfg).unwrap();
+        let vpn_id = insert_template(&vpn, &cfg).unwrap();
+        assert!(insert_template(&vpn, &cfg).is_err(), "names are unique");
+
+        let templates = read_templates(&cfg).unwrap();
+        assert_eq!(templates.len(), 2);
+        assert_eq!(templates[0], iso);
+        assert_eq!(templates[1].headers, vpn.headers);
+
+        iso.segments = None;
+        update_template(&iso, &cfg).unwrap();
+        assert_eq!(read_template(iso.id, &cfg).unwrap().segments, None);
+
+        delete_template(vpn_id, &cfg).unwrap();
+        assert!(read_template(vpn_id, &cfg).is_err());
+        assert!(update_template(&Template { id: vpn_id, ..vpn }, &cfg).is_err());
+    }
+
+    #[test]
+    fn test_applied_preset_is_saved() {
+        let cfg = test_config("applied_preset");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://i.pximg.net/img/1.png".into(),
+            file_name: "1.png".into(),
+            destination_path: "/tmp/1.png".into(),
+            applied_preset: Some("Pixiv".into()),
+            original_file_name: Some("1?.png".into()),
+            chunk_size: Some(4 * 1024 * 1024),
+            parent_id: Some(7),
+            byte_range: Some(ByteRange::first(10)),
+            template_id: Some(3),
+            criteria: Some(SuccessCriteria {
+                sha256: Some("ab".into()),
+                ..SuccessCriteria::default()
+            }),
+            referer: Some("https://www.pixiv.net/".into()),
+            ..DownloadRecord::default()
+        };
+        insert_record(&record, 10, &cfg).unwrap();
+        let found = search_by_url("https://i.pximg.net/img/1.png", &cfg).unwrap();
+        assert_eq!(found.applied_preset.as_deref(), Some("Pixiv"));
+        assert_eq!(found.original_file_name.as_deref(), Some("1?.png"));
+        assert_eq!(found.chunk_size, Some(4 * 1024 * 1024));
+        assert_eq!(found.parent_id, Some(7));
+        assert_eq!(found.byte_range, Some(ByteRange::first(10)));
+        assert_eq!(found.template_id, Some(3));
+        assert_eq!(found.criteria, record.criteria);
+        assert_eq!(found.referer, record.referer);
+        assert_eq!(found.priority, 0);
+
+        update_record_priority(found.id, 5, &cfg).unwrap();
+        assert_eq!(read_records_by_ids(&[found.id], &cfg).unwrap()[0].priority, 5);
+        assert!(update_record_priority(found.id + 1, 5, &cfg).is_err());
+
+        assert_eq!(found.failure_note, None);
+        update_failure_note(found.id, Some("Retry later"), &cfg).unwrap();
+        let note = &read_records_by_ids(&[found.id], &cfg).unwrap()[0].failure_note;
+        assert_eq!(note.as_deref(), Some("Retry later"));
+
+        assert_eq!(found.checksum, None);
+        update_record_checksum(found.id, Some("blake3:00"), &cfg).unwrap();
+        let checksum = &read_records_by_ids(&[found.id], &cfg).unwrap()[0].checksum;
+        assert_eq!(checksum.as_deref(), Some("blake3:00"));
+
+        assert!(!found.outdated);
+        update_record_outdated(found.id, true, &cfg).unwrap();
+        assert!(read_records_by_ids(&[found.id], &cfg).unwrap()[0].outdated);
+
+        add_spot_check_failure(found.id, &cfg).unwrap();
+        add_spot_check_failure(found.id, &cfg).unwrap();
+        assert_eq!(read_records_by_ids(&[found.id], &cfg).unwrap()[0].spot_check_failures, 2);
+
+        assert_eq!(found.open_when_done, None);
+        update_open_when_done(found.id, Some(OpenWhenDone::Folder), &cfg).unwrap();
+        let open = read_records_by_ids(&[found.id], &cfg).unwrap()[0].open_when_done;
+        assert_eq!(open, Some(OpenWhenDone::Folder));
+
+        assert_eq!(found.retry_overrides, None);
+        let overrides = retry::RetryOverrides {
+            retries: Some(8),
+            ..retry::RetryOverrides::default()
+        };
+        update_retry_overrides(found.id, Some(overrides), &cfg).unwrap();
+        let saved = read_records_by_ids(&[found.id], &cfg).unwrap()[0].retry_overrides;
+        assert_eq!(saved, Some(overrides));
+    }
+
+    #[test]
+    fn test_redirect_chain_is_saved() {
+        let cfg = test_config("redirect_chain");
+        create_tables(&cfg).unwrap();
+
+        let hop = RedirectHop {
+            url: "https://example.com/file.zip".into(),
+            status: 302,
+            location: "https://mirror.example.com/file.zip".into(),
+        };
+        let record = DownloadRecord {
+            file_url: "https://example.com/file.zip".into(),
+            destination_path: "/tmp/file.zip".into(),
+            redirect_chain: vec![hop.clone()],
+            final_url: Some("https://mirror.example.com/file.zip".into()),
+            ..DownloadRecord::default()
+        };
+        let id = insert_record(&record, 10, &cfg).unwrap();
+        let found = search_by_url("https://example.com/file.zip", &cfg).unwrap();
+        assert_eq!(found.redirect_chain, vec![hop]);
+        assert_eq!(found.final_url.as_deref(), Some("https://mirror.example.com/file.zip"));
+
+        // a resumed download may be sent to another mirror
+        update_redirect_chain(id, "https://cdn2.example.com/file.zip", &[], &cfg).unwrap();
+        let found = search_by_url("https://example.com/file.zip", &cfg).unwrap();
+        assert!(found.redirect_chain.is_empty());
+        assert_eq!(found.final_url.as_deref(), Some("https://cdn2.example.com/file.zip"));
+    }
+
+    #[test]
+    fn test_add_column_to_old_table() {
+        let cfg = test_config("add_column");
+        let conn = get_db(&cfg).unwrap();
+        conn.execute("CREATE TABLE download_record (id INTEGER PRIMARY KEY)", [])
+            .unwrap();
+        add_column_if_missing(&conn, "download_record", "applied_preset", "TEXT NULL").unwrap();
+        // adding it again should be a no-op
+        add_column_if_missing(&conn, "download_record", "applied_preset", "TEXT NULL").unwrap();
+        conn.execute("INSERT INTO download_record (applied_preset) VALUES ('x')", [])
+            .unwrap();
+    }
+
+    #[test]
+    fn test_soft_delete_restore_and_purge() {
+        let cfg = test_config("soft_delete");
+        create_tables(&cfg).unwrap();
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/soft.zip".into(),
+            destination_path: "/tmp/soft.zip".into(),
+            ..DownloadRecord::default()
+        };
+        let id = insert_record(&record, 1000, &cfg).unwrap();
+        save_chunk(&Chunk::new(id, 0, 999), &cfg).unwrap();
+
+        soft_delete_record(id, 100, &cfg).unwrap();
+        assert!(read_download_records(&cfg).unwrap().is_empty());
+        let deleted = search_by_url("https://example.com/soft.zip", &cfg).unwrap();
+        assert_eq!(deleted.deleted_at, Some(100));
+
+        assert!(restore_record(id, &cfg).unwrap());
+        assert!(!restore_record(id, &cfg).unwrap(), "a restored record is not deleted");
+        assert_eq!(read_download_records(&cfg).unwrap().len(), 1);
+
+        soft_delete_record(id, 100, &cfg).unwrap();
+        assert_eq!(purge_deleted_records(99, &cfg).unwrap(), 0, "not expired yet");
+        assert_eq!(purge_deleted_records(100, &cfg).unwrap(), 1);
+        assert!(search_by_url("https://example.com/soft.zip", &cfg).is_err());
+        assert!(get_chunks_by_record(id, &cfg).unwrap().is_empty());
+        assert!(!restore_record(id, &cfg).unwrap());
+    }
+
+    #[test]
+    fn test_bulk_updates() {
+        let cfg = test_config("bulk");
+        create_tables(&cfg).unwrap();
+
+        let mut ids = Vec::new();
+        for i in 0..3 {
+            let record = DownloadRecord {
+                file_url: format!("https://example.com/bulk{i}.zip"),
+                destination_path: format!("/tmp/bulk{i}.zip"),
+                download_status: "Failed".into(),
+                ..DownloadRecord::default()
+            };
+            ids.push(insert_record(&record, 10, &cfg).unwrap());
+        }
+
+        let updated = update_records_status(&[ids[0], ids[1], 9999], "Cancelled", &cfg).unwrap();
+        assert_eq!(updated, vec![ids[0], ids[1]]);
+        let records = read_records_by_ids(&[ids[1], ids[2], 9999], &cfg).unwrap();
+        let statuses: Vec<&str> = records.iter().map(|r| r.download_status.as_str()).collect();
+        assert_eq!(statuses, vec!["Cancelled", "Failed"]);
+
+        let deleted = soft_delete_records(&[ids[0], ids[2], 9999], 100, &cfg).unwrap();
+        assert_eq!(deleted, vec![ids[0], ids[2]]);
+        assert!(soft_delete_records(&[ids[0]], 100, &cfg).unwrap().is_empty());
+        assert_eq!(read_records_by_ids(&ids, &cfg).unwrap().len(), 1);
+        assert!(update_records_status(&[ids[0]], "Pending", &cfg).unwrap().is_empty());
+
+        update_records_status(&[ids[1]], "InProgress", &cfg).unwrap();
+        assert_eq!(replace_status("InProgress", "Interrupted", &cfg).unwrap(), 1);
+        assert_eq!(replace_status("InProgress", "Interrupted", &cfg).unwrap(), 0);
+        let record = read_records_by_ids(&[ids[1]], &cfg).unwrap().pop().unwrap();
+        assert_eq!(record.download_status, "Interrupted");
+        assert_eq!(record_ids_with_status("Interrupted", &cfg).unwrap(), vec![ids[1]]);
+        // the failed record is deleted
+        assert!(record_ids_with_status("Failed", &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_concurrent_writes_wait_for_the_lock() {
+        let cfg = test_config("concurrent_writes");
+        create_tables(&cfg).unwrap();
+        let mode: String = get_db(&cfg)
+            .unwrap()
+            .query_row("PRAGMA journal_mode", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+
+        let record = DownloadRecord {
+            file_url: "https://example.com/big.iso".into(),
+            file_name: "big.iso".into(),
+            destination_path: "/tmp/big.iso".into(),
+            ..DownloadRecord::default()
+        };
+        let ranges: Vec<(u64, u64)> = (0..8).map(|n| (n * 10, n * 10 + 9)).collect();
+        let id = insert_record_with_chunks(&record, 80, &ranges, &cfg).unwrap();
+        let writers: Vec<_> = ranges
+            .iter()
+            .map(|(start, _)| {
+                let (cfg, start) = (cfg.clone(), *start);
+                std::thread::spawn(move || {
+                    for _ in 0..20 {
+                        update_chunk(id, start, "Pending", &cfg).unwrap();
+                        let batch = [(id, start, "Finished".to_string(), 0)];
+                        update_chunks(&batch, &cfg).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(count_chunks(id, &cfg).unwrap(), (0, 8, 0));
+    }
+
+    #[test]
+    fn test_records_are_inserted_with_their_chunks() {
+        let cfg = test_config("record_with_chunks");
+        create_tables(&cfg).unwrap();
+        let record = |n: u32| DownloadRecord {
+            file_url: format!("https://example.com/{n}.zip"),
+            file_name: format!("{n}.zip"),
+            destination_path: format!("/tmp/{n}.zip"),
+            download_status: "Pending".into(),
+            ..DownloadRecord::default()
+        };
+        let planned = insert_record_with_chunks(&record(1), 20, &[(0, 9), (10, 19)], &cfg).unwrap();
+        assert_eq!(get_chunks_by_record(planned, &cfg).unwrap().len(), 2);
+        // the url is taken, so neither the record nor its chunks are saved
+        assert!(insert_record_with_chunks(&record(1), 20, &[(0, 19)], &cfg).is_err());
+        assert_eq!(count_records(&cfg).unwrap(), 1);
+
+        // saved by an older version that crashed before the chunks were saved
+        let orphan = insert_record(&record(2), 20, &cfg).unwrap();
+        let read = read_records_by_ids(&[orphan], &cfg).unwrap().pop().unwrap();
+        assert_eq!(read.download_status, "Pending");
+        let listed = read_download_records(&cfg).unwrap();
+        let listed = listed.iter().find(|r| r.id == orphan).unwrap();
+        assert_eq!(listed.download_status, "Pending", "not finished without any chunks");
+        assert_eq!(listed.downloaded_percentage, 0.0);
+
+        assert_eq!(interrupt_records_without_chunks("Interrupted", &cfg).unwrap(), 1);
+        assert_eq!(record_ids_with_status("Interrupted", &cfg).unwrap(), vec![orphan]);
+        assert_eq!(interrupt_records_without_chunks("Interrupted", &cfg).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_mirrors() {
+        let cfg = test_config("mirrors");
+        create_tables(&cfg).unwrap();
+        let record = DownloadRecord {
+            file_url: "https://example.com/big.iso".into(),
+            destination_path: "/tmp/big.iso".into(),
+            ..DownloadRecord::default()
+        };
+        let id = insert_record(&record, 10, &cfg).unwrap();
+        let urls = ["https://a.example/big.iso".to_string(), "https://b.example/big.iso".to_string()];
+        add_mirrors(id, &urls, &cfg).unwrap();
+        add_mirrors(id, &urls[..1], &cfg).unwrap();
+        assert_eq!(read_mirrors(id, &cfg).unwrap(), urls);
+
+        delete_record(id, &cfg).unwrap();
+        assert!(read_mirrors(id, &cfg).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_read_download_records_empty() {
+        let cfg = test_config("read_empty");
+        create_tables(&cfg).unwrap();
+        let records = read_download_records(&cfg).unwrap();
+        assert!(records.is_empty(), "fresh DB should have no records");
+    }
+}
+
+/// Fetches all chunks for a given download record. Used for resume/retry logic.
+pub fn get_chunks_by_record(record_id: i64, cfg: &Config) -> Result<Vec<Chunk>, Box<dyn Error>> {
+    let conn = get_db(cfg)?;
+    let sql = "SELECT id, record_id, start, end, status, attempts FROM chunk WHERE record_id = ?1";
+    let mut stmt = conn.prepare(sql)?;
+    let chunks = stmt
+        .query_map(params![record_id], |row| {
+            Ok(Chunk {
+                id: row.get(0)?,
+                record_id: row.get(1)?,
+                start: row.get(2)?,
+                end: row.get(3)?,
+                status: row.get(4)?,
+                attempts: row.get(5)?,
+            })
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(chunks)
+}
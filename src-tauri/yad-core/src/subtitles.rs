@@ -0,0 +1,258 @@
+//! This module looks for subtitles of downloaded videos. A subtitle provider is configured with a
+//! search url containing placeholders for the hash, size and name of the video, and must answer
+//! with a json list of the subtitles it has, e.g.
+//! `[{"url": "https://subs.example.com/1.srt", "language": "en"}]`. The hash is the one used by
+//! OpenSubtitles and most players, so the same release matches regardless of its file name.
+
+use std::{
+    fs,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+
+/// The number of bytes hashed at the start and at the end of a video.
+pub const HASH_BLOCK: u64 = 64 * 1024;
+
+/// This struct represents a service subtitles are looked up from.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+pub struct SubtitleProvider {
+    pub name: String,
+    /// The url searched, e.g. `https://subs.example.com/search?hash={hash}&size={size}`. `{hash}`,
+    /// `{size}` and `{name}` are replaced with the hash, size in bytes and file name of the video.
+    pub search_url: String,
+}
+
+impl SubtitleProvider {
+    /// This function checks that the search url is a http url with at least one placeholder.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.search_url.starts_with("http://") && !self.search_url.starts_with("https://") {
+            return Err(format!("Subtitle provider {} needs a http url", self.name));
+        }
+        if !["{hash}", "{size}", "{name}"]
+            .iter()
+            .any(|p| self.search_url.contains(p))
+        {
+            return Err(format!(
+                "The url of subtitle provider {} has no {{hash}}, {{size}} or {{name}}",
+                self.name
+            ));
+        }
+        Ok(())
+    }
+
+    /// This function returns the search url for a video.
+    pub fn url_for(&self, video: &Video) -> String {
+        self.search_url
+            .replace("{hash}", &video.hash)
+            .replace("{size}", &video.size.to_string())
+            .replace("{name}", &encode(&video.name))
+    }
+}
+
+/// This struct represents the video subtitles are looked for.
+#[derive(Debug, Clone)]
+pub struct Video {
+    pub hash: String,
+    pub size: u64,
+    pub name: String,
+}
+
+/// This struct represents a subtitle found by a provider.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SubtitleMatch {
+    pub url: String,
+    #[serde(default)]
+    pub language: Option<String>,
+}
+
+/// This function percent-encodes everything but the unreserved characters of a url.
+fn encode(value: &str) -> String {
+    let mut encoded = String::new();
+    for b in value.bytes() {
+        if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{b:02X}"));
+        }
+    }
+    encoded
+}
+
+/// This function adds up a block of the file as little endian 64 bit words.
+fn sum_block(file: &mut fs::File, from: SeekFrom) -> std::io::Result<u64> {
+    file.seek(from)?;
+    let mut block = vec![0u8; HASH_BLOCK as usize];
+    file.read_exact(&mut block)?;
+    Ok(block.chunks_exact(8).fold(0u64, |sum, word| {
+        sum.wrapping_add(u64::from_le_bytes(word.try_into().unwrap()))
+    }))
+}
+
+/// This function computes the hash subtitle providers look videos up by: the size of the file
+/// plus the sum of its first and last 64 KiB read as 64 bit words.
+///
+/// # Returns
+/// - `Ok(String)`: The hash as 16 hex digits.
+/// - `Err(String)`: If the file cannot be read or is smaller than two blocks.
+pub fn movie_hash(path: &Path) -> Result<String, String> {
+    let mut file = fs::File::open(path).map_err(|e| format!("Failed to open video: {e}"))?;
+    let size = file
+        .metadata()
+        .map_err(|e| format!("Failed to read video size: {e}"))?
+        .len();
+    if size < 2 * HASH_BLOCK {
+        return Err("The video is too small to be hashed".into());
+    }
+    let head = sum_block(&mut file, SeekFrom::Start(0))
+        .map_err(|e| format!("Failed to hash video: {e}"))?;
+    let tail = sum_block(&mut file, SeekFrom::End(-(HASH_BLOCK as i64)))
+        .map_err(|e| format!("Failed to hash video: {e}"))?;
+    Ok(format!("{:016x}", size.wrapping_add(head).wrapping_add(tail)))
+}
+
+/// This function reads the answer of a provider. Relative subtitle urls are resolved against the
+/// search url.
+pub fn parse_matches(body: &str, search_url: &str) -> Result<Vec<SubtitleMatch>, String> {
+    let mut matches: Vec<SubtitleMatch> =
+        serde_json::from_str(body).map_err(|e| format!("Invalid subtitle list: {e}"))?;
+    if let Ok(base) = Url::parse(search_url) {
+        for m in &mut matches {
+            if let Ok(url) = base.join(&m.url) {
+                m.url = url.to_string();
+            }
+        }
+    }
+    Ok(matches)
+}
+
+/// This function picks one subtitle for every wanted language, in the order of the languages.
+/// The first subtitle is picked when no language is wanted.
+pub fn pick<'a>(matches: &'a [SubtitleMatch], languages: &[String]) -> Vec<&'a SubtitleMatch> {
+    if languages.is_empty() {
+        return matches.iter().take(1).collect();
+    }
+    languages
+        .iter()
+        .filter_map(|lang| {
+            matches.iter().find(|m| {
+                m.language
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(lang))
+            })
+        })
+        .collect()
+}
+
+/// This function returns the name a subtitle is saved as, next to the video it belongs to so that
+/// players pick it up, e.g. `movie.en.srt` for `movie.mkv`.
+pub fn file_name(video_name: &str, language: Option<&str>) -> String {
+    let stem = Path::new(video_name)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(video_name);
+    match language {
+        Some(lang) => format!("{stem}.{lang}.srt"),
+        None => format!("{stem}.srt"),
+    }
+}
+
+/// This function asks a provider for the subtitles of a video.
+pub async fn search(
+    client: &Client,
+    provider: &SubtitleProvider,
+    video: &Video,
+) -> Result<Vec<SubtitleMatch>, String> {
+    let url = provider.url_for(video);
+    let body = client
+        .get(&url)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("{} did not answer: {e}", provider.name))?
+        .text()
+        .await
+        .map_err(|e| format!("{} did not answer: {e}", provider.name))?;
+    parse_matches(&body, &url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_movie_hash() {
+        let dir = std::env::temp_dir().join("yad_test_movie_hash");
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("zeros.mkv");
+        fs::write(&path, vec![0u8; 2 * HASH_BLOCK as usize]).unwrap();
+        // only the size is left when every word is zero
+        assert_eq!(movie_hash(&path).unwrap(), "0000000000020000");
+
+        let mut content = vec![0u8; 3 * HASH_BLOCK as usize];
+        content[0] = 1;
+        content[3 * HASH_BLOCK as usize - 8] = 2;
+        // the middle block is not hashed
+        content[HASH_BLOCK as usize + 8] = 9;
+        fs::write(&path, content).unwrap();
+        assert_eq!(movie_hash(&path).unwrap(), "0000000000030003");
+
+        fs::write(&path, b"tiny").unwrap();
+        assert!(movie_hash(&path).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_url_for() {
+        let provider = SubtitleProvider {
+            name: "Example".into(),
+            search_url: "https://subs.example.com/search?hash={hash}&size={size}&q={name}".into(),
+        };
+        assert!(provider.validate().is_ok());
+        let video = Video {
+            hash: "8e245d9679d31e12".into(),
+            size: 12909756,
+            name: "The Movie (2020).mkv".into(),
+        };
+        assert_eq!(
+            provider.url_for(&video),
+            "https://subs.example.com/search?hash=8e245d9679d31e12&size=12909756&q=The%20Movie%20%282020%29.mkv"
+        );
+
+        let provider = SubtitleProvider {
+            name: "Static".into(),
+            search_url: "https://subs.example.com/all".into(),
+        };
+        assert!(provider.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_and_pick() {
+        let body = r#"[
+            {"url": "/dl/1.srt", "language": "fr"},
+            {"url": "https://cdn.example.com/2.srt", "language": "EN"},
+            {"url": "/dl/3.srt"}
+        ]"#;
+        let matches = parse_matches(body, "https://subs.example.com/search?hash=1").unwrap();
+        assert_eq!(matches[0].url, "https://subs.example.com/dl/1.srt");
+        assert_eq!(matches[1].url, "https://cdn.example.com/2.srt");
+
+        let picked = pick(&matches, &["en".into(), "de".into(), "fr".into()]);
+        let urls: Vec<&str> = picked.iter().map(|m| m.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            ["https://cdn.example.com/2.srt", "https://subs.example.com/dl/1.srt"]
+        );
+        assert_eq!(pick(&matches, &[]), vec![&matches[0]]);
+        assert!(parse_matches("not json", "https://subs.example.com").is_err());
+    }
+
+    #[test]
+    fn test_file_name() {
+        assert_eq!(file_name("movie.mkv", Some("en")), "movie.en.srt");
+        assert_eq!(file_name("movie.2020.mkv", None), "movie.2020.srt");
+    }
+}
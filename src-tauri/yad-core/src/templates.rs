@@ -0,0 +1,173 @@
+//! This module handles download templates. A template is a named set of options, e.g. "Work VPN"
+//! or "Linux ISO", which is applied when a download is added instead of entering the same
+//! destination, headers and limits every time. Templates are stored in the database.
+
+use reqwest::header::{HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+
+use crate::{settings::Settings, throttle};
+
+/// This struct represents a download template. Options that are not set fall back to the
+/// settings.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default)]
+pub struct Template {
+    pub id: i64,
+    pub name: String,
+    /// The folder the file is saved in.
+    pub destination_dir: Option<String>,
+    /// Extra headers sent with every request, e.g. an authorization token.
+    pub headers: Vec<(String, String)>,
+    /// The maximum speed in bytes per second. The global limit still applies if it is lower.
+    pub max_speed: Option<u64>,
+    /// How many chunks are downloaded at the same time.
+    pub segments: Option<usize>,
+    /// A program run with the path of the file once the download has finished.
+    pub post_command: Option<String>,
+}
+
+impl Template {
+    /// This function checks that the template can be applied.
+    ///
+    /// # Returns
+    /// - `Ok(())`: if the template is valid.
+    /// - `Err(String)`: a message describing the invalid option.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("Template has no name".into());
+        }
+        if self.segments == Some(0) {
+            return Err(format!(
+                "Template {} must use at least 1 segment",
+                self.name
+            ));
+        }
+        for (name, value) in &self.headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Template {} has an invalid header name {name}", self.name))?;
+            HeaderValue::from_str(value).map_err(|_| {
+                format!(
+                    "Template {} has an invalid value for header {name}",
+                    self.name
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// This function adds the headers of the template to `headers`, replacing headers with the
+    /// same name.
+    pub fn apply_headers(&self, headers: &mut Vec<(String, String)>) {
+        for (name, value) in &self.headers {
+            headers.retain(|(n, _)| !n.eq_ignore_ascii_case(name));
+            headers.push((name.clone(), value.clone()));
+        }
+    }
+}
+
+/// This function returns how many chunks of a download using `template` are downloaded at the
+/// same time.
+pub fn max_concurrent_chunks(template: Option<&Template>, settings: &Settings) -> usize {
+    template
+        .and_then(|t| t.segments)
+        .unwrap_or(settings.max_concurrent_chunks)
+}
+
+/// This function returns the speed limit of a download using `template`, the lower of the
+/// template and the global limit. 0 means unlimited.
+pub fn max_speed(template: Option<&Template>, settings: &Settings) -> u64 {
+    let speed = template.and_then(|t| t.max_speed).unwrap_or(0);
+    throttle::lowest_limit(&[speed, settings.max_speed])
+}
+
+/// This function returns the command run once a download using `template` has finished, if any.
+pub fn post_command(template: Option<&Template>) -> Option<&str> {
+    template
+        .and_then(|t| t.post_command.as_deref())
+        .filter(|c| !c.trim().is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn template() -> Template {
+        Template {
+            name: "Work VPN".into(),
+            ..Template::default()
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(template().validate().is_ok());
+        assert!(Template::default().validate().is_err());
+        let t = Template {
+            segments: Some(0),
+            ..template()
+        };
+        assert!(t.validate().is_err());
+        let t = Template {
+            headers: vec![("Bad Header".into(), "x".into())],
+            ..template()
+        };
+        assert!(t.validate().is_err());
+    }
+
+    #[test]
+    fn test_apply_headers_replaces_same_name() {
+        let t = Template {
+            headers: vec![("referer".into(), "https://intranet.example.com/".into())],
+            ..template()
+        };
+        let mut headers = vec![
+            ("Referer".to_string(), "https://example.com/".to_string()),
+            ("Origin".to_string(), "https://example.com".to_string()),
+        ];
+        t.apply_headers(&mut headers);
+        assert_eq!(
+            headers,
+            vec![
+                ("Origin".to_string(), "https://example.com".to_string()),
+                (
+                    "referer".to_string(),
+                    "https://intranet.example.com/".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_limits_fall_back_to_settings() {
+        let settings = Settings {
+            max_speed: 1000,
+            max_concurrent_chunks: 4,
+            ..Settings::default()
+        };
+        assert_eq!(max_concurrent_chunks(None, &settings), 4);
+        assert_eq!(max_speed(None, &settings), 1000);
+
+        let t = Template {
+            segments: Some(8),
+            max_speed: Some(500),
+            ..template()
+        };
+        assert_eq!(max_concurrent_chunks(Some(&t), &settings), 8);
+        assert_eq!(max_speed(Some(&t), &settings), 500);
+
+        let t = Template {
+            max_speed: Some(5000),
+            ..template()
+        };
+        assert_eq!(
+            max_speed(Some(&t), &settings),
+            1000,
+            "the global limit is lower"
+        );
+        let unlimited = Settings {
+            max_speed: 0,
+            ..settings
+        };
+        assert_eq!(max_speed(Some(&t), &unlimited), 5000);
+    }
+}
@@ -0,0 +1,148 @@
+//! This module limits the transfer speed of downloads, each on its own and all of them together.
+//! The limits can be changed while the downloads are running.
+
+use std::{
+    sync::{Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+
+/// This struct is a token bucket. Every byte read takes a token, tokens are added at the limit
+/// and up to one second worth of them can be saved up, so short pauses do not cause bursts.
+#[derive(Debug)]
+pub struct RateLimiter {
+    state: Mutex<RateState>,
+}
+
+#[derive(Debug)]
+struct RateState {
+    /// Bytes per second. 0 means unlimited.
+    rate: u64,
+    /// Negative when more bytes have been read than the limit allows so far.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        RateLimiter::new(0)
+    }
+}
+
+impl RateLimiter {
+    /// This function creates a limiter allowing `rate` bytes per second. 0 means unlimited.
+    pub fn new(rate: u64) -> Self {
+        RateLimiter {
+            state: Mutex::new(RateState {
+                rate,
+                tokens: 0.0,
+                refilled: Instant::now(),
+            }),
+        }
+    }
+
+    /// This function changes the limit. The bucket is emptied so that the bytes saved up under
+    /// the old limit do not count against the new one.
+    pub fn set_rate(&self, rate: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.rate = rate;
+        state.tokens = 0.0;
+        state.refilled = Instant::now();
+    }
+
+    /// This function returns the limit in bytes per second. 0 means unlimited.
+    pub fn rate(&self) -> u64 {
+        self.state.lock().unwrap().rate
+    }
+
+    /// This function takes tokens for `bytes` and returns how long the caller should wait
+    /// before reading more.
+    pub fn delay_for(&self, bytes: u64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        if state.rate == 0 {
+            return Duration::ZERO;
+        }
+        let rate = state.rate as f64;
+        let now = Instant::now();
+        let refill = now.duration_since(state.refilled).as_secs_f64() * rate;
+        state.tokens = (state.tokens + refill).min(rate) - bytes as f64;
+        state.refilled = now;
+        if state.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-state.tokens / rate)
+        }
+    }
+}
+
+/// This function returns the limiter shared by every download, set to `Settings::max_total_speed`
+/// or the bandwidth rule of the time of day, see `bandwidth`.
+pub fn global() -> &'static RateLimiter {
+    static GLOBAL: OnceLock<RateLimiter> = OnceLock::new();
+    GLOBAL.get_or_init(RateLimiter::default)
+}
+
+/// This function returns the lowest of several limits, ignoring the ones that are 0 (unlimited).
+///
+/// # Example
+/// ```ignore
+/// assert_eq!(throttle::lowest_limit(&[0, 500, 1000]), 500);
+/// assert_eq!(throttle::lowest_limit(&[0, 0]), 0);
+/// ```
+pub fn lowest_limit(limits: &[u64]) -> u64 {
+    limits.iter().copied().filter(|l| *l > 0).min().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_never_waits() {
+        let limiter = RateLimiter::new(0);
+        assert_eq!(limiter.delay_for(10 * 1024 * 1024), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_limited_waits_proportionally() {
+        let limiter = RateLimiter::new(1000);
+        let wait = limiter.delay_for(2000);
+        assert!(wait > Duration::from_millis(1900), "waited {wait:?}");
+        assert!(wait <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_waits_add_up_across_reads() {
+        let limiter = RateLimiter::new(1000);
+        limiter.delay_for(500);
+        let wait = limiter.delay_for(500);
+        assert!(wait > Duration::from_millis(900), "waited {wait:?}");
+    }
+
+    #[test]
+    fn test_idle_time_becomes_tokens() {
+        let limiter = RateLimiter::new(1_000_000);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(limiter.delay_for(10_000), Duration::ZERO);
+        let wait = limiter.delay_for(2_000_000);
+        assert!(wait > Duration::from_millis(900), "waited {wait:?}");
+    }
+
+    #[test]
+    fn test_set_rate_resets_window() {
+        let limiter = RateLimiter::new(1000);
+        limiter.delay_for(5000);
+        limiter.set_rate(0);
+        assert_eq!(limiter.delay_for(5000), Duration::ZERO);
+        limiter.set_rate(1000);
+        assert!(limiter.delay_for(1000) <= Duration::from_secs(1));
+        assert_eq!(limiter.rate(), 1000);
+    }
+
+    #[test]
+    fn test_lowest_limit() {
+        assert_eq!(lowest_limit(&[0, 500, 1000]), 500);
+        assert_eq!(lowest_limit(&[2000]), 2000);
+        assert_eq!(lowest_limit(&[0, 0]), 0);
+        assert_eq!(lowest_limit(&[]), 0);
+    }
+}
@@ -0,0 +1,257 @@
+//! This module holds the certificate options shared by HTTP and FTPS downloads: the certificate
+//! authorities trusted besides the ones of the system, e.g. the one of a company signing the
+//! certificates of its internal servers, and the certificate pins of `pins`. Pins are enforced
+//! during the TLS handshake, so no request made with a client of the settings can reach a pinned
+//! host that presents another certificate.
+
+use std::{fs, sync::Arc};
+
+use reqwest::ClientBuilder;
+use tokio_rustls::{
+    rustls::{
+        client::{
+            danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+            WebPkiServerVerifier,
+        },
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, ServerName, UnixTime},
+        ClientConfig, DigitallySignedStruct, Error, RootCertStore, SignatureScheme,
+    },
+    TlsConnector,
+};
+
+use crate::{pins::CertPin, settings::Settings};
+
+/// This function reads the certificates of the authorities in the PEM files at `paths`. A file
+/// may hold more than one certificate, e.g. a root and an intermediate.
+///
+/// # Returns
+/// The DER encoded certificates.
+pub fn read_authorities(paths: &[String]) -> Result<Vec<Vec<u8>>, String> {
+    let mut certificates = Vec::new();
+    for path in paths {
+        let pem = fs::read(path).map_err(|e| format!("Failed to read certificate {path}: {e}"))?;
+        let stack = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Invalid certificate in {path}: {e}"))?;
+        if stack.is_empty() {
+            return Err(format!("{path} is not a PEM certificate"));
+        }
+        certificates.extend(stack.into_iter().map(|c| c.to_vec()));
+    }
+    Ok(certificates)
+}
+
+/// This struct verifies the certificate of a server like any client does, then refuses it if the
+/// host is pinned and the certificate does not match its pin.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<CertPin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        crate::pins::check_certificate(&self.pins, &server_name.to_str(), Some(end_entity))
+            .map_err(Error::General)?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+/// This function returns the TLS configuration of a client that trusts the certificate
+/// authorities of the system and of the settings, and refuses pinned hosts that present a
+/// certificate not in their pin.
+fn client_config(settings: &Settings) -> Result<ClientConfig, String> {
+    let mut roots = RootCertStore::empty();
+    // the system has no certificates to offer on some machines, then only the settings count
+    roots.add_parsable_certificates(rustls_native_certs::load_native_certs().certs);
+    for der in read_authorities(&settings.ca_certificates)? {
+        roots
+            .add(CertificateDer::from(der))
+            .map_err(|e| format!("Invalid certificate: {e}"))?;
+    }
+    let provider = Arc::new(ring::default_provider());
+    let inner = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| format!("Failed to set up TLS: {e}"))?;
+    let verifier = PinnedVerifier {
+        inner,
+        pins: settings.cert_pins.clone(),
+    };
+    Ok(ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {e}"))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth())
+}
+
+/// This function makes an http client trust the certificate authorities of the settings and
+/// enforce the certificate pins, which also lets `pins::check` see the certificate of a response.
+pub fn apply(mut builder: ClientBuilder, settings: &Settings) -> Result<ClientBuilder, String> {
+    if !settings.cert_pins.is_empty() {
+        let mut config = client_config(settings)?;
+        // a preconfigured TLS configuration is used as is, without the protocols of the client
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        return Ok(builder.use_preconfigured_tls(config).tls_info(true));
+    }
+    for der in read_authorities(&settings.ca_certificates)? {
+        let certificate = reqwest::Certificate::from_der(&der)
+            .map_err(|e| format!("Invalid certificate: {e}"))?;
+        builder = builder.add_root_certificate(certificate);
+    }
+    Ok(builder)
+}
+
+/// This function returns the TLS connector of FTPS connections, which trusts the certificate
+/// authorities of the system and of the settings and enforces the certificate pins.
+///
+/// Every connection made with the connector shares its cache of TLS sessions, so the data
+/// connections of an FTPS session resume the TLS session of its control connection, which many
+/// servers insist on, e.g. vsftpd with `require_ssl_reuse`.
+pub fn ftps_connector(settings: &Settings) -> Result<TlsConnector, String> {
+    Ok(TlsConnector::from(Arc::new(client_config(settings)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_config;
+    use std::path::Path;
+
+    use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{PrivateKeyDer, PrivatePkcs8KeyDer},
+            ServerConfig,
+        },
+        TlsAcceptor,
+    };
+
+    #[test]
+    fn test_read_authorities() {
+        let cfg = test_config("trust_authorities");
+        let dir = Path::new(&cfg.config_dir);
+        fs::create_dir_all(dir).unwrap();
+        let not_pem = dir.join("ca.txt");
+        fs::write(&not_pem, b"not a certificate").unwrap();
+        let not_pem = not_pem.to_str().unwrap().to_string();
+
+        assert_eq!(read_authorities(&[]).unwrap(), Vec::<Vec<u8>>::new());
+        let err = read_authorities(&[not_pem]).unwrap_err();
+        assert!(err.contains("not a PEM certificate"), "{err}");
+        let missing = dir.join("missing.pem").to_str().unwrap().to_string();
+        assert!(read_authorities(&[missing]).is_err());
+    }
+
+    #[test]
+    fn test_pins_enforced_by_client() {
+        let cfg = test_config("trust_pins");
+        let dir = Path::new(&cfg.config_dir);
+        fs::create_dir_all(dir).unwrap();
+        let ca_key = KeyPair::generate().unwrap();
+        let mut ca_params = CertificateParams::new(Vec::new()).unwrap();
+        ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = ca_params.self_signed(&ca_key).unwrap();
+        let key = KeyPair::generate().unwrap();
+        let certificate = CertificateParams::new(vec!["localhost".to_string()])
+            .unwrap()
+            .signed_by(&key, &ca, &ca_key)
+            .unwrap();
+        let ca_path = dir.join("ca.pem");
+        fs::write(&ca_path, ca.pem()).unwrap();
+        let fingerprint = crate::pins::fingerprint(certificate.der());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key.serialize_der()));
+        let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![certificate.der().clone()], key)
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let port = listener.local_addr().unwrap().port();
+            tokio::spawn(async move {
+                loop {
+                    let (stream, _) = listener.accept().await.unwrap();
+                    let Ok(mut stream) = acceptor.accept(stream).await else {
+                        continue;
+                    };
+                    let mut request = [0; 1024];
+                    let _ = stream.read(&mut request).await;
+                    let reply =
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok";
+                    let _ = stream.write_all(reply).await;
+                    let _ = stream.shutdown().await;
+                }
+            });
+
+            let url = format!("https://localhost:{port}/");
+            let get = |fingerprint: &str| {
+                let settings = Settings {
+                    ca_certificates: vec![ca_path.to_str().unwrap().to_string()],
+                    cert_pins: vec![CertPin {
+                        host: "localhost".into(),
+                        fingerprints: vec![fingerprint.to_string()],
+                    }],
+                    ..Settings::default()
+                };
+                let client = apply(reqwest::Client::builder(), &settings)
+                    .unwrap()
+                    .build()
+                    .unwrap();
+                let url = url.clone();
+                async move { client.get(url).send().await }
+            };
+            let response = get(&fingerprint).await.unwrap();
+            assert_eq!(response.text().await.unwrap(), "ok");
+            assert!(get(&"00".repeat(32)).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,100 @@
+//! This module formats sizes and speeds the way the user prefers. Sizes are either SI, where a MB
+//! is 1000 KB, or IEC, where a MiB is 1024 KiB. Internet plans are sold in bits per second, so
+//! speeds can also be shown in bits, e.g. `100 Mbit/s` instead of `12.5 MB/s`.
+
+use serde::{Deserialize, Serialize};
+
+/// This enum represents the units sizes are shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitSystem {
+    /// Powers of 1000: kB, MB, GB.
+    Si,
+    /// Powers of 1024: KiB, MiB, GiB.
+    #[default]
+    Iec,
+}
+
+/// This struct represents the preferred units, see `Settings::size_units`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Units {
+    pub system: UnitSystem,
+    /// Whether speeds are shown in bits instead of bytes per second.
+    pub speed_in_bits: bool,
+}
+
+impl Units {
+    /// This function formats a size, e.g. `12.5 MiB`.
+    pub fn size(&self, bytes: u64) -> String {
+        match self.system {
+            UnitSystem::Si => scale(bytes as f64, 1000.0, &["B", "kB", "MB", "GB", "TB"]),
+            UnitSystem::Iec => scale(bytes as f64, 1024.0, &["B", "KiB", "MiB", "GiB", "TiB"]),
+        }
+    }
+
+    /// This function formats a speed given in bytes per second, e.g. `12.5 MB/s` or
+    /// `100 Mbit/s`.
+    pub fn speed(&self, bytes_per_sec: u64) -> String {
+        let (value, units): (f64, [&str; 4]) = match (self.system, self.speed_in_bits) {
+            (UnitSystem::Si, false) => (bytes_per_sec as f64, ["B/s", "kB/s", "MB/s", "GB/s"]),
+            (UnitSystem::Iec, false) => (bytes_per_sec as f64, ["B/s", "KiB/s", "MiB/s", "GiB/s"]),
+            (UnitSystem::Si, true) => (
+                bytes_per_sec as f64 * 8.0,
+                ["bit/s", "kbit/s", "Mbit/s", "Gbit/s"],
+            ),
+            (UnitSystem::Iec, true) => (
+                bytes_per_sec as f64 * 8.0,
+                ["bit/s", "Kibit/s", "Mibit/s", "Gibit/s"],
+            ),
+        };
+        let base = match self.system {
+            UnitSystem::Si => 1000.0,
+            UnitSystem::Iec => 1024.0,
+        };
+        scale(value, base, &units)
+    }
+}
+
+/// This function divides `value` by `base` until it is below it, rounded to one decimal.
+fn scale(mut value: f64, base: f64, units: &[&str]) -> String {
+    let mut unit = 0;
+    while value >= base && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    format!("{} {}", (value * 10.0).round() / 10.0, units[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sizes() {
+        let iec = Units::default();
+        assert_eq!(iec.size(512), "512 B");
+        assert_eq!(iec.size(1536), "1.5 KiB");
+        assert_eq!(iec.size(5 * 1024 * 1024 * 1024), "5 GiB");
+        let si = Units {
+            system: UnitSystem::Si,
+            speed_in_bits: false,
+        };
+        assert_eq!(si.size(1500), "1.5 kB");
+        assert_eq!(si.size(5_000_000_000), "5 GB");
+    }
+
+    #[test]
+    fn test_speeds() {
+        let bits = Units {
+            system: UnitSystem::Si,
+            speed_in_bits: true,
+        };
+        assert_eq!(bits.speed(12_500_000), "100 Mbit/s");
+        assert_eq!(bits.speed(100), "800 bit/s");
+        let bytes = Units {
+            system: UnitSystem::Iec,
+            speed_in_bits: false,
+        };
+        assert_eq!(bytes.speed(3 * 1024 * 1024 / 2), "1.5 MiB/s");
+    }
+}
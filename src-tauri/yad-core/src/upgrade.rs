@@ -0,0 +1,235 @@
+//! This module checks the unfinished downloads after YAD was updated. A download that runs for
+//! days may have been started by the previous version, which may have planned its chunks or kept
+//! its file differently, and the database was migrated since. Before anything resumes, each
+//! unfinished download is checked against its file: what can be fixed is fixed so that it carries
+//! on where it stopped, the rest is marked as failed with a note saying what to do, rather than
+//! writing into a file that does not match its chunks.
+
+use std::{fs, path::PathBuf};
+
+use crate::{
+    config::Config,
+    files,
+    storage::{self, Chunk, DownloadRecord},
+};
+
+/// The key of the version of YAD that last opened the database, see `storage::read_meta`.
+pub const VERSION_KEY: &str = "app_version";
+
+/// This enum represents what the check of an unfinished download found.
+#[derive(Debug, PartialEq)]
+pub enum Finding {
+    /// The download can resume as it is.
+    Valid,
+    /// The file ends before the finished chunks do. The chunks from this byte on are downloaded
+    /// again.
+    Truncated { from: u64 },
+    /// The download cannot resume safely, the note says what to do.
+    NeedsAction(String),
+}
+
+/// This function checks the chunks of an unfinished download against its file.
+///
+/// # Arguments
+/// - `record`: The download.
+/// - `chunks`: Its saved chunks.
+/// - `file_len`: The size of the file written so far, `None` if there is none.
+pub fn check(record: &DownloadRecord, chunks: &[Chunk], file_len: Option<u64>) -> Finding {
+    // downloads of unknown size and parts of files start over anyway
+    if record.chunk_size.is_none() || record.file_size == 0 || record.byte_range.is_some() {
+        return Finding::Valid;
+    }
+    let size = record.file_size;
+    if chunks.iter().any(|c| c.start > c.end || c.end >= size) {
+        return Finding::NeedsAction(
+            "The saved chunks do not fit the size of the file after the update. Delete the \
+             download and add it again."
+                .to_string(),
+        );
+    }
+    let Some(finished_end) = chunks
+        .iter()
+        .filter(|c| c.status == "Finished")
+        .map(|c| c.end)
+        .max()
+    else {
+        return Finding::Valid;
+    };
+    match file_len {
+        None => Finding::Truncated { from: 0 },
+        Some(len) if len > size => Finding::NeedsAction(format!(
+            "The partial file is {len} bytes, bigger than the {size} bytes of the download. \
+             Delete the download and add it again."
+        )),
+        Some(len) if len <= finished_end => Finding::Truncated { from: len },
+        Some(_) => Finding::Valid,
+    }
+}
+
+/// This function returns the size of the file an unfinished download writes to. Downloads started
+/// before they were written to a working file still write to their destination.
+fn partial_file_len(record: &DownloadRecord) -> Option<u64> {
+    [
+        files::working_path(record.id, &record.destination_path),
+        PathBuf::from(&record.destination_path),
+    ]
+    .iter()
+    .find_map(|p| fs::metadata(p).ok())
+    .map(|m| m.len())
+}
+
+/// This function checks every unfinished download, see `check`, and fixes or marks it.
+///
+/// # Returns
+/// The ids of the downloads whose lost chunks are downloaded again, and of the ones marked as
+/// failed.
+pub fn revalidate(cfg: &Config) -> Result<(Vec<i64>, Vec<i64>), String> {
+    let records = storage::read_download_records(cfg)
+        .map_err(|e| format!("Failed to read the downloads: {e}"))?;
+    let (mut repaired, mut flagged) = (Vec::new(), Vec::new());
+    for record in records.iter().filter(|r| r.download_status != "Finished") {
+        let finding = match fs::create_dir_all(&record.destination_dir) {
+            Err(e) => Finding::NeedsAction(format!(
+                "The folder {} cannot be written to ({e}). Move the download to another folder.",
+                record.destination_dir
+            )),
+            Ok(()) => {
+                let chunks = storage::get_chunks_by_record(record.id, cfg).unwrap_or_default();
+                check(record, &chunks, partial_file_len(record))
+            }
+        };
+        match finding {
+            Finding::Valid => {}
+            Finding::Truncated { from } => {
+                eprintln!("Download {} lost the bytes from {from} on", record.id);
+                if storage::reset_chunks_from(record.id, from, cfg).is_ok() {
+                    repaired.push(record.id);
+                }
+            }
+            Finding::NeedsAction(note) => {
+                eprintln!("Download {} cannot resume: {note}", record.id);
+                let _ = storage::update_records_status(&[record.id], "Failed", cfg);
+                let _ = storage::update_failure_note(record.id, Some(&note), cfg);
+                flagged.push(record.id);
+            }
+        }
+    }
+    Ok((repaired, flagged))
+}
+
+/// This function checks the unfinished downloads when `version` is not the version of YAD that
+/// last opened the database. It must be called on start, after the database was migrated and
+/// before any download resumes.
+pub fn after_update(version: &str, cfg: &Config) {
+    let last = storage::read_meta(VERSION_KEY, cfg).ok().flatten();
+    if last.as_deref() == Some(version) {
+        return;
+    }
+    match revalidate(cfg) {
+        Ok((repaired, flagged)) => {
+            println!(
+                "updated from {} to {version}: {} downloads repaired, {} need attention",
+                last.as_deref().unwrap_or("an older version"),
+                repaired.len(),
+                flagged.len()
+            );
+        }
+        // checked again on the next start
+        Err(e) => {
+            eprintln!("failed to check the downloads after the update because {e}");
+            return;
+        }
+    }
+    if let Err(e) = storage::write_meta(VERSION_KEY, version, cfg) {
+        eprintln!("failed to save the version because {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunks::plan_chunks;
+
+    fn record() -> DownloadRecord {
+        DownloadRecord {
+            file_size: 30,
+            chunk_size: Some(10),
+            ..DownloadRecord::default()
+        }
+    }
+
+    fn chunks(statuses: [&str; 3]) -> Vec<Chunk> {
+        plan_chunks(30, 10)
+            .into_iter()
+            .zip(statuses)
+            .map(|((start, end), status)| Chunk {
+                status: status.to_string(),
+                ..Chunk::new(1, start, end)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_check() {
+        let two_finished = chunks(["Finished", "Finished", "Pending"]);
+        assert_eq!(check(&record(), &two_finished, Some(30)), Finding::Valid);
+        assert_eq!(
+            check(&record(), &chunks(["Pending"; 3]), None),
+            Finding::Valid,
+            "nothing to lose"
+        );
+        assert_eq!(
+            check(&record(), &two_finished, None),
+            Finding::Truncated { from: 0 }
+        );
+        // written by a version that did not allocate the file up front
+        assert_eq!(
+            check(&record(), &two_finished, Some(15)),
+            Finding::Truncated { from: 15 }
+        );
+        assert!(matches!(
+            check(&record(), &two_finished, Some(40)),
+            Finding::NeedsAction(_)
+        ));
+
+        let mut past_the_end = two_finished.clone();
+        past_the_end[2].end = 35;
+        assert!(matches!(
+            check(&record(), &past_the_end, Some(30)),
+            Finding::NeedsAction(_)
+        ));
+        let unknown_size = DownloadRecord {
+            chunk_size: None,
+            ..record()
+        };
+        assert_eq!(check(&unknown_size, &past_the_end, None), Finding::Valid);
+    }
+
+    #[test]
+    fn test_after_update_runs_once_per_version() {
+        let cfg = storage::test_config("upgrade");
+        storage::create_tables(&cfg).unwrap();
+        let r = DownloadRecord {
+            file_url: "https://example.com/big.iso".into(),
+            file_name: "big.iso".into(),
+            destination_dir: cfg.download_dir.clone(),
+            destination_path: format!("{}/big.iso", cfg.download_dir),
+            download_status: "Interrupted".into(),
+            chunk_size: Some(10),
+            ..DownloadRecord::default()
+        };
+        let id = storage::insert_record_with_chunks(&r, 30, &plan_chunks(30, 10), &cfg).unwrap();
+        storage::update_chunk(id, 0, "Finished", &cfg).unwrap();
+        // the partial file went missing, e.g. the tmp folder was emptied
+        after_update("2.0.0", &cfg);
+        assert_eq!(storage::count_chunks(id, &cfg).unwrap(), (3, 0, 0));
+        assert_eq!(
+            storage::read_meta(VERSION_KEY, &cfg).unwrap().as_deref(),
+            Some("2.0.0")
+        );
+
+        storage::update_chunk(id, 0, "Finished", &cfg).unwrap();
+        after_update("2.0.0", &cfg);
+        assert_eq!(storage::count_chunks(id, &cfg).unwrap(), (2, 1, 0));
+    }
+}
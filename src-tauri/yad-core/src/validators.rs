@@ -0,0 +1,97 @@
+//! This module tells whether the file on the server changed since its download started. The
+//! `ETag` of the file, or its `Last-Modified` date when it has none, is saved when the download
+//! starts. Resuming compares it with what the server sends now, and every chunk is requested with
+//! it in `If-Range`, which makes the server send the whole new file instead of a part of it if the
+//! file changed. Either way the bytes of two versions of the file are never put together.
+
+use reqwest::header::{HeaderMap, CONTENT_LENGTH, ETAG, LAST_MODIFIED};
+
+/// This function returns the value that identifies the version of a file, from the headers of a
+/// response. Weak ETags, e.g. `W/"abc"`, cannot be used in `If-Range` and are skipped.
+pub fn validator(headers: &HeaderMap) -> Option<String> {
+    let header = |name| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::trim)
+            .filter(|v| !v.is_empty())
+    };
+    header(ETAG)
+        .filter(|etag| !etag.starts_with("W/"))
+        .or_else(|| header(LAST_MODIFIED))
+        .map(str::to_string)
+}
+
+/// This function checks whether a response is for another version of the file than the one
+/// identified by `saved`. The same kind of value is compared, so a server that only sent a date
+/// before and sends an ETag now is compared by date. Nothing has changed as far as can be told
+/// when the server sends neither.
+pub fn changed(saved: Option<&str>, headers: &HeaderMap) -> bool {
+    let Some(saved) = saved else {
+        return false;
+    };
+    // ETags are quoted, dates are not
+    let name = if saved.starts_with('"') {
+        ETAG
+    } else {
+        LAST_MODIFIED
+    };
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|current| current.trim() != saved)
+}
+
+/// This function checks whether the file of a finished download is no longer the one on the
+/// server: its validator changed, see `changed`, or the server announces another size. A `size`
+/// of 0 is not compared, e.g. for parts of files.
+pub fn outdated(saved: Option<&str>, size: u64, headers: &HeaderMap) -> bool {
+    let announced = headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<u64>().ok());
+    changed(saved, headers) || (size > 0 && announced.is_some_and(|a| a != size))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.insert(*name, value.parse().unwrap());
+        }
+        map
+    }
+
+    const DATE: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    #[test]
+    fn test_validator() {
+        let both = headers(&[("etag", "\"v1\""), ("last-modified", DATE)]);
+        assert_eq!(validator(&both).as_deref(), Some("\"v1\""));
+        let weak = headers(&[("etag", "W/\"v1\""), ("last-modified", DATE)]);
+        assert_eq!(validator(&weak).as_deref(), Some(DATE));
+        assert_eq!(validator(&headers(&[("etag", "W/\"v1\"")])), None);
+        assert_eq!(validator(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_changed() {
+        let v2 = headers(&[("etag", "\"v2\""), ("last-modified", DATE)]);
+        assert!(changed(Some("\"v1\""), &v2));
+        assert!(!changed(Some("\"v2\""), &v2));
+        // saved when the server only sent a date
+        assert!(!changed(Some(DATE), &v2));
+        assert!(changed(Some("Thu, 22 Oct 2015 07:28:00 GMT"), &v2));
+        assert!(!changed(Some("\"v1\""), &HeaderMap::new()), "cannot tell");
+        assert!(!changed(None, &v2));
+
+        let sized = headers(&[("etag", "\"v2\""), ("content-length", "100")]);
+        assert!(!outdated(Some("\"v2\""), 100, &sized));
+        assert!(outdated(Some("\"v2\""), 90, &sized));
+        assert!(outdated(None, 90, &sized), "no validator was saved");
+        assert!(!outdated(None, 0, &sized));
+    }
+}
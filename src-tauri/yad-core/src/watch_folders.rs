@@ -0,0 +1,234 @@
+//! This module handles the folders configured in the settings as watch folders. Files dropped into
+//! a watch folder are read for urls which are then downloaded. Once a file has been read, it is
+//! either moved into a `processed` sub folder or deleted depending on the settings.
+//!
+//! Supported files are:
+//! - `.txt`: one url per line. Empty lines and lines starting with `#` are ignored.
+//! - `.meta4`: every file listed is downloaded once, from all its mirrors, see `metalink`.
+//! - `.metalink`: every `<url>` element is downloaded.
+//!
+//! `.torrent` and `.nzb` files are recognised but YAD can not download them yet, so they are moved
+//! into a `rejected` sub folder instead of being left to be picked up again.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use crate::engine::is_supported_url;
+
+/// How often the watch folders are scanned.
+pub const SCAN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Files modified more recently than this are skipped because they may still be being written.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+
+const PROCESSED_DIR: &str = "processed";
+const REJECTED_DIR: &str = "rejected";
+
+/// This function returns the files in `folder` that should be ingested.
+pub fn scan(folder: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(folder) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!(
+                "failed to read watch folder {} because {e}",
+                folder.display()
+            );
+            return Vec::new();
+        }
+    };
+    let now = SystemTime::now();
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().map(|t| t.is_file()).unwrap_or(false))
+        .filter(|e| {
+            e.metadata()
+                .and_then(|m| m.modified())
+                .map(|m| now.duration_since(m).unwrap_or_default() >= SETTLE_TIME)
+                .unwrap_or(false)
+        })
+        .map(|e| e.path())
+        .filter(|p| is_trigger_file(p))
+        .collect();
+    files.sort();
+    files
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_lowercase()
+}
+
+/// This function checks whether a file is one of the types handled by watch folders.
+pub fn is_trigger_file(path: &Path) -> bool {
+    matches!(
+        extension(path).as_str(),
+        "txt" | "metalink" | "meta4" | "torrent" | "nzb"
+    )
+}
+
+/// This function extracts the urls to download from a dropped file. `.meta4` files are read with
+/// `metalink::parse` instead.
+///
+/// # Arguments
+/// - `path`: The path of the dropped file, used to determine its type.
+/// - `contents`: The contents of the file.
+///
+/// # Returns
+/// - `Ok(Vec<String>)`: The urls found in the file.
+/// - `Err(String)`: If the file type is not supported.
+pub fn extract_urls(path: &Path, contents: &str) -> Result<Vec<String>, String> {
+    match extension(path).as_str() {
+        "txt" => Ok(contents
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .filter(|l| is_supported_url(l))
+            .map(String::from)
+            .collect()),
+        "metalink" => Ok(metalink_urls(contents)),
+        ext => Err(format!(".{ext} files are not supported yet")),
+    }
+}
+
+/// This function returns the text of every `<url>` element in a Metalink 3 document.
+fn metalink_urls(contents: &str) -> Vec<String> {
+    let mut urls = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find("<url") {
+        rest = &rest[start + 4..];
+        // skip elements such as <urls> that only share the prefix
+        if !rest.starts_with('>') && !rest.starts_with(char::is_whitespace) {
+            continue;
+        }
+        let Some(open_end) = rest.find('>') else {
+            break;
+        };
+        rest = &rest[open_end + 1..];
+        let Some(close) = rest.find("</url>") else {
+            break;
+        };
+        let url = rest[..close].trim().replace("&amp;", "&");
+        if is_supported_url(&url) {
+            urls.push(url);
+        }
+        rest = &rest[close..];
+    }
+    urls
+}
+
+/// This function moves a handled file out of the watch folder so that it is not picked up again.
+///
+/// # Arguments
+/// - `path`: The file to move.
+/// - `accepted`: Whether the file was ingested. Rejected files are always kept in `rejected`.
+/// - `archive`: Whether accepted files are moved into `processed` instead of being deleted.
+pub fn finish(path: &Path, accepted: bool, archive: bool) -> std::io::Result<()> {
+    if accepted && !archive {
+        return fs::remove_file(path);
+    }
+    let folder = path.parent().unwrap_or(Path::new("."));
+    let dir = folder.join(if accepted {
+        PROCESSED_DIR
+    } else {
+        REJECTED_DIR
+    });
+    fs::create_dir_all(&dir)?;
+    let file_name = path.file_name().unwrap_or_default();
+    let mut target = dir.join(file_name);
+    let mut n = 1;
+    while target.exists() {
+        target = dir.join(format!("{n}-{}", file_name.to_string_lossy()));
+        n += 1;
+    }
+    fs::rename(path, target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join("yad_test").join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_extract_urls_from_txt() {
+        let contents =
+            "# my list\nhttps://example.com/a.zip\n\n  sftp://example.com/b.iso  \nnot a url\n";
+        let urls = extract_urls(Path::new("list.txt"), contents).unwrap();
+        assert_eq!(
+            urls,
+            vec!["https://example.com/a.zip", "sftp://example.com/b.iso"]
+        );
+    }
+
+    #[test]
+    fn test_extract_urls_from_metalink() {
+        let contents = r#"<?xml version="1.0" encoding="UTF-8"?>
+<metalink xmlns="urn:ietf:params:xml:ns:metalink">
+  <file name="example.iso">
+    <size>1024</size>
+    <url location="de" priority="1">https://mirror.example.com/example.iso?a=1&amp;b=2</url>
+    <url>ftp://ftp.example.com/example.iso</url>
+  </file>
+</metalink>"#;
+        let urls = extract_urls(Path::new("example.metalink"), contents).unwrap();
+        assert_eq!(
+            urls,
+            vec![
+                "https://mirror.example.com/example.iso?a=1&b=2",
+                "ftp://ftp.example.com/example.iso"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_torrent_is_not_supported() {
+        assert!(extract_urls(Path::new("linux.torrent"), "").is_err());
+        assert!(extract_urls(Path::new("file.NZB"), "").is_err());
+    }
+
+    #[test]
+    fn test_is_trigger_file() {
+        assert!(is_trigger_file(Path::new("a.txt")));
+        assert!(is_trigger_file(Path::new("a.META4")));
+        assert!(!is_trigger_file(Path::new("a.zip")));
+    }
+
+    #[test]
+    fn test_finish_archives_and_rejects() {
+        let dir = test_dir("watch_finish");
+        let accepted = dir.join("list.txt");
+        let rejected = dir.join("file.torrent");
+        fs::write(&accepted, "https://example.com/a.zip").unwrap();
+        fs::write(&rejected, "").unwrap();
+
+        finish(&accepted, true, true).unwrap();
+        finish(&rejected, false, false).unwrap();
+        assert!(dir.join(PROCESSED_DIR).join("list.txt").exists());
+        assert!(dir.join(REJECTED_DIR).join("file.torrent").exists());
+
+        // a second file with the same name should not overwrite the first one
+        fs::write(&accepted, "https://example.com/b.zip").unwrap();
+        finish(&accepted, true, true).unwrap();
+        assert!(dir.join(PROCESSED_DIR).join("1-list.txt").exists());
+    }
+
+    #[test]
+    fn test_finish_deletes_when_not_archiving() {
+        let dir = test_dir("watch_delete");
+        let file = dir.join("list.txt");
+        fs::write(&file, "").unwrap();
+        finish(&file, true, false).unwrap();
+        assert!(!file.exists());
+        assert!(!dir.join(PROCESSED_DIR).exists());
+    }
+}
@@ -0,0 +1,41 @@
+//! This module notices downloads that are stuck: they are running, yet for a while no chunk has
+//! started an attempt or written anything, e.g. because a worker died without reporting it. Such
+//! a download would otherwise stay `InProgress` forever. `engine::watchdog_loop` stops it, sets
+//! its chunks that have not finished back to `Pending` and starts its workers again, keeping the
+//! chunks that have finished.
+
+use std::time::Duration;
+
+/// How often the running downloads are checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The default of `Settings::stuck_after_secs`.
+pub const DEFAULT_STUCK_AFTER_SECS: u64 = 300;
+
+/// How long a stuck download is given to stop before it is started again anyway.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// This function returns whether a download is stuck.
+///
+/// # Arguments
+/// - `last_activity`: When a chunk of the download last started an attempt or wrote, in seconds
+///   since the epoch.
+/// - `now`: The current time in seconds since the epoch.
+/// - `stuck_after_secs`: See `Settings::stuck_after_secs`, 0 turns the watchdog off.
+pub fn is_stuck(last_activity: u64, now: u64, stuck_after_secs: u64) -> bool {
+    stuck_after_secs > 0 && now.saturating_sub(last_activity) >= stuck_after_secs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_stuck() {
+        assert!(!is_stuck(1000, 1100, 300));
+        assert!(is_stuck(1000, 1300, 300));
+        // the clock going back does not make a download stuck
+        assert!(!is_stuck(1000, 900, 300));
+        assert!(!is_stuck(1000, 99_000, 0));
+    }
+}
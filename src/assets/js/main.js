@@ -5,9 +5,11 @@ const state = {
   sortColumn: '',
   sortDir: 'asc',
   filterText: '',
-  activeDownloads: new Map(), // downloadId → { timestamp, bytes, speed, eta }
+  activeDownloads: new Map(), // downloadId → { speed, eta }
   customDir: '',
   pendingUrl: '', // URL waiting for rename confirmation
+  lastDeleted: [], // ids that can still be restored with "Undo"
+  units: { system: 'iec', speedInBits: false }, // see Settings::size_units
 };
 
 // ── Utilities ──────────────────────────────────────────────────────
@@ -17,15 +19,35 @@ const listen = (ev, cb) => window.__TAURI__.event.listen(ev, cb);
 
 function log(m) { console.log(`${Date.now()}: ${m}`); }
 
-function getSize(s) {
-  if (!s || s === 0) return '0 B';
-  const u = ['Bytes', 'KB', 'MB', 'GB'];
+function scale(v, base, u) {
   let i = 0;
-  let v = s;
-  while (v >= 1024 && i < 3) { v /= 1024; i++; }
+  while (v >= base && i < u.length - 1) { v /= base; i++; }
   return `${Math.round(v * 10) / 10} ${u[i]}`;
 }
 
+function getSize(s) {
+  if (!s || s === 0) return '0 B';
+  return state.units.system === 'si'
+    ? scale(s, 1000, ['B', 'kB', 'MB', 'GB', 'TB'])
+    : scale(s, 1024, ['B', 'KiB', 'MiB', 'GiB', 'TiB']);
+}
+
+function getSpeed(bytesPerSec) {
+  const si = state.units.system === 'si';
+  const base = si ? 1000 : 1024;
+  if (state.units.speedInBits) {
+    return scale(bytesPerSec * 8, base, si ? ['bit/s', 'kbit/s', 'Mbit/s', 'Gbit/s'] : ['bit/s', 'Kibit/s', 'Mibit/s', 'Gibit/s']);
+  }
+  return scale(bytesPerSec, base, si ? ['B/s', 'kB/s', 'MB/s', 'GB/s'] : ['B/s', 'KiB/s', 'MiB/s', 'GiB/s']);
+}
+
+async function loadUnits() {
+  try {
+    const s = await invoke('get_settings');
+    state.units = { system: s.size_units, speedInBits: s.speed_in_bits };
+  } catch (e) { log(`get_settings error: ${e}`); }
+}
+
 function formatTime(ts) {
   if (!ts || ts === 0) return '—';
   const d = new Date(ts * 1000);
@@ -33,16 +55,45 @@ function formatTime(ts) {
 }
 
 function statusLabel(s) {
-  const m = { Finished: 'Complete', InProgress: 'Downloading', Failed: 'Failed', Pending: 'Pending', Cancelled: 'Cancelled' };
+  const m = { Finished: 'Complete', InProgress: 'Downloading', Failed: 'Failed', Pending: 'Pending', Cancelled: 'Cancelled', Interrupted: 'Interrupted', Queued: 'Queued', Waiting: 'Waiting for network' };
   return m[s] || s;
 }
 
 function statusBadge(s) {
-  const cls = ({ Finished: 'finished', InProgress: 'inprogress', Failed: 'failed', Pending: 'pending', Cancelled: 'cancelled' })[s] || 'pending';
+  const cls = ({ Finished: 'finished', InProgress: 'inprogress', Failed: 'failed', Pending: 'pending', Cancelled: 'cancelled', Interrupted: 'cancelled', Queued: 'pending', Waiting: 'pending' })[s] || 'pending';
   return `<span class="status-badge ${cls}">${statusLabel(s)}</span>`;
 }
 
-function isUrl(str) { return /^https?:\/\/.+/i.test(str.trim()); }
+// Downloads of a part of a file are marked so that they are not mistaken for the whole file
+function partialBadge(range) {
+  if (!range) return '';
+  return `<span class="status-badge partial" title="Bytes ${range.start}-${range.end}">Partial</span>`;
+}
+
+// A finished file that changed on the server since, see check_for_update
+function outdatedBadge(r) {
+  if (r.download_status !== 'Finished' || !r.outdated) return '';
+  return '<span class="status-badge outdated" title="The file changed on the server">Outdated</span>';
+}
+
+// samples of the file that did not match the server, their chunks were downloaded again
+function spotCheckBadge(r) {
+  if (!r.spot_check_failures) return '';
+  return `<span class="status-badge outdated" title="${r.spot_check_failures} spot checks did not match the server">Corrected</span>`;
+}
+
+function importedBadge(r) {
+  if (!r.imported) return '';
+  return '<span class="status-badge imported" title="Found in a downloads folder rather than downloaded by YAD">Imported</span>';
+}
+
+// how to fix a failed download, suggested by the backend
+function failureNote(r) {
+  if (r.download_status !== 'Failed' || !r.failure_note) return '';
+  return `<small class="failure-note text-danger d-block"><i class="fa fa-lightbulb me-1"></i>${escHtml(r.failure_note)}</small>`;
+}
+
+function isUrl(str) { return /^((https?|ftps?|ftpes|sftp|s3):\/\/.+|data:[^,]*,)/i.test(str.trim()); }
 
 // ── Core rendering ─────────────────────────────────────────────────
 
@@ -61,8 +112,14 @@ function filterRows() {
   return q ? state.records.filter(r => r.file_name.toLowerCase().includes(q) || (r.file_url || '').toLowerCase().includes(q)) : state.records;
 }
 
+// Matches health::ATTENTION_THRESHOLD in the backend
+const HEALTH_ATTENTION = 50;
+
+function needsAttention(r) { return r.health != null && r.health < HEALTH_ATTENTION; }
+
 function sortRows(rows) {
-  if (!state.sortColumn) return rows;
+  // Unsorted, running downloads that need attention come first
+  if (!state.sortColumn) return [...rows].sort((a, b) => needsAttention(b) - needsAttention(a));
   const c = state.sortColumn;
   const d = state.sortDir === 'asc' ? 1 : -1;
   return [...rows].sort((a, b) => {
@@ -103,13 +160,16 @@ function render() {
     const pct = status === 'Finished' ? 100 : status === 'Pending' ? 0 : Math.round(r.downloaded_percentage || 0);
     const barCls = status === 'Finished' ? 'success' : status === 'InProgress' ? 'info' : status === 'Failed' ? 'danger' : 'warning';
     const pBarCls = status === 'InProgress' ? 'progress-bar-striped progress-bar-animated active-anim' : '';
-    const actCls = status === 'Finished' ? 'primary' : status === 'InProgress' ? 'warning' : 'success';
-    const icon = status === 'Finished' ? 'fa-folder-open' : status === 'InProgress' ? 'fa-pause' : 'fa-play';
+    // a queued download is cancelled like a running one, it leaves the queue, and a waiting one
+    // stops waiting for the network
+    const stoppable = ['InProgress', 'Queued', 'Waiting'].includes(status);
+    const actCls = status === 'Finished' ? 'primary' : stoppable ? 'warning' : 'success';
+    const icon = status === 'Finished' ? 'fa-folder-open' : stoppable ? 'fa-pause' : 'fa-play';
 
     html += `
       <tr id="row-${r.id}" class="${sel ? 'row-selected' : ''}" tabindex="0" data-id="${r.id}">
         <td class="col-select"><input type="checkbox" class="row-check" data-id="${r.id}"${sel} /></td>
-        <td class="col-file"><span class="file-name-cell d-block" title="${escAttr(r.file_name)}">${escHtml(r.file_name)}</span></td>
+        <td class="col-file"><span class="file-name-cell d-block" title="${escAttr(r.file_name)}">${escHtml(r.file_name)}</span>${failureNote(r)}</td>
         <td class="col-size" id="size-${r.id}">${getSize(r.file_size)}</td>
         <td class="col-progress" id="progress-${r.id}">
           <div class="progress" role="progressbar" aria-valuenow="${pct}" aria-valuemax="100">
@@ -117,10 +177,10 @@ function render() {
           </div>
           <div id="speed-${r.id}" class="speed-eta mt-1"></div>
         </td>
-        <td class="col-type">${escHtml(r.file_type)}${statusBadge(status)}</td>
+        <td class="col-type">${escHtml(r.file_type)}${statusBadge(status)}${partialBadge(r.byte_range)}${outdatedBadge(r)}${spotCheckBadge(r)}${importedBadge(r)}</td>
         <td class="col-date">${formatTime(r.download_start_time)}</td>
         <td class="col-actions">
-          <span class="action-link btn btn-sm btn-outline-${actCls}" data-id="${r.id}" data-url="${escAttr(r.file_url)}" data-status="${status}" data-path="${escAttr(r.destination_path)}" title="${status === 'Finished' ? 'Open file' : status === 'InProgress' ? 'Cancel' : 'Retry download'}"><i class="fa ${icon}"></i></span>
+          <span class="action-link btn btn-sm btn-outline-${actCls}" data-id="${r.id}" data-url="${escAttr(r.file_url)}" data-status="${status}" data-path="${escAttr(r.destination_path)}" title="${status === 'Finished' ? 'Open file' : stoppable ? 'Cancel' : 'Retry download'}"><i class="fa ${icon}"></i></span>
           <span class="delete-link btn btn-sm btn-outline-danger ms-1" data-id="${r.id}" title="Delete record"><i class="fa fa-trash"></i></span>
         </td>
       </tr>`;
@@ -168,12 +228,11 @@ function attachRowHandlers() {
   document.querySelectorAll('.action-link').forEach(el => {
     el.onclick = () => {
       const id = Number(el.dataset.id);
-      const url = el.dataset.url;
       const status = el.dataset.status;
       const path = el.dataset.path;
       if (status === 'Finished') invoke('open_file', { path });
-      else if (status === 'InProgress') invoke('cancel_download', { downloadId: id });
-      else startDownload(url);
+      else if (['InProgress', 'Queued', 'Waiting'].includes(status)) invoke('cancel_download', { downloadId: id });
+      else retryDownload(id);
     };
   });
 
@@ -209,9 +268,16 @@ function showContextMenu(e, id) {
   // Show/hide items based on status
   menu.querySelectorAll('[data-action]').forEach(item => {
     const a = item.dataset.action;
-    if (a === 'cancel') item.style.display = r.download_status === 'InProgress' ? 'block' : 'none';
-    else if (a === 'retry') item.style.display = ['Failed', 'Cancelled', 'Pending'].includes(r.download_status) ? 'block' : 'none';
+    if (a === 'cancel') item.style.display = ['InProgress', 'Queued', 'Waiting'].includes(r.download_status) ? 'block' : 'none';
+    else if (a === 'retry') item.style.display = ['Failed', 'Cancelled', 'Interrupted', 'Pending', 'Waiting'].includes(r.download_status) ? 'block' : 'none';
     else if (a === 'open') item.style.display = r.download_status === 'Finished' ? 'block' : 'none';
+    else if (a === 'queue-front') item.style.display = r.download_status === 'Queued' ? 'block' : 'none';
+    else if (a === 'check-update') item.style.display = r.download_status === 'Finished' ? 'block' : 'none';
+    else if (a === 'redownload') item.style.display = r.download_status === 'Finished' && r.outdated ? 'block' : 'none';
+    else if (a === 'open-when-done') {
+      item.style.display = r.download_status !== 'Finished' ? 'block' : 'none';
+      item.classList.toggle('checked', r.open_when_done === item.dataset.open);
+    }
     else item.style.display = 'block';
   });
 }
@@ -223,13 +289,84 @@ document.getElementById('context-menu').addEventListener('click', async (e) => {
   if (!r) return;
   const a = item.dataset.action;
   if (a === 'open') await invoke('open_file', { path: r.destination_path });
-  else if (a === 'open-folder') await invoke('open_file', { path: r.destination_dir });
+  else if (a === 'open-folder') {
+    if (r.download_status === 'Finished') await invoke('reveal_file', { path: r.destination_path });
+    else await invoke('open_file', { path: r.destination_dir });
+  }
   else if (a === 'copy-url') navigator.clipboard.writeText(r.file_url);
-  else if (a === 'retry') await startDownload(r.file_url);
+  else if (a === 'export-job') await exportJobFile(r);
+  else if (a === 'category') openCategoryModal(r);
+  else if (a === 'retry') await retryDownload(r.id);
+  else if (a === 'check-update') await checkForUpdate(r);
+  else if (a === 'open-when-done') {
+    // choosing the option already set turns it off
+    const action = r.open_when_done === item.dataset.open ? null : item.dataset.open;
+    try {
+      await invoke('set_open_when_done', { id: r.id, action });
+      r.open_when_done = action;
+    } catch (e) { log(`set_open_when_done error: ${e}`); }
+  }
+  else if (a === 'redownload') {
+    try { await invoke('redownload', { id: r.id }); } catch (e) { showAlert(`Download failed: ${e}`, 'danger'); }
+  }
   else if (a === 'cancel') await invoke('cancel_download', { downloadId: r.id });
+  else if (a === 'queue-front') {
+    try { await invoke('move_in_queue', { id: r.id, position: 0 }); } catch (e) { log(`move_in_queue error: ${e}`); }
+  }
   else if (a === 'delete') await deleteRecord(r.id);
   hideContextMenu();
 });
+// A .yad job file lets a teammate download exactly the same file, verified by its checksum
+async function exportJobFile(r) {
+  const stem = r.file_name.replace(/\.[^.]*$/, '') || 'download';
+  const path = await window.__TAURI__.dialog.save({
+    title: 'Share as job file',
+    defaultPath: `${stem}.yad`,
+    filters: [{ name: 'YAD job', extensions: ['yad'] }],
+  });
+  if (!path) return;
+  try {
+    await invoke('export_job_file', { id: r.id, path });
+    showAlert(`Saved ${path}`, 'success');
+  } catch (e) {
+    showAlert(`${e}`);
+  }
+}
+// Later downloads with the same extension get the category chosen here
+let categoryId = null;
+
+function openCategoryModal(r) {
+  categoryId = r.id;
+  document.getElementById('category-select').value = r.file_type;
+  document.getElementById('category-move').checked = true;
+  const modal = document.getElementById('category-modal');
+  modal.style.display = 'block';
+  modal.classList.add('show');
+}
+
+function closeCategoryModal() {
+  const modal = document.getElementById('category-modal');
+  modal.style.display = 'none';
+  modal.classList.remove('show');
+  categoryId = null;
+}
+
+document.getElementById('category-confirm').onclick = async () => {
+  const id = categoryId;
+  const category = document.getElementById('category-select').value;
+  const moveFile = document.getElementById('category-move').checked;
+  closeCategoryModal();
+  try {
+    await invoke('set_category', { downloadId: id, category, moveFile });
+    await getRecords();
+  } catch (e) {
+    showAlert(`${e}`);
+  }
+};
+
+document.querySelectorAll('#category-modal .btn-close, #category-modal [data-bs-dismiss="modal"]').forEach(el => {
+  el.onclick = closeCategoryModal;
+});
 document.addEventListener('click', hideContextMenu);
 function hideContextMenu() { document.getElementById('context-menu').style.display = 'none'; contextId = null; }
 
@@ -267,36 +404,108 @@ function updateBulkBar() {
 }
 
 document.getElementById('retry-selected-btn').onclick = async () => {
-  const ids = [...state.selected];
-  for (const id of ids) {
-    const r = state.records.find(x => x.id === id);
-    if (r && ['Failed', 'Cancelled', 'Pending'].includes(r.download_status)) await startDownload(r.file_url);
-  }
+  try { await invoke('retry_downloads', { ids: [...state.selected] }); } catch (e) { log(`retry error: ${e}`); }
   state.selected.clear();
   updateBulkBar();
 };
+// The report lists name, url, size, checksum and date, e.g. to document the datasets fetched
+document.getElementById('report-btn').onclick = async () => {
+  const ids = state.selected.size > 0 ? [...state.selected] : null;
+  const path = await window.__TAURI__.dialog.save({
+    title: ids ? `Export a report of ${ids.length} download(s)` : 'Export a report of all downloads',
+    defaultPath: 'downloads-report.md',
+    filters: [
+      { name: 'Markdown', extensions: ['md'] },
+      { name: 'HTML', extensions: ['html', 'htm'] },
+    ],
+  });
+  if (!path) return;
+  const format = /\.html?$/i.test(path) ? 'html' : 'markdown';
+  try {
+    await invoke('export_report', { format, ids, path });
+    showAlert(`Saved ${path}`, 'success');
+  } catch (e) {
+    showAlert(`${e}`);
+  }
+};
+// Brings the files of a downloads folder filled before YAD into its folders and history
+document.getElementById('organize-btn').onclick = async () => {
+  const path = await window.__TAURI__.dialog.open({ directory: true, title: 'Choose a downloads folder to organize' });
+  if (!path) return;
+  if (!confirm(`Move the files in ${path} into the YAD folders?`)) return;
+  try {
+    const summary = await invoke('organize_existing', { path });
+    const parts = [`Imported ${summary.imported} file(s)`];
+    if (summary.skipped) parts.push(`${summary.skipped} already in the history`);
+    if (summary.failed.length) parts.push(`${summary.failed.length} failed`);
+    summary.failed.forEach(e => log(`organize error: ${e}`));
+    showAlert(parts.join(', ') + '.', summary.failed.length ? 'warning' : 'success');
+  } catch (e) {
+    showAlert(`${e}`);
+  }
+  await getRecords();
+};
 document.getElementById('delete-selected-btn').onclick = async () => {
   if (!confirm(`Delete ${state.selected.size} record(s)?`)) return;
-  for (const id of state.selected) await deleteRecord(id);
+  await deleteRecords([...state.selected]);
   state.selected.clear();
   updateBulkBar();
 };
 
-// ── Delete & Clear completed ───────────────────────────────────────
+// ── Delete, Undo & Clear completed ─────────────────────────────────
 
 async function deleteRecord(id) {
   if (!confirm('Delete this download record?')) return;
-  try { await invoke('delete_record', { id }); } catch (e) { log(`delete error: ${e}`); }
-  state.selected.delete(id);
+  await deleteRecords([id]);
+}
+
+// Deleted records are kept for a short while by the backend so the deletion can be undone.
+async function deleteRecords(ids) {
+  let deleted = [];
+  try {
+    deleted = (await invoke('delete_records', { ids })).succeeded;
+  } catch (e) { log(`delete error: ${e}`); }
+  ids.forEach(id => state.selected.delete(id));
+  await getRecords();
+  if (deleted.length === 0) return;
+  state.lastDeleted = deleted;
+  showAlert(`Deleted ${deleted.length} record(s).`, 'secondary');
+  const undo = document.createElement('button');
+  undo.type = 'button';
+  undo.className = 'btn btn-link btn-sm p-0 ms-2 align-baseline';
+  undo.textContent = 'Undo';
+  undo.onclick = undoDelete;
+  const purge = document.createElement('button');
+  purge.type = 'button';
+  purge.className = 'btn btn-link btn-sm p-0 ms-2 align-baseline text-danger';
+  purge.textContent = 'Delete permanently';
+  purge.onclick = purgeDeleted;
+  document.getElementById('live-alert-text').append(undo, purge);
+}
+
+async function purgeDeleted() {
+  state.lastDeleted = [];
+  document.getElementById('live-alert').style.display = 'none';
+  try { await invoke('purge_deleted'); } catch (e) { log(`purge error: ${e}`); }
+}
+
+async function undoDelete() {
+  const ids = state.lastDeleted;
+  state.lastDeleted = [];
+  document.getElementById('live-alert').style.display = 'none';
+  const failed = [];
+  for (const id of ids) {
+    try { await invoke('undo_delete', { downloadId: id }); } catch (e) { failed.push(id); log(`undo error: ${e}`); }
+  }
   await getRecords();
+  if (failed.length) showAlert(`${failed.length} record(s) could no longer be restored.`);
 }
 
 document.getElementById('clear-completed-btn').onclick = async () => {
   const completed = state.records.filter(r => r.download_status === 'Finished');
   if (completed.length === 0) return;
   if (!confirm(`Delete ${completed.length} completed record(s)?`)) return;
-  for (const r of completed) { try { await invoke('delete_record', { id: r.id }); } catch (_) {} }
-  await getRecords();
+  await deleteRecords(completed.map(r => r.id));
 };
 
 // ── Stats ──────────────────────────────────────────────────────────
@@ -324,30 +533,14 @@ function updateTitle() {
 
 // ── Speed & ETA ────────────────────────────────────────────────────
 
-function updateSpeed(id, downloaded, total, timestamp) {
-  const now = Date.now();
-  let info = state.activeDownloads.get(id);
-  if (!info) {
-    info = { lastBytes: downloaded, lastTime: now, started: now, speed: 0 };
-    state.activeDownloads.set(id, info);
-    return;
-  }
-  const dt = (now - info.lastTime) / 1000;
-  if (dt < 0.5) return;
-  const dBytes = downloaded - info.lastBytes;
-  info.speed = dBytes / dt;
-  info.lastBytes = downloaded;
-  info.lastTime = now;
-  state.activeDownloads.set(id, info);
-
-  const speed = info.speed;
-  const remaining = total - downloaded;
-  const eta = speed > 0 ? remaining / speed : 0;
+// speed and eta are measured by the backend over the last few seconds
+function updateSpeed(id, speed, eta) {
+  state.activeDownloads.set(id, { speed, eta });
 
   const el = document.getElementById(`speed-${id}`);
   if (!el) return;
-  const speedStr = speed > 1024 * 1024 ? `${(speed / 1024 / 1024).toFixed(1)} MB/s` : speed > 1024 ? `${(speed / 1024).toFixed(1)} KB/s` : `${speed.toFixed(0)} B/s`;
-  if (remaining > 0 && speed > 0) {
+  const speedStr = getSpeed(speed);
+  if (eta > 0) {
     const etaStr = eta > 3600 ? `${(eta / 3600).toFixed(1)}h` : eta > 60 ? `${(eta / 60).toFixed(1)}m` : `${eta.toFixed(0)}s`;
     el.textContent = `${speedStr} · ETA ${etaStr}`;
   } else {
@@ -363,21 +556,85 @@ function clearSpeed(id) {
 
 // ── Download flow ──────────────────────────────────────────────────
 
-async function startDownload(url, customName, customDir) {
+async function startDownload(url, customName, customDir, templateId, byteRange, videoHeight) {
   try {
-    await invoke('download', { url, fileName: customName || null, destinationDir: customDir || null });
+    await invoke('download', {
+      url,
+      fileName: customName || null,
+      destinationDir: customDir || null,
+      templateId: templateId || null,
+      byteRange: byteRange || null,
+      videoHeight: videoHeight || null,
+    });
   } catch (e) {
     log(`Download error: ${e}`);
     showAlert(`Download failed: ${e}`, 'danger');
   }
 }
 
+// Only the failed and pending chunks are downloaded again, into the existing file
+async function retryDownload(id) {
+  try {
+    await invoke('retry_download', { id });
+  } catch (e) {
+    log(`Retry error: ${e}`);
+    showAlert(`Retry failed: ${e}`, 'danger');
+  }
+}
+
+// Compares the ETag, date and size of a finished file with the ones on the server
+async function checkForUpdate(r) {
+  try {
+    const outdated = await invoke('check_for_update', { id: r.id });
+    if (outdated) showAlert(`${r.file_name} changed on the server, right-click it to download the new version`, 'warning');
+    else showAlert(`${r.file_name} is up to date`, 'success');
+    await getRecords();
+  } catch (e) {
+    log(`check_for_update error: ${e}`);
+    showAlert(`Could not check ${r.file_name}: ${e}`, 'danger');
+  }
+}
+
+// Templates ("Work VPN", "Linux ISO", ...) preset the folder, headers and limits of new downloads
+async function loadTemplates() {
+  const select = document.getElementById('template-select');
+  let templates = [];
+  try { templates = await invoke('fetch_templates') || []; } catch (e) { log(`fetch_templates error: ${e}`); }
+  select.innerHTML = '<option value="">No template</option>';
+  for (const t of templates) {
+    const opt = document.createElement('option');
+    opt.value = t.id;
+    opt.textContent = t.name;
+    select.appendChild(opt);
+  }
+  select.classList.toggle('d-none', templates.length === 0);
+}
+
+function selectedTemplate() {
+  const v = document.getElementById('template-select').value;
+  return v ? Number(v) : null;
+}
+
 // File rename modal
 let renameResolve = null;
 
+// DASH manifests are downloaded as the MP4 file their video and audio are muxed into
+function isDashManifest(url) {
+  return /^https?:\/\/[^?#]+\.mpd([?#].*)?$/i.test(url);
+}
+
 function promptFileName(url) {
-  const name = url.split('/').filter(s => s).pop() || 'download';
+  const dash = isDashManifest(url);
+  // a data: url holds its content, the file is named after its media type unless renamed
+  const data = /^data:/i.test(url);
+  let name = data ? '' : url.split(/[?#]/)[0].split('/').filter(s => s).pop() || 'download';
+  if (dash) name = name.replace(/\.mpd$/i, '.mp4');
   document.getElementById('rename-input').value = name;
+  document.getElementById('first-mb-input').value = '';
+  // parts of files make no sense for a stream, its quality does
+  document.getElementById('first-mb-group').classList.toggle('d-none', dash || data);
+  document.getElementById('quality-group').classList.toggle('d-none', !dash);
+  document.getElementById('quality-select').value = '';
   const modal = document.getElementById('rename-modal');
   modal.style.display = 'block';
   modal.classList.add('show');
@@ -394,8 +651,12 @@ function promptFileName(url) {
 
 document.getElementById('rename-confirm').onclick = () => {
   const val = document.getElementById('rename-input').value.trim();
+  // only the first MB of the file are downloaded when set, e.g. to look at the header of an archive
+  const mb = Number(document.getElementById('first-mb-input').value);
+  const byteRange = mb > 0 ? { start: 0, end: Math.round(mb * 1024 * 1024) - 1 } : null;
+  const videoHeight = Number(document.getElementById('quality-select').value) || null;
   closeRenameModal();
-  if (renameResolve) renameResolve(val || null);
+  if (renameResolve) renameResolve({ name: val || null, byteRange, videoHeight });
 };
 
 document.querySelectorAll('#rename-modal .btn-close, #rename-modal [data-bs-dismiss="modal"]').forEach(el => {
@@ -424,9 +685,8 @@ urlInput.addEventListener('paste', async (e) => {
   if (urls.length === 0) { showAlert('No valid URLs found in paste.', 'warning'); return; }
   urlInput.value = '';
   for (const u of urls) {
-    const name = await promptFileName(u);
-    if (name) await startDownload(u, name, state.customDir || null);
-    else await startDownload(u, null, state.customDir || null);
+    const choice = await promptFileName(u);
+    await startDownload(u, choice?.name, state.customDir || null, selectedTemplate(), choice?.byteRange, choice?.videoHeight);
   }
 });
 
@@ -435,21 +695,19 @@ urlInput.addEventListener('keydown', async (e) => {
   if (e.key !== 'Enter') return;
   e.preventDefault();
   const url = urlInput.value.trim();
-  if (!isUrl(url)) { showAlert('Invalid URL. Must start with http:// or https://.', 'warning'); return; }
+  if (!isUrl(url)) { showAlert('Invalid URL. Must start with http://, https://, ftp://, ftps://, ftpes://, sftp://, s3:// or data:.', 'warning'); return; }
   urlInput.value = '';
-  const name = await promptFileName(url);
-  if (name) await startDownload(url, name, state.customDir || null);
-  else await startDownload(url, null, state.customDir || null);
+  const choice = await promptFileName(url);
+  await startDownload(url, choice?.name, state.customDir || null, selectedTemplate(), choice?.byteRange, choice?.videoHeight);
 });
 
 // Download button
 document.getElementById('download-btn').onclick = async () => {
   const url = urlInput.value.trim();
-  if (!isUrl(url)) { showAlert('Invalid URL. Must start with http:// or https://.', 'warning'); return; }
+  if (!isUrl(url)) { showAlert('Invalid URL. Must start with http://, https://, ftp://, ftps://, ftpes://, sftp://, s3:// or data:.', 'warning'); return; }
   urlInput.value = '';
-  const name = await promptFileName(url);
-  if (name) await startDownload(url, name, state.customDir || null);
-  else await startDownload(url, null, state.customDir || null);
+  const choice = await promptFileName(url);
+  await startDownload(url, choice?.name, state.customDir || null, selectedTemplate(), choice?.byteRange, choice?.videoHeight);
 };
 
 // ── Directory picker ───────────────────────────────────────────────
@@ -520,8 +778,21 @@ listen('download-started', (e) => {
   getRecords();
 });
 
-listen('download-progress', (e) => {
-  const d = e.payload;
+// Screen readers read out whatever is written into this polite live region
+function announce(text) {
+  let el = document.getElementById('progress-announcer');
+  if (!el) {
+    el = document.createElement('div');
+    el.id = 'progress-announcer';
+    el.className = 'visually-hidden';
+    el.setAttribute('aria-live', 'polite');
+    el.setAttribute('role', 'status');
+    document.body.appendChild(el);
+  }
+  el.textContent = text;
+}
+
+function applyProgress(d) {
   const id = d.downloadId;
   const pct = d.totalSize > 0 ? Math.min(100, (d.downloaded / d.totalSize) * 100) : 0;
   const intPct = Math.round(pct);
@@ -530,7 +801,7 @@ listen('download-progress', (e) => {
   const pc = document.getElementById(`progress-${id}`);
   if (pc) {
     pc.innerHTML = `
-      <div class="progress" role="progressbar" aria-valuenow="${intPct}" aria-valuemax="100">
+      <div class="progress" role="progressbar" aria-valuenow="${intPct}" aria-valuemax="100" aria-valuetext="${escAttr(d.summary || '')}">
         <div class="progress-bar text-bg-info progress-bar-striped progress-bar-animated active-anim" style="width:${intPct}%">${intPct}%</div>
       </div>
       <div id="speed-${id}" class="speed-eta mt-1"></div>`;
@@ -538,24 +809,125 @@ listen('download-progress', (e) => {
 
   // Size cell
   const sc = document.getElementById(`size-${id}`);
-  if (sc) sc.textContent = `${getSize(d.downloaded)} / ${getSize(d.totalSize)}`;
+  // totalSize is 0 while the size of a streamed download is unknown
+  if (sc) sc.textContent = d.totalSize > 0 ? `${getSize(d.downloaded)} / ${getSize(d.totalSize)}` : getSize(d.downloaded);
 
   // Speed & ETA
-  updateSpeed(id, d.downloaded, d.totalSize, d.timestamp);
+  updateSpeed(id, d.speed, d.eta);
+
+  if (d.milestone) announce(d.summary);
 
   if (intPct >= 100) {
     clearSpeed(id);
     setTimeout(() => getRecords(), 600);
   }
+}
+
+listen('download-progress', (e) => applyProgress(e.payload));
+
+// hashing a big file takes a while once all of it is downloaded
+listen('download-verifying', (e) => {
+  const d = e.payload;
+  const el = document.getElementById(`speed-${d.downloadId}`);
+  if (el) el.textContent = d.percent >= 100 ? '' : `Verifying ${d.percent}%`;
 });
 
+// Restore progress bars of downloads that were running before the window was (re)loaded
+async function restoreActiveDownloads() {
+  try {
+    const active = await invoke('get_active_downloads') || [];
+    active.forEach(applyProgress);
+  } catch (e) {
+    log(`get_active_downloads error: ${e}`);
+  }
+}
+
 listen('download-message', (e) => {
   const d = e.payload;
   log(`download-message: ${d.status} — ${d.message}`);
-  showAlert(d.message, d.status === 'error' ? 'danger' : 'success');
+  showAlert(d.message, { error: 'danger', info: 'info' }[d.status] || 'success');
   getRecords();
 });
 
+// ── Decisions ──────────────────────────────────────────────────────
+// A download that needs the user to decide waits until it is answered, one question at a time
+
+const decisionLabels = {
+  rename: 'Keep both',
+  overwrite: 'Overwrite',
+  skip: 'Skip',
+  download_again: 'Download again',
+  download: 'Download',
+  cancel: 'Cancel',
+  sign_in: 'Sign in',
+  trust: 'Trust',
+};
+const decisions = [];
+
+function showNextDecision() {
+  const modal = document.getElementById('decision-modal');
+  const d = decisions[0];
+  if (!d) {
+    modal.style.display = 'none';
+    modal.classList.remove('show');
+    return;
+  }
+  document.getElementById('decision-message').textContent = d.message;
+  const credentials = d.kind === 'credentials';
+  document.getElementById('decision-credentials').style.display = credentials ? 'block' : 'none';
+  document.getElementById('decision-user').value = '';
+  document.getElementById('decision-password').value = '';
+  const footer = document.getElementById('decision-choices');
+  footer.innerHTML = '';
+  for (const choice of d.choices) {
+    const btn = document.createElement('button');
+    btn.type = 'button';
+    btn.className = `btn btn-sm ${choice === d.default ? 'btn-secondary' : 'btn-primary'}`;
+    btn.textContent = decisionLabels[choice] ?? choice;
+    btn.onclick = () => answerDecision(choice);
+    footer.appendChild(btn);
+  }
+  modal.style.display = 'block';
+  modal.classList.add('show');
+}
+
+async function answerDecision(choice) {
+  const d = decisions.shift();
+  if (!d) return;
+  let input = null;
+  if (d.kind === 'credentials' && choice === 'sign_in') {
+    const user = document.getElementById('decision-user').value;
+    const password = document.getElementById('decision-password').value;
+    input = `${user}:${password}`;
+  }
+  try {
+    await invoke('resolve_decision', { token: d.token, choice, input });
+  } catch (e) {
+    log(`resolve_decision error: ${e}`);
+  }
+  showNextDecision();
+}
+
+function queueDecision(d) {
+  if (decisions.some(q => q.token === d.token)) return;
+  decisions.push(d);
+  if (decisions.length === 1) showNextDecision();
+}
+
+listen('decision-required', (e) => queueDecision(e.payload));
+
+document.querySelectorAll('#decision-modal .btn-close').forEach(el => {
+  el.onclick = () => answerDecision(decisions[0]?.default);
+});
+
+async function restoreDecisions() {
+  try {
+    (await invoke('get_pending_decisions')).forEach(queueDecision);
+  } catch (e) {
+    log(`get_pending_decisions error: ${e}`);
+  }
+}
+
 // ── Theme ──────────────────────────────────────────────────────────
 
 window.addEventListener('DOMContentLoaded', () => {
@@ -581,4 +953,4 @@ window.addEventListener('DOMContentLoaded', () => {
 
 // ── Init ───────────────────────────────────────────────────────────
 
-window.onload = () => getRecords();
+window.onload = () => loadUnits().then(getRecords).then(restoreActiveDownloads).then(loadTemplates).then(restoreDecisions);